@@ -1,6 +1,14 @@
+#[cfg(feature = "gtk")]
 use std::fs;
 
 fn main() {
+    // The UI and resources are only used by the app, which needs GTK
+    #[cfg(feature = "gtk")]
+    build_resources();
+}
+
+#[cfg(feature = "gtk")]
+fn build_resources() {
     build_blueprints("ui", "data/ui");
 
     glib_build_tools::compile_resources(
@@ -8,8 +16,70 @@ fn main() {
         "data/potato-md.gresource.xml",
         "potato-md.gresource",
     );
+
+    compile_schemas("data");
+    compile_translations("po", "potato-md");
+}
+
+/// Compile the catalogues of the languages listed in `po/LINGUAS` into the output
+/// directory, laid out like an installed locale directory
+#[cfg(feature = "gtk")]
+fn compile_translations(po_dir: &str, domain: &str) {
+    let linguas = format!("{po_dir}/LINGUAS");
+    println!("cargo:rerun-if-changed={linguas}");
+    let languages = fs::read_to_string(&linguas).expect("Failed to read LINGUAS");
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is not set");
+
+    for language in languages
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(str::split_whitespace)
+    {
+        let input = format!("{po_dir}/{language}.po");
+        println!("cargo:rerun-if-changed={input}");
+        let dest_dir = format!("{out_dir}/locale/{language}/LC_MESSAGES");
+        fs::create_dir_all(&dest_dir).expect("Failed to create destination directory");
+
+        let status = std::process::Command::new("msgfmt")
+            .arg("--check")
+            .arg("--output-file")
+            .arg(format!("{dest_dir}/{domain}.mo"))
+            .arg(&input)
+            .status()
+            .expect("Failed to execute msgfmt");
+
+        if !status.success() {
+            panic!("msgfmt failed with status: {}", status);
+        }
+    }
 }
 
+/// Compile the GSettings schemas of `src_dir` into the output directory, where the app
+/// finds them when it runs without being installed
+#[cfg(feature = "gtk")]
+fn compile_schemas(src_dir: &str) {
+    for entry in fs::read_dir(src_dir).expect("Failed to read directory") {
+        let path = entry.expect("Failed to read directory entry").path();
+        if path.to_string_lossy().ends_with(".gschema.xml") {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is not set");
+    let status = std::process::Command::new("glib-compile-schemas")
+        .arg("--strict")
+        .arg("--targetdir")
+        .arg(&out_dir)
+        .arg(src_dir)
+        .status()
+        .expect("Failed to execute glib-compile-schemas");
+
+    if !status.success() {
+        panic!("glib-compile-schemas failed with status: {}", status);
+    }
+}
+
+#[cfg(feature = "gtk")]
 fn build_blueprints(src_dir: &str, dest_dir: &str) {
     fn compile_blueprint(input: &str, output: &str) {
         let status = std::process::Command::new("blueprint-compiler")
@@ -38,20 +108,20 @@ fn build_blueprints(src_dir: &str, dest_dir: &str) {
                 );
                 fs::create_dir_all(&dest_subdir).expect("Failed to create destination directory");
                 process_directory(path.to_str().expect("Invalid directory path"), &dest_subdir);
-            } else if let Some(extension) = path.extension() {
-                if extension == "blp" {
-                    let input = path.to_str().expect("Invalid file path").to_string();
-                    let output = format!(
-                        "{}/{}",
-                        dest_dir,
-                        path.file_stem()
-                            .expect("Invalid file name")
-                            .to_str()
-                            .expect("Invalid file name")
-                    );
-                    let output = format!("{}.ui", output);
-                    compile_blueprint(&input, &output);
-                }
+            } else if let Some(extension) = path.extension()
+                && extension == "blp"
+            {
+                let input = path.to_str().expect("Invalid file path").to_string();
+                let output = format!(
+                    "{}/{}",
+                    dest_dir,
+                    path.file_stem()
+                        .expect("Invalid file name")
+                        .to_str()
+                        .expect("Invalid file name")
+                );
+                let output = format!("{}.ui", output);
+                compile_blueprint(&input, &output);
             }
         }
     }
@@ -0,0 +1,572 @@
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "gtk")]
+use adw::{glib, gtk};
+#[cfg(feature = "gtk")]
+use sourceview5::prelude::*;
+
+use crate::i18n::gettext;
+use crate::markdown::{self, Block, Document, Footnote, FrontMatter, Image, Inline};
+use crate::math;
+
+/// Log a warning through GLib with `gtk`, and on stderr without it, when GLib is not
+/// a dependency
+macro_rules! warning {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "gtk")]
+        glib::g_warning!("potato-md", $($arg)*);
+        #[cfg(not(feature = "gtk"))]
+        eprintln!("potato-md-WARNING: {}", format_args!($($arg)*));
+    }};
+}
+
+/// Stylesheet embedded in exported documents, following the Adwaita colours of the app
+const STYLESHEET: &str = include_str!("../../data/export.css");
+
+/// Finds the markdown file a wiki-link target names
+pub type WikiLinkResolver<'a> = &'a dyn Fn(&str) -> Option<PathBuf>;
+
+/// Standalone HTML page for a document: styles are embedded, local images inlined as
+/// data URLs and code blocks highlighted with the same schemes as the viewer.
+/// Wiki-links point at the markdown file `resolve_wiki_link` finds, relative to
+/// `base_dir`, or at a file named after the page next to the document. Pages are not
+/// exported along with the document, so the links lead to the markdown source.
+pub fn document_html(
+    document: &Document,
+    base_dir: Option<&Path>,
+    dark: bool,
+    resolve_wiki_link: Option<WikiLinkResolver>,
+) -> String {
+    let writer = HtmlWriter {
+        base_dir,
+        dark,
+        resolve_wiki_link,
+    };
+    let title = document
+        .front_matter
+        .as_ref()
+        .and_then(FrontMatter::title)
+        .or_else(|| {
+            document.blocks.iter().find_map(|block| match block {
+                Block::Heading { content, .. } => Some(Inline::plain_text(content)),
+                _ => None,
+            })
+        })
+        .unwrap_or_default();
+
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str("<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n");
+    out.push_str(&format!("<title>{}</title>\n", escape(&title)));
+    out.push_str(&format!("<style>\n{STYLESHEET}</style>\n"));
+    out.push_str("</head>\n");
+    out.push_str(if dark {
+        "<body class=\"dark\">\n"
+    } else {
+        "<body>\n"
+    });
+    out.push_str("<main class=\"document\">\n");
+
+    if let Some(front_matter) = &document.front_matter {
+        writer.metadata_header(front_matter, &mut out);
+    }
+    for block in &document.blocks {
+        writer.block(block, &mut out);
+    }
+    if !document.footnotes.is_empty() {
+        writer.footnotes(&document.footnotes, &mut out);
+    }
+
+    out.push_str("</main>\n</body>\n</html>\n");
+    out
+}
+
+struct HtmlWriter<'a> {
+    /// Directory that relative image paths are resolved against
+    base_dir: Option<&'a Path>,
+    dark: bool,
+    resolve_wiki_link: Option<WikiLinkResolver<'a>>,
+}
+
+impl HtmlWriter<'_> {
+    /// Title, author, date and tags from the front matter. Unlike the viewer and print, the
+    /// date and numbers are kept as written, since the file is read under any locale.
+    fn metadata_header(&self, front_matter: &FrontMatter, out: &mut String) {
+        out.push_str("<header class=\"metadata\">\n");
+        if let Some(title) = front_matter.title() {
+            out.push_str(&format!("<h1 class=\"title\">{}</h1>\n", escape(&title)));
+        }
+
+        let byline = [front_matter.author(), front_matter.date()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" · ");
+        if !byline.is_empty() {
+            out.push_str(&format!("<p class=\"byline\">{}</p>\n", escape(&byline)));
+        }
+
+        let tags = front_matter.tags();
+        if !tags.is_empty() {
+            out.push_str("<ul class=\"tags\">");
+            for tag in tags {
+                out.push_str(&format!("<li>{}</li>", escape(&tag)));
+            }
+            out.push_str("</ul>\n");
+        }
+        out.push_str("</header>\n");
+    }
+
+    fn block(&self, block: &Block, out: &mut String) {
+        match block {
+            Block::Heading { level, content } => {
+                let slug = markdown::slug(&Inline::plain_text(content));
+                out.push_str(&format!("<h{level} id=\"{}\">", escape(&slug)));
+                self.inlines(content, out);
+                out.push_str(&format!("</h{level}>\n"));
+            }
+            Block::Paragraph(content) => {
+                out.push_str("<p>");
+                self.inlines(content, out);
+                out.push_str("</p>\n");
+            }
+            Block::Image(image) => {
+                out.push_str("<p class=\"image\">");
+                self.image(image, out);
+                out.push_str("</p>\n");
+            }
+            Block::Code { language, code } => {
+                let code = code.strip_suffix('\n').unwrap_or(code);
+                match language {
+                    Some(language) => out.push_str(&format!(
+                        "<pre class=\"code-block\"><code class=\"language-{}\">",
+                        escape(language)
+                    )),
+                    None => out.push_str("<pre class=\"code-block\"><code>"),
+                }
+                out.push_str(&highlight(language.as_deref(), code, self.dark));
+                out.push_str("</code></pre>\n");
+            }
+            Block::Quote(blocks) => {
+                out.push_str("<blockquote>\n");
+                for block in blocks {
+                    self.block(block, out);
+                }
+                out.push_str("</blockquote>\n");
+            }
+            Block::List { start, items } => {
+                match start {
+                    Some(1) => out.push_str("<ol>\n"),
+                    Some(start) => out.push_str(&format!("<ol start=\"{start}\">\n")),
+                    None => out.push_str("<ul>\n"),
+                }
+                for item in items {
+                    out.push_str("<li>\n");
+                    for block in item {
+                        self.block(block, out);
+                    }
+                    out.push_str("</li>\n");
+                }
+                out.push_str(if start.is_some() {
+                    "</ol>\n"
+                } else {
+                    "</ul>\n"
+                });
+            }
+            Block::Math(source) => {
+                out.push_str("<div class=\"math-display\">");
+                math_html(source, true, out);
+                out.push_str("</div>\n");
+            }
+            Block::Rule => out.push_str("<hr>\n"),
+        }
+    }
+
+    fn footnotes(&self, footnotes: &[Footnote], out: &mut String) {
+        out.push_str("<section class=\"footnotes\">\n<hr>\n<ol>\n");
+        for footnote in footnotes {
+            let label = escape(&footnote.label);
+            out.push_str(&format!(
+                "<li id=\"fn-{label}\" value=\"{}\">\n",
+                footnote.number
+            ));
+            for block in &footnote.blocks {
+                self.block(block, out);
+            }
+            out.push_str(&format!(
+                "<a class=\"footnote-back\" href=\"#fnref-{label}\" title=\"{}\">↩</a>\n</li>\n",
+                escape(&gettext("Back to reference"))
+            ));
+        }
+        out.push_str("</ol>\n</section>\n");
+    }
+
+    fn inlines(&self, content: &[Inline], out: &mut String) {
+        for inline in content {
+            self.inline(inline, out);
+        }
+    }
+
+    fn inline(&self, inline: &Inline, out: &mut String) {
+        match inline {
+            Inline::Text(text) => out.push_str(&escape(text)),
+            Inline::Code(code) => out.push_str(&format!("<code>{}</code>", escape(code))),
+            Inline::Emphasis(content) => self.wrapped("em", content, out),
+            Inline::Strong(content) => self.wrapped("strong", content, out),
+            Inline::Strikethrough(content) => self.wrapped("s", content, out),
+            Inline::Link {
+                url,
+                title,
+                content,
+            } => {
+                out.push_str(&format!("<a href=\"{}\"", escape(url)));
+                if !title.is_empty() {
+                    out.push_str(&format!(" title=\"{}\"", escape(title)));
+                }
+                out.push('>');
+                self.inlines(content, out);
+                out.push_str("</a>");
+            }
+            Inline::WikiLink { target, content } => {
+                let href = self.wiki_link_href(target);
+                out.push_str(&format!("<a href=\"{}\">", escape(&href)));
+                self.inlines(content, out);
+                out.push_str("</a>");
+            }
+            Inline::Image(image) => self.image(image, out),
+            Inline::FootnoteReference { label, number } => {
+                let label = escape(label);
+                out.push_str(&format!(
+                    "<sup id=\"fnref-{label}\"><a href=\"#fn-{label}\">{number}</a></sup>"
+                ));
+            }
+            Inline::Math { source, display } => math_html(source, *display, out),
+            Inline::SoftBreak => out.push('\n'),
+            Inline::HardBreak => out.push_str("<br>\n"),
+        }
+    }
+
+    /// URL of the page a wiki-link names, headings are slugs
+    fn wiki_link_href(&self, target: &str) -> String {
+        let (page, heading) = match target.split_once('#') {
+            Some((page, heading)) => (page.trim(), Some(heading)),
+            None => (target.trim(), None),
+        };
+        let path = self.resolve_wiki_link.and_then(|resolve| resolve(target));
+        let mut href = match (path, self.base_dir) {
+            (Some(path), Some(base_dir)) => encode_path(&relative_path(&path, base_dir)),
+            (Some(path), None) => format!("file://{}", encode_path(&path)),
+            (None, _) => encode_path(Path::new(&format!("{page}.md"))),
+        };
+        if let Some(heading) = heading {
+            href.push('#');
+            href.push_str(&markdown::slug(heading));
+        }
+        href
+    }
+
+    fn wrapped(&self, tag: &str, content: &[Inline], out: &mut String) {
+        out.push_str(&format!("<{tag}>"));
+        self.inlines(content, out);
+        out.push_str(&format!("</{tag}>"));
+    }
+
+    /// Image with local files embedded as data URLs, remote ones are left as links
+    fn image(&self, image: &Image, out: &mut String) {
+        let src = image
+            .local_path(self.base_dir)
+            .and_then(|path| match data_url(&path) {
+                Ok(url) => Some(url),
+                Err(err) => {
+                    warning!("Failed to embed {}: {err}", path.display());
+                    None
+                }
+            })
+            .unwrap_or_else(|| image.url.clone());
+
+        out.push_str(&format!(
+            "<img src=\"{}\" alt=\"{}\"",
+            escape(&src),
+            escape(&image.alt)
+        ));
+        if !image.title.is_empty() {
+            out.push_str(&format!(" title=\"{}\"", escape(&image.title)));
+        }
+        out.push('>');
+    }
+}
+
+/// `path` relative to the directory `dir`, going up with `..` where it is not below it
+fn relative_path(path: &Path, dir: &Path) -> PathBuf {
+    let path: Vec<_> = path.components().collect();
+    let dir: Vec<_> = dir.components().collect();
+    let common = path.iter().zip(&dir).take_while(|(a, b)| a == b).count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..dir.len() {
+        relative.push("..");
+    }
+    relative.extend(&path[common..]);
+    relative
+}
+
+/// Path as the path of a URL, percent-encoding everything but unreserved characters and
+/// the slashes between folders
+fn encode_path(path: &Path) -> String {
+    let mut encoded = String::new();
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            byte => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Base64 `data:` URL with the contents of a file
+fn data_url(path: &Path) -> std::io::Result<String> {
+    let contents = std::fs::read(path)?;
+    Ok(format!(
+        "data:{};base64,{}",
+        mime_type(path, &contents),
+        base64(&contents)
+    ))
+}
+
+/// Media type of an image, from the signature of its contents or else its extension
+fn mime_type(path: &Path, contents: &[u8]) -> &'static str {
+    let signatures: [(&[u8], &str); 5] = [
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF8", "image/gif"),
+        (b"BM", "image/bmp"),
+        (b"\0\0\x01\0", "image/vnd.microsoft.icon"),
+    ];
+    if let Some((_, mime_type)) = signatures
+        .iter()
+        .find(|(signature, _)| contents.starts_with(signature))
+    {
+        return mime_type;
+    }
+    if contents.starts_with(b"RIFF") && contents.get(8..12) == Some(b"WEBP") {
+        return "image/webp";
+    }
+
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("svg") => "image/svg+xml",
+        Some("avif") => "image/avif",
+        Some("webp") => "image/webp",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Standard base64 with padding
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let group = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// MathML for a formula, the TeX source if it does not parse
+fn math_html(source: &str, display: bool, out: &mut String) {
+    match math::parse(source) {
+        Ok(formula) => out.push_str(&math::to_mathml(&formula, display)),
+        Err(err) => {
+            warning!("Failed to parse formula: {err}");
+            out.push_str(&format!("<code class=\"math\">{}</code>", escape(source)));
+        }
+    }
+}
+
+/// Code as HTML with a styled span for every highlighted run. Highlighting needs GTK,
+/// so the code is only escaped when it has not been initialised, as when exporting
+/// without a display.
+#[cfg(feature = "gtk")]
+fn highlight(language: Option<&str>, code: &str, dark: bool) -> String {
+    if !gtk::is_initialized_main_thread() {
+        return escape(code);
+    }
+
+    let buffer = sourceview5::Buffer::new(None);
+    buffer.set_text(code);
+    buffer.set_highlight_syntax(true);
+    if let Some(language) =
+        language.and_then(|l| sourceview5::LanguageManager::default().language(&l.to_lowercase()))
+    {
+        buffer.set_language(Some(&language));
+    }
+    let scheme = if dark { "Adwaita-dark" } else { "Adwaita" };
+    buffer.set_style_scheme(
+        sourceview5::StyleSchemeManager::default()
+            .scheme(scheme)
+            .as_ref(),
+    );
+    buffer.ensure_highlight(&buffer.start_iter(), &buffer.end_iter());
+
+    let mut out = String::new();
+    let mut start = buffer.start_iter();
+    while !start.is_end() {
+        let mut end = start;
+        if !end.forward_to_tag_toggle(None::<&gtk::TextTag>) {
+            end = buffer.end_iter();
+        }
+
+        let text = escape(&buffer.text(&start, &end, true));
+        let style = start
+            .tags()
+            .iter()
+            .map(tag_style)
+            .collect::<Vec<_>>()
+            .join("");
+        if style.is_empty() {
+            out.push_str(&text);
+        } else {
+            out.push_str(&format!("<span style=\"{style}\">{text}</span>"));
+        }
+        start = end;
+    }
+    out
+}
+
+/// Code escaped as HTML, without GTK there is no highlighting
+#[cfg(not(feature = "gtk"))]
+fn highlight(_language: Option<&str>, code: &str, _dark: bool) -> String {
+    escape(code)
+}
+
+/// CSS declarations for the styling a highlighting tag sets
+#[cfg(feature = "gtk")]
+fn tag_style(tag: &gtk::TextTag) -> String {
+    let mut style = String::new();
+    if tag.is_foreground_set()
+        && let Some(color) = tag.foreground_rgba()
+    {
+        style.push_str(&format!("color: {};", css_color(&color)));
+    }
+    if tag.is_background_set()
+        && let Some(color) = tag.background_rgba()
+    {
+        style.push_str(&format!("background-color: {};", css_color(&color)));
+    }
+    if tag.is_weight_set() {
+        style.push_str(&format!("font-weight: {};", tag.weight()));
+    }
+    if tag.is_style_set() && tag.style() != gtk::pango::Style::Normal {
+        style.push_str("font-style: italic;");
+    }
+    if tag.is_underline_set() && tag.underline() != gtk::pango::Underline::None {
+        style.push_str("text-decoration: underline;");
+    }
+    if tag.is_strikethrough_set() && tag.is_strikethrough() {
+        style.push_str("text-decoration: line-through;");
+    }
+    style
+}
+
+#[cfg(feature = "gtk")]
+fn css_color(color: &gtk::gdk::RGBA) -> String {
+    let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    format!(
+        "rgba({}, {}, {}, {})",
+        channel(color.red()),
+        channel(color.green()),
+        channel(color.blue()),
+        color.alpha()
+    )
+}
+
+/// `text` with the characters that are special in HTML text and attributes escaped
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_is_escaped() {
+        assert_eq!(
+            escape(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn wiki_links_are_resolved_and_encoded() {
+        let document = markdown::parse("[[Page Name]] [[Plans#Next Steps|plans]]");
+        let html = document_html(&document, Some(Path::new("/notes/work")), false, None);
+        assert!(html.contains(r#"<a href="Page%20Name.md">Page Name</a>"#));
+        assert!(html.contains(r#"<a href="Plans.md#next-steps">plans</a>"#));
+
+        let resolve = |target: &str| {
+            (!target.starts_with("Page")).then(|| PathBuf::from("/notes/home/Plans ü.md"))
+        };
+        let html = document_html(
+            &document,
+            Some(Path::new("/notes/work")),
+            false,
+            Some(&resolve),
+        );
+        assert!(html.contains(r#"<a href="Page%20Name.md">"#));
+        assert!(html.contains(r#"<a href="../home/Plans%20%C3%BC.md#next-steps">"#));
+        let html = document_html(&document, None, false, Some(&resolve));
+        assert!(html.contains(r#"<a href="file:///notes/home/Plans%20%C3%BC.md#next-steps">"#));
+    }
+
+    #[test]
+    fn base64_pads_the_last_group() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&[0xff, 0xfe, 0xfd]), "//79");
+    }
+
+    #[test]
+    fn mime_type_comes_from_contents_then_extension() {
+        let png = b"\x89PNG\r\n\x1a\n....";
+        assert_eq!(mime_type(Path::new("image.jpg"), png), "image/png");
+        assert_eq!(mime_type(Path::new("a"), b"RIFF....WEBPVP8 "), "image/webp");
+        assert_eq!(mime_type(Path::new("icon.SVG"), b"<svg/>"), "image/svg+xml");
+        assert_eq!(
+            mime_type(Path::new("notes.txt"), b"text"),
+            "application/octet-stream"
+        );
+    }
+}
@@ -0,0 +1,9 @@
+#[cfg(feature = "export-html")]
+mod html;
+#[cfg(feature = "gtk")]
+mod png;
+
+#[cfg(feature = "export-html")]
+pub use html::document_html;
+#[cfg(feature = "gtk")]
+pub use png::render_texture;
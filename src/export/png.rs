@@ -0,0 +1,172 @@
+use std::cell::Cell;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use adw::gtk::prelude::*;
+use adw::gtk::{self, gdk, gio, glib, gsk};
+use sourceview5::prelude::*;
+
+use crate::widgets::MdViewer;
+
+/// Longest time the document may take to be laid out and drawn
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Render markdown the way `MdViewer` shows it, `width` pixels wide and as tall as the
+/// document, with the light or dark style; the app's own colour scheme is restored before
+/// returning. The viewer is drawn once in an undecorated window and its content rendered
+/// again with the software renderer, so the result does not depend on the graphics
+/// driver; this still needs a display, such as Broadway in CI. It runs the default main
+/// context until the document is drawn, so call it from the thread GTK was initialised on.
+pub fn render_texture(
+    source: &str,
+    base_dir: Option<&Path>,
+    width: i32,
+    dark: bool,
+) -> Result<gdk::Texture, glib::Error> {
+    let _scheme = ColorSchemeGuard::force(if dark {
+        adw::ColorScheme::ForceDark
+    } else {
+        adw::ColorScheme::ForceLight
+    });
+
+    let viewer = MdViewer::new();
+    viewer.set_fit_content(true);
+    viewer.set_markdown(source, base_dir);
+
+    let window = gtk::Window::builder()
+        .decorated(false)
+        .default_width(width)
+        .child(&viewer)
+        .build();
+    window.present();
+    let drawn = draw(&window).and_then(|()| {
+        // Code is highlighted in the background, finish it before drawing again
+        ensure_highlight(viewer.upcast_ref());
+        draw(&window)
+    });
+    if let Err(err) = drawn {
+        window.destroy();
+        return Err(err);
+    }
+
+    let paintable = gtk::WidgetPaintable::new(Some(&viewer));
+    let snapshot = gtk::Snapshot::new();
+    paintable.snapshot(&snapshot, viewer.width() as f64, viewer.height() as f64);
+    window.destroy();
+
+    let Some(node) = snapshot.to_node() else {
+        return Err(glib::Error::new(
+            gio::IOErrorEnum::Failed,
+            "The document was not drawn",
+        ));
+    };
+    let renderer = gsk::CairoRenderer::new();
+    renderer.realize(None::<&gdk::Surface>)?;
+    let texture = renderer.render_texture(node, None);
+    renderer.unrealize();
+    Ok(texture)
+}
+
+/// Colour scheme of the default style manager, forced for as long as the guard lives.
+/// The style manager is shared by the whole process, so the scheme the app had before is
+/// put back when the guard is dropped, whichever way rendering ends.
+struct ColorSchemeGuard {
+    previous: adw::ColorScheme,
+}
+
+impl ColorSchemeGuard {
+    fn force(scheme: adw::ColorScheme) -> Self {
+        let manager = adw::StyleManager::default();
+        let previous = manager.color_scheme();
+        manager.set_color_scheme(scheme);
+        Self { previous }
+    }
+}
+
+impl Drop for ColorSchemeGuard {
+    fn drop(&mut self) {
+        adw::StyleManager::default().set_color_scheme(self.previous);
+    }
+}
+
+/// Draw `window` and run the main context until it has been painted and no other work,
+/// such as idle callbacks, is left. Fails if that takes longer than `TIMEOUT`, as when the
+/// window never gets a frame clock or the clock never paints.
+fn draw(window: &gtk::Window) -> Result<(), glib::Error> {
+    let context = glib::MainContext::default();
+    let deadline = Instant::now() + TIMEOUT;
+    let timed_out = || {
+        glib::Error::new(
+            gio::IOErrorEnum::TimedOut,
+            "The document was not drawn in time",
+        )
+    };
+
+    // The window has no frame clock until it is realized, which happens in the main loop
+    let clock = loop {
+        if let Some(clock) = window.frame_clock() {
+            break clock;
+        }
+        if !iterate_until(&context, deadline) {
+            return Err(timed_out());
+        }
+    };
+    let painted = Rc::new(Cell::new(false));
+    let handler = clock.connect_after_paint(glib_macros::clone!(
+        #[strong]
+        painted,
+        move |_| painted.set(true)
+    ));
+    window.queue_draw();
+
+    let mut result = Ok(());
+    while !painted.get() || context.pending() {
+        if !iterate_until(&context, deadline) {
+            result = Err(timed_out());
+            break;
+        }
+    }
+    clock.disconnect(handler);
+    result
+}
+
+/// Run one iteration of `context`, waiting for events no longer than until `deadline`.
+/// Returns whether the deadline is still ahead.
+fn iterate_until(context: &glib::MainContext, deadline: Instant) -> bool {
+    let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+        return false;
+    };
+    // Wakes the iteration up if nothing else happens before the deadline
+    let woken = Rc::new(Cell::new(false));
+    let wakeup = glib::timeout_add_local_once(
+        remaining,
+        glib_macros::clone!(
+            #[strong]
+            woken,
+            move || woken.set(true)
+        ),
+    );
+    context.iteration(true);
+    if !woken.get() {
+        wakeup.remove();
+    }
+    !woken.get()
+}
+
+/// Highlight the whole text of every source view under `widget` right away
+fn ensure_highlight(widget: &gtk::Widget) {
+    if let Some(buffer) = widget
+        .downcast_ref::<sourceview5::View>()
+        .and_then(|view| view.buffer().downcast::<sourceview5::Buffer>().ok())
+    {
+        let (start, end) = buffer.bounds();
+        buffer.ensure_highlight(&start, &end);
+    }
+
+    let mut child = widget.first_child();
+    while let Some(widget) = child {
+        ensure_highlight(&widget);
+        child = widget.next_sibling();
+    }
+}
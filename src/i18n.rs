@@ -0,0 +1,110 @@
+//! Translations of the strings the library and the app show, and numbers and dates
+//! written the way the user's locale writes them
+
+#[cfg(feature = "gtk")]
+use std::ffi::CStr;
+
+/// Gettext domain of the messages, shared with the app
+pub const GETTEXT_PACKAGE: &str = "potato-md";
+
+/// Translation of `msgid`
+#[cfg(feature = "gtk")]
+pub fn gettext(msgid: &str) -> String {
+    glib::dgettext(Some(GETTEXT_PACKAGE), msgid).into()
+}
+
+/// `msgid` as it is: without GTK the library links no gettext, so the HTML export
+/// stays in English
+#[cfg(not(feature = "gtk"))]
+pub fn gettext(msgid: &str) -> String {
+    msgid.to_string()
+}
+
+/// Translation of `msgid` or `msgid_plural`, whichever fits `n` in the user's language
+#[cfg(feature = "gtk")]
+pub fn ngettext(msgid: &str, msgid_plural: &str, n: u32) -> String {
+    glib::dngettext(Some(GETTEXT_PACKAGE), msgid, msgid_plural, n.into()).into()
+}
+
+/// `msgid` for one and `msgid_plural` otherwise, as in English
+#[cfg(not(feature = "gtk"))]
+pub fn ngettext(msgid: &str, msgid_plural: &str, n: u32) -> String {
+    if n == 1 { msgid } else { msgid_plural }.to_string()
+}
+
+/// Decimal point and thousands separator of the numeric locale
+#[cfg(feature = "gtk")]
+fn separators() -> (String, String) {
+    // SAFETY: localeconv returns a pointer to static data that stays valid until the
+    // locale changes, and the strings are copied right away
+    unsafe {
+        let conv = &*libc::localeconv();
+        let text = |ptr: *const libc::c_char| {
+            if ptr.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(ptr).to_string_lossy().into_owned()
+            }
+        };
+        let decimal_point = Some(text(conv.decimal_point)).filter(|point| !point.is_empty());
+        (
+            decimal_point.unwrap_or_else(|| ".".to_string()),
+            text(conv.thousands_sep),
+        )
+    }
+}
+
+/// `text` with the separators of the user's locale if it is a number, as it is otherwise
+#[cfg(feature = "gtk")]
+pub(crate) fn number(text: &str) -> String {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    let (int, fraction) = match digits.split_once('.') {
+        Some((int, fraction)) => (int, Some(fraction)),
+        None => (digits, None),
+    };
+    let all_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if !all_digits(int) || !fraction.is_none_or(all_digits) {
+        return text.to_string();
+    }
+
+    let (decimal_point, thousands_sep) = separators();
+    let mut formatted = String::from(&text[..text.len() - digits.len()]);
+    // Like CLDR's minimum grouping digits, four digit numbers such as years are not grouped
+    let group = int.len() > 4 && !thousands_sep.is_empty();
+    for (i, digit) in int.chars().enumerate() {
+        if group && i > 0 && (int.len() - i) % 3 == 0 {
+            formatted.push_str(&thousands_sep);
+        }
+        formatted.push(digit);
+    }
+    if let Some(fraction) = fraction {
+        formatted.push_str(&decimal_point);
+        formatted.push_str(fraction);
+    }
+    formatted
+}
+
+/// `text` in the date format of the user's locale if it is an ISO 8601 date, with the
+/// time when it has one, and as it is otherwise
+#[cfg(feature = "gtk")]
+pub(crate) fn date(text: &str) -> String {
+    let text = text.trim();
+    let local = glib::TimeZone::local();
+    let formatted = if text.len() == 10 {
+        let mut parts = text.splitn(3, '-').map(str::parse::<i32>);
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) => {
+                glib::DateTime::new(&local, year, month, day, 0, 0, 0.0)
+                    .and_then(|date| date.format("%x"))
+                    .ok()
+            }
+            _ => None,
+        }
+    } else {
+        glib::DateTime::from_iso8601(text, Some(&local))
+            .and_then(|date| date.to_local())
+            .and_then(|date| date.format("%x %X"))
+            .ok()
+    };
+    formatted.map_or_else(|| text.to_string(), String::from)
+}
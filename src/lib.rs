@@ -0,0 +1,52 @@
+//! Markdown viewer and rich text widgets for GTK 4 and libadwaita.
+//!
+//! Call [`init`] once GTK is initialised, then use [`MdViewer`] to show a document or
+//! [`InlineView`] with an [`InlineBuffer`] for formatted text of your own.
+//!
+//! Without the default `gtk` feature only the markdown parser and document model are
+//! built, with the `export-html` and `math` features adding HTML export and formulas.
+
+#[cfg(any(feature = "gtk", feature = "export-html"))]
+pub mod export;
+#[cfg(any(feature = "gtk", feature = "export-html"))]
+pub mod i18n;
+pub mod markdown;
+#[cfg(feature = "math")]
+pub mod math;
+#[cfg(feature = "gtk")]
+mod widgets;
+
+#[cfg(feature = "gtk")]
+use adw::gtk::{self, gdk};
+#[cfg(feature = "gtk")]
+use adw::prelude::*;
+
+#[cfg(feature = "gtk")]
+pub use widgets::{
+    Heading, HeadingView, InlineAnchor, InlineBuffer, InlinePos, InlineView, MdViewer, PrintLayout,
+    TextAttr, TextAttrType,
+};
+
+/// Stylesheet of the widgets
+#[cfg(feature = "gtk")]
+const STYLESHEET: &str = include_str!("../data/style.css");
+
+/// Register the widget types, so they can be used in UI files, and add their stylesheet
+/// to the default display. Call it after GTK has been initialised.
+#[cfg(feature = "gtk")]
+pub fn init() {
+    sourceview5::init();
+    InlineView::ensure_type();
+    HeadingView::ensure_type();
+    MdViewer::ensure_type();
+
+    if let Some(display) = gdk::Display::default() {
+        let provider = gtk::CssProvider::new();
+        provider.load_from_string(STYLESHEET);
+        gtk::style_context_add_provider_for_display(
+            &display,
+            &provider,
+            gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
+        );
+    }
+}
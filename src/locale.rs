@@ -0,0 +1,52 @@
+//! Locale of the app and the gettext catalogues compiled from `po/`
+
+use std::ffi::{CString, c_char};
+use std::path::{Path, PathBuf};
+
+use adw::glib;
+use potato_md::i18n::GETTEXT_PACKAGE;
+
+// Part of the C library on glibc, and of libintl, which GLib links, elsewhere
+unsafe extern "C" {
+    fn bindtextdomain(domainname: *const c_char, dirname: *const c_char) -> *mut c_char;
+    fn bind_textdomain_codeset(domainname: *const c_char, codeset: *const c_char) -> *mut c_char;
+    fn textdomain(domainname: *const c_char) -> *mut c_char;
+}
+
+/// Take the locale from the environment and make the catalogues of the app the default
+/// for gettext, which the UI files are translated with. Call it before anything is shown.
+pub fn init() {
+    let dir = locale_dir();
+    let domain = CString::new(GETTEXT_PACKAGE).unwrap();
+    let dir = CString::new(dir.as_os_str().as_encoded_bytes()).unwrap_or_default();
+    // SAFETY: all strings are valid and nul-terminated for the length of the calls
+    unsafe {
+        libc::setlocale(libc::LC_ALL, c"".as_ptr());
+        bindtextdomain(domain.as_ptr(), dir.as_ptr());
+        bind_textdomain_codeset(domain.as_ptr(), c"UTF-8".as_ptr());
+        textdomain(domain.as_ptr());
+    }
+}
+
+/// Directory with the compiled catalogues. They are looked up where they are installed,
+/// or where the build compiled them when running from the source tree.
+fn locale_dir() -> PathBuf {
+    glib::system_data_dirs()
+        .into_iter()
+        .map(|dir| dir.join("locale"))
+        .find(|dir| has_catalogue(dir))
+        .unwrap_or_else(|| PathBuf::from(concat!(env!("OUT_DIR"), "/locale")))
+}
+
+fn has_catalogue(dir: &Path) -> bool {
+    let Ok(languages) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    languages.flatten().any(|language| {
+        language
+            .path()
+            .join("LC_MESSAGES")
+            .join(format!("{GETTEXT_PACKAGE}.mo"))
+            .is_file()
+    })
+}
@@ -1,29 +1,226 @@
-mod widgets;
+mod locale;
+mod page;
+mod preferences;
+mod search;
+mod session;
+mod settings;
+mod sidebar;
+mod wiki;
 mod window;
 
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+
+use adw::glib;
 use adw::prelude::*;
+use potato_md::i18n::gettext;
+use potato_md::{MdViewer, export, markdown};
+
 const APP_ID: &str = "com.example.potato-md";
 
 fn main() -> Result<adw::glib::ExitCode, anyhow::Error> {
+    locale::init();
     adw::gio::resources_register_include!("potato-md.gresource")?;
 
-    let app = adw::Application::builder().application_id(APP_ID).build();
+    let app = adw::Application::builder()
+        .application_id(APP_ID)
+        .flags(adw::gio::ApplicationFlags::HANDLES_OPEN)
+        .build();
+
+    app.add_main_option(
+        "export-html",
+        glib::Char::from(0),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::Filename,
+        &gettext("Write a markdown file as standalone HTML and exit"),
+        Some(&gettext("FILE")),
+    );
+    app.add_main_option(
+        "export-pdf",
+        glib::Char::from(0),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::Filename,
+        &gettext("Write a markdown file as PDF, paginated like when printing, and exit"),
+        Some(&gettext("FILE")),
+    );
+    app.add_main_option(
+        "render-png",
+        glib::Char::from(0),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::Filename,
+        &gettext("Render a markdown file as the viewer shows it to a PNG image and exit"),
+        Some(&gettext("FILE")),
+    );
+    app.add_main_option(
+        "width",
+        glib::Char::from(0),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::Int,
+        &gettext("Width of the rendered image in pixels, 800 by default"),
+        Some(&gettext("PIXELS")),
+    );
+    app.add_main_option(
+        "dark",
+        glib::Char::from(0),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::None,
+        &gettext("Render with the dark style"),
+        None,
+    );
+    app.add_main_option(
+        "output",
+        glib::Char::from(b'o'),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::Filename,
+        &gettext("File to write the export to, next to the input by default"),
+        Some(&gettext("FILE")),
+    );
+    app.connect_handle_local_options(handle_local_options);
 
     app.connect_startup(startup);
     app.connect_activate(build_ui);
+    app.connect_open(open_files);
+    app.connect_shutdown(session::save);
 
     Ok(app.run())
 }
 
+/// Run an export given on the command line instead of starting the app
+fn handle_local_options(
+    _app: &adw::Application,
+    options: &glib::VariantDict,
+) -> ControlFlow<glib::ExitCode> {
+    type Export = fn(&Path, &Path, &glib::VariantDict) -> Result<(), anyhow::Error>;
+    let exports: [(&str, &str, Export); 3] = [
+        ("export-html", "html", export_html),
+        ("export-pdf", "pdf", export_pdf),
+        ("render-png", "png", render_png),
+    ];
+    let Some((input, extension, export)) =
+        exports.into_iter().find_map(|(option, extension, export)| {
+            let input = options.lookup::<PathBuf>(option).ok().flatten()?;
+            Some((input, extension, export))
+        })
+    else {
+        return ControlFlow::Continue(());
+    };
+    let output = options
+        .lookup::<PathBuf>("output")
+        .ok()
+        .flatten()
+        .unwrap_or_else(|| input.with_extension(extension));
+
+    match export(&input, &output, options) {
+        Ok(()) => ControlFlow::Break(glib::ExitCode::SUCCESS),
+        Err(err) => {
+            eprintln!(
+                "{}",
+                // Translators: {file} is the path of the markdown file, {error} says what went wrong
+                gettext("Failed to export {file}: {error}")
+                    .replace("{file}", &input.display().to_string())
+                    .replace("{error}", &err.to_string())
+            );
+            ControlFlow::Break(glib::ExitCode::FAILURE)
+        }
+    }
+}
+
+fn export_html(
+    input: &Path,
+    output: &Path,
+    options: &glib::VariantDict,
+) -> Result<(), anyhow::Error> {
+    // Code highlighting needs GTK, which needs a display; export without it otherwise
+    if adw::gtk::init().is_ok() {
+        sourceview5::init();
+    }
+
+    let source = std::fs::read_to_string(input)?;
+    let document = markdown::parse(&source);
+    let dark = options.contains("dark");
+    // Without an open folder, pages are looked for next to the document
+    let resolve_wiki_link = |target: &str| wiki::resolve(target, &[], input.parent());
+    let html = export::document_html(&document, input.parent(), dark, Some(&resolve_wiki_link));
+    std::fs::write(output, html)?;
+    Ok(())
+}
+
+fn export_pdf(
+    input: &Path,
+    output: &Path,
+    _options: &glib::VariantDict,
+) -> Result<(), anyhow::Error> {
+    // Pages are laid out with the viewer's renderer, so unlike HTML this needs a display
+    init_display()?;
+
+    let source = std::fs::read_to_string(input)?;
+    let viewer = MdViewer::new();
+    viewer.set_markdown(&source, input.parent());
+    viewer.export_pdf(output)?;
+    Ok(())
+}
+
+fn render_png(
+    input: &Path,
+    output: &Path,
+    options: &glib::VariantDict,
+) -> Result<(), anyhow::Error> {
+    init_display()?;
+
+    let width = options.lookup::<i32>("width").ok().flatten().unwrap_or(800);
+    let dark = options.contains("dark");
+    let source = std::fs::read_to_string(input)?;
+    let texture = export::render_texture(&source, input.parent(), width, dark)?;
+    texture.save_to_png(output)?;
+    Ok(())
+}
+
+/// Initialise GTK for an export that renders with the widgets. Without a display, the
+/// Broadway backend serves as one: start `broadwayd` and set `GDK_BACKEND=broadway`.
+fn init_display() -> Result<(), anyhow::Error> {
+    adw::init().map_err(|_| {
+        anyhow::anyhow!(gettext(
+            "No display to render on, without one run broadwayd and set GDK_BACKEND=broadway"
+        ))
+    })?;
+    potato_md::init();
+    Ok(())
+}
+
 fn startup(_app: &adw::Application) {
     adw::gtk::IconTheme::for_display(&adw::gdk::Display::default().unwrap())
         .add_resource_path("/com/example/potato-md/icons");
 
-    // Регистрируем кастомные виджеты
-    widgets::InlineView::ensure_type();
+    // Custom widgets have to be registered before the UI files that use them are built
+    potato_md::init();
+    settings::apply_style();
 }
 
 fn build_ui(app: &adw::Application) {
+    // Launching the app without files picks up where the last session ended
+    if app.windows().is_empty() && session::restore(app) {
+        return;
+    }
     let window = window::PotatoWindow::new(app);
     window.present();
 }
+
+/// Open files as tabs of the active window, or of a new one, and folders in its sidebar
+fn open_files(app: &adw::Application, files: &[adw::gio::File], _hint: &str) {
+    let window = app
+        .active_window()
+        .and_downcast::<window::PotatoWindow>()
+        .unwrap_or_else(|| window::PotatoWindow::new(app));
+    for file in files {
+        match file.query_file_type(
+            adw::gio::FileQueryInfoFlags::NONE,
+            adw::gio::Cancellable::NONE,
+        ) {
+            adw::gio::FileType::Directory => window.open_folder(file),
+            _ => {
+                window.open_file(file);
+            }
+        }
+    }
+    window.present();
+}
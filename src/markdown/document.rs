@@ -0,0 +1,384 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use super::front_matter::FrontMatter;
+
+/// A parsed markdown document
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Document {
+    pub front_matter: Option<FrontMatter>,
+    pub blocks: Vec<Block>,
+    /// Byte range of every top-level block in the source, without trailing line breaks
+    pub block_ranges: Vec<Range<usize>>,
+    /// Footnote definitions, ordered by their number
+    pub footnotes: Vec<Footnote>,
+    pub style: Style,
+}
+
+/// Markup choices found in the source, so that blocks written back look like the rest
+#[derive(Debug, Clone, PartialEq)]
+pub struct Style {
+    /// Delimiter of emphasis, `*` or `_`
+    pub emphasis: char,
+    /// Delimiter of strong emphasis, doubled when written
+    pub strong: char,
+    /// Marker of unordered list items: `-`, `*` or `+`
+    pub bullet: char,
+    /// Character after the number of ordered list items, `.` or `)`
+    pub ordered_delimiter: char,
+    /// Character of code fences, `` ` `` or `~`
+    pub fence: char,
+    /// Thematic break as written, such as `---` or `***`
+    pub rule: String,
+    /// Level 1 and 2 headings are underlined rather than prefixed with `#`
+    pub setext_headings: bool,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            emphasis: '*',
+            strong: '*',
+            bullet: '-',
+            ordered_delimiter: '.',
+            fence: '`',
+            rule: "---".to_string(),
+            setext_headings: false,
+        }
+    }
+}
+
+/// Footnote definition, numbered in the order of first reference
+#[derive(Debug, Clone, PartialEq)]
+pub struct Footnote {
+    pub label: String,
+    pub number: usize,
+    pub blocks: Vec<Block>,
+}
+
+/// Prefix of the URL a wiki-link stands for where only a URL can be kept, such as
+/// in the link attribute of rendered text: `wiki:target`
+pub const WIKI_LINK_SCHEME: &str = "wiki:";
+
+/// Character that stands for a formula or image in the text of a view
+const OBJECT_REPLACEMENT: char = '\u{FFFC}';
+
+/// Block-level element of a document
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    Heading {
+        level: u8,
+        content: Vec<Inline>,
+    },
+    Paragraph(Vec<Inline>),
+    /// A paragraph that consists of a single image
+    Image(Image),
+    Code {
+        language: Option<String>,
+        code: String,
+    },
+    Quote(Vec<Block>),
+    List {
+        start: Option<u64>,
+        items: Vec<Vec<Block>>,
+    },
+    /// A paragraph that consists of a single `$$…$$` formula
+    Math(String),
+    Rule,
+}
+
+/// Inline (span-level) element of a document
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inline {
+    Text(String),
+    Code(String),
+    Emphasis(Vec<Inline>),
+    Strong(Vec<Inline>),
+    Strikethrough(Vec<Inline>),
+    Link {
+        url: String,
+        title: String,
+        content: Vec<Inline>,
+    },
+    /// `[[target]]` or `[[target|content]]`, naming a page of the folder the document
+    /// is in, optionally followed by `#heading`
+    WikiLink {
+        target: String,
+        content: Vec<Inline>,
+    },
+    Image(Image),
+    FootnoteReference {
+        label: String,
+        number: usize,
+    },
+    /// TeX formula, `$…$` or `$$…$$` when `display` is set
+    Math {
+        source: String,
+        display: bool,
+    },
+    SoftBreak,
+    HardBreak,
+}
+
+/// Image reference, either inline or on its own line
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Image {
+    pub url: String,
+    pub title: String,
+    pub alt: String,
+}
+
+impl Image {
+    /// Resolve the URL against the directory of the document.
+    /// Returns `None` for remote URLs, only local images are supported.
+    pub fn local_path(&self, base_dir: Option<&Path>) -> Option<PathBuf> {
+        let (url, base_dir) = if let Some(rest) = self.url.strip_prefix("file://") {
+            // Only files on the local host: `file:///path` or `file://localhost/path`
+            let path = rest.strip_prefix("localhost").unwrap_or(rest);
+            if !path.starts_with('/') {
+                return None;
+            }
+            (path, None)
+        } else if self.url.contains("://") || self.url.starts_with("data:") {
+            return None;
+        } else {
+            (self.url.as_str(), base_dir)
+        };
+
+        // Strip a fragment or query and decode percent-escapes such as `%20`
+        let path = url.split(['#', '?']).next().unwrap_or(url);
+        let path = percent_decode(path).map_or_else(|| PathBuf::from(path), PathBuf::from);
+
+        match base_dir {
+            Some(base_dir) if path.is_relative() => Some(base_dir.join(path)),
+            _ => Some(path),
+        }
+    }
+}
+
+/// Decode `%XX` escapes, `None` if one is malformed or the result is not UTF-8
+fn percent_decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = text.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+impl Document {
+    /// Visible text of the front matter, every top-level block and the footnotes, in
+    /// the rows a viewer shows them in
+    pub fn plain_text_rows(&self) -> Vec<String> {
+        let mut rows = Vec::with_capacity(self.blocks.len() + 2);
+        rows.extend(self.front_matter.as_ref().map(FrontMatter::plain_text));
+        rows.extend(self.blocks.iter().map(Block::plain_text));
+        if !self.footnotes.is_empty() {
+            rows.push(Footnote::plain_text(&self.footnotes));
+        }
+        rows
+    }
+
+    /// Text of the views a viewer shows each row in, in the same rows as
+    /// `plain_text_rows`. Search matches are counted in it so that they line up with
+    /// the ones the viewer highlights.
+    pub fn searchable_rows(&self) -> Vec<String> {
+        let mut rows = Vec::with_capacity(self.blocks.len() + 2);
+        // The header is made of labels, which are not searched
+        rows.extend(self.front_matter.as_ref().map(|_| String::new()));
+        rows.extend(self.blocks.iter().map(Block::searchable_text));
+        if !self.footnotes.is_empty() {
+            rows.push(Footnote::searchable_text(&self.footnotes));
+        }
+        rows
+    }
+}
+
+impl Footnote {
+    /// Visible text of a run of footnotes, one line per block
+    pub fn plain_text(footnotes: &[Footnote]) -> String {
+        footnotes
+            .iter()
+            .flat_map(|footnote| footnote.blocks.iter().map(Block::plain_text))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Text of the views of a run of footnotes, one line per block
+    pub fn searchable_text(footnotes: &[Footnote]) -> String {
+        footnotes
+            .iter()
+            .flat_map(|footnote| footnote.blocks.iter().map(Block::searchable_text))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl Block {
+    /// Visible text of the block and its children, one line per nested block
+    pub fn plain_text(&self) -> String {
+        match self {
+            Block::Heading { content, .. } | Block::Paragraph(content) => {
+                Inline::plain_text(content)
+            }
+            Block::Image(image) => image.alt.clone(),
+            Block::Code { code, .. } => code.clone(),
+            Block::Quote(blocks) => join_plain_text(blocks),
+            Block::List { items, .. } => items
+                .iter()
+                .map(|item| join_plain_text(item))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Block::Math(source) => source.clone(),
+            Block::Rule => String::new(),
+        }
+    }
+
+    /// Text of the views the block is shown in, one line per view. Code blocks are
+    /// shown in source views and images in pictures, so they have none.
+    pub fn searchable_text(&self) -> String {
+        match self {
+            Block::Heading { content, .. } | Block::Paragraph(content) => {
+                Inline::searchable_text(content)
+            }
+            Block::Quote(blocks) => join_searchable_text(blocks),
+            Block::List { items, .. } => items
+                .iter()
+                .map(|item| join_searchable_text(item))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Block::Math(source) => formula_text(source),
+            Block::Image(_) | Block::Code { .. } | Block::Rule => String::new(),
+        }
+    }
+}
+
+fn join_plain_text(blocks: &[Block]) -> String {
+    blocks
+        .iter()
+        .map(Block::plain_text)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn join_searchable_text(blocks: &[Block]) -> String {
+    blocks
+        .iter()
+        .map(Block::searchable_text)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Text a formula takes up in a view: an object, or the source if it does not parse
+#[cfg_attr(not(feature = "math"), allow(unused_variables))]
+fn formula_text(source: &str) -> String {
+    #[cfg(feature = "math")]
+    if crate::math::parse(source).is_err() {
+        return source.to_string();
+    }
+    OBJECT_REPLACEMENT.to_string()
+}
+
+impl Inline {
+    /// Concatenate the visible text of a run of inlines, dropping all styling
+    pub fn plain_text(inlines: &[Inline]) -> String {
+        let mut text = String::new();
+        for inline in inlines {
+            inline.push_plain_text(&mut text);
+        }
+        text
+    }
+
+    fn push_plain_text(&self, text: &mut String) {
+        match self {
+            Inline::Text(s) | Inline::Code(s) | Inline::Math { source: s, .. } => text.push_str(s),
+            Inline::Emphasis(content)
+            | Inline::Strong(content)
+            | Inline::Strikethrough(content)
+            | Inline::Link { content, .. }
+            | Inline::WikiLink { content, .. } => {
+                for inline in content {
+                    inline.push_plain_text(text);
+                }
+            }
+            Inline::Image(image) => text.push_str(&image.alt),
+            Inline::FootnoteReference { number, .. } => text.push_str(&number.to_string()),
+            Inline::SoftBreak => text.push(' '),
+            Inline::HardBreak => text.push('\n'),
+        }
+    }
+
+    /// Text of the view a run of inlines is shown in, when it is not being edited.
+    /// Formulas and local images are objects in it, remote images show their alt text.
+    pub fn searchable_text(inlines: &[Inline]) -> String {
+        let mut text = String::new();
+        for inline in inlines {
+            inline.push_searchable_text(&mut text);
+        }
+        text
+    }
+
+    fn push_searchable_text(&self, text: &mut String) {
+        match self {
+            Inline::Text(s) | Inline::Code(s) => text.push_str(s),
+            Inline::Emphasis(content)
+            | Inline::Strong(content)
+            | Inline::Strikethrough(content)
+            | Inline::Link { content, .. }
+            | Inline::WikiLink { content, .. } => {
+                for inline in content {
+                    inline.push_searchable_text(text);
+                }
+            }
+            Inline::Math { source, .. } => text.push_str(&formula_text(source)),
+            Inline::Image(image) => match image.local_path(None) {
+                Some(_) => text.push(OBJECT_REPLACEMENT),
+                None => text.push_str(&image.alt),
+            },
+            Inline::FootnoteReference { number, .. } => text.push_str(&number.to_string()),
+            Inline::SoftBreak => text.push(' '),
+            Inline::HardBreak => text.push('\n'),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markdown::{find_matches, parse};
+
+    fn count(source: &str, query: &str) -> Vec<usize> {
+        parse(source)
+            .searchable_rows()
+            .iter()
+            .map(|row| find_matches(row, query).len())
+            .collect()
+    }
+
+    #[test]
+    fn formulas_and_images_are_objects_in_searchable_text() {
+        let document = parse("see $a$ and a ![a](a.png) ![a](https://example.com/a.png)");
+        assert_eq!(
+            document.searchable_rows(),
+            ["see \u{FFFC} and a \u{FFFC} a"]
+        );
+        assert_eq!(count("see $a$ and a", "a"), [2]);
+        // A formula that does not parse is shown as its source
+        #[cfg(feature = "math")]
+        assert_eq!(count(r"see $\foo{a}$ and a", "a"), [3]);
+    }
+
+    #[test]
+    fn only_text_shown_in_views_is_searchable() {
+        let source = "---\ntitle: Potato\n---\n\nPotato `potato`\n\n```\npotato\n```\n\n$$\npotato\n$$\n\n![potato](potato.png)\n\n> - potato[^1]\n\n[^1]: Potato.\n";
+        assert_eq!(count(source, "potato"), [0, 2, 0, 0, 0, 1, 1]);
+        assert_eq!(count(source, "1"), [0, 0, 0, 0, 0, 1, 0]);
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use yaml_rust2::{Yaml, YamlLoader};
+
+/// Metadata block at the very start of a document,
+/// either `---` delimited YAML or `+++` delimited TOML
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrontMatter {
+    pub fields: BTreeMap<String, MetaValue>,
+}
+
+/// Value of a front matter field
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetaValue {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    List(Vec<MetaValue>),
+    Map(BTreeMap<String, MetaValue>),
+}
+
+impl FrontMatter {
+    /// Parse YAML front matter, returns `None` unless it is a mapping
+    pub fn from_yaml(source: &str) -> Option<Self> {
+        let docs = YamlLoader::load_from_str(source).ok()?;
+        match docs.into_iter().next()? {
+            Yaml::Hash(hash) => Some(Self {
+                fields: hash
+                    .into_iter()
+                    .filter_map(|(key, value)| {
+                        Some((yaml_key(&key)?, MetaValue::from_yaml(value)?))
+                    })
+                    .collect(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Parse TOML front matter
+    pub fn from_toml(source: &str) -> Option<Self> {
+        let table = toml::from_str::<toml::Table>(source).ok()?;
+        Some(Self {
+            fields: table
+                .into_iter()
+                .map(|(key, value)| (key, MetaValue::from_toml(value)))
+                .collect(),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&MetaValue> {
+        self.fields.get(key)
+    }
+
+    pub fn title(&self) -> Option<String> {
+        self.text("title")
+    }
+
+    /// Author, or a comma separated list of `authors`
+    pub fn author(&self) -> Option<String> {
+        self.text("author").or_else(|| self.text("authors"))
+    }
+
+    pub fn date(&self) -> Option<String> {
+        self.text("date")
+    }
+
+    /// Tags given either as a list or as a comma separated string
+    pub fn tags(&self) -> Vec<String> {
+        self.tag_values().iter().map(MetaValue::to_string).collect()
+    }
+
+    /// Tags as their values, so numbers can be told apart from text that looks like one
+    pub fn tag_values(&self) -> Vec<MetaValue> {
+        match self.get("tags").or_else(|| self.get("keywords")) {
+            Some(MetaValue::List(values)) => values.clone(),
+            Some(MetaValue::String(s)) => s
+                .split(',')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(|tag| MetaValue::String(tag.to_string()))
+                .collect(),
+            Some(value) => vec![value.clone()],
+            None => Vec::new(),
+        }
+    }
+
+    /// Title, author, date and tags, one per line, as shown above the document
+    pub fn plain_text(&self) -> String {
+        [self.title(), self.author(), self.date()]
+            .into_iter()
+            .flatten()
+            .chain(self.tags())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// A scalar or list field rendered as text, empty values count as missing
+    fn text(&self, key: &str) -> Option<String> {
+        match self.get(key)? {
+            MetaValue::Map(_) => None,
+            value => Some(value.to_string()).filter(|s| !s.is_empty()),
+        }
+    }
+}
+
+impl MetaValue {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            MetaValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn from_yaml(value: Yaml) -> Option<Self> {
+        Some(match value {
+            Yaml::String(s) => MetaValue::String(s),
+            Yaml::Integer(i) => MetaValue::Integer(i),
+            Yaml::Real(s) => s
+                .parse()
+                .map(MetaValue::Float)
+                .unwrap_or(MetaValue::String(s)),
+            Yaml::Boolean(b) => MetaValue::Bool(b),
+            Yaml::Array(values) => {
+                MetaValue::List(values.into_iter().filter_map(Self::from_yaml).collect())
+            }
+            Yaml::Hash(hash) => MetaValue::Map(
+                hash.into_iter()
+                    .filter_map(|(key, value)| Some((yaml_key(&key)?, Self::from_yaml(value)?)))
+                    .collect(),
+            ),
+            Yaml::Alias(_) | Yaml::Null | Yaml::BadValue => return None,
+        })
+    }
+
+    fn from_toml(value: toml::Value) -> Self {
+        match value {
+            toml::Value::String(s) => MetaValue::String(s),
+            toml::Value::Integer(i) => MetaValue::Integer(i),
+            toml::Value::Float(f) => MetaValue::Float(f),
+            toml::Value::Boolean(b) => MetaValue::Bool(b),
+            toml::Value::Datetime(dt) => MetaValue::String(dt.to_string()),
+            toml::Value::Array(values) => {
+                MetaValue::List(values.into_iter().map(Self::from_toml).collect())
+            }
+            toml::Value::Table(table) => MetaValue::Map(
+                table
+                    .into_iter()
+                    .map(|(key, value)| (key, Self::from_toml(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl fmt::Display for MetaValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetaValue::String(s) => f.write_str(s),
+            MetaValue::Integer(i) => write!(f, "{i}"),
+            MetaValue::Float(x) => write!(f, "{x}"),
+            MetaValue::Bool(b) => write!(f, "{b}"),
+            MetaValue::List(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                Ok(())
+            }
+            MetaValue::Map(fields) => {
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{key}: {value}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn yaml_key(key: &Yaml) -> Option<String> {
+    match key {
+        Yaml::String(s) | Yaml::Real(s) => Some(s.clone()),
+        Yaml::Integer(i) => Some(i.to_string()),
+        Yaml::Boolean(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
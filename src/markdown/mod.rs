@@ -0,0 +1,11 @@
+mod document;
+mod front_matter;
+mod parser;
+mod text;
+mod writer;
+
+pub use document::{Block, Document, Footnote, Image, Inline, Style, WIKI_LINK_SCHEME};
+pub use front_matter::{FrontMatter, MetaValue};
+pub use parser::parse;
+pub use text::{find_matches, slug};
+pub use writer::{write_block, write_inlines};
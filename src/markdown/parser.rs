@@ -0,0 +1,620 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use pulldown_cmark::{
+    CodeBlockKind, Event, HeadingLevel, LinkType, MetadataBlockKind, Options, Parser, Tag, TagEnd,
+};
+
+use super::document::{Block, Document, Footnote, Image, Inline, Style};
+use super::front_matter::FrontMatter;
+
+/// Parse markdown source into a document tree
+pub fn parse(source: &str) -> Document {
+    let mut builder = Builder::new();
+    let mut style = StyleDetector::default();
+    let options = Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_YAML_STYLE_METADATA_BLOCKS
+        | Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS
+        | Options::ENABLE_MATH
+        | Options::ENABLE_WIKILINKS;
+    for (event, range) in Parser::new_ext(source, options).into_offset_iter() {
+        style.observe(&event, &source[range.clone()]);
+        builder.push_event(event, range);
+    }
+
+    let mut document = builder.finish();
+    for range in &mut document.block_ranges {
+        range.end = range.start + source[range.clone()].trim_end().len();
+    }
+    document.style = style.finish();
+    document
+}
+
+/// Remembers the first markup choice of each kind found in the source
+#[derive(Default)]
+struct StyleDetector {
+    emphasis: Option<char>,
+    strong: Option<char>,
+    bullet: Option<char>,
+    ordered_delimiter: Option<char>,
+    fence: Option<char>,
+    rule: Option<String>,
+    setext_headings: Option<bool>,
+}
+
+impl StyleDetector {
+    /// Look at the source text of an event, which starts with its markup
+    fn observe(&mut self, event: &Event<'_>, markup: &str) {
+        let markup = markup.trim_start();
+        let first = markup.chars().next();
+        match event {
+            Event::Start(Tag::Emphasis) => self.emphasis = self.emphasis.or(first),
+            Event::Start(Tag::Strong) => self.strong = self.strong.or(first),
+            Event::Start(Tag::List(None)) => self.bullet = self.bullet.or(first),
+            Event::Start(Tag::List(Some(_))) => {
+                self.ordered_delimiter = self
+                    .ordered_delimiter
+                    .or_else(|| markup.chars().find(|c| !c.is_ascii_digit()));
+            }
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(_))) => {
+                self.fence = self.fence.or(first)
+            }
+            Event::Start(Tag::Heading {
+                level: HeadingLevel::H1 | HeadingLevel::H2,
+                ..
+            }) => {
+                self.setext_headings.get_or_insert(first != Some('#'));
+            }
+            Event::Rule => {
+                self.rule
+                    .get_or_insert_with(|| markup.trim_end().to_string());
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(self) -> Style {
+        let default = Style::default();
+        Style {
+            emphasis: self.emphasis.unwrap_or(default.emphasis),
+            strong: self.strong.unwrap_or(default.strong),
+            bullet: self.bullet.unwrap_or(default.bullet),
+            ordered_delimiter: self.ordered_delimiter.unwrap_or(default.ordered_delimiter),
+            fence: self.fence.unwrap_or(default.fence),
+            rule: self.rule.unwrap_or(default.rule),
+            setext_headings: self.setext_headings.unwrap_or(default.setext_headings),
+        }
+    }
+}
+
+/// Block container that is currently being filled
+enum Container {
+    Root,
+    BlockQuote,
+    List {
+        start: Option<u64>,
+        items: Vec<Vec<Block>>,
+    },
+    Item,
+    Footnote(String),
+}
+
+/// Inline container that is currently being filled
+enum Span {
+    Paragraph,
+    Heading(u8),
+    Emphasis,
+    Strong,
+    Strikethrough,
+    Link { url: String, title: String },
+    WikiLink(String),
+    Image { url: String, title: String },
+}
+
+/// Metadata block that is currently being read
+struct Metadata {
+    kind: MetadataBlockKind,
+    source: String,
+    /// Whether the block opens the document, only then is it front matter
+    front: bool,
+    /// Source range of the whole block, with its delimiters
+    range: Range<usize>,
+    /// Source range of the text between the delimiters
+    content: Option<Range<usize>>,
+}
+
+/// Turns the flat pulldown-cmark event stream into a `Document`
+struct Builder {
+    containers: Vec<(Container, Vec<Block>)>,
+    spans: Vec<(Span, Vec<Inline>)>,
+    code: Option<(Option<String>, String)>,
+    metadata: Option<Metadata>,
+    front_matter: Option<FrontMatter>,
+    /// Footnote numbers by label, assigned on first reference
+    footnote_numbers: HashMap<String, usize>,
+    footnotes: Vec<(String, Vec<Block>)>,
+    /// Nesting of the current event, 0 between top-level blocks
+    depth: usize,
+    /// Source range of the top-level element being built
+    top_range: Range<usize>,
+    block_ranges: Vec<Range<usize>>,
+}
+
+impl Builder {
+    fn new() -> Self {
+        Self {
+            containers: vec![(Container::Root, Vec::new())],
+            spans: Vec::new(),
+            code: None,
+            metadata: None,
+            front_matter: None,
+            footnote_numbers: HashMap::new(),
+            footnotes: Vec::new(),
+            depth: 0,
+            top_range: 0..0,
+            block_ranges: Vec::new(),
+        }
+    }
+
+    fn push_event(&mut self, event: Event<'_>, range: Range<usize>) {
+        match &event {
+            Event::Start(_) => {
+                if self.depth == 0 {
+                    self.top_range = range.clone();
+                }
+                self.depth += 1;
+            }
+            Event::End(_) => self.depth = self.depth.saturating_sub(1),
+            Event::Rule if self.depth == 0 => self.top_range = range.clone(),
+            _ => {}
+        }
+
+        match event {
+            // pulldown-cmark reports metadata blocks anywhere in the document
+            Event::Start(Tag::MetadataBlock(kind)) => {
+                self.metadata = Some(Metadata {
+                    kind,
+                    source: String::new(),
+                    front: range.start == 0,
+                    range,
+                    content: None,
+                });
+            }
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => {
+                if let Some((_, code)) = self.code.as_mut() {
+                    code.push_str(&text);
+                } else if let Some(metadata) = self.metadata.as_mut() {
+                    metadata.source.push_str(&text);
+                    let start = metadata.content.as_ref().map_or(range.start, |c| c.start);
+                    metadata.content = Some(start..range.end);
+                } else {
+                    self.push_inline(Inline::Text(text.into_string()));
+                }
+            }
+            Event::Code(code) => self.push_inline(Inline::Code(code.into_string())),
+            Event::InlineMath(source) => self.push_inline(Inline::Math {
+                source: source.into_string(),
+                display: false,
+            }),
+            Event::DisplayMath(source) => self.push_inline(Inline::Math {
+                source: source.into_string(),
+                display: true,
+            }),
+            Event::SoftBreak => self.push_inline(Inline::SoftBreak),
+            Event::HardBreak => self.push_inline(Inline::HardBreak),
+            Event::FootnoteReference(label) => {
+                let next = self.footnote_numbers.len() + 1;
+                let number = *self
+                    .footnote_numbers
+                    .entry(label.to_string())
+                    .or_insert(next);
+                self.push_inline(Inline::FootnoteReference {
+                    label: label.into_string(),
+                    number,
+                });
+            }
+            Event::Rule => {
+                self.close_spans();
+                self.push_block(Block::Rule);
+            }
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag<'_>) {
+        match tag {
+            Tag::Paragraph => {
+                self.close_spans();
+                self.spans.push((Span::Paragraph, Vec::new()));
+            }
+            Tag::Heading { level, .. } => {
+                self.close_spans();
+                self.spans.push((Span::Heading(level as u8), Vec::new()));
+            }
+            Tag::BlockQuote(_) => self.open_container(Container::BlockQuote),
+            Tag::List(start) => self.open_container(Container::List {
+                start,
+                items: Vec::new(),
+            }),
+            Tag::Item => self.open_container(Container::Item),
+            Tag::FootnoteDefinition(label) => {
+                self.open_container(Container::Footnote(label.into_string()))
+            }
+            Tag::CodeBlock(kind) => {
+                self.close_spans();
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => {
+                        info.split_whitespace().next().map(|lang| lang.to_string())
+                    }
+                    CodeBlockKind::Indented => None,
+                };
+                self.code = Some((language, String::new()));
+            }
+            Tag::Emphasis => self.open_span(Span::Emphasis),
+            Tag::Strong => self.open_span(Span::Strong),
+            Tag::Strikethrough => self.open_span(Span::Strikethrough),
+            Tag::Link {
+                link_type: LinkType::WikiLink { .. },
+                dest_url,
+                ..
+            } => self.open_span(Span::WikiLink(dest_url.into_string())),
+            Tag::Link {
+                dest_url, title, ..
+            } => self.open_span(Span::Link {
+                url: dest_url.into_string(),
+                title: title.into_string(),
+            }),
+            Tag::Image {
+                dest_url, title, ..
+            } => self.open_span(Span::Image {
+                url: dest_url.into_string(),
+                title: title.into_string(),
+            }),
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph | TagEnd::Heading(_) => self.close_spans(),
+            TagEnd::BlockQuote(_) => {
+                if let Some(blocks) = self.close_container() {
+                    self.push_block(Block::Quote(blocks));
+                }
+            }
+            TagEnd::List(_) => {
+                self.close_spans();
+                if let Some((Container::List { start, items }, _)) = self.containers.pop() {
+                    self.push_block(Block::List { start, items });
+                }
+            }
+            TagEnd::Item => {
+                if let Some(blocks) = self.close_container()
+                    && let Some((Container::List { items, .. }, _)) = self.containers.last_mut()
+                {
+                    items.push(blocks);
+                }
+            }
+            TagEnd::FootnoteDefinition => {
+                self.close_spans();
+                if let Some((Container::Footnote(label), blocks)) = self.containers.pop() {
+                    self.footnotes.push((label, blocks));
+                }
+            }
+            TagEnd::MetadataBlock(_) => {
+                if let Some(metadata) = self.metadata.take() {
+                    self.end_metadata(metadata);
+                }
+            }
+            TagEnd::CodeBlock => {
+                if let Some((language, code)) = self.code.take() {
+                    self.push_block(Block::Code { language, code });
+                }
+            }
+            TagEnd::Emphasis
+            | TagEnd::Strong
+            | TagEnd::Strikethrough
+            | TagEnd::Link
+            | TagEnd::Image => {
+                if let Some((span, content)) = self.spans.pop() {
+                    self.close_span(span, content);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Read a block opening the document as front matter. Elsewhere its delimiters are
+    /// rules, or text for `+++`, around a paragraph of its lines.
+    fn end_metadata(&mut self, metadata: Metadata) {
+        let Metadata {
+            kind,
+            source,
+            front,
+            range,
+            content,
+        } = metadata;
+        if front {
+            self.front_matter = match kind {
+                MetadataBlockKind::YamlStyle => FrontMatter::from_yaml(&source),
+                MetadataBlockKind::PlusesStyle => FrontMatter::from_toml(&source),
+            };
+            return;
+        }
+
+        let content = content.unwrap_or(range.end..range.end);
+        let lines = source.lines().map(str::to_string);
+        if kind == MetadataBlockKind::PlusesStyle {
+            self.top_range = range;
+            let lines = std::iter::once("+++".to_string())
+                .chain(lines)
+                .chain(std::iter::once("+++".to_string()));
+            self.push_lines(lines);
+            return;
+        }
+
+        self.top_range = range.start..content.start;
+        self.push_block(Block::Rule);
+        if !source.trim().is_empty() {
+            self.top_range = content.clone();
+            self.push_lines(lines);
+        }
+        self.top_range = content.end..range.end;
+        self.push_block(Block::Rule);
+    }
+
+    /// Add a paragraph of `lines` of plain text
+    fn push_lines(&mut self, lines: impl Iterator<Item = String>) {
+        let mut content = Vec::new();
+        for line in lines {
+            if !content.is_empty() {
+                content.push(Inline::SoftBreak);
+            }
+            content.push(Inline::Text(line));
+        }
+        self.push_block(Block::Paragraph(content));
+    }
+
+    fn open_container(&mut self, container: Container) {
+        self.close_spans();
+        self.containers.push((container, Vec::new()));
+    }
+
+    /// Pop the innermost container and return its blocks
+    fn close_container(&mut self) -> Option<Vec<Block>> {
+        self.close_spans();
+        if self.containers.len() > 1 {
+            self.containers.pop().map(|(_, blocks)| blocks)
+        } else {
+            None
+        }
+    }
+
+    fn open_span(&mut self, span: Span) {
+        // Tight list items contain inline content without a paragraph
+        if self.spans.is_empty() {
+            self.spans.push((Span::Paragraph, Vec::new()));
+        }
+        self.spans.push((span, Vec::new()));
+    }
+
+    /// Close every open span, flushing the enclosing paragraph or heading
+    fn close_spans(&mut self) {
+        while let Some((span, content)) = self.spans.pop() {
+            self.close_span(span, content);
+        }
+    }
+
+    fn close_span(&mut self, span: Span, content: Vec<Inline>) {
+        let inline = match span {
+            Span::Paragraph => {
+                if let Some(block) = paragraph_block(content) {
+                    self.push_block(block);
+                }
+                return;
+            }
+            Span::Heading(level) => {
+                self.push_block(Block::Heading { level, content });
+                return;
+            }
+            Span::Emphasis => Inline::Emphasis(content),
+            Span::Strong => Inline::Strong(content),
+            Span::Strikethrough => Inline::Strikethrough(content),
+            Span::Link { url, title } => Inline::Link {
+                url,
+                title,
+                content,
+            },
+            Span::WikiLink(target) => Inline::WikiLink { target, content },
+            Span::Image { url, title } => Inline::Image(Image {
+                url,
+                title,
+                alt: Inline::plain_text(&content),
+            }),
+        };
+        self.push_inline(inline);
+    }
+
+    fn push_inline(&mut self, inline: Inline) {
+        if self.spans.is_empty() {
+            self.spans.push((Span::Paragraph, Vec::new()));
+        }
+        let Some((_, content)) = self.spans.last_mut() else {
+            return;
+        };
+
+        // Merge adjacent text runs, pulldown-cmark splits them on escapes and entities
+        if let (Some(Inline::Text(last)), Inline::Text(text)) = (content.last_mut(), &inline) {
+            last.push_str(text);
+        } else {
+            content.push(inline);
+        }
+    }
+
+    fn push_block(&mut self, block: Block) {
+        if self.containers.len() == 1 {
+            self.block_ranges.push(self.top_range.clone());
+        }
+        if let Some((_, blocks)) = self.containers.last_mut() {
+            blocks.push(block);
+        }
+    }
+
+    fn finish(mut self) -> Document {
+        self.close_spans();
+        while self.containers.len() > 1 {
+            self.end(match self.containers.last() {
+                Some((Container::List { .. }, _)) => TagEnd::List(false),
+                Some((Container::Item, _)) => TagEnd::Item,
+                Some((Container::Footnote(_), _)) => TagEnd::FootnoteDefinition,
+                _ => TagEnd::BlockQuote(None),
+            });
+        }
+
+        // Definitions that are never referenced are dropped
+        let mut footnotes: Vec<Footnote> = self
+            .footnotes
+            .into_iter()
+            .filter_map(|(label, blocks)| {
+                let number = *self.footnote_numbers.get(&label)?;
+                Some(Footnote {
+                    label,
+                    number,
+                    blocks,
+                })
+            })
+            .collect();
+        footnotes.sort_by_key(|footnote| footnote.number);
+
+        let blocks = self.containers.pop().map(|(_, blocks)| blocks);
+        Document {
+            front_matter: self.front_matter,
+            blocks: blocks.unwrap_or_default(),
+            block_ranges: self.block_ranges,
+            footnotes,
+            style: Style::default(),
+        }
+    }
+}
+
+/// A paragraph holding nothing but an image or a display formula
+/// is rendered as a block of its own
+fn paragraph_block(content: Vec<Inline>) -> Option<Block> {
+    let mut visible = content
+        .iter()
+        .filter(|inline| !matches!(inline, Inline::Text(text) if text.trim().is_empty()));
+
+    match (visible.next(), visible.next()) {
+        (None, _) => None,
+        (Some(Inline::Image(image)), None) => Some(Block::Image(image.clone())),
+        (
+            Some(Inline::Math {
+                source,
+                display: true,
+            }),
+            None,
+        ) => Some(Block::Math(source.clone())),
+        _ => Some(Block::Paragraph(content)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown::MetaValue;
+
+    fn text(text: &str) -> Inline {
+        Inline::Text(text.to_string())
+    }
+
+    #[test]
+    fn yaml_front_matter() {
+        let document = parse("---\ntitle: Notes\ntags: [a, b]\n---\n\nBody text.\n");
+        let front_matter = document.front_matter.unwrap();
+        assert_eq!(front_matter.title().as_deref(), Some("Notes"));
+        assert_eq!(front_matter.tags(), ["a", "b"]);
+        assert_eq!(
+            document.blocks,
+            [Block::Paragraph(vec![text("Body text.")])]
+        );
+    }
+
+    #[test]
+    fn toml_front_matter() {
+        let document = parse("+++\ntitle = \"Notes\"\ncount = 3\n+++\n\nBody text.\n");
+        let front_matter = document.front_matter.unwrap();
+        assert_eq!(front_matter.title().as_deref(), Some("Notes"));
+        assert_eq!(front_matter.get("count"), Some(&MetaValue::Integer(3)));
+        assert_eq!(
+            document.blocks,
+            [Block::Paragraph(vec![text("Body text.")])]
+        );
+    }
+
+    #[test]
+    fn numeric_tags_keep_their_type() {
+        let document = parse("---\ntags: [2024, \"1.5\", rust]\n---\n");
+        let front_matter = document.front_matter.unwrap();
+        assert_eq!(
+            front_matter.tag_values(),
+            [
+                MetaValue::Integer(2024),
+                MetaValue::String("1.5".to_string()),
+                MetaValue::String("rust".to_string()),
+            ]
+        );
+        assert_eq!(front_matter.tags(), ["2024", "1.5", "rust"]);
+    }
+
+    #[test]
+    fn metadata_block_inside_the_document_is_content() {
+        let source = "---\ntitle: Notes\n---\n\nIntro.\n\n---\nauthor: Someone\n---\n\nEnd.\n";
+        let document = parse(source);
+        assert_eq!(
+            document.front_matter.unwrap().title().as_deref(),
+            Some("Notes")
+        );
+        assert_eq!(
+            document.blocks,
+            [
+                Block::Paragraph(vec![text("Intro.")]),
+                Block::Rule,
+                Block::Paragraph(vec![text("author: Someone")]),
+                Block::Rule,
+                Block::Paragraph(vec![text("End.")]),
+            ]
+        );
+        let ranges: Vec<&str> = document
+            .block_ranges
+            .iter()
+            .map(|range| &source[range.clone()])
+            .collect();
+        assert_eq!(ranges, ["Intro.", "---", "author: Someone", "---", "End."]);
+    }
+
+    #[test]
+    fn metadata_block_inside_the_document_keeps_no_front_matter() {
+        let document = parse("Intro.\n\n---\ntitle: Other\n---\n");
+        assert_eq!(document.front_matter, None);
+        assert_eq!(document.blocks.len(), 4);
+    }
+
+    #[test]
+    fn toml_block_inside_the_document_is_a_paragraph() {
+        let document = parse("Intro.\n\n+++\nkey = 1\n+++\n");
+        assert_eq!(document.front_matter, None);
+        assert_eq!(
+            document.blocks[1],
+            Block::Paragraph(vec![
+                text("+++"),
+                Inline::SoftBreak,
+                text("key = 1"),
+                Inline::SoftBreak,
+                text("+++"),
+            ])
+        );
+    }
+}
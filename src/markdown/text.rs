@@ -0,0 +1,49 @@
+use std::ops::Range;
+
+/// Anchor name for a heading, following GitHub: lowercase, spaces become
+/// dashes and punctuation is dropped
+pub fn slug(text: &str) -> String {
+    text.trim()
+        .chars()
+        .filter_map(|c| match c {
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c.to_lowercase().next()?),
+            c if c.is_whitespace() => Some('-'),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Byte ranges of case-insensitive, non-overlapping occurrences of `query` in `text`
+pub fn find_matches(text: &str, query: &str) -> Vec<Range<usize>> {
+    let query: Vec<char> = query.chars().flat_map(char::to_lowercase).collect();
+    let mut matches = Vec::new();
+    if query.is_empty() {
+        return matches;
+    }
+
+    let mut from = 0;
+    for (start, _) in text.char_indices() {
+        if start < from {
+            continue;
+        }
+
+        let mut pending = query.iter();
+        let mut end = None;
+        for (offset, c) in text[start..].char_indices() {
+            if !c.to_lowercase().all(|c| pending.next() == Some(&c)) {
+                break;
+            }
+            if pending.len() == 0 {
+                end = Some(start + offset + c.len_utf8());
+                break;
+            }
+        }
+
+        if let Some(end) = end {
+            matches.push(start..end);
+            from = end;
+        }
+    }
+
+    matches
+}
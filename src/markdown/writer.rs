@@ -0,0 +1,359 @@
+use super::document::{Block, Image, Inline, Style};
+
+/// Markdown source for a block, without a trailing line break
+pub fn write_block(block: &Block, style: &Style) -> String {
+    match block {
+        Block::Heading { level, content } => {
+            let text = write_inlines(content, style);
+            if style.setext_headings && *level <= 2 {
+                let underline = if *level == 1 { '=' } else { '-' };
+                let width = text.lines().map(|line| line.chars().count()).max();
+                let underline = underline.to_string().repeat(width.unwrap_or(0).max(3));
+                format!("{text}\n{underline}")
+            } else {
+                format!("{} {text}", "#".repeat(*level as usize))
+            }
+        }
+        Block::Paragraph(content) => escape_block_start(&write_inlines(content, style)),
+        Block::Image(image) => write_image(image),
+        Block::Code { language, code } => {
+            // The fence has to be longer than any run of fence characters inside
+            let longest = longest_run(code, style.fence);
+            let fence = style.fence.to_string().repeat(longest.max(2) + 1);
+            let code = code.strip_suffix('\n').unwrap_or(code);
+            let language = language.as_deref().unwrap_or_default();
+            format!("{fence}{language}\n{code}\n{fence}")
+        }
+        Block::Quote(blocks) => write_blocks(blocks, style)
+            .lines()
+            .map(|line| {
+                if line.is_empty() {
+                    ">".to_string()
+                } else {
+                    format!("> {line}")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Block::List { start, items } => items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                let marker = match start {
+                    Some(start) => format!("{}{}", start + i as u64, style.ordered_delimiter),
+                    None => style.bullet.to_string(),
+                };
+                let indent = " ".repeat(marker.len() + 1);
+                let content = write_blocks(item, style);
+                let mut lines = content.lines();
+                let first = lines.next().unwrap_or_default();
+                let mut item = format!("{marker} {first}");
+                for line in lines {
+                    item.push('\n');
+                    if !line.is_empty() {
+                        item.push_str(&indent);
+                        item.push_str(line);
+                    }
+                }
+                item
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Block::Math(source) => format!("$$\n{}\n$$", source.trim()),
+        Block::Rule => style.rule.clone(),
+    }
+}
+
+/// Blocks separated by blank lines, nested lists stay attached to the text before them
+fn write_blocks(blocks: &[Block], style: &Style) -> String {
+    let mut out = String::new();
+    for (i, block) in blocks.iter().enumerate() {
+        if i > 0 {
+            out.push_str(if matches!(block, Block::List { .. }) {
+                "\n"
+            } else {
+                "\n\n"
+            });
+        }
+        out.push_str(&write_block(block, style));
+    }
+    out
+}
+
+/// Markdown source for a run of inline content
+pub fn write_inlines(inlines: &[Inline], style: &Style) -> String {
+    let mut out = String::new();
+    for inline in inlines {
+        write_inline(inline, style, &mut out);
+    }
+    out
+}
+
+fn write_inline(inline: &Inline, style: &Style, out: &mut String) {
+    match inline {
+        Inline::Text(text) => {
+            // Lines after a break are read for block markers just like the first one
+            for (i, line) in escape(text).split('\n').enumerate() {
+                if i > 0 {
+                    out.push('\n');
+                }
+                if out.ends_with('\n') {
+                    out.push_str(&escape_block_start(line));
+                } else {
+                    out.push_str(line);
+                }
+            }
+        }
+        Inline::Code(code) => {
+            let ticks = "`".repeat(longest_run(code, '`') + 1);
+            // Padding keeps backticks at the edges apart from the delimiters
+            let pad = if code.starts_with('`') || code.ends_with('`') {
+                " "
+            } else {
+                ""
+            };
+            out.push_str(&format!("{ticks}{pad}{code}{pad}{ticks}"));
+        }
+        Inline::Emphasis(content) => {
+            write_delimited(content, &style.emphasis.to_string(), style, out)
+        }
+        Inline::Strong(content) => {
+            write_delimited(content, &style.strong.to_string().repeat(2), style, out)
+        }
+        Inline::Strikethrough(content) => write_delimited(content, "~~", style, out),
+        Inline::Link {
+            url,
+            title,
+            content,
+        } => {
+            out.push('[');
+            out.push_str(&write_inlines(content, style));
+            out.push(']');
+            write_destination(url, title, out);
+        }
+        Inline::WikiLink { target, content } => {
+            let text = write_inlines(content, style);
+            if text == *target {
+                out.push_str(&format!("[[{target}]]"));
+            } else {
+                out.push_str(&format!("[[{target}|{text}]]"));
+            }
+        }
+        Inline::Image(image) => out.push_str(&write_image(image)),
+        Inline::FootnoteReference { label, .. } => out.push_str(&format!("[^{label}]")),
+        Inline::Math { source, display } => {
+            let dollars = if *display { "$$" } else { "$" };
+            out.push_str(&format!("{dollars}{source}{dollars}"));
+        }
+        Inline::SoftBreak => out.push('\n'),
+        Inline::HardBreak => out.push_str("\\\n"),
+    }
+}
+
+/// Wrap content in delimiters, keeping surrounding whitespace outside where it cannot close them
+fn write_delimited(content: &[Inline], delimiter: &str, style: &Style, out: &mut String) {
+    let inner = write_inlines(content, style);
+    let core = inner.trim();
+    if core.is_empty() {
+        out.push_str(&inner);
+        return;
+    }
+
+    let leading = &inner[..inner.len() - inner.trim_start().len()];
+    let trailing = &inner[inner.trim_end().len()..];
+    out.push_str(&format!("{leading}{delimiter}{core}{delimiter}{trailing}"));
+}
+
+fn write_image(image: &Image) -> String {
+    let mut out = format!("![{}]", escape(&image.alt));
+    write_destination(&image.url, &image.title, &mut out);
+    out
+}
+
+/// Link destination with its optional title, in angle brackets if it has spaces or parentheses
+fn write_destination(url: &str, title: &str, out: &mut String) {
+    out.push('(');
+    if url.contains([' ', '(', ')']) {
+        out.push_str(&format!("<{url}>"));
+    } else {
+        out.push_str(url);
+    }
+    if !title.is_empty() {
+        out.push_str(&format!(" \"{}\"", title.replace('"', "\\\"")));
+    }
+    out.push(')');
+}
+
+/// Backslash-escape characters that would otherwise start inline markup, and ampersands
+/// that would be read as the start of an entity
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for (i, c) in text.char_indices() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '$' | '~')
+            || (c == '&' && starts_with_entity(&text[i..]))
+        {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Whether `text` starts with a named or numeric character reference, like `&amp;`,
+/// `&#42;` or `&#x2a;`
+fn starts_with_entity(text: &str) -> bool {
+    let Some(rest) = text.strip_prefix('&') else {
+        return false;
+    };
+    let Some(end) = rest.find(';') else {
+        return false;
+    };
+    let name = &rest[..end];
+    if let Some(number) = name.strip_prefix('#') {
+        return match number.strip_prefix(['x', 'X']) {
+            Some(hex) => (1..=6).contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit()),
+            None => (1..=7).contains(&number.len()) && number.chars().all(|c| c.is_ascii_digit()),
+        };
+    }
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Escape text at the start of a line that would be read as another kind of block
+fn escape_block_start(text: &str) -> String {
+    let digits = text.chars().take_while(char::is_ascii_digit).count();
+    if digits > 0 && text[digits..].starts_with(['.', ')']) {
+        return format!("{}\\{}", &text[..digits], &text[digits..]);
+    }
+    if text.starts_with(['#', '>', '-', '+', '=']) {
+        return format!("\\{text}");
+    }
+    text.to_string()
+}
+
+/// Length of the longest run of `c` in `text`
+fn longest_run(text: &str, c: char) -> usize {
+    let mut longest = 0;
+    let mut current = 0;
+    for ch in text.chars() {
+        if ch == c {
+            current += 1;
+            longest = longest.max(current);
+        } else {
+            current = 0;
+        }
+    }
+    longest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown::parse;
+
+    /// Write the blocks of `source` back and check that they parse to the same blocks
+    fn assert_round_trip(source: &str) {
+        let document = parse(source);
+        let written = document
+            .blocks
+            .iter()
+            .map(|block| write_block(block, &document.style))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        assert_eq!(
+            parse(&written).blocks,
+            document.blocks,
+            "written as:\n{written}"
+        );
+    }
+
+    #[test]
+    fn nested_inline_markup_round_trips() {
+        assert_round_trip("Some **strong *and emphasised* text** with ~~struck~~ words.");
+        assert_round_trip("A [link with **bold**](https://example.com/a_b \"Title\") here.");
+        assert_round_trip("Code `a`` ``b` and `` `tick` `` and [[Page|a wiki link]].");
+        assert_round_trip("Formula $x^2$ and $$\\sum_i i$$ inline.");
+    }
+
+    #[test]
+    fn footnote_references_are_kept() {
+        // Definitions are not blocks, only the reference is written back
+        let document = parse("A footnote[^note].\n\n[^note]: The note.");
+        assert_eq!(
+            write_block(&document.blocks[0], &document.style),
+            "A footnote[^note]."
+        );
+    }
+
+    #[test]
+    fn special_characters_round_trip() {
+        assert_round_trip(r"2 \* 3 = 6, snake\_case and \_under\_ \*star\*");
+        assert_round_trip(r"Less \< more & \[brackets\] \$5 and back\\slash");
+        assert_round_trip(r"\# Not a heading");
+        assert_round_trip(r"1\. Not a list");
+        assert_round_trip(r"\- Not a bullet");
+    }
+
+    #[test]
+    fn block_markers_after_breaks_round_trip() {
+        for (line_break, text) in [
+            (Inline::HardBreak, "# b"),
+            (Inline::SoftBreak, "- b"),
+            (Inline::SoftBreak, "+ b"),
+            (Inline::HardBreak, "> b"),
+            (Inline::SoftBreak, "==="),
+            (Inline::SoftBreak, "---"),
+            (Inline::HardBreak, "1. b"),
+        ] {
+            let blocks = vec![Block::Paragraph(vec![
+                Inline::Text("a".to_string()),
+                line_break,
+                Inline::Text(text.to_string()),
+            ])];
+            let written = write_block(&blocks[0], &Style::default());
+            assert_eq!(parse(&written).blocks, blocks, "written as:\n{written}");
+        }
+        assert_round_trip("a\\\n\\# b");
+    }
+
+    #[test]
+    fn entities_round_trip() {
+        let blocks = vec![Block::Paragraph(vec![Inline::Text(
+            "&amp; &#42; &#x2A; & &;".to_string(),
+        )])];
+        let written = write_block(&blocks[0], &Style::default());
+        assert_eq!(written, r"\&amp; \&#42; \&#x2A; & &;");
+        assert_eq!(parse(&written).blocks, blocks);
+    }
+
+    #[test]
+    fn blocks_round_trip() {
+        assert_round_trip("# Heading\n\nText\n\n> Quoted\n>\n> > nested");
+        assert_round_trip("- one\n- two\n  - nested\n\n3. three\n4. four");
+        assert_round_trip("````rust\nlet fence = \"```\";\n````");
+        assert_round_trip("![Alt text](image (1).png)\n\n---\n\n$$\nx + y\n$$");
+    }
+
+    #[test]
+    fn source_style_is_kept() {
+        let document = parse("_emphasis_ and __strong__\n\n* item\n\nTitle\n=====");
+        let written: Vec<_> = document
+            .blocks
+            .iter()
+            .map(|block| write_block(block, &document.style))
+            .collect();
+        assert_eq!(
+            written,
+            ["_emphasis_ and __strong__", "* item", "Title\n====="]
+        );
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        let text = [Inline::Text("a*b_c [d] `e` <f> $g ~h".to_string())];
+        assert_eq!(
+            write_inlines(&text, &Style::default()),
+            r"a\*b\_c \[d\] \`e\` \<f> \$g \~h"
+        );
+    }
+}
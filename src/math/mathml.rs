@@ -0,0 +1,192 @@
+use super::{MathNode, SymbolClass};
+
+/// MathML Core markup for a formula, for browsers to typeset
+pub fn to_mathml(node: &MathNode, display: bool) -> String {
+    let mut out = String::from(if display {
+        "<math display=\"block\">"
+    } else {
+        "<math>"
+    });
+    write_node(node, display, &mut out);
+    out.push_str("</math>");
+    out
+}
+
+fn write_node(node: &MathNode, display: bool, out: &mut String) {
+    match node {
+        MathNode::Row(nodes) => {
+            out.push_str("<mrow>");
+            for node in nodes {
+                write_node(node, display, out);
+            }
+            out.push_str("</mrow>");
+        }
+        MathNode::Ident(text) => element("mi", "", text, out),
+        MathNode::Number(text) => element("mn", "", text, out),
+        MathNode::Text(text) => element("mtext", "", text, out),
+        MathNode::Symbol(text, class) => {
+            let attributes = match class {
+                SymbolClass::Open | SymbolClass::Close => " stretchy=\"false\"",
+                _ => "",
+            };
+            element("mo", attributes, text, out);
+        }
+        MathNode::Operator { text, large, .. } => {
+            if *large {
+                element("mo", " largeop=\"true\"", text, out);
+            } else {
+                element("mi", " mathvariant=\"normal\"", text, out);
+            }
+        }
+        MathNode::Frac {
+            numerator,
+            denominator,
+        } => {
+            out.push_str("<mfrac>");
+            write_node(numerator, display, out);
+            write_node(denominator, display, out);
+            out.push_str("</mfrac>");
+        }
+        MathNode::Sqrt { index: None, body } => {
+            out.push_str("<msqrt>");
+            write_node(body, display, out);
+            out.push_str("</msqrt>");
+        }
+        MathNode::Sqrt {
+            index: Some(index),
+            body,
+        } => {
+            out.push_str("<mroot>");
+            write_node(body, display, out);
+            write_node(index, display, out);
+            out.push_str("</mroot>");
+        }
+        MathNode::Scripts { base, sub, sup } => {
+            // Operators with limits take their scripts above and below in display style
+            let limits = display && matches!(**base, MathNode::Operator { limits: true, .. });
+            let tag = match (sub, sup, limits) {
+                (Some(_), Some(_), false) => "msubsup",
+                (Some(_), None, false) => "msub",
+                (None, _, false) => "msup",
+                (Some(_), Some(_), true) => "munderover",
+                (Some(_), None, true) => "munder",
+                (None, _, true) => "mover",
+            };
+            out.push_str(&format!("<{tag}>"));
+            write_node(base, display, out);
+            for script in [sub, sup].into_iter().flatten() {
+                write_node(script, display, out);
+            }
+            if sub.is_none() && sup.is_none() {
+                out.push_str("<mrow></mrow>");
+            }
+            out.push_str(&format!("</{tag}>"));
+        }
+        MathNode::Fenced { open, close, body } => {
+            out.push_str("<mrow>");
+            fence(open, out);
+            write_node(body, display, out);
+            fence(close, out);
+            out.push_str("</mrow>");
+        }
+        MathNode::Matrix {
+            rows,
+            open,
+            close,
+            align_left,
+        } => {
+            out.push_str("<mrow>");
+            fence(open, out);
+            out.push_str("<mtable>");
+            for row in rows {
+                out.push_str("<mtr>");
+                for cell in row {
+                    out.push_str(if *align_left {
+                        "<mtd style=\"text-align: left\">"
+                    } else {
+                        "<mtd>"
+                    });
+                    write_node(cell, display, out);
+                    out.push_str("</mtd>");
+                }
+                out.push_str("</mtr>");
+            }
+            out.push_str("</mtable>");
+            fence(close, out);
+            out.push_str("</mrow>");
+        }
+        MathNode::Accent { accent, body } => {
+            out.push_str("<mover accent=\"true\">");
+            write_node(body, display, out);
+            element("mo", "", accent, out);
+            out.push_str("</mover>");
+        }
+        MathNode::Overline(body) => {
+            out.push_str("<mover accent=\"true\">");
+            write_node(body, display, out);
+            element("mo", "", "‾", out);
+            out.push_str("</mover>");
+        }
+        MathNode::Bold(body) => {
+            out.push_str("<mrow style=\"font-weight: bold\">");
+            write_node(body, display, out);
+            out.push_str("</mrow>");
+        }
+        MathNode::Space(width) => out.push_str(&format!("<mspace width=\"{width}em\"/>")),
+    }
+}
+
+/// Stretchy delimiter, nothing for the empty `\left.`
+fn fence(delimiter: &str, out: &mut String) {
+    if !delimiter.is_empty() && delimiter != "." {
+        element("mo", " stretchy=\"true\"", delimiter, out);
+    }
+}
+
+fn element(tag: &str, attributes: &str, text: &str, out: &mut String) {
+    out.push_str(&format!("<{tag}{attributes}>"));
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            c => out.push(c),
+        }
+    }
+    out.push_str(&format!("</{tag}>"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::parse;
+
+    fn mathml(source: &str, display: bool) -> String {
+        to_mathml(&parse(source).unwrap(), display)
+    }
+
+    #[test]
+    fn fractions_and_roots() {
+        assert_eq!(
+            mathml(r"\frac{1}{\sqrt[3]{x}}", false),
+            "<math><mfrac><mn>1</mn><mroot><mi>x</mi><mrow><mn>3</mn></mrow></mroot></mfrac></math>"
+        );
+    }
+
+    #[test]
+    fn limits_go_under_and_over_in_display_style() {
+        assert_eq!(
+            mathml(r"\sum_{i=1}^n i", true),
+            "<math display=\"block\"><mrow><munderover><mo largeop=\"true\">∑</mo><mrow><mi>i</mi><mo>=</mo><mn>1</mn></mrow><mi>n</mi></munderover><mi>i</mi></mrow></math>"
+        );
+        assert!(mathml(r"\sum_{i=1}^n i", false).contains("<msubsup>"));
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        assert_eq!(
+            mathml(r"a < b", false),
+            "<math><mrow><mi>a</mi><mo>&lt;</mo><mi>b</mi></mrow></math>"
+        );
+    }
+}
@@ -0,0 +1,71 @@
+mod mathml;
+mod parser;
+mod symbols;
+
+pub use mathml::to_mathml;
+pub use parser::parse;
+
+/// Node of a parsed TeX math expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum MathNode {
+    Row(Vec<MathNode>),
+    /// Variable, set in italics
+    Ident(String),
+    Number(String),
+    /// Upright text from `\text{…}` and friends
+    Text(String),
+    Symbol(String, SymbolClass),
+    /// Named or large operator such as `\sin`, `\lim` or `\sum`
+    Operator {
+        text: String,
+        /// Scripts go above and below in display style
+        limits: bool,
+        /// Drawn enlarged in display style
+        large: bool,
+    },
+    Frac {
+        numerator: Box<MathNode>,
+        denominator: Box<MathNode>,
+    },
+    Sqrt {
+        index: Option<Box<MathNode>>,
+        body: Box<MathNode>,
+    },
+    Scripts {
+        base: Box<MathNode>,
+        sub: Option<Box<MathNode>>,
+        sup: Option<Box<MathNode>>,
+    },
+    /// Delimiters that stretch to the height of the body, `\left( … \right)`
+    Fenced {
+        open: String,
+        close: String,
+        body: Box<MathNode>,
+    },
+    Matrix {
+        rows: Vec<Vec<MathNode>>,
+        open: String,
+        close: String,
+        /// Cells are left aligned, as in `cases`
+        align_left: bool,
+    },
+    Accent {
+        accent: String,
+        body: Box<MathNode>,
+    },
+    Overline(Box<MathNode>),
+    Bold(Box<MathNode>),
+    /// Horizontal space in em
+    Space(f32),
+}
+
+/// Spacing class of a symbol, following TeX's atom types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolClass {
+    Ordinary,
+    Binary,
+    Relation,
+    Punctuation,
+    Open,
+    Close,
+}
@@ -0,0 +1,679 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use super::symbols;
+use super::{MathNode, SymbolClass};
+
+/// Error for TeX the parser does not understand
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Deepest the parser recurses into groups and arguments before giving up, so deeply
+/// nested input fails to parse instead of overflowing the stack
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Command(String),
+    Char(char),
+    Open,
+    Close,
+    Sup,
+    Sub,
+    Align,
+    NewRow,
+    /// Whitespace only matters inside `\text{…}`
+    Space,
+}
+
+/// Parse a subset of TeX math: fractions, roots, scripts, Greek letters and
+/// symbols, named functions, `\left … \right`, `\text` and matrix environments.
+pub fn parse(source: &str) -> Result<MathNode, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(source),
+        pos: 0,
+        depth: 0,
+    };
+    let row = parser.row()?;
+    match parser.peek() {
+        None => Ok(row),
+        Some(token) => Err(ParseError(format!("Unexpected {token:?}"))),
+    }
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let token = match c {
+            '\\' => command(&mut chars),
+            '{' => Token::Open,
+            '}' => Token::Close,
+            '^' => Token::Sup,
+            '_' => Token::Sub,
+            '&' => Token::Align,
+            '%' => {
+                // Comment until the end of the line
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                continue;
+            }
+            c if c.is_whitespace() => {
+                if tokens.last() == Some(&Token::Space) {
+                    continue;
+                }
+                Token::Space
+            }
+            c => Token::Char(c),
+        };
+        tokens.push(token);
+    }
+
+    tokens
+}
+
+fn command(chars: &mut Peekable<Chars>) -> Token {
+    let mut name = String::new();
+    while let Some(&c) = chars.peek() {
+        if !c.is_ascii_alphabetic() {
+            break;
+        }
+        name.push(c);
+        chars.next();
+    }
+
+    if name.is_empty() {
+        match chars.next() {
+            Some('\\') => return Token::NewRow,
+            Some(c) => name.push(c),
+            None => name.push(' '),
+        }
+    }
+
+    Token::Command(name)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Rows and atoms being parsed, one inside the other
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens[self.pos.min(self.tokens.len())..]
+            .iter()
+            .find(|token| **token != Token::Space)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        while self.tokens.get(self.pos) == Some(&Token::Space) {
+            self.pos += 1;
+        }
+        self.next_raw()
+    }
+
+    /// Next token including whitespace
+    fn next_raw(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ParseError> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(ParseError(format!(
+                "Expected {expected:?}, found {token:?}"
+            ))),
+            None => Err(ParseError(format!(
+                "Expected {expected:?}, found end of input"
+            ))),
+        }
+    }
+
+    /// Run `parse` a level deeper, failing once `MAX_DEPTH` levels are reached
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<MathNode, ParseError>,
+    ) -> Result<MathNode, ParseError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(ParseError("Formula is nested too deeply".into()));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Parse atoms until a closing brace, `&`, `\\`, `\right`, `\end` or the end
+    fn row(&mut self) -> Result<MathNode, ParseError> {
+        self.nested(Self::row_items)
+    }
+
+    fn row_items(&mut self) -> Result<MathNode, ParseError> {
+        let mut items = Vec::new();
+
+        loop {
+            match self.peek() {
+                None | Some(Token::Close | Token::Align | Token::NewRow) => break,
+                Some(Token::Command(name)) if name == "right" || name == "end" => break,
+                Some(Token::Sup | Token::Sub) => {
+                    // Script without a base, as in `^2`
+                    let node = self.scripts(MathNode::Row(Vec::new()))?;
+                    items.push(node);
+                }
+                Some(_) => {
+                    let atom = self.atom()?;
+                    let node = self.scripts(atom)?;
+                    items.push(node);
+                }
+            }
+        }
+
+        Ok(if items.len() == 1 {
+            items.pop().unwrap()
+        } else {
+            MathNode::Row(items)
+        })
+    }
+
+    fn scripts(&mut self, base: MathNode) -> Result<MathNode, ParseError> {
+        let mut sub = None;
+        let mut sup = None;
+
+        loop {
+            match self.peek() {
+                Some(Token::Sub) if sub.is_none() => {
+                    self.next();
+                    sub = Some(Box::new(self.argument()?));
+                }
+                Some(Token::Sup) if sup.is_none() => {
+                    self.next();
+                    sup = Some(Box::new(self.argument()?));
+                }
+                Some(Token::Char('\'')) if sup.is_none() => {
+                    let mut primes = String::new();
+                    while self.peek() == Some(&Token::Char('\'')) {
+                        self.next();
+                        primes.push('′');
+                    }
+                    sup = Some(Box::new(MathNode::Symbol(primes, SymbolClass::Ordinary)));
+                }
+                _ => break,
+            }
+        }
+
+        if sub.is_none() && sup.is_none() {
+            return Ok(base);
+        }
+        Ok(MathNode::Scripts {
+            base: Box::new(base),
+            sub,
+            sup,
+        })
+    }
+
+    /// A braced group or a single atom, as taken by `\frac` or `^`
+    fn argument(&mut self) -> Result<MathNode, ParseError> {
+        match self.peek() {
+            Some(Token::Open) => self.group(),
+            // A single digit, so that `\frac12` and `x^23` read like TeX
+            Some(&Token::Char(c)) if c.is_ascii_digit() => {
+                self.next();
+                Ok(MathNode::Number(c.to_string()))
+            }
+            Some(_) => self.atom(),
+            None => Err(ParseError("Missing argument".into())),
+        }
+    }
+
+    fn group(&mut self) -> Result<MathNode, ParseError> {
+        self.expect(Token::Open)?;
+        let row = self.row()?;
+        self.expect(Token::Close)?;
+        Ok(row)
+    }
+
+    /// Raw text of a braced group, for `\text` and environment names
+    fn text_argument(&mut self) -> Result<String, ParseError> {
+        self.expect(Token::Open)?;
+        let mut text = String::new();
+        let mut depth = 0;
+
+        loop {
+            match self.next_raw() {
+                Some(Token::Close) if depth == 0 => break,
+                Some(Token::Close) => {
+                    depth -= 1;
+                    text.push('}');
+                }
+                Some(Token::Open) => {
+                    depth += 1;
+                    text.push('{');
+                }
+                Some(Token::Char(c)) => text.push(c),
+                Some(Token::Command(name)) if name == " " => text.push(' '),
+                Some(Token::Command(name)) => {
+                    if let Some(space) = symbols::space(&name)
+                        && space > 0.0
+                    {
+                        text.push(' ');
+                    } else if name.len() == 1 {
+                        text.push_str(&name);
+                    } else {
+                        text.push('\\');
+                        text.push_str(&name);
+                    }
+                }
+                Some(Token::Sup) => text.push('^'),
+                Some(Token::Sub) => text.push('_'),
+                Some(Token::Align) => text.push('&'),
+                Some(Token::NewRow | Token::Space) => text.push(' '),
+                None => return Err(ParseError("Unclosed group".into())),
+            }
+        }
+
+        Ok(text)
+    }
+
+    fn atom(&mut self) -> Result<MathNode, ParseError> {
+        self.nested(Self::single_atom)
+    }
+
+    fn single_atom(&mut self) -> Result<MathNode, ParseError> {
+        match self.next() {
+            Some(Token::Open) => {
+                let row = self.row()?;
+                self.expect(Token::Close)?;
+                Ok(row)
+            }
+            Some(Token::Char(c)) => Ok(self.char_atom(c)),
+            Some(Token::Command(name)) => self.command(&name),
+            Some(token) => Err(ParseError(format!("Unexpected {token:?}"))),
+            None => Err(ParseError("Unexpected end of input".into())),
+        }
+    }
+
+    fn char_atom(&mut self, c: char) -> MathNode {
+        if c.is_ascii_digit() || c == '.' {
+            let mut number = String::from(c);
+            while let Some(&Token::Char(c)) = self.peek() {
+                if !(c.is_ascii_digit() || c == '.') {
+                    break;
+                }
+                number.push(c);
+                self.next();
+            }
+            return MathNode::Number(number);
+        }
+        if c.is_alphabetic() {
+            return MathNode::Ident(c.to_string());
+        }
+
+        let text = match c {
+            '-' => '−',
+            '*' => '∗',
+            c => c,
+        };
+        MathNode::Symbol(text.to_string(), symbols::char_class(c))
+    }
+
+    fn command(&mut self, name: &str) -> Result<MathNode, ParseError> {
+        if let Some(letter) = symbols::greek(name) {
+            return Ok(if name.starts_with(char::is_uppercase) {
+                MathNode::Text(letter.into())
+            } else {
+                MathNode::Ident(letter.into())
+            });
+        }
+        if let Some((text, class)) = symbols::symbol(name) {
+            return Ok(MathNode::Symbol(text.into(), class));
+        }
+        if let Some((text, limits)) = symbols::large_operator(name) {
+            return Ok(MathNode::Operator {
+                text: text.into(),
+                limits,
+                large: true,
+            });
+        }
+        if let Some(limits) = symbols::function(name) {
+            return Ok(MathNode::Operator {
+                text: name.into(),
+                limits,
+                large: false,
+            });
+        }
+        if let Some(space) = symbols::space(name) {
+            return Ok(MathNode::Space(space));
+        }
+        if let Some(accent) = symbols::accent(name) {
+            return Ok(MathNode::Accent {
+                accent: accent.into(),
+                body: Box::new(self.argument()?),
+            });
+        }
+
+        match name {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => Ok(MathNode::Frac {
+                numerator: Box::new(self.argument()?),
+                denominator: Box::new(self.argument()?),
+            }),
+            "binom" | "dbinom" | "tbinom" => {
+                let top = self.argument()?;
+                let bottom = self.argument()?;
+                Ok(MathNode::Matrix {
+                    rows: vec![vec![top], vec![bottom]],
+                    open: "(".into(),
+                    close: ")".into(),
+                    align_left: false,
+                })
+            }
+            "sqrt" => {
+                let index = if self.peek() == Some(&Token::Char('[')) {
+                    self.next();
+                    let mut items = Vec::new();
+                    while self.peek() != Some(&Token::Char(']')) {
+                        if self.peek().is_none() {
+                            return Err(ParseError("Unclosed root index".into()));
+                        }
+                        items.push(self.atom()?);
+                    }
+                    self.next();
+                    Some(Box::new(MathNode::Row(items)))
+                } else {
+                    None
+                };
+                Ok(MathNode::Sqrt {
+                    index,
+                    body: Box::new(self.argument()?),
+                })
+            }
+            "text" | "textrm" | "textup" | "textit" | "mbox" => {
+                Ok(MathNode::Text(self.text_argument()?))
+            }
+            "mathrm" | "operatorname" => {
+                let text = self.text_argument()?;
+                Ok(if name == "operatorname" {
+                    MathNode::Operator {
+                        text,
+                        limits: false,
+                        large: false,
+                    }
+                } else {
+                    MathNode::Text(text)
+                })
+            }
+            "mathbf" | "boldsymbol" | "textbf" => Ok(MathNode::Bold(Box::new(self.argument()?))),
+            "mathbb" => {
+                let text = self.text_argument()?;
+                Ok(MathNode::Text(
+                    text.chars().map(symbols::double_struck).collect(),
+                ))
+            }
+            "mathit" | "mathsf" | "mathcal" | "mathtt" | "displaystyle" | "textstyle" => {
+                // Font variants are rendered with the regular math font
+                if name.ends_with("style") {
+                    Ok(MathNode::Row(Vec::new()))
+                } else {
+                    self.argument()
+                }
+            }
+            "overline" => Ok(MathNode::Overline(Box::new(self.argument()?))),
+            "left" => self.fenced(),
+            "begin" => self.environment(),
+            "not" => {
+                let next = self.atom()?;
+                Ok(match next {
+                    MathNode::Symbol(text, class) => {
+                        MathNode::Symbol(format!("{text}\u{338}"), class)
+                    }
+                    node => node,
+                })
+            }
+            "big" | "Big" | "bigg" | "Bigg" | "bigl" | "bigr" | "Bigl" | "Bigr" => {
+                // Sized delimiters are drawn at their natural size
+                self.atom()
+            }
+            _ => Err(ParseError(format!("Unknown command \\{name}"))),
+        }
+    }
+
+    fn delimiter(&mut self) -> Result<String, ParseError> {
+        let name = match self.next() {
+            Some(Token::Char(c)) => c.to_string(),
+            Some(Token::Command(name)) if name.len() == 1 => format!("\\{name}"),
+            Some(Token::Command(name)) => name,
+            _ => return Err(ParseError("Missing delimiter".into())),
+        };
+        symbols::delimiter(&name)
+            .map(String::from)
+            .ok_or_else(|| ParseError(format!("Unknown delimiter {name}")))
+    }
+
+    fn fenced(&mut self) -> Result<MathNode, ParseError> {
+        let open = self.delimiter()?;
+        let body = self.row()?;
+        match self.next() {
+            Some(Token::Command(name)) if name == "right" => {}
+            _ => return Err(ParseError("\\left without \\right".into())),
+        }
+        let close = self.delimiter()?;
+
+        Ok(MathNode::Fenced {
+            open,
+            close,
+            body: Box::new(body),
+        })
+    }
+
+    fn environment(&mut self) -> Result<MathNode, ParseError> {
+        let name = self.text_argument()?;
+        let (open, close, align_left) = match name.as_str() {
+            "matrix" | "smallmatrix" | "aligned" | "align" | "align*" | "gathered" => {
+                ("", "", false)
+            }
+            "pmatrix" => ("(", ")", false),
+            "bmatrix" => ("[", "]", false),
+            "Bmatrix" => ("{", "}", false),
+            "vmatrix" => ("|", "|", false),
+            "Vmatrix" => ("‖", "‖", false),
+            "cases" => ("{", "", true),
+            _ => return Err(ParseError(format!("Unknown environment {name}"))),
+        };
+
+        let mut rows = Vec::new();
+        let mut row = Vec::new();
+        loop {
+            row.push(self.row()?);
+            match self.next() {
+                Some(Token::Align) => {}
+                Some(Token::NewRow) => rows.push(std::mem::take(&mut row)),
+                Some(Token::Command(end)) if end == "end" => {
+                    let end_name = self.text_argument()?;
+                    if end_name != name {
+                        return Err(ParseError(format!(
+                            "\\begin{{{name}}} ended by \\end{{{end_name}}}"
+                        )));
+                    }
+                    break;
+                }
+                _ => return Err(ParseError(format!("Unclosed environment {name}"))),
+            }
+        }
+        // A trailing `\\` leaves an empty last row
+        if !(row.len() == 1 && row[0] == MathNode::Row(Vec::new())) {
+            rows.push(row);
+        }
+
+        Ok(MathNode::Matrix {
+            rows,
+            open: open.into(),
+            close: close.into(),
+            align_left,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ident(name: &str) -> MathNode {
+        MathNode::Ident(name.to_string())
+    }
+
+    fn number(digits: &str) -> MathNode {
+        MathNode::Number(digits.to_string())
+    }
+
+    fn symbol(text: &str, class: SymbolClass) -> MathNode {
+        MathNode::Symbol(text.to_string(), class)
+    }
+
+    fn error(source: &str) -> String {
+        parse(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn fractions() {
+        assert_eq!(
+            parse(r"\frac{a+b}{2}"),
+            Ok(MathNode::Frac {
+                numerator: Box::new(MathNode::Row(vec![
+                    ident("a"),
+                    symbol("+", SymbolClass::Binary),
+                    ident("b"),
+                ])),
+                denominator: Box::new(number("2")),
+            })
+        );
+        // Single digits need no braces, as in TeX
+        assert_eq!(
+            parse(r"\frac12"),
+            Ok(MathNode::Frac {
+                numerator: Box::new(number("1")),
+                denominator: Box::new(number("2")),
+            })
+        );
+    }
+
+    #[test]
+    fn scripts() {
+        assert_eq!(
+            parse("x_i^{2n}"),
+            Ok(MathNode::Scripts {
+                base: Box::new(ident("x")),
+                sub: Some(Box::new(ident("i"))),
+                sup: Some(Box::new(MathNode::Row(vec![number("2"), ident("n")]))),
+            })
+        );
+        assert_eq!(
+            parse("f''"),
+            Ok(MathNode::Scripts {
+                base: Box::new(ident("f")),
+                sub: None,
+                sup: Some(Box::new(symbol("′′", SymbolClass::Ordinary))),
+            })
+        );
+        assert!(parse("x^").is_err());
+    }
+
+    #[test]
+    fn roots() {
+        assert_eq!(
+            parse(r"\sqrt{x}"),
+            Ok(MathNode::Sqrt {
+                index: None,
+                body: Box::new(ident("x")),
+            })
+        );
+        assert_eq!(
+            parse(r"\sqrt[3]{x+1}"),
+            Ok(MathNode::Sqrt {
+                index: Some(Box::new(MathNode::Row(vec![number("3")]))),
+                body: Box::new(MathNode::Row(vec![
+                    ident("x"),
+                    symbol("+", SymbolClass::Binary),
+                    number("1"),
+                ])),
+            })
+        );
+        assert_eq!(error(r"\sqrt[3"), "Unclosed root index");
+    }
+
+    #[test]
+    fn matrices() {
+        assert_eq!(
+            parse(r"\begin{pmatrix} a & b \\ c & d \\ \end{pmatrix}"),
+            Ok(MathNode::Matrix {
+                rows: vec![vec![ident("a"), ident("b")], vec![ident("c"), ident("d")]],
+                open: "(".into(),
+                close: ")".into(),
+                align_left: false,
+            })
+        );
+        let Ok(MathNode::Matrix { align_left, .. }) =
+            parse(r"\begin{cases} 1 & x > 0 \\ 0 & \text{otherwise} \end{cases}")
+        else {
+            panic!("cases is not a matrix");
+        };
+        assert!(align_left);
+    }
+
+    #[test]
+    fn greek_letters() {
+        assert_eq!(
+            parse(r"\alpha \pi \Gamma"),
+            Ok(MathNode::Row(vec![
+                ident("α"),
+                ident("π"),
+                // Capitals are upright
+                MathNode::Text("Γ".into()),
+            ]))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("{x"), "Expected Close, found end of input");
+        assert_eq!(error("x}"), "Unexpected Close");
+        assert_eq!(error(r"\foo"), "Unknown command \\foo");
+        assert_eq!(error(r"\left( x"), "\\left without \\right");
+        assert_eq!(error(r"\begin{matrix} a"), "Unclosed environment matrix");
+        assert_eq!(
+            error(r"\begin{matrix} a \end{pmatrix}"),
+            "\\begin{matrix} ended by \\end{pmatrix}"
+        );
+        assert_eq!(error(r"\begin{tabular}"), "Unknown environment tabular");
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        for source in [
+            "{".repeat(100_000),
+            r"\sqrt".repeat(100_000),
+            r"\frac{".repeat(100_000),
+            "x^{".repeat(100_000),
+        ] {
+            assert_eq!(error(&source), "Formula is nested too deeply");
+        }
+        // Nesting below the limit still parses
+        let source = format!("{}x{}", "{".repeat(50), "}".repeat(50));
+        assert_eq!(parse(&source), Ok(ident("x")));
+    }
+}
@@ -0,0 +1,262 @@
+use super::SymbolClass;
+
+/// Greek letters, lowercase ones are set in italics like other variables
+pub(super) fn greek(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "alpha" => "α",
+        "beta" => "β",
+        "gamma" => "γ",
+        "delta" => "δ",
+        "epsilon" => "ϵ",
+        "varepsilon" => "ε",
+        "zeta" => "ζ",
+        "eta" => "η",
+        "theta" => "θ",
+        "vartheta" => "ϑ",
+        "iota" => "ι",
+        "kappa" => "κ",
+        "lambda" => "λ",
+        "mu" => "μ",
+        "nu" => "ν",
+        "xi" => "ξ",
+        "pi" => "π",
+        "varpi" => "ϖ",
+        "rho" => "ρ",
+        "varrho" => "ϱ",
+        "sigma" => "σ",
+        "varsigma" => "ς",
+        "tau" => "τ",
+        "upsilon" => "υ",
+        "phi" => "ϕ",
+        "varphi" => "φ",
+        "chi" => "χ",
+        "psi" => "ψ",
+        "omega" => "ω",
+        "Gamma" => "Γ",
+        "Delta" => "Δ",
+        "Theta" => "Θ",
+        "Lambda" => "Λ",
+        "Xi" => "Ξ",
+        "Pi" => "Π",
+        "Sigma" => "Σ",
+        "Upsilon" => "Υ",
+        "Phi" => "Φ",
+        "Psi" => "Ψ",
+        "Omega" => "Ω",
+        _ => return None,
+    })
+}
+
+/// Symbol commands with their spacing class
+pub(super) fn symbol(name: &str) -> Option<(&'static str, SymbolClass)> {
+    use SymbolClass::*;
+
+    Some(match name {
+        // Binary operators
+        "pm" => ("±", Binary),
+        "mp" => ("∓", Binary),
+        "times" => ("×", Binary),
+        "div" => ("÷", Binary),
+        "cdot" => ("·", Binary),
+        "ast" => ("∗", Binary),
+        "star" => ("⋆", Binary),
+        "circ" => ("∘", Binary),
+        "bullet" => ("∙", Binary),
+        "oplus" => ("⊕", Binary),
+        "ominus" => ("⊖", Binary),
+        "otimes" => ("⊗", Binary),
+        "cup" => ("∪", Binary),
+        "cap" => ("∩", Binary),
+        "setminus" => ("∖", Binary),
+        "wedge" | "land" => ("∧", Binary),
+        "vee" | "lor" => ("∨", Binary),
+        // Relations
+        "leq" | "le" => ("≤", Relation),
+        "geq" | "ge" => ("≥", Relation),
+        "neq" | "ne" => ("≠", Relation),
+        "approx" => ("≈", Relation),
+        "equiv" => ("≡", Relation),
+        "sim" => ("∼", Relation),
+        "simeq" => ("≃", Relation),
+        "cong" => ("≅", Relation),
+        "propto" => ("∝", Relation),
+        "ll" => ("≪", Relation),
+        "gg" => ("≫", Relation),
+        "in" => ("∈", Relation),
+        "notin" => ("∉", Relation),
+        "ni" => ("∋", Relation),
+        "subset" => ("⊂", Relation),
+        "supset" => ("⊃", Relation),
+        "subseteq" => ("⊆", Relation),
+        "supseteq" => ("⊇", Relation),
+        "perp" => ("⊥", Relation),
+        "parallel" => ("∥", Relation),
+        "mid" => ("∣", Relation),
+        "to" | "rightarrow" => ("→", Relation),
+        "leftarrow" | "gets" => ("←", Relation),
+        "leftrightarrow" => ("↔", Relation),
+        "Rightarrow" | "implies" => ("⇒", Relation),
+        "Leftarrow" => ("⇐", Relation),
+        "Leftrightarrow" | "iff" => ("⇔", Relation),
+        "mapsto" => ("↦", Relation),
+        "uparrow" => ("↑", Relation),
+        "downarrow" => ("↓", Relation),
+        // Ordinary symbols
+        "infty" => ("∞", Ordinary),
+        "partial" => ("∂", Ordinary),
+        "nabla" => ("∇", Ordinary),
+        "forall" => ("∀", Ordinary),
+        "exists" => ("∃", Ordinary),
+        "nexists" => ("∄", Ordinary),
+        "emptyset" | "varnothing" => ("∅", Ordinary),
+        "neg" | "lnot" => ("¬", Ordinary),
+        "angle" => ("∠", Ordinary),
+        "hbar" => ("ℏ", Ordinary),
+        "ell" => ("ℓ", Ordinary),
+        "Re" => ("ℜ", Ordinary),
+        "Im" => ("ℑ", Ordinary),
+        "aleph" => ("ℵ", Ordinary),
+        "prime" => ("′", Ordinary),
+        "degree" => ("°", Ordinary),
+        "ldots" | "dots" => ("…", Ordinary),
+        "cdots" => ("⋯", Ordinary),
+        "vdots" => ("⋮", Ordinary),
+        "ddots" => ("⋱", Ordinary),
+        "%" => ("%", Ordinary),
+        "$" => ("$", Ordinary),
+        "#" => ("#", Ordinary),
+        "&" => ("&", Ordinary),
+        "_" => ("_", Ordinary),
+        "|" | "Vert" => ("‖", Ordinary),
+        "vert" => ("|", Ordinary),
+        // Delimiters
+        "{" | "lbrace" => ("{", Open),
+        "}" | "rbrace" => ("}", Close),
+        "langle" => ("⟨", Open),
+        "rangle" => ("⟩", Close),
+        "lfloor" => ("⌊", Open),
+        "rfloor" => ("⌋", Close),
+        "lceil" => ("⌈", Open),
+        "rceil" => ("⌉", Close),
+        _ => return None,
+    })
+}
+
+/// Class of a plain character in math mode
+pub(super) fn char_class(c: char) -> SymbolClass {
+    match c {
+        '+' | '-' | '*' | '/' => {
+            if c == '/' {
+                SymbolClass::Ordinary
+            } else {
+                SymbolClass::Binary
+            }
+        }
+        '=' | '<' | '>' | ':' => SymbolClass::Relation,
+        ',' | ';' => SymbolClass::Punctuation,
+        '(' | '[' => SymbolClass::Open,
+        ')' | ']' => SymbolClass::Close,
+        _ => SymbolClass::Ordinary,
+    }
+}
+
+/// Large operators: (symbol, takes limits in display style)
+pub(super) fn large_operator(name: &str) -> Option<(&'static str, bool)> {
+    Some(match name {
+        "sum" => ("∑", true),
+        "prod" => ("∏", true),
+        "coprod" => ("∐", true),
+        "bigcup" => ("⋃", true),
+        "bigcap" => ("⋂", true),
+        "bigoplus" => ("⨁", true),
+        "bigotimes" => ("⨂", true),
+        "int" => ("∫", false),
+        "iint" => ("∬", false),
+        "iiint" => ("∭", false),
+        "oint" => ("∮", false),
+        _ => return None,
+    })
+}
+
+/// Named functions set upright: (takes limits in display style)
+pub(super) fn function(name: &str) -> Option<bool> {
+    Some(match name {
+        "lim" | "liminf" | "limsup" | "max" | "min" | "sup" | "inf" | "det" | "gcd" | "Pr"
+        | "argmax" | "argmin" => true,
+        "sin" | "cos" | "tan" | "cot" | "sec" | "csc" | "arcsin" | "arccos" | "arctan" | "sinh"
+        | "cosh" | "tanh" | "coth" | "log" | "ln" | "lg" | "exp" | "arg" | "deg" | "dim"
+        | "hom" | "ker" | "mod" => false,
+        _ => return None,
+    })
+}
+
+/// Horizontal spacing commands in em
+pub(super) fn space(name: &str) -> Option<f32> {
+    Some(match name {
+        "," | "thinspace" => 3.0 / 18.0,
+        ":" | ">" | "medspace" => 4.0 / 18.0,
+        ";" | "thickspace" => 5.0 / 18.0,
+        "!" | "negthinspace" => -3.0 / 18.0,
+        " " => 0.25,
+        "quad" => 1.0,
+        "qquad" => 2.0,
+        _ => return None,
+    })
+}
+
+/// Accents placed above their argument
+pub(super) fn accent(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "hat" | "widehat" => "ˆ",
+        "tilde" | "widetilde" => "˜",
+        "bar" => "¯",
+        "vec" => "→",
+        "dot" => "˙",
+        "ddot" => "¨",
+        "acute" => "´",
+        "grave" => "`",
+        "check" => "ˇ",
+        "breve" => "˘",
+        _ => return None,
+    })
+}
+
+/// Delimiter accepted after `\left` and `\right`, `.` stands for none
+pub(super) fn delimiter(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "." => "",
+        "(" => "(",
+        ")" => ")",
+        "[" => "[",
+        "]" => "]",
+        "|" | "vert" => "|",
+        "\\|" | "Vert" => "‖",
+        "\\{" | "lbrace" => "{",
+        "\\}" | "rbrace" => "}",
+        "langle" => "⟨",
+        "rangle" => "⟩",
+        "lfloor" => "⌊",
+        "rfloor" => "⌋",
+        "lceil" => "⌈",
+        "rceil" => "⌉",
+        "/" => "/",
+        _ => return None,
+    })
+}
+
+/// Map a letter to its double-struck form for `\mathbb`
+pub(super) fn double_struck(c: char) -> char {
+    match c {
+        'C' => 'ℂ',
+        'H' => 'ℍ',
+        'N' => 'ℕ',
+        'P' => 'ℙ',
+        'Q' => 'ℚ',
+        'R' => 'ℝ',
+        'Z' => 'ℤ',
+        'A'..='Z' => char::from_u32(0x1D538 + (c as u32 - 'A' as u32)).unwrap_or(c),
+        'a'..='z' => char::from_u32(0x1D552 + (c as u32 - 'a' as u32)).unwrap_or(c),
+        '0'..='9' => char::from_u32(0x1D7D8 + (c as u32 - '0' as u32)).unwrap_or(c),
+        _ => c,
+    }
+}
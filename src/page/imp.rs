@@ -0,0 +1,85 @@
+use std::cell::{Cell, RefCell};
+use std::path::PathBuf;
+
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+use adw::{gio, glib};
+use potato_md::MdViewer;
+
+use crate::settings;
+
+#[derive(Default, glib::Properties)]
+#[properties(wrapper_type = super::DocumentPage)]
+pub struct DocumentPage {
+    /// Title of the open document, from its front matter or file name
+    #[property(get)]
+    pub(super) title: RefCell<String>,
+    /// Whether there is a previously opened document to go back to
+    #[property(get)]
+    pub(super) can_go_back: Cell<bool>,
+    /// Whether there is a document to go forward to after going back
+    #[property(get)]
+    pub(super) can_go_forward: Cell<bool>,
+    pub(super) md_viewer: MdViewer,
+    /// File the open document was read from and is saved to
+    pub(super) file: RefCell<Option<gio::File>>,
+    /// Documents opened before the current one, most recent last
+    pub(super) back: RefCell<Vec<gio::File>>,
+    /// Documents gone back from, most recent last
+    pub(super) forward: RefCell<Vec<gio::File>>,
+    /// Markdown files of the folder open in the window, that wiki-links name
+    pub(super) folder_files: RefCell<Vec<PathBuf>>,
+    /// Watches the open file for changes made by other programs
+    pub(super) monitor: RefCell<Option<gio::FileMonitor>>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for DocumentPage {
+    const NAME: &'static str = "PotatoDocumentPage";
+    type Type = super::DocumentPage;
+    type ParentType = adw::Bin;
+}
+
+#[glib::derived_properties]
+impl ObjectImpl for DocumentPage {
+    fn signals() -> &'static [glib::subclass::Signal] {
+        use std::sync::OnceLock;
+        static SIGNALS: OnceLock<Vec<glib::subclass::Signal>> = OnceLock::new();
+        SIGNALS.get_or_init(|| {
+            vec![
+                // Signal emitted when a linked document is to be opened in a new tab:
+                // (file: gio::File)
+                glib::subclass::Signal::builder("open-in-new-tab")
+                    .param_types([gio::File::static_type()])
+                    .build(),
+            ]
+        })
+    }
+
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let obj = self.obj();
+        obj.set_child(Some(&self.md_viewer));
+        settings::bind_viewer(&self.md_viewer);
+
+        self.md_viewer.connect_open_link(glib_macros::clone!(
+            #[weak(rename_to = page)]
+            self,
+            #[upgrade_or]
+            false,
+            move |_, url, new_tab| page.obj().follow_link(url, new_tab)
+        ));
+        self.md_viewer
+            .connect_resolve_wiki_link(glib_macros::clone!(
+                #[weak(rename_to = page)]
+                self,
+                #[upgrade_or]
+                true,
+                move |_, target| page.obj().wiki_link_file(target).is_some()
+            ));
+    }
+}
+
+impl WidgetImpl for DocumentPage {}
+impl BinImpl for DocumentPage {}
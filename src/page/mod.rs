@@ -0,0 +1,335 @@
+mod imp;
+
+use std::path::{Path, PathBuf};
+
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+use adw::{gio, glib, gtk};
+use potato_md::i18n::gettext;
+use potato_md::{MdViewer, markdown};
+
+use crate::{settings, sidebar, wiki};
+
+glib::wrapper! {
+    /// Tab content: a document in an `MdViewer` with the file it came from and the
+    /// documents opened before it through links
+    pub struct DocumentPage(ObjectSubclass<imp::DocumentPage>)
+        @extends adw::Bin, adw::gtk::Widget,
+        @implements adw::gtk::Accessible, adw::gtk::Buildable, adw::gtk::ConstraintTarget;
+}
+
+impl DocumentPage {
+    pub fn new() -> Self {
+        glib::Object::builder().build()
+    }
+
+    pub fn md_viewer(&self) -> MdViewer {
+        self.imp().md_viewer.clone()
+    }
+
+    /// File the open document was read from and is saved to
+    pub fn file(&self) -> Option<gio::File> {
+        self.imp().file.borrow().clone()
+    }
+
+    /// Show a markdown file, replacing the current document without recording it in
+    /// the history
+    pub fn open_file(&self, file: &gio::File) -> Result<(), glib::Error> {
+        let imp = self.imp();
+        // Wiki-links are resolved next to the file as it is rendered
+        let previous = imp.file.replace(Some(file.clone()));
+        if let Err(err) = imp.md_viewer.load_file(file) {
+            imp.file.replace(previous);
+            return Err(err);
+        }
+        self.update_title(file);
+        self.watch_file(file);
+        Ok(())
+    }
+
+    /// Open a linked document, so that going back returns to the current one
+    pub fn navigate(&self, file: &gio::File) -> Result<(), glib::Error> {
+        let previous = self.file();
+        self.open_file(file)?;
+
+        let imp = self.imp();
+        imp.back.borrow_mut().extend(previous);
+        imp.forward.borrow_mut().clear();
+        self.update_history();
+        Ok(())
+    }
+
+    /// Return to the document opened before the current one
+    pub fn go_back(&self) {
+        self.leave_document(|page| page.step_history(true));
+    }
+
+    /// Return to the document last gone back from
+    pub fn go_forward(&self) {
+        self.leave_document(|page| page.step_history(false));
+    }
+
+    /// Ask whether to save the unsaved edits before they are lost, for the reason told
+    /// by `detail`. Returns whether to go on, right away when there are no edits.
+    pub async fn confirm_discard(&self, detail: &str) -> bool {
+        if !self.imp().md_viewer.modified() {
+            return true;
+        }
+
+        let dialog = gtk::AlertDialog::builder()
+            // Translators: {title} is the title of the document
+            .message(gettext("Save changes to “{title}”?").replace("{title}", &self.title()))
+            .detail(detail)
+            .buttons([gettext("Cancel"), gettext("Discard"), gettext("Save")])
+            .cancel_button(0)
+            .default_button(2)
+            .modal(true)
+            .build();
+        let window = self.root().and_downcast::<gtk::Window>();
+        match dialog.choose_future(window.as_ref()).await {
+            Ok(1) => true,
+            Ok(2) => {
+                self.save();
+                // Saving failed if the edits are still there
+                !self.imp().md_viewer.modified()
+            }
+            _ => false,
+        }
+    }
+
+    /// Write the edited document back to its file
+    pub fn save(&self) {
+        let imp = self.imp();
+        let Some(file) = self.file() else {
+            return;
+        };
+        if !imp.md_viewer.modified() {
+            return;
+        }
+
+        let contents = imp.md_viewer.to_markdown();
+        match file.replace_contents(
+            contents.as_bytes(),
+            None,
+            false,
+            gio::FileCreateFlags::NONE,
+            gio::Cancellable::NONE,
+        ) {
+            Ok(_) => imp.md_viewer.set_modified(false),
+            Err(err) => glib::g_warning!("potato-md", "Failed to save {}: {err}", file.uri()),
+        }
+    }
+
+    /// Resolve wiki-links against `files`, the markdown files of the open folder. Links
+    /// that point somewhere else with the new files are shown again.
+    pub fn set_folder_files(&self, files: Vec<PathBuf>) {
+        let imp = self.imp();
+        if *imp.folder_files.borrow() == files {
+            return;
+        }
+        imp.folder_files.replace(files);
+        imp.md_viewer.update_wiki_links();
+    }
+
+    /// Connect to the signal emitted when a linked document is to be opened in a new tab
+    pub fn connect_open_in_new_tab<F: Fn(&Self, &gio::File) + 'static>(
+        &self,
+        f: F,
+    ) -> glib::SignalHandlerId {
+        self.connect_local("open-in-new-tab", false, move |args| {
+            let page = args[0].get::<Self>().ok()?;
+            let file = args[1].get::<gio::File>().ok()?;
+            f(&page, &file);
+            None
+        })
+    }
+
+    /// Replace the document with `replace`, once the user chose what to do with its
+    /// unsaved edits
+    fn leave_document(&self, replace: impl FnOnce(&Self) + 'static) {
+        if !self.imp().md_viewer.modified() {
+            replace(self);
+            return;
+        }
+        glib::spawn_future_local(glib_macros::clone!(
+            #[weak(rename_to = page)]
+            self,
+            async move {
+                let detail = gettext("Unsaved edits are lost when another document is opened.");
+                if page.confirm_discard(&detail).await {
+                    replace(&page);
+                }
+            }
+        ));
+    }
+
+    fn step_history(&self, back: bool) {
+        let imp = self.imp();
+        let (from, to) = if back {
+            (&imp.back, &imp.forward)
+        } else {
+            (&imp.forward, &imp.back)
+        };
+        let Some(file) = from.borrow_mut().pop() else {
+            return;
+        };
+
+        let current = self.file();
+        match self.open_file(&file) {
+            Ok(()) => to.borrow_mut().extend(current),
+            Err(err) => glib::g_warning!("potato-md", "Failed to open {}: {err}", file.uri()),
+        }
+        self.update_history();
+    }
+
+    fn update_history(&self) {
+        let imp = self.imp();
+        let can_go_back = !imp.back.borrow().is_empty();
+        if imp.can_go_back.replace(can_go_back) != can_go_back {
+            self.notify_can_go_back();
+        }
+        let can_go_forward = !imp.forward.borrow().is_empty();
+        if imp.can_go_forward.replace(can_go_forward) != can_go_forward {
+            self.notify_can_go_forward();
+        }
+    }
+
+    /// Take the title from the front matter of the document, or the name of `file`
+    fn update_title(&self, file: &gio::File) {
+        let imp = self.imp();
+        let title = imp
+            .md_viewer
+            .title()
+            .or_else(|| {
+                file.basename()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+            .unwrap_or_default();
+        if *imp.title.borrow() != title {
+            imp.title.replace(title);
+            self.notify_title();
+        }
+    }
+
+    /// Reload the document when its file is written by another program, if the
+    /// `live-reload` setting is on
+    fn watch_file(&self, file: &gio::File) {
+        let monitor = file
+            .monitor_file(gio::FileMonitorFlags::NONE, gio::Cancellable::NONE)
+            .inspect_err(|err| {
+                glib::g_warning!("potato-md", "Failed to watch {}: {err}", file.uri())
+            })
+            .ok();
+        if let Some(monitor) = &monitor {
+            monitor.connect_changed(glib_macros::clone!(
+                #[weak(rename_to = page)]
+                self,
+                move |_, _, _, event| {
+                    if matches!(
+                        event,
+                        gio::FileMonitorEvent::ChangesDoneHint | gio::FileMonitorEvent::Created
+                    ) && settings::settings().boolean("live-reload")
+                    {
+                        page.reload();
+                    }
+                }
+            ));
+        }
+        self.imp().monitor.replace(monitor);
+    }
+
+    /// Show the file again as it is on disk, keeping the scroll position. Documents
+    /// with unsaved edits are left alone, as are files that match the document, such
+    /// as right after saving.
+    fn reload(&self) {
+        let imp = self.imp();
+        let Some(file) = self.file() else {
+            return;
+        };
+        if imp.md_viewer.modified() {
+            return;
+        }
+        let contents = match file.load_contents(gio::Cancellable::NONE) {
+            Ok((contents, _)) => String::from_utf8_lossy(&contents).into_owned(),
+            Err(err) => {
+                glib::g_warning!("potato-md", "Failed to reload {}: {err}", file.uri());
+                return;
+            }
+        };
+        if contents == imp.md_viewer.to_markdown() {
+            return;
+        }
+
+        let offset = imp.md_viewer.scroll_offset();
+        let base_dir = file.parent().and_then(|parent| parent.path());
+        imp.md_viewer.set_markdown(&contents, base_dir.as_deref());
+        imp.md_viewer.set_scroll_offset(offset);
+        self.update_title(&file);
+    }
+
+    /// Open links to markdown files here or in a new tab, others are left to the viewer.
+    /// Broken wiki-links do nothing.
+    fn follow_link(&self, url: &str, new_tab: bool) -> bool {
+        let (file, heading) = match url.strip_prefix(markdown::WIKI_LINK_SCHEME) {
+            Some(target) => match self.wiki_link_file(target) {
+                Some(file) => (file, wiki::split_target(target).1),
+                None => return true,
+            },
+            None => match self.linked_document(url) {
+                Some(file) => (file, None),
+                None => return false,
+            },
+        };
+
+        if new_tab {
+            self.emit_by_name::<()>("open-in-new-tab", &[&file]);
+            return true;
+        }
+        let anchor = heading.map(markdown::slug);
+        self.leave_document(move |page| {
+            if let Err(err) = page.navigate(&file) {
+                glib::g_warning!("potato-md", "Failed to open {}: {err}", file.uri());
+            } else if let Some(anchor) = anchor {
+                page.imp().md_viewer.scroll_to_anchor(&anchor);
+            }
+        });
+        true
+    }
+
+    /// Markdown file a `[[target]]` wiki-link names, looked for in the open folder or
+    /// next to the document
+    pub fn wiki_link_path(&self, target: &str) -> Option<PathBuf> {
+        let dir = self
+            .file()
+            .and_then(|file| file.parent())
+            .and_then(|parent| parent.path());
+        let files = self.imp().folder_files.borrow();
+        wiki::resolve(target, &files, dir.as_deref())
+    }
+
+    fn wiki_link_file(&self, target: &str) -> Option<gio::File> {
+        self.wiki_link_path(target).map(gio::File::for_path)
+    }
+
+    /// Markdown file a link points to, relative links are resolved against the open file
+    fn linked_document(&self, url: &str) -> Option<gio::File> {
+        let local = url.starts_with("file://") || !url.contains("://");
+        let path = url.split(['#', '?']).next().unwrap_or(url);
+        if !local || !sidebar::is_markdown(Path::new(path)) {
+            return None;
+        }
+
+        if url.starts_with("file://") {
+            return Some(gio::File::for_uri(path));
+        }
+        let path = glib::Uri::unescape_string(path, None::<&str>)?;
+        let parent = self.file()?.parent()?;
+        Some(parent.resolve_relative_path(path.as_str()))
+    }
+}
+
+impl Default for DocumentPage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,117 @@
+use adw::gtk::pango;
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+use adw::{glib, glib::subclass::InitializingObject, gtk};
+
+use crate::settings;
+
+/// Values of the `color-scheme` setting, in the order of the style row
+const COLOR_SCHEMES: [&str; 3] = ["system", "light", "dark"];
+/// Values of the `open-mode` setting, in the order of the open mode row
+const OPEN_MODES: [&str; 2] = ["view", "edit"];
+
+#[derive(Default, gtk4_macros::CompositeTemplate)]
+#[template(resource = "/com/example/potato-md/ui/preferences.ui")]
+pub struct PreferencesDialog {
+    #[template_child]
+    font_button: TemplateChild<gtk::FontDialogButton>,
+    #[template_child]
+    font_size_row: TemplateChild<adw::SpinRow>,
+    #[template_child]
+    line_height_row: TemplateChild<adw::SpinRow>,
+    #[template_child]
+    code_font_button: TemplateChild<gtk::FontDialogButton>,
+    #[template_child]
+    max_width_row: TemplateChild<adw::SpinRow>,
+    #[template_child]
+    style_row: TemplateChild<adw::ComboRow>,
+    #[template_child]
+    open_mode_row: TemplateChild<adw::ComboRow>,
+    #[template_child]
+    live_reload_row: TemplateChild<adw::SwitchRow>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for PreferencesDialog {
+    const NAME: &'static str = "PotatoPreferencesDialog";
+    type Type = super::PreferencesDialog;
+    type ParentType = adw::PreferencesDialog;
+
+    fn class_init(klass: &mut Self::Class) {
+        klass.bind_template();
+    }
+
+    fn instance_init(obj: &InitializingObject<Self>) {
+        obj.init_template();
+    }
+}
+
+impl ObjectImpl for PreferencesDialog {
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let settings = settings::settings();
+        // An empty font setting stands for the font of the desktop
+        bind_font(&settings, "font-family", &self.font_button, "Sans");
+        bind_font(&settings, "code-font", &self.code_font_button, "Monospace");
+        settings
+            .bind("font-size", &*self.font_size_row, "value")
+            .build();
+        settings
+            .bind("line-height", &*self.line_height_row, "value")
+            .build();
+        settings
+            .bind("max-content-width", &*self.max_width_row, "value")
+            .build();
+        bind_choice(&settings, "color-scheme", &self.style_row, &COLOR_SCHEMES);
+        bind_choice(&settings, "open-mode", &self.open_mode_row, &OPEN_MODES);
+        settings
+            .bind("live-reload", &*self.live_reload_row, "active")
+            .build();
+    }
+}
+
+impl WidgetImpl for PreferencesDialog {}
+impl AdwDialogImpl for PreferencesDialog {}
+impl PreferencesDialogImpl for PreferencesDialog {}
+
+/// Bind a font family setting to the font of `button`, showing `fallback` when it is empty
+fn bind_font(
+    settings: &adw::gio::Settings,
+    key: &str,
+    button: &gtk::FontDialogButton,
+    fallback: &'static str,
+) {
+    settings
+        .bind(key, button, "font-desc")
+        .mapping(move |variant, _| {
+            let family = variant.str().filter(|family| !family.is_empty());
+            Some(pango::FontDescription::from_string(family.unwrap_or(fallback)).to_value())
+        })
+        .set_mapping(|value, _| {
+            let font = value.get::<Option<pango::FontDescription>>().ok()??;
+            Some(font.family()?.to_variant())
+        })
+        .build();
+}
+
+/// Bind a setting with a choice of `values` to the selected item of `row`
+fn bind_choice(
+    settings: &adw::gio::Settings,
+    key: &str,
+    row: &adw::ComboRow,
+    values: &'static [&'static str],
+) {
+    settings
+        .bind(key, row, "selected")
+        .mapping(|variant, _| {
+            let value = variant.str()?;
+            let index = values.iter().position(|v| *v == value)?;
+            Some((index as u32).to_value())
+        })
+        .set_mapping(|value, _| {
+            let index = value.get::<u32>().ok()?;
+            Some(values.get(index as usize)?.to_variant())
+        })
+        .build();
+}
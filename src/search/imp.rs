@@ -0,0 +1,246 @@
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+use adw::{gio, glib, gtk};
+use potato_md::i18n::{gettext, ngettext};
+
+use super::index::{Hit, Index};
+
+/// Matches shown at most, the rest of a broad search is left out
+const MAX_HITS: usize = 500;
+
+#[derive(Default)]
+pub struct SearchPanel {
+    search_entry: gtk::SearchEntry,
+    status: gtk::Label,
+    list_box: gtk::ListBox,
+    /// Folder the result paths are shown relative to
+    pub(super) folder: RefCell<Option<PathBuf>>,
+    /// Shared with the worker threads that update and search it
+    index: Arc<Mutex<Index>>,
+    /// Matches shown in the list, in the order of its rows
+    hits: RefCell<Vec<Hit>>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for SearchPanel {
+    const NAME: &'static str = "PotatoSearchPanel";
+    type Type = super::SearchPanel;
+    type ParentType = gtk::Box;
+}
+
+impl ObjectImpl for SearchPanel {
+    fn signals() -> &'static [glib::subclass::Signal] {
+        use std::sync::OnceLock;
+        static SIGNALS: OnceLock<Vec<glib::subclass::Signal>> = OnceLock::new();
+        SIGNALS.get_or_init(|| {
+            vec![
+                // Signal emitted when a match is clicked:
+                // (file: gio::File, query: String, occurrence: u32)
+                glib::subclass::Signal::builder("match-activated")
+                    .param_types([
+                        gio::File::static_type(),
+                        String::static_type(),
+                        u32::static_type(),
+                    ])
+                    .build(),
+                // Signal emitted when files read again are in the index
+                glib::subclass::Signal::builder("index-updated").build(),
+            ]
+        })
+    }
+
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let obj = self.obj();
+        obj.set_orientation(gtk::Orientation::Vertical);
+
+        self.search_entry
+            .set_placeholder_text(Some(&gettext("Search All Files")));
+        self.search_entry.set_margin_top(6);
+        self.search_entry.set_margin_start(6);
+        self.search_entry.set_margin_end(6);
+        self.search_entry
+            .connect_search_changed(glib_macros::clone!(
+                #[weak(rename_to = panel)]
+                self,
+                move |_| panel.search()
+            ));
+        obj.append(&self.search_entry);
+
+        self.status.add_css_class("dim-label");
+        self.status.add_css_class("caption");
+        self.status.set_xalign(0.0);
+        self.status.set_margin_top(6);
+        self.status.set_margin_start(12);
+        self.status.set_margin_end(12);
+        obj.append(&self.status);
+
+        self.list_box.set_selection_mode(gtk::SelectionMode::None);
+        self.list_box.add_css_class("navigation-sidebar");
+        self.list_box.connect_row_activated(glib_macros::clone!(
+            #[weak(rename_to = panel)]
+            self,
+            move |_, row| panel.activate(row.index())
+        ));
+
+        let scrolled = gtk::ScrolledWindow::builder()
+            .hscrollbar_policy(gtk::PolicyType::Never)
+            .vexpand(true)
+            .child(&self.list_box)
+            .build();
+        obj.append(&scrolled);
+    }
+}
+
+impl WidgetImpl for SearchPanel {}
+impl BoxImpl for SearchPanel {}
+
+impl SearchPanel {
+    /// Index `files` on a worker thread, then search again to show their current text
+    pub(super) fn update_files(&self, files: Vec<PathBuf>) {
+        let index = self.index.clone();
+        glib::spawn_future_local(glib_macros::clone!(
+            #[weak(rename_to = panel)]
+            self,
+            async move {
+                let updated = gio::spawn_blocking(move || {
+                    index.lock().ok().map(|mut index| index.update(&files))
+                })
+                .await;
+                if matches!(updated, Ok(Some(()))) {
+                    panel.search();
+                    panel.obj().emit_by_name::<()>("index-updated", &[]);
+                }
+            }
+        ));
+    }
+
+    /// Indexed documents that link to `path`, looked up on a worker thread
+    pub(super) async fn backlinks(&self, path: PathBuf) -> Vec<PathBuf> {
+        let index = self.index.clone();
+        gio::spawn_blocking(move || index.lock().ok().map(|index| index.backlinks(&path)))
+            .await
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+    }
+
+    pub(super) fn focus_entry(&self) {
+        self.search_entry.grab_focus();
+        self.search_entry.select_region(0, -1);
+    }
+
+    /// Search the index for the entry text on a worker thread and list the matches
+    fn search(&self) {
+        let query = self.search_entry.text().to_string();
+        if query.is_empty() {
+            self.show_hits(Vec::new());
+            self.status.set_label("");
+            return;
+        }
+
+        let index = self.index.clone();
+        glib::spawn_future_local(glib_macros::clone!(
+            #[weak(rename_to = panel)]
+            self,
+            async move {
+                let search_query = query.clone();
+                let Ok(Some(hits)) = gio::spawn_blocking(move || {
+                    index
+                        .lock()
+                        .ok()
+                        .map(|index| index.search(&search_query, MAX_HITS))
+                })
+                .await
+                else {
+                    return;
+                };
+                // Results of a query typed over are dropped
+                if panel.search_entry.text() != query {
+                    return;
+                }
+
+                let count = hits.len();
+                let status = match count {
+                    0 => gettext("No matches"),
+                    MAX_HITS => {
+                        // Translators: {count} is how many of the matches in all files are shown
+                        ngettext("First {count} match", "First {count} matches", count as u32)
+                    }
+                    _ => ngettext("{count} match", "{count} matches", count as u32),
+                };
+                panel
+                    .status
+                    .set_label(&status.replace("{count}", &count.to_string()));
+                panel.show_hits(hits);
+            }
+        ));
+    }
+
+    fn show_hits(&self, hits: Vec<Hit>) {
+        self.list_box.remove_all();
+        let folder = self.folder.borrow().clone();
+        for hit in &hits {
+            let path = folder
+                .as_deref()
+                .and_then(|folder| hit.path.strip_prefix(folder).ok())
+                .unwrap_or(&hit.path);
+            let mut location = path.display().to_string();
+            if let Some(heading) = &hit.heading {
+                location.push_str(" › ");
+                location.push_str(heading);
+            }
+
+            let snippet = format!(
+                "{}<b>{}</b>{}",
+                glib::markup_escape_text(&hit.snippet[..hit.range.start]),
+                glib::markup_escape_text(&hit.snippet[hit.range.clone()]),
+                glib::markup_escape_text(&hit.snippet[hit.range.end..]),
+            );
+
+            let content = gtk::Box::new(gtk::Orientation::Vertical, 2);
+            content.append(
+                &gtk::Label::builder()
+                    .label(&location)
+                    .xalign(0.0)
+                    .ellipsize(gtk::pango::EllipsizeMode::Middle)
+                    .css_classes(["caption", "dim-label"])
+                    .build(),
+            );
+            content.append(
+                &gtk::Label::builder()
+                    .label(&snippet)
+                    .use_markup(true)
+                    .xalign(0.0)
+                    .wrap(true)
+                    .wrap_mode(gtk::pango::WrapMode::WordChar)
+                    .lines(2)
+                    .ellipsize(gtk::pango::EllipsizeMode::End)
+                    .build(),
+            );
+            self.list_box
+                .append(&gtk::ListBoxRow::builder().child(&content).build());
+        }
+        self.hits.replace(hits);
+    }
+
+    fn activate(&self, row: i32) {
+        let Some(hit) = usize::try_from(row)
+            .ok()
+            .and_then(|row| self.hits.borrow().get(row).cloned())
+        else {
+            return;
+        };
+        let file = gio::File::for_path(&hit.path);
+        let query = self.search_entry.text().to_string();
+        self.obj().emit_by_name::<()>(
+            "match-activated",
+            &[&file, &query, &(hit.occurrence as u32)],
+        );
+    }
+}
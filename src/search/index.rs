@@ -0,0 +1,373 @@
+//! Text and links of the markdown files of a folder, for the folder search and
+//! backlinks
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use adw::glib;
+use potato_md::markdown::{self, Block, Inline};
+
+use crate::{sidebar, wiki};
+
+/// Characters of context shown before a match
+const SNIPPET_BEFORE: usize = 30;
+/// Characters of context shown after a match
+const SNIPPET_AFTER: usize = 70;
+
+/// Plain text and links of every indexed document, read again when the file is modified
+#[derive(Default)]
+pub struct Index {
+    documents: HashMap<PathBuf, Indexed>,
+}
+
+struct Indexed {
+    modified: Option<SystemTime>,
+    /// Searchable text of the rows a viewer shows, with the heading each row is under
+    rows: Vec<(Option<String>, String)>,
+    /// Targets of the wiki-links, resolved when asked for backlinks as files come and go
+    wiki_links: Vec<String>,
+    /// Local files that plain links point to
+    linked_files: Vec<PathBuf>,
+}
+
+/// A match of the query in a document
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub path: PathBuf,
+    /// Heading the match is under, or the heading the match is in
+    pub heading: Option<String>,
+    /// Line of the match, shortened around it
+    pub snippet: String,
+    /// Byte range of the match in the snippet
+    pub range: Range<usize>,
+    /// Number of the match in the document, counting from 0
+    pub occurrence: usize,
+}
+
+impl Index {
+    /// Index exactly `files`, only reading those that are new or modified since they
+    /// were last indexed. Blocks on the file system, so it is meant for a worker thread.
+    pub fn update(&mut self, files: &[PathBuf]) {
+        let listed: HashSet<&PathBuf> = files.iter().collect();
+        self.documents.retain(|path, _| listed.contains(path));
+
+        for path in files {
+            let modified = std::fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            let current = self
+                .documents
+                .get(path)
+                .is_some_and(|indexed| modified.is_some() && indexed.modified == modified);
+            if current {
+                continue;
+            }
+
+            match std::fs::read_to_string(path) {
+                Ok(source) => {
+                    let document = markdown::parse(&source);
+                    let mut indexed = Indexed {
+                        modified,
+                        rows: rows(&document),
+                        wiki_links: Vec::new(),
+                        linked_files: Vec::new(),
+                    };
+                    let blocks = document
+                        .footnotes
+                        .iter()
+                        .flat_map(|footnote| &footnote.blocks);
+                    for block in document.blocks.iter().chain(blocks) {
+                        indexed.add_block_links(block, path.parent());
+                    }
+                    self.documents.insert(path.clone(), indexed);
+                }
+                Err(_) => {
+                    self.documents.remove(path);
+                }
+            }
+        }
+    }
+
+    /// Matches of `query` in every document, ordered by path and position, stopping
+    /// after `limit` matches
+    pub fn search(&self, query: &str, limit: usize) -> Vec<Hit> {
+        let mut paths: Vec<&PathBuf> = self.documents.keys().collect();
+        paths.sort();
+
+        let mut hits = Vec::new();
+        for path in paths {
+            let mut occurrence = 0;
+            for (heading, text) in &self.documents[path].rows {
+                for range in markdown::find_matches(text, query) {
+                    if hits.len() == limit {
+                        return hits;
+                    }
+                    let (snippet, range) = snippet(text, range);
+                    hits.push(Hit {
+                        path: path.clone(),
+                        heading: heading.clone(),
+                        snippet,
+                        range,
+                        occurrence,
+                    });
+                    occurrence += 1;
+                }
+            }
+        }
+        hits
+    }
+
+    /// Documents with a link to `path`, sorted
+    pub fn backlinks(&self, path: &Path) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self.documents.keys().cloned().collect();
+        files.sort();
+        files
+            .iter()
+            .filter(|file| file.as_path() != path)
+            .filter(|file| {
+                let indexed = &self.documents[*file];
+                indexed.linked_files.iter().any(|linked| linked == path)
+                    || indexed.wiki_links.iter().any(|target| {
+                        wiki::resolve(target, &files, None).is_some_and(|found| found == path)
+                    })
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+impl Indexed {
+    fn add_block_links(&mut self, block: &Block, dir: Option<&Path>) {
+        match block {
+            Block::Heading { content, .. } | Block::Paragraph(content) => {
+                self.add_inline_links(content, dir)
+            }
+            Block::Quote(blocks) => {
+                for block in blocks {
+                    self.add_block_links(block, dir);
+                }
+            }
+            Block::List { items, .. } => {
+                for block in items.iter().flatten() {
+                    self.add_block_links(block, dir);
+                }
+            }
+            Block::Image(_) | Block::Code { .. } | Block::Math(_) | Block::Rule => {}
+        }
+    }
+
+    fn add_inline_links(&mut self, inlines: &[Inline], dir: Option<&Path>) {
+        for inline in inlines {
+            match inline {
+                Inline::WikiLink { target, .. } => self.wiki_links.push(target.clone()),
+                Inline::Link { url, content, .. } => {
+                    self.linked_files.extend(linked_file(url, dir));
+                    self.add_inline_links(content, dir);
+                }
+                Inline::Emphasis(content)
+                | Inline::Strong(content)
+                | Inline::Strikethrough(content) => self.add_inline_links(content, dir),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Markdown file a relative link in a document in `dir` points to
+fn linked_file(url: &str, dir: Option<&Path>) -> Option<PathBuf> {
+    if url.contains("://") || url.starts_with('#') {
+        return None;
+    }
+    let path = url.split(['#', '?']).next().unwrap_or(url);
+    let path = glib::Uri::unescape_string(path, None::<&str>)?;
+    if !sidebar::is_markdown(Path::new(path.as_str())) {
+        return None;
+    }
+
+    // Fold `..` so the path compares equal to the one found by the scan
+    let mut normal = PathBuf::new();
+    for component in dir?.join(path.as_str()).components() {
+        match component {
+            Component::ParentDir => {
+                normal.pop();
+            }
+            Component::CurDir => {}
+            component => normal.push(component),
+        }
+    }
+    Some(normal)
+}
+
+/// Searchable text of the rows of `document`, each with the last heading at or before it
+fn rows(document: &markdown::Document) -> Vec<(Option<String>, String)> {
+    let first_block = usize::from(document.front_matter.is_some());
+    let mut heading = None;
+    document
+        .plain_text_rows()
+        .into_iter()
+        .zip(document.searchable_rows())
+        .enumerate()
+        .map(|(i, (plain_text, text))| {
+            let block = i
+                .checked_sub(first_block)
+                .and_then(|b| document.blocks.get(b));
+            if let Some(Block::Heading { .. }) = block {
+                heading = Some(plain_text);
+            }
+            (heading.clone(), text)
+        })
+        .collect()
+}
+
+/// The line of `text` containing `range`, cut down to some context around it, and
+/// where the match is in the result
+fn snippet(text: &str, range: Range<usize>) -> (String, Range<usize>) {
+    let line_start = text[..range.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[range.end..]
+        .find('\n')
+        .map_or(text.len(), |i| range.end + i);
+
+    let start = text[line_start..range.start]
+        .char_indices()
+        .rev()
+        .nth(SNIPPET_BEFORE - 1)
+        .map_or(line_start, |(i, _)| line_start + i);
+    let end = text[range.end..line_end]
+        .char_indices()
+        .nth(SNIPPET_AFTER)
+        .map_or(line_end, |(i, _)| range.end + i);
+
+    let mut snippet = String::new();
+    if start > line_start {
+        snippet.push('…');
+    }
+    let range = snippet.len() + range.start - start..snippet.len() + range.end - start;
+    snippet.push_str(&text[start..end]);
+    if end < line_end {
+        snippet.push('…');
+    }
+    (snippet, range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Index of documents given by path and source, without touching the file system
+    fn index(documents: &[(&str, &str)]) -> Index {
+        let documents = documents
+            .iter()
+            .map(|(path, source)| {
+                let indexed = Indexed {
+                    modified: None,
+                    rows: rows(&markdown::parse(source)),
+                    wiki_links: Vec::new(),
+                    linked_files: Vec::new(),
+                };
+                (PathBuf::from(path), indexed)
+            })
+            .collect();
+        Index { documents }
+    }
+
+    #[test]
+    fn snippet_cuts_multibyte_context_on_char_boundaries() {
+        let text = format!("{}match{}", "ü".repeat(40), "é".repeat(80));
+        let start = "ü".repeat(40).len();
+        let (snippet, range) = snippet(&text, start..start + 5);
+        assert_eq!(&snippet[range], "match");
+        assert_eq!(
+            snippet,
+            format!(
+                "…{}match{}…",
+                "ü".repeat(SNIPPET_BEFORE),
+                "é".repeat(SNIPPET_AFTER)
+            )
+        );
+    }
+
+    #[test]
+    fn snippet_keeps_to_the_line_of_the_match() {
+        let text = "first line\nmatch here\nends with match";
+        let (snippet_start, range) = snippet(text, 11..16);
+        assert_eq!(snippet_start, "match here");
+        assert_eq!(range, 0..5);
+
+        let (snippet_end, range) = snippet(text, 32..37);
+        assert_eq!(snippet_end, "ends with match");
+        assert_eq!(range, 10..15);
+    }
+
+    #[test]
+    fn linked_file_folds_parent_folders() {
+        let dir = Path::new("/notes/work");
+        assert_eq!(
+            linked_file("../home/a%20b.md#plans", Some(dir)),
+            Some(PathBuf::from("/notes/home/a b.md"))
+        );
+        assert_eq!(
+            linked_file("./sub/../c.md?x=1", Some(dir)),
+            Some(PathBuf::from("/notes/work/c.md"))
+        );
+        assert_eq!(linked_file("https://example.com/a.md", Some(dir)), None);
+        assert_eq!(linked_file("#heading", Some(dir)), None);
+        assert_eq!(linked_file("photo.png", Some(dir)), None);
+        assert_eq!(linked_file("a.md", None), None);
+    }
+
+    #[test]
+    fn occurrences_count_across_rows_of_each_document() {
+        let source = "---\ntitle: Potato notes\n---\n\n# Potato $x$\n\nPotato salad and potato soup.\n\n```\npotato\n```\n\nLast potato.\n";
+        let index = index(&[("/b.md", source), ("/a.md", "A potato.\n")]);
+        let hits = index.search("potato", usize::MAX);
+
+        let found: Vec<(&str, Option<&str>, usize)> = hits
+            .iter()
+            .map(|hit| {
+                (
+                    hit.path.to_str().unwrap(),
+                    hit.heading.as_deref(),
+                    hit.occurrence,
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("/a.md", None, 0),
+                ("/b.md", Some("Potato x"), 0),
+                ("/b.md", Some("Potato x"), 1),
+                ("/b.md", Some("Potato x"), 2),
+                ("/b.md", Some("Potato x"), 3),
+            ]
+        );
+
+        // The viewer finds the row of an occurrence by counting matches in its rows
+        let rows = markdown::parse(source).searchable_rows();
+        for hit in &hits[1..] {
+            let mut seen = 0;
+            let row = rows
+                .iter()
+                .position(|row| {
+                    seen += markdown::find_matches(row, "potato").len();
+                    seen > hit.occurrence
+                })
+                .unwrap();
+            let line = hit.snippet.trim_matches('…');
+            assert!(
+                rows[row].contains(line),
+                "{line:?} is not in {:?}",
+                rows[row]
+            );
+        }
+    }
+
+    #[test]
+    fn search_stops_at_the_limit() {
+        let index = index(&[("/a.md", "potato potato potato\n")]);
+        assert_eq!(index.search("potato", 2).len(), 2);
+        assert!(index.search("", 10).is_empty());
+    }
+}
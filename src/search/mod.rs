@@ -0,0 +1,74 @@
+mod imp;
+mod index;
+
+use std::path::PathBuf;
+
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+use adw::{gio, glib, gtk};
+
+glib::wrapper! {
+    /// Search through the text of all markdown files of a folder. The files are indexed
+    /// in the background, and only files written since are read again on updates. The
+    /// index also knows which files link to which, for backlinks.
+    pub struct SearchPanel(ObjectSubclass<imp::SearchPanel>)
+        @extends gtk::Box, gtk::Widget,
+        @implements gtk::Accessible, gtk::Buildable, gtk::ConstraintTarget, gtk::Orientable;
+}
+
+impl SearchPanel {
+    pub fn new() -> Self {
+        glib::Object::builder().build()
+    }
+
+    /// Search the markdown files `files` of `folder`, replacing those searched before
+    pub fn set_files(&self, folder: &gio::File, files: Vec<PathBuf>) {
+        self.imp().folder.replace(folder.path());
+        self.imp().update_files(files);
+    }
+
+    /// Searched files that link to `file`, with a wiki-link or a relative link
+    pub async fn backlinks(&self, file: &gio::File) -> Vec<PathBuf> {
+        match file.path() {
+            Some(path) => self.imp().backlinks(path).await,
+            None => Vec::new(),
+        }
+    }
+
+    /// Move the keyboard focus to the search entry
+    pub fn focus_entry(&self) {
+        self.imp().focus_entry();
+    }
+
+    /// Connect to the signal emitted when a match is clicked, with the file, the query
+    /// and the number of the match in the file
+    pub fn connect_match_activated<F: Fn(&Self, &gio::File, &str, usize) + 'static>(
+        &self,
+        f: F,
+    ) -> glib::SignalHandlerId {
+        self.connect_local("match-activated", false, move |args| {
+            let panel = args[0].get::<Self>().ok()?;
+            let file = args[1].get::<gio::File>().ok()?;
+            let query = args[2].get::<String>().ok()?;
+            let occurrence = args[3].get::<u32>().ok()?;
+            f(&panel, &file, &query, occurrence as usize);
+            None
+        })
+    }
+
+    /// Connect to the signal emitted when written, added or removed files have been
+    /// indexed again
+    pub fn connect_index_updated<F: Fn(&Self) + 'static>(&self, f: F) -> glib::SignalHandlerId {
+        self.connect_local("index-updated", false, move |args| {
+            let panel = args[0].get::<Self>().ok()?;
+            f(&panel);
+            None
+        })
+    }
+}
+
+impl Default for SearchPanel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
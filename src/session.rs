@@ -0,0 +1,285 @@
+//! Saving the windows and documents open when the app quits and restoring them on
+//! the next launch
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use adw::prelude::*;
+use adw::{gio, glib};
+use potato_md::MdViewer;
+
+use crate::window::PotatoWindow;
+
+/// Size, folder and tabs of a window
+pub struct WindowState {
+    pub width: i32,
+    pub height: i32,
+    pub maximized: bool,
+    pub folder: Option<gio::File>,
+    pub documents: Vec<DocumentState>,
+    /// Position of the selected tab in `documents`
+    pub selected: usize,
+}
+
+/// Document open in a tab and how far it was read
+pub struct DocumentState {
+    pub file: gio::File,
+    pub scroll_offset: f64,
+    /// Anchor of the heading at the top of the viewer
+    pub heading: Option<String>,
+}
+
+impl DocumentState {
+    pub fn new(file: gio::File, viewer: &MdViewer) -> Self {
+        Self {
+            file,
+            scroll_offset: viewer.scroll_offset(),
+            heading: viewer.visible_heading().map(|heading| heading.anchor),
+        }
+    }
+
+    /// Scroll `viewer` back to where the document was. A file written since the session
+    /// was saved may have moved, so it goes to the heading instead when it has one.
+    pub fn restore_position(&self, viewer: &MdViewer, saved: SystemTime) {
+        let written = self
+            .file
+            .path()
+            .and_then(|path| path.metadata().ok())
+            .and_then(|metadata| metadata.modified().ok());
+        match &self.heading {
+            Some(heading) if written.is_some_and(|written| written > saved) => {
+                viewer.scroll_to_anchor(heading);
+            }
+            _ => viewer.set_scroll_offset(self.scroll_offset),
+        }
+    }
+}
+
+thread_local! {
+    /// Last window of the app, kept as it closes since the app quits with it
+    static LAST_CLOSED: RefCell<Option<WindowState>> = const { RefCell::new(None) };
+}
+
+/// File the session is kept in, under the XDG data directory
+fn path() -> PathBuf {
+    glib::user_data_dir().join("potato-md").join("session.ini")
+}
+
+/// Keep the state of `window` as it closes if it is the last window of `app`, for the
+/// session saved on shutdown. Windows closed while others stay open are left out.
+pub fn window_closed(app: &adw::Application, window: &PotatoWindow) {
+    let others = app
+        .windows()
+        .into_iter()
+        .filter_map(|other| other.downcast::<PotatoWindow>().ok())
+        .any(|other| other != *window);
+    let state = (!others).then(|| window.session_state());
+    LAST_CLOSED.replace(state);
+}
+
+/// Write the windows open when the app quit, replacing the previous session. A run
+/// without windows leaves the previous session alone.
+pub fn save(app: &adw::Application) {
+    let states: Vec<WindowState> = LAST_CLOSED
+        .take()
+        .into_iter()
+        .chain(
+            app.windows()
+                .into_iter()
+                .filter_map(|window| window.downcast::<PotatoWindow>().ok())
+                .map(|window| window.session_state()),
+        )
+        .collect();
+    if states.is_empty() {
+        return;
+    }
+    let key_file = glib::KeyFile::new();
+    for (index, state) in states.iter().enumerate() {
+        write_window(&key_file, index, state);
+    }
+
+    let path = path();
+    let saved = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .map_err(|err| err.to_string())
+        .and_then(|()| key_file.save_to_file(&path).map_err(|err| err.to_string()));
+    if let Err(err) = saved {
+        glib::g_warning!("potato-md", "Failed to save the session: {err}");
+    }
+}
+
+/// Open the windows of the last session, returning whether there were any
+pub fn restore(app: &adw::Application) -> bool {
+    let path = path();
+    let key_file = glib::KeyFile::new();
+    // There is no session on the first launch
+    if key_file
+        .load_from_file(&path, glib::KeyFileFlags::NONE)
+        .is_err()
+    {
+        return false;
+    }
+    let saved = path
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .unwrap_or_else(|_| SystemTime::now());
+
+    let mut restored = false;
+    let windows = (0..).map_while(|index| read_window(&key_file, index));
+    for state in windows {
+        let window = PotatoWindow::new(app);
+        window.restore_session_state(&state, saved);
+        window.present();
+        restored = true;
+    }
+    restored
+}
+
+/// Group of a window, with a group per tab named after it
+fn window_group(index: usize) -> String {
+    format!("window {index}")
+}
+
+fn tab_group(window: usize, tab: usize) -> String {
+    format!("window {window} tab {tab}")
+}
+
+fn write_window(key_file: &glib::KeyFile, index: usize, state: &WindowState) {
+    let group = window_group(index);
+    key_file.set_integer(&group, "width", state.width);
+    key_file.set_integer(&group, "height", state.height);
+    key_file.set_boolean(&group, "maximized", state.maximized);
+    if let Some(folder) = &state.folder {
+        key_file.set_string(&group, "folder", &folder.uri());
+    }
+    key_file.set_integer(&group, "selected", state.selected as i32);
+
+    for (tab, document) in state.documents.iter().enumerate() {
+        let group = tab_group(index, tab);
+        key_file.set_string(&group, "file", &document.file.uri());
+        key_file.set_double(&group, "scroll-offset", document.scroll_offset);
+        if let Some(heading) = &document.heading {
+            key_file.set_string(&group, "heading", heading);
+        }
+    }
+}
+
+fn read_window(key_file: &glib::KeyFile, index: usize) -> Option<WindowState> {
+    let group = window_group(index);
+    let documents = (0..)
+        .map(|tab| tab_group(index, tab))
+        .map_while(|group| {
+            Some(DocumentState {
+                file: gio::File::for_uri(&key_file.string(&group, "file").ok()?),
+                scroll_offset: key_file.double(&group, "scroll-offset").unwrap_or_default(),
+                heading: key_file
+                    .string(&group, "heading")
+                    .ok()
+                    .map(|heading| heading.to_string()),
+            })
+        })
+        .collect();
+
+    Some(WindowState {
+        width: key_file.integer(&group, "width").ok()?,
+        height: key_file.integer(&group, "height").ok()?,
+        maximized: key_file.boolean(&group, "maximized").unwrap_or_default(),
+        folder: key_file
+            .string(&group, "folder")
+            .ok()
+            .map(|uri| gio::File::for_uri(&uri)),
+        documents,
+        selected: key_file
+            .integer(&group, "selected")
+            .unwrap_or_default()
+            .max(0) as usize,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_same_window(read: &WindowState, written: &WindowState) {
+        assert_eq!(
+            (read.width, read.height, read.maximized, read.selected),
+            (
+                written.width,
+                written.height,
+                written.maximized,
+                written.selected
+            )
+        );
+        assert_eq!(
+            read.folder.as_ref().map(|folder| folder.uri()),
+            written.folder.as_ref().map(|folder| folder.uri())
+        );
+        assert_eq!(read.documents.len(), written.documents.len());
+        for (read, written) in read.documents.iter().zip(&written.documents) {
+            assert_eq!(read.file.uri(), written.file.uri());
+            assert_eq!(read.scroll_offset, written.scroll_offset);
+            assert_eq!(read.heading, written.heading);
+        }
+    }
+
+    #[test]
+    fn windows_round_trip() {
+        let windows = [
+            WindowState {
+                width: 1200,
+                height: 800,
+                maximized: true,
+                folder: Some(gio::File::for_path("/home/user/notes")),
+                documents: vec![
+                    DocumentState {
+                        file: gio::File::for_path("/home/user/notes/index.md"),
+                        scroll_offset: 420.5,
+                        heading: Some("getting-started".to_string()),
+                    },
+                    DocumentState {
+                        file: gio::File::for_path("/home/user/notes/a file #1.md"),
+                        scroll_offset: 0.0,
+                        heading: None,
+                    },
+                ],
+                selected: 1,
+            },
+            WindowState {
+                width: 640,
+                height: 480,
+                maximized: false,
+                folder: None,
+                documents: Vec::new(),
+                selected: 0,
+            },
+        ];
+
+        let key_file = glib::KeyFile::new();
+        for (index, state) in windows.iter().enumerate() {
+            write_window(&key_file, index, state);
+        }
+        let key_file = {
+            let data = key_file.to_data();
+            let read = glib::KeyFile::new();
+            read.load_from_data(&data, glib::KeyFileFlags::NONE)
+                .unwrap();
+            read
+        };
+
+        let read: Vec<_> = (0..)
+            .map_while(|index| read_window(&key_file, index))
+            .collect();
+        assert_eq!(read.len(), windows.len());
+        for (read, written) in read.iter().zip(&windows) {
+            assert_same_window(read, written);
+        }
+    }
+
+    #[test]
+    fn missing_window_ends_the_session() {
+        let key_file = glib::KeyFile::new();
+        assert!(read_window(&key_file, 0).is_none());
+    }
+}
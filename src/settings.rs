@@ -0,0 +1,98 @@
+//! Preferences stored with GSettings and applied to the app
+
+use std::cell::OnceCell;
+
+use adw::prelude::*;
+use adw::{gdk, gio, gtk};
+use potato_md::MdViewer;
+
+use crate::APP_ID;
+
+thread_local! {
+    static SETTINGS: OnceCell<gio::Settings> = const { OnceCell::new() };
+    static FONT_PROVIDER: gtk::CssProvider = gtk::CssProvider::new();
+}
+
+/// Settings of the app. The schema is looked up where it is installed, or where the
+/// build compiled it when running from the source tree.
+pub fn settings() -> gio::Settings {
+    SETTINGS.with(|settings| {
+        settings
+            .get_or_init(|| {
+                let schema = gio::SettingsSchemaSource::default()
+                    .and_then(|source| source.lookup(APP_ID, true))
+                    .or_else(|| {
+                        gio::SettingsSchemaSource::from_directory(
+                            env!("OUT_DIR"),
+                            gio::SettingsSchemaSource::default().as_ref(),
+                            false,
+                        )
+                        .ok()?
+                        .lookup(APP_ID, false)
+                    })
+                    .expect("The GSettings schema of the app is not installed");
+                gio::Settings::new_full(&schema, gio::SettingsBackend::NONE, None)
+            })
+            .clone()
+    })
+}
+
+/// Follow the color scheme and text font settings, now and when they change
+pub fn apply_style() {
+    let settings = settings();
+    update_color_scheme(&settings);
+    settings.connect_changed(Some("color-scheme"), |settings, _| {
+        update_color_scheme(settings)
+    });
+
+    if let Some(display) = gdk::Display::default() {
+        FONT_PROVIDER.with(|provider| {
+            gtk::style_context_add_provider_for_display(
+                &display,
+                provider,
+                gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
+            );
+        });
+    }
+    update_font(&settings);
+    for key in ["font-family", "font-size"] {
+        settings.connect_changed(Some(key), |settings, _| update_font(settings));
+    }
+}
+
+/// Keep the line height, code font and text width of `viewer` in line with the settings
+pub fn bind_viewer(viewer: &MdViewer) {
+    let settings = settings();
+    for (key, property) in [
+        ("line-height", "line-height"),
+        ("code-font", "code-font"),
+        ("max-content-width", "max-content-width"),
+    ] {
+        settings.bind(key, viewer, property).get().build();
+    }
+}
+
+fn update_color_scheme(settings: &gio::Settings) {
+    let color_scheme = match settings.string("color-scheme").as_str() {
+        "light" => adw::ColorScheme::ForceLight,
+        "dark" => adw::ColorScheme::ForceDark,
+        _ => adw::ColorScheme::Default,
+    };
+    adw::StyleManager::default().set_color_scheme(color_scheme);
+}
+
+/// Set the font of documents with CSS, so headings keep their size relative to it
+fn update_font(settings: &gio::Settings) {
+    let family = settings.string("font-family");
+    let mut css = format!(
+        ".md-viewer .document {{ font-size: {}pt; ",
+        settings.int("font-size")
+    );
+    if !family.is_empty() {
+        let family = family.replace('\\', "\\\\").replace('"', "\\\"");
+        css.push_str(&format!("font-family: \"{family}\"; "));
+    }
+    css.push('}');
+
+    FONT_PROVIDER.with(|provider| provider.load_from_string(&css));
+}
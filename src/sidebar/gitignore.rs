@@ -0,0 +1,196 @@
+//! Matching paths against the patterns of `.gitignore` files
+
+/// Patterns of one `.gitignore` file, matched against paths relative to its folder
+pub struct Gitignore {
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    pattern: Vec<char>,
+    /// Starts with `!`, re-including what earlier rules ignored
+    negated: bool,
+    /// Ends with `/`, only matching folders
+    dir_only: bool,
+    /// Contains a `/`, so it matches the whole relative path rather than any name
+    anchored: bool,
+}
+
+impl Gitignore {
+    pub fn parse(source: &str) -> Self {
+        let rules = source
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim_end();
+                if line.is_empty() || line.starts_with('#') {
+                    return None;
+                }
+
+                let (negated, line) = match line.strip_prefix('!') {
+                    Some(line) => (true, line),
+                    None => (false, line),
+                };
+                let (dir_only, line) = match line.strip_suffix('/') {
+                    Some(line) => (true, line),
+                    None => (false, line),
+                };
+                let anchored = line.contains('/');
+                let line = line.strip_prefix('/').unwrap_or(line);
+                (!line.is_empty()).then(|| Rule {
+                    pattern: line.chars().collect(),
+                    negated,
+                    dir_only,
+                    anchored,
+                })
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// Whether the last matching rule ignores `path`, `None` when no rule matches
+    pub fn matched(&self, path: &str, is_dir: bool) -> Option<bool> {
+        let path: Vec<char> = path.chars().collect();
+        let name_start = path
+            .iter()
+            .rposition(|&c| c == '/')
+            .map_or(0, |slash| slash + 1);
+
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| {
+                (is_dir || !rule.dir_only)
+                    && if rule.anchored {
+                        glob_match(&rule.pattern, &path)
+                    } else {
+                        glob_match(&rule.pattern, &path[name_start..])
+                    }
+            })
+            .map(|rule| !rule.negated)
+    }
+}
+
+/// Match `text` against a glob where `*` and `?` stay within a path component,
+/// `**` crosses components and `[…]` is a character class
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // `**/` also matches no folder at all
+            let after_slash = rest.strip_prefix(&['/']).unwrap_or(rest);
+            glob_match(after_slash, text) || (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        }
+        ['*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != '/')
+            .any(|i| glob_match(rest, &text[i..])),
+        ['?', rest @ ..] => text.first().is_some_and(|&c| c != '/') && glob_match(rest, &text[1..]),
+        ['[', rest @ ..] if rest.iter().skip(1).any(|&c| c == ']') => {
+            let end = 1 + rest.iter().skip(1).position(|&c| c == ']').unwrap_or(0);
+            let (class, rest) = (&rest[..end], &rest[end + 1..]);
+            let Some(&c) = text.first() else {
+                return false;
+            };
+            c != '/' && class_match(class, c) && glob_match(rest, &text[1..])
+        }
+        ['\\', c, rest @ ..] | [c, rest @ ..] => {
+            text.first() == Some(c) && glob_match(rest, &text[1..])
+        }
+    }
+}
+
+/// Whether `c` is in a character class such as `abc`, `a-z` or `!0-9`
+fn class_match(class: &[char], c: char) -> bool {
+    let (negated, class) = match class {
+        ['!' | '^', class @ ..] => (true, class),
+        _ => (false, class),
+    };
+
+    let mut matched = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            matched |= (class[i]..=class[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            matched |= class[i] == c;
+            i += 1;
+        }
+    }
+    matched != negated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignored(source: &str, path: &str, is_dir: bool) -> Option<bool> {
+        Gitignore::parse(source).matched(path, is_dir)
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let gitignore = Gitignore::parse("# build output\n\n   \ntarget\n");
+        assert_eq!(gitignore.rules.len(), 1);
+    }
+
+    #[test]
+    fn negation_re_includes_what_was_ignored() {
+        let source = "*.md\n!README.md\n";
+        assert_eq!(ignored(source, "notes.md", false), Some(true));
+        assert_eq!(ignored(source, "README.md", false), Some(false));
+        assert_eq!(ignored(source, "docs/README.md", false), Some(false));
+        assert_eq!(ignored(source, "notes.txt", false), None);
+    }
+
+    #[test]
+    fn dir_only_patterns_skip_files() {
+        assert_eq!(ignored("build/", "build", true), Some(true));
+        assert_eq!(ignored("build/", "src/build", true), Some(true));
+        assert_eq!(ignored("build/", "build", false), None);
+    }
+
+    #[test]
+    fn patterns_with_a_slash_match_from_the_folder() {
+        assert_eq!(ignored("/todo.md", "todo.md", false), Some(true));
+        assert_eq!(ignored("/todo.md", "notes/todo.md", false), None);
+        assert_eq!(ignored("todo.md", "notes/todo.md", false), Some(true));
+        assert_eq!(ignored("a/b", "a/b", false), Some(true));
+        assert_eq!(ignored("a/b", "x/a/b", false), None);
+    }
+
+    #[test]
+    fn double_star_matches_any_number_of_folders() {
+        let source = "**/drafts\ndocs/**/old.md\n";
+        assert_eq!(ignored(source, "drafts", true), Some(true));
+        assert_eq!(ignored(source, "a/b/drafts", true), Some(true));
+        assert_eq!(ignored(source, "docs/old.md", false), Some(true));
+        assert_eq!(ignored(source, "docs/a/b/old.md", false), Some(true));
+        assert_eq!(ignored(source, "other/old.md", false), None);
+    }
+
+    #[test]
+    fn single_star_stays_within_a_folder() {
+        let source = "docs/*.md\n";
+        assert_eq!(ignored(source, "docs/a.md", false), Some(true));
+        assert_eq!(ignored(source, "docs/sub/a.md", false), None);
+        assert!(glob_match(&['a', '?', 'c'], &['a', 'b', 'c']));
+        assert!(!glob_match(&['a', '?', 'c'], &['a', '/', 'c']));
+    }
+
+    #[test]
+    fn character_classes() {
+        let source = "draft[0-9].md\n[!a]x.md\n";
+        assert_eq!(ignored(source, "draft3.md", false), Some(true));
+        assert_eq!(ignored(source, "drafta.md", false), None);
+        assert_eq!(ignored(source, "bx.md", false), Some(true));
+        assert_eq!(ignored(source, "ax.md", false), None);
+        assert!(class_match(&['a', 'b', 'c'], 'b'));
+        assert!(class_match(&['^', 'a', '-', 'c'], 'd'));
+        assert!(!class_match(&['^', 'a', '-', 'c'], 'b'));
+    }
+
+    #[test]
+    fn escaped_hash_is_a_pattern() {
+        assert_eq!(ignored("\\#notes.md", "#notes.md", false), Some(true));
+        assert_eq!(ignored("#notes.md", "#notes.md", false), None);
+    }
+}
@@ -0,0 +1,377 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+use adw::{gio, glib, gtk};
+use potato_md::i18n::gettext;
+
+use super::scan;
+
+/// Delay before rescanning after a change, so a burst of events causes one scan
+const RESCAN_DELAY: Duration = Duration::from_millis(300);
+
+/// Entries of every folder in the tree: whether it is a file, the name folded for
+/// sorting, and the name
+type Tree = BTreeMap<PathBuf, BTreeSet<(bool, String, String)>>;
+
+#[derive(Default)]
+pub struct FileSidebar {
+    search_entry: gtk::SearchEntry,
+    list_view: gtk::ListView,
+    /// Folder whose markdown files are listed
+    pub(super) folder: RefCell<Option<gio::File>>,
+    /// Markdown files of the folder, relative to it and sorted
+    pub(super) files: RefCell<Vec<PathBuf>>,
+    /// Monitors of the folder and its subfolders, replaced on every scan
+    monitors: RefCell<Vec<gio::FileMonitor>>,
+    rescan_source: RefCell<Option<glib::SourceId>>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for FileSidebar {
+    const NAME: &'static str = "PotatoFileSidebar";
+    type Type = super::FileSidebar;
+    type ParentType = gtk::Box;
+}
+
+impl ObjectImpl for FileSidebar {
+    fn signals() -> &'static [glib::subclass::Signal] {
+        use std::sync::OnceLock;
+        static SIGNALS: OnceLock<Vec<glib::subclass::Signal>> = OnceLock::new();
+        SIGNALS.get_or_init(|| {
+            vec![
+                // Signal emitted when a markdown file is clicked: (file: gio::File)
+                glib::subclass::Signal::builder("file-activated")
+                    .param_types([gio::File::static_type()])
+                    .build(),
+                // Signal emitted after the folder is searched for markdown files again
+                glib::subclass::Signal::builder("files-changed").build(),
+            ]
+        })
+    }
+
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let obj = self.obj();
+        obj.set_orientation(gtk::Orientation::Vertical);
+
+        self.search_entry
+            .set_placeholder_text(Some(&gettext("Filter Files")));
+        self.search_entry.set_margin_top(6);
+        self.search_entry.set_margin_bottom(6);
+        self.search_entry.set_margin_start(6);
+        self.search_entry.set_margin_end(6);
+        self.search_entry
+            .connect_search_changed(glib_macros::clone!(
+                #[weak(rename_to = sidebar)]
+                self,
+                move |_| sidebar.update_model()
+            ));
+        obj.append(&self.search_entry);
+
+        self.list_view.set_factory(Some(&row_factory()));
+        self.list_view.set_single_click_activate(true);
+        self.list_view.add_css_class("navigation-sidebar");
+        self.list_view.connect_activate(glib_macros::clone!(
+            #[weak(rename_to = sidebar)]
+            self,
+            move |_, position| sidebar.activate(position)
+        ));
+
+        let scrolled = gtk::ScrolledWindow::builder()
+            .hscrollbar_policy(gtk::PolicyType::Never)
+            .vexpand(true)
+            .child(&self.list_view)
+            .build();
+        obj.append(&scrolled);
+    }
+
+    fn dispose(&self) {
+        if let Some(source) = self.rescan_source.take() {
+            source.remove();
+        }
+    }
+}
+
+impl WidgetImpl for FileSidebar {}
+impl BoxImpl for FileSidebar {}
+
+impl FileSidebar {
+    /// Search the folder for markdown files on a worker thread, then show them
+    pub(super) fn rescan(&self) {
+        let Some(root) = self
+            .folder
+            .borrow()
+            .as_ref()
+            .and_then(|folder| folder.path())
+        else {
+            return;
+        };
+
+        glib::spawn_future_local(glib_macros::clone!(
+            #[weak(rename_to = sidebar)]
+            self,
+            async move {
+                let scan_root = root.clone();
+                let Ok(scan) = gio::spawn_blocking(move || scan::scan(&scan_root)).await else {
+                    return;
+                };
+                // The folder may have changed while scanning
+                let current = sidebar.folder.borrow().as_ref().and_then(|f| f.path());
+                if current.as_deref() != Some(root.as_path()) {
+                    return;
+                }
+
+                sidebar.watch(&scan.dirs);
+                if *sidebar.files.borrow() != scan.files {
+                    sidebar.files.replace(scan.files);
+                    sidebar.update_model();
+                }
+                sidebar.obj().emit_by_name::<()>("files-changed", &[]);
+            }
+        ));
+    }
+
+    /// Monitor `dirs` for files being added, removed, renamed or written
+    fn watch(&self, dirs: &[PathBuf]) {
+        let monitors = dirs
+            .iter()
+            .filter_map(|dir| {
+                let monitor = gio::File::for_path(dir)
+                    .monitor_directory(gio::FileMonitorFlags::WATCH_MOVES, gio::Cancellable::NONE)
+                    .ok()?;
+                monitor.connect_changed(glib_macros::clone!(
+                    #[weak(rename_to = sidebar)]
+                    self,
+                    move |_, file, _, event| {
+                        let relevant = match event {
+                            gio::FileMonitorEvent::Created
+                            | gio::FileMonitorEvent::Deleted
+                            | gio::FileMonitorEvent::MovedIn
+                            | gio::FileMonitorEvent::MovedOut
+                            | gio::FileMonitorEvent::Renamed => true,
+                            // Written files are searched again for the folder search
+                            gio::FileMonitorEvent::ChangesDoneHint => {
+                                file.path().is_some_and(|path| {
+                                    scan::is_markdown(&path)
+                                        || path.file_name().is_some_and(|name| name == ".gitignore")
+                                })
+                            }
+                            _ => false,
+                        };
+                        if relevant {
+                            sidebar.schedule_rescan();
+                        }
+                    }
+                ));
+                Some(monitor)
+            })
+            .collect();
+
+        for monitor in self.monitors.replace(monitors) {
+            monitor.cancel();
+        }
+    }
+
+    fn schedule_rescan(&self) {
+        if self.rescan_source.borrow().is_some() {
+            return;
+        }
+        let source = glib::timeout_add_local_once(
+            RESCAN_DELAY,
+            glib_macros::clone!(
+                #[weak(rename_to = sidebar)]
+                self,
+                move || {
+                    sidebar.rescan_source.take();
+                    sidebar.rescan();
+                }
+            ),
+        );
+        self.rescan_source.replace(Some(source));
+    }
+
+    /// Show the files matching the filter as a tree, keeping expanded folders open
+    fn update_model(&self) {
+        let Some(folder) = self.folder.borrow().clone() else {
+            self.list_view.set_model(None::<&gtk::SelectionModel>);
+            return;
+        };
+
+        let query = self.search_entry.text().to_lowercase();
+        let mut tree = Tree::new();
+        for path in self.files.borrow().iter() {
+            let Some(name) = path.file_name().map(|name| name.to_string_lossy()) else {
+                continue;
+            };
+            if !name.to_lowercase().contains(&query) {
+                continue;
+            }
+
+            let mut parent = PathBuf::new();
+            for component in path.parent().into_iter().flat_map(Path::components) {
+                let name = component.as_os_str().to_string_lossy().into_owned();
+                let dir = parent.join(&name);
+                tree.entry(parent)
+                    .or_default()
+                    .insert((false, name.to_lowercase(), name));
+                parent = dir;
+            }
+            tree.entry(parent)
+                .or_default()
+                .insert((true, name.to_lowercase(), name.into_owned()));
+        }
+
+        let expanded = self.expanded_dirs();
+        let tree = Rc::new(tree);
+        let root = folder_store(&folder, &tree, Path::new(""));
+        let model = gtk::TreeListModel::new(root, false, !query.is_empty(), move |item| {
+            let info = item.downcast_ref::<gio::FileInfo>()?;
+            if info.file_type() != gio::FileType::Directory {
+                return None;
+            }
+            let file = info
+                .attribute_object("standard::file")
+                .and_downcast::<gio::File>()?;
+            let relative = folder.relative_path(&file)?;
+            Some(folder_store(&folder, &tree, &relative).upcast())
+        });
+
+        for i in 0.. {
+            let Some(row) = model.row(i) else {
+                break;
+            };
+            if row_path(&row).is_some_and(|path| expanded.contains(&path)) {
+                row.set_expanded(true);
+            }
+        }
+
+        let selection = gtk::SingleSelection::builder()
+            .model(&model)
+            .autoselect(false)
+            .can_unselect(true)
+            .build();
+        self.list_view.set_model(Some(&selection));
+    }
+
+    /// Folders currently expanded in the tree
+    fn expanded_dirs(&self) -> HashSet<PathBuf> {
+        let Some(model) = self.tree_model() else {
+            return HashSet::new();
+        };
+        (0..model.n_items())
+            .filter_map(|i| model.row(i))
+            .filter(|row| row.is_expanded())
+            .filter_map(|row| row_path(&row))
+            .collect()
+    }
+
+    fn tree_model(&self) -> Option<gtk::TreeListModel> {
+        self.list_view
+            .model()
+            .and_downcast::<gtk::SingleSelection>()?
+            .model()
+            .and_downcast()
+    }
+
+    /// Open a clicked file, or expand or collapse a clicked folder
+    fn activate(&self, position: u32) {
+        let Some(row) = self.tree_model().and_then(|model| model.row(position)) else {
+            return;
+        };
+        if row.is_expandable() {
+            row.set_expanded(!row.is_expanded());
+        } else if let Some(file) = row
+            .item()
+            .and_downcast::<gio::FileInfo>()
+            .and_then(|info| info.attribute_object("standard::file"))
+            .and_downcast::<gio::File>()
+        {
+            self.obj().emit_by_name::<()>("file-activated", &[&file]);
+        }
+    }
+}
+
+/// Entries of the folder at `relative`, as file infos naming the file they are for
+fn folder_store(folder: &gio::File, tree: &Tree, relative: &Path) -> gio::ListStore {
+    let store = gio::ListStore::new::<gio::FileInfo>();
+    for (is_file, _, name) in tree.get(relative).into_iter().flatten() {
+        let info = gio::FileInfo::new();
+        info.set_display_name(name);
+        info.set_file_type(if *is_file {
+            gio::FileType::Regular
+        } else {
+            gio::FileType::Directory
+        });
+        let file = folder.resolve_relative_path(relative.join(name));
+        info.set_attribute_object("standard::file", &file);
+        store.append(&info);
+    }
+    store
+}
+
+/// File of a tree row
+fn row_path(row: &gtk::TreeListRow) -> Option<PathBuf> {
+    row.item()
+        .and_downcast::<gio::FileInfo>()?
+        .attribute_object("standard::file")
+        .and_downcast::<gio::File>()?
+        .path()
+}
+
+/// Rows with an expander, an icon and the name
+fn row_factory() -> gtk::SignalListItemFactory {
+    let factory = gtk::SignalListItemFactory::new();
+    factory.connect_setup(|_, item| {
+        let Some(item) = item.downcast_ref::<gtk::ListItem>() else {
+            return;
+        };
+        let content = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+        content.append(&gtk::Image::new());
+        content.append(
+            &gtk::Label::builder()
+                .xalign(0.0)
+                .ellipsize(gtk::pango::EllipsizeMode::Middle)
+                .build(),
+        );
+        let expander = gtk::TreeExpander::new();
+        expander.set_child(Some(&content));
+        item.set_child(Some(&expander));
+    });
+    factory.connect_bind(|_, item| {
+        let Some(item) = item.downcast_ref::<gtk::ListItem>() else {
+            return;
+        };
+        let row = item.item().and_downcast::<gtk::TreeListRow>();
+        let Some(expander) = item.child().and_downcast::<gtk::TreeExpander>() else {
+            return;
+        };
+        expander.set_list_row(row.as_ref());
+
+        let Some(info) = row
+            .and_then(|row| row.item())
+            .and_downcast::<gio::FileInfo>()
+        else {
+            return;
+        };
+        let Some(content) = expander.child() else {
+            return;
+        };
+        if let Some(icon) = content.first_child().and_downcast::<gtk::Image>() {
+            icon.set_icon_name(Some(if info.file_type() == gio::FileType::Directory {
+                "folder-symbolic"
+            } else {
+                "text-x-generic-symbolic"
+            }));
+        }
+        if let Some(label) = content.last_child().and_downcast::<gtk::Label>() {
+            label.set_label(&info.display_name());
+        }
+    });
+    factory
+}
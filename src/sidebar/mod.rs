@@ -0,0 +1,78 @@
+mod gitignore;
+mod imp;
+mod scan;
+
+use std::path::PathBuf;
+
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+use adw::{gio, glib, gtk};
+
+pub use scan::is_markdown;
+
+glib::wrapper! {
+    /// Tree of the markdown files in a folder, kept up to date as files are added or
+    /// removed. Hidden files and those ignored by `.gitignore` are left out.
+    pub struct FileSidebar(ObjectSubclass<imp::FileSidebar>)
+        @extends gtk::Box, gtk::Widget,
+        @implements gtk::Accessible, gtk::Buildable, gtk::ConstraintTarget, gtk::Orientable;
+}
+
+impl FileSidebar {
+    pub fn new() -> Self {
+        glib::Object::builder().build()
+    }
+
+    /// Folder whose files are listed
+    pub fn folder(&self) -> Option<gio::File> {
+        self.imp().folder.borrow().clone()
+    }
+
+    /// List the markdown files of `folder`
+    pub fn set_folder(&self, folder: &gio::File) {
+        self.imp().folder.replace(Some(folder.clone()));
+        self.imp().rescan();
+    }
+
+    /// Markdown files of the folder found by the last search
+    pub fn files(&self) -> Vec<PathBuf> {
+        let Some(root) = self.folder().and_then(|folder| folder.path()) else {
+            return Vec::new();
+        };
+        self.imp()
+            .files
+            .borrow()
+            .iter()
+            .map(|path| root.join(path))
+            .collect()
+    }
+
+    /// Connect to the signal emitted when a markdown file is clicked
+    pub fn connect_file_activated<F: Fn(&Self, &gio::File) + 'static>(
+        &self,
+        f: F,
+    ) -> glib::SignalHandlerId {
+        self.connect_local("file-activated", false, move |args| {
+            let sidebar = args[0].get::<Self>().ok()?;
+            let file = args[1].get::<gio::File>().ok()?;
+            f(&sidebar, &file);
+            None
+        })
+    }
+
+    /// Connect to the signal emitted when the files are listed again, after files were
+    /// added, removed or written
+    pub fn connect_files_changed<F: Fn(&Self) + 'static>(&self, f: F) -> glib::SignalHandlerId {
+        self.connect_local("files-changed", false, move |args| {
+            let sidebar = args[0].get::<Self>().ok()?;
+            f(&sidebar);
+            None
+        })
+    }
+}
+
+impl Default for FileSidebar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,80 @@
+//! Finding the markdown files of a folder
+
+use std::path::{Path, PathBuf};
+
+use super::gitignore::Gitignore;
+
+/// Markdown files and the folders searched for them
+#[derive(Debug, Default)]
+pub struct Scan {
+    /// Markdown files relative to the root, sorted
+    pub files: Vec<PathBuf>,
+    /// Every folder searched, including the root, to monitor for changes
+    pub dirs: Vec<PathBuf>,
+}
+
+/// Search `root` for markdown files, skipping hidden entries and what `.gitignore`
+/// files ignore. Blocks on the file system, so it is meant for a worker thread.
+pub fn scan(root: &Path) -> Scan {
+    let mut scan = Scan::default();
+    visit(root, Path::new(""), &mut Vec::new(), &mut scan);
+    scan.files.sort();
+    scan
+}
+
+pub fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "md" || extension == "markdown")
+}
+
+/// `.gitignore` files of the folders above, with the folder each is in
+type Ignores = Vec<(PathBuf, Gitignore)>;
+
+fn visit(root: &Path, relative: &Path, ignores: &mut Ignores, scan: &mut Scan) {
+    let dir = root.join(relative);
+    let gitignore = std::fs::read_to_string(dir.join(".gitignore")).ok();
+    if let Some(source) = &gitignore {
+        ignores.push((relative.to_path_buf(), Gitignore::parse(source)));
+    }
+
+    if let Ok(entries) = std::fs::read_dir(&dir) {
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let Some(name) = name.to_str().filter(|name| !name.starts_with('.')) else {
+                continue;
+            };
+            // Links to folders are not followed, so there are no cycles
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let is_dir = file_type.is_dir();
+            let path = relative.join(name);
+            if is_ignored(ignores, &path, is_dir) {
+                continue;
+            }
+
+            if is_dir {
+                visit(root, &path, ignores, scan);
+            } else if is_markdown(&path) && entry.path().is_file() {
+                scan.files.push(path);
+            }
+        }
+    }
+    scan.dirs.push(dir);
+
+    if gitignore.is_some() {
+        ignores.pop();
+    }
+}
+
+/// Rules of deeper `.gitignore` files take precedence over those above them
+fn is_ignored(ignores: &Ignores, path: &Path, is_dir: bool) -> bool {
+    ignores
+        .iter()
+        .rev()
+        .find_map(|(base, gitignore)| {
+            let relative = path.strip_prefix(base).ok()?.to_str()?;
+            gitignore.matched(relative, is_dir)
+        })
+        .unwrap_or(false)
+}
@@ -0,0 +1,102 @@
+use std::cell::RefCell;
+use std::time::SystemTime;
+
+use adw::gdk;
+use adw::gtk::gdk_pixbuf::{self, prelude::*};
+use adw::gtk::glib;
+use adw::gtk::prelude::*;
+use adw::gtk::subclass::prelude::*;
+
+#[derive(Default)]
+pub struct AnimatedPaintable {
+    pub(super) animation: RefCell<Option<gdk_pixbuf::PixbufAnimation>>,
+    iter: RefCell<Option<gdk_pixbuf::PixbufAnimationIter>>,
+    frame: RefCell<Option<gdk::Texture>>,
+    timeout: RefCell<Option<glib::SourceId>>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for AnimatedPaintable {
+    const NAME: &'static str = "AnimatedPaintable";
+    type Type = super::AnimatedPaintable;
+    type Interfaces = (gdk::Paintable,);
+}
+
+impl ObjectImpl for AnimatedPaintable {
+    fn dispose(&self) {
+        if let Some(source) = self.timeout.take() {
+            source.remove();
+        }
+    }
+}
+
+impl PaintableImpl for AnimatedPaintable {
+    fn intrinsic_width(&self) -> i32 {
+        self.animation
+            .borrow()
+            .as_ref()
+            .map(|a| a.width())
+            .unwrap_or(0)
+    }
+
+    fn intrinsic_height(&self) -> i32 {
+        self.animation
+            .borrow()
+            .as_ref()
+            .map(|a| a.height())
+            .unwrap_or(0)
+    }
+
+    fn flags(&self) -> gdk::PaintableFlags {
+        gdk::PaintableFlags::SIZE
+    }
+
+    fn snapshot(&self, snapshot: &gdk::Snapshot, width: f64, height: f64) {
+        if let Some(frame) = self.frame.borrow().as_ref() {
+            frame.snapshot(snapshot, width, height);
+        }
+    }
+}
+
+impl AnimatedPaintable {
+    /// Start playing from the first frame
+    pub(super) fn start(&self) {
+        let iter = self
+            .animation
+            .borrow()
+            .as_ref()
+            .map(|a| a.iter(Some(SystemTime::now())));
+        self.iter.replace(iter);
+        self.show_current_frame();
+    }
+
+    fn show_current_frame(&self) {
+        let Some(iter) = self.iter.borrow().clone() else {
+            return;
+        };
+
+        #[allow(deprecated)]
+        self.frame
+            .replace(Some(gdk::Texture::for_pixbuf(&iter.pixbuf())));
+        self.obj().invalidate_contents();
+
+        // A negative delay means the frame is shown forever
+        if let Some(delay) = iter.delay_time() {
+            let source = glib::timeout_add_local_once(
+                delay,
+                glib_macros::clone!(
+                    #[weak(rename_to = paintable)]
+                    self,
+                    move || {
+                        paintable.timeout.take();
+                        if let Some(iter) = paintable.iter.borrow().as_ref() {
+                            iter.advance(SystemTime::now());
+                        }
+                        paintable.show_current_frame();
+                    }
+                ),
+            );
+            self.timeout.replace(Some(source));
+        }
+    }
+}
@@ -0,0 +1,21 @@
+mod imp;
+
+use adw::gdk;
+use adw::gtk::gdk_pixbuf;
+use adw::gtk::glib;
+use adw::gtk::subclass::prelude::*;
+
+glib::wrapper! {
+    /// Paintable that plays a `PixbufAnimation`, used for animated GIFs
+    pub struct AnimatedPaintable(ObjectSubclass<imp::AnimatedPaintable>)
+        @implements gdk::Paintable;
+}
+
+impl AnimatedPaintable {
+    pub fn new(animation: &gdk_pixbuf::PixbufAnimation) -> Self {
+        let paintable: Self = glib::Object::builder().build();
+        paintable.imp().animation.replace(Some(animation.clone()));
+        paintable.imp().start();
+        paintable
+    }
+}
@@ -1,4 +1,4 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 
 use adw::gdk;
 use adw::gtk::glib;
@@ -7,6 +7,9 @@ use adw::gtk::subclass::prelude::*;
 use glib::prelude::*;
 use glib_macros::Properties;
 
+use super::serialize;
+use crate::markdown::Inline;
+
 /// Opaque handle to a position in the text buffer
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, glib::Boxed)]
 #[boxed_type(name = "InlinePos")]
@@ -41,7 +44,7 @@ impl InlineAnchor {
 }
 
 /// Text attribute for styling
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TextAttr {
     Bold,
     Italic,
@@ -51,9 +54,10 @@ pub enum TextAttr {
     Link(String),
     FontSize(i32),
     FontFamily(String),
+    Superscript,
 }
 
-#[derive(Debug, Clone, Copy, glib::Enum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, glib::Enum)]
 #[enum_type(name = "TextAttrType")]
 pub enum TextAttrType {
     Bold,
@@ -64,6 +68,7 @@ pub enum TextAttrType {
     Link,
     FontSize,
     FontFamily,
+    Superscript,
 }
 
 impl TextAttr {
@@ -78,6 +83,7 @@ impl TextAttr {
             TextAttr::Link(_) => TextAttrType::Link,
             TextAttr::FontSize(_) => TextAttrType::FontSize,
             TextAttr::FontFamily(_) => TextAttrType::FontFamily,
+            TextAttr::Superscript => TextAttrType::Superscript,
         }
     }
 
@@ -142,10 +148,28 @@ impl TextAttr {
                 attr.set_end_index(end);
                 attr_list.insert(attr);
             }
+            TextAttr::Superscript => {
+                let mut shift_attr =
+                    pango::AttrInt::new_baseline_shift(pango::BaselineShift::Superscript);
+                shift_attr.set_start_index(start);
+                shift_attr.set_end_index(end);
+                attr_list.insert(shift_attr);
+
+                let mut scale_attr = pango::AttrInt::new_font_scale(pango::FontScale::Superscript);
+                scale_attr.set_start_index(start);
+                scale_attr.set_end_index(end);
+                attr_list.insert(scale_attr);
+            }
         }
     }
 }
 
+/// Placeholder character that stands in for an anchor in the text
+pub(crate) const OBJECT_REPLACEMENT: &str = "\u{FFFC}";
+
+/// Paintable inserted at an anchor with its baseline, see `AnchorData`
+pub(crate) type AnchoredPaintable = (gdk::Paintable, Option<i32>);
+
 /// Stored attribute with its range
 #[derive(Debug, Clone)]
 struct AttributeSpan {
@@ -154,14 +178,61 @@ struct AttributeSpan {
     end: InlinePos,
 }
 
+impl AttributeSpan {
+    fn same_as(&self, other: &AttributeSpan) -> bool {
+        self.attr == other.attr && self.start == other.start && self.end == other.end
+    }
+}
+
 /// Anchor data
 #[derive(Debug, Clone)]
 struct AnchorData {
     id: usize,
     pos: InlinePos,
     paintable: Option<gdk::Paintable>,
+    /// Distance from the top of the paintable to its baseline, the bottom edge if unset
+    baseline: Option<i32>,
+    /// Markup the anchor stands for, such as an image reference, kept for exporting the text
+    source: Option<String>,
+}
+
+/// Reversible edit kept in the undo history
+#[derive(Debug, Clone)]
+enum Edit {
+    Insert {
+        offset: usize,
+        text: String,
+    },
+    /// Deleted text with the spans it overlapped and the anchors inside it, at their
+    /// indices from before the deletion. Other spans and anchors only shifted.
+    Delete {
+        offset: usize,
+        text: String,
+        attributes: Vec<(usize, AttributeSpan)>,
+        anchors: Vec<(usize, AnchorData)>,
+    },
+    /// Attribute appended to the end of the span list
+    Attribute(AttributeSpan),
+    /// Attribute spans replaced as a whole, when an attribute is removed from a range
+    Attributes {
+        before: Vec<AttributeSpan>,
+        after: Vec<AttributeSpan>,
+    },
+    /// Anchor together with its placeholder character
+    Anchor {
+        index: usize,
+        anchor: AnchorData,
+    },
+    Paintable {
+        id: usize,
+        old: Option<AnchoredPaintable>,
+        new: Option<AnchoredPaintable>,
+    },
 }
 
+/// Edits that are undone and redone together
+type UserAction = Vec<Edit>;
+
 mod imp {
     use super::*;
 
@@ -173,6 +244,23 @@ mod imp {
         pub(super) attributes: RefCell<Vec<AttributeSpan>>,
         pub(super) anchors: RefCell<Vec<AnchorData>>,
         pub(super) next_anchor_id: RefCell<usize>,
+        /// Incremented on every change, so views can tell whether cached layouts are current
+        #[property(get)]
+        pub(super) generation: Cell<u64>,
+        #[property(get)]
+        pub(super) can_undo: Cell<bool>,
+        #[property(get)]
+        pub(super) can_redo: Cell<bool>,
+        pub(super) undo_stack: RefCell<Vec<UserAction>>,
+        pub(super) redo_stack: RefCell<Vec<UserAction>>,
+        /// Edits of the user action in progress
+        pub(super) pending_action: RefCell<UserAction>,
+        pub(super) user_action_depth: Cell<u32>,
+        pub(super) irreversible_depth: Cell<u32>,
+        /// Set while running edits that are left out of the history without clearing it
+        pub(super) unrecorded_depth: Cell<u32>,
+        /// Set while undoing or redoing, so the replayed edits are not recorded again
+        pub(super) replaying: Cell<bool>,
     }
 
     #[glib::object_subclass]
@@ -206,19 +294,44 @@ mod imp {
                     glib::subclass::Signal::builder("paintable-inserted")
                         .param_types([InlineAnchor::static_type()])
                         .build(),
+                    // Signal emitted after text is inserted: (pos: InlinePos, len: u64 bytes)
+                    glib::subclass::Signal::builder("text-inserted")
+                        .param_types([InlinePos::static_type(), u64::static_type()])
+                        .build(),
+                    // Signal emitted after text is deleted: (start: InlinePos, end: InlinePos),
+                    // positions refer to the text before the deletion
+                    glib::subclass::Signal::builder("text-deleted")
+                        .param_types([InlinePos::static_type(), InlinePos::static_type()])
+                        .build(),
+                    // Signal emitted when styling of a range changes: (start: InlinePos, end: InlinePos)
+                    glib::subclass::Signal::builder("attributes-changed")
+                        .param_types([InlinePos::static_type(), InlinePos::static_type()])
+                        .build(),
                 ]
             })
         }
     }
 
     impl InlineBuffer {
-        /// Set the text content (clears all attributes and anchors)
+        /// Set the text content (clears all attributes, anchors and the undo history)
         pub fn set_text(&self, text: String) {
+            let old_len = self.text.borrow().len();
+            let len = text.len();
             self.text.replace(text);
             self.attributes.borrow_mut().clear();
             self.anchors.borrow_mut().clear();
+            self.undo_stack.borrow_mut().clear();
+            self.redo_stack.borrow_mut().clear();
+            self.pending_action.borrow_mut().clear();
+            self.update_history_state();
 
-            // Emit changed signal
+            self.bump_generation();
+            if old_len > 0 {
+                self.emit_text_deleted(0, old_len);
+            }
+            if len > 0 {
+                self.emit_text_inserted(0, len);
+            }
             self.obj().emit_by_name::<()>("changed", &[]);
         }
 
@@ -230,39 +343,265 @@ mod imp {
         /// Append text to the buffer and return the position at the end
         pub fn push_str(&self, text: &str) -> InlinePos {
             let mut buffer = self.text.borrow_mut();
+            let start = buffer.len();
             buffer.push_str(text);
             let pos = InlinePos::new(buffer.len());
             drop(buffer);
 
-            // Emit changed signal
+            self.record(Edit::Insert {
+                offset: start,
+                text: text.to_owned(),
+            });
+            self.bump_generation();
+            self.emit_text_inserted(start, text.len());
             self.obj().emit_by_name::<()>("changed", &[]);
             pos
         }
 
+        /// Insert text at `pos` and return the position after it.
+        /// Attributes covering `pos` grow to include the new text.
+        pub fn insert(&self, pos: InlinePos, text: &str) -> InlinePos {
+            let offset = pos.offset();
+            if !self.text.borrow().is_char_boundary(offset) {
+                glib::g_warning!(
+                    "potato-md",
+                    "Insert position {offset} is not a char boundary"
+                );
+                return pos;
+            }
+            if text.is_empty() {
+                return pos;
+            }
+
+            self.record(Edit::Insert {
+                offset,
+                text: text.to_owned(),
+            });
+            self.insert_text(offset, text)
+        }
+
+        /// Insert text without recording it, shifting attributes and anchors
+        fn insert_text(&self, offset: usize, text: &str) -> InlinePos {
+            let len = text.len();
+            self.text.borrow_mut().insert_str(offset, text);
+            for span in self.attributes.borrow_mut().iter_mut() {
+                if span.start.offset() >= offset {
+                    span.start = InlinePos::new(span.start.offset() + len);
+                }
+                if span.end.offset() > offset {
+                    span.end = InlinePos::new(span.end.offset() + len);
+                }
+            }
+            for anchor in self.anchors.borrow_mut().iter_mut() {
+                if anchor.pos.offset() >= offset {
+                    anchor.pos = InlinePos::new(anchor.pos.offset() + len);
+                }
+            }
+
+            self.bump_generation();
+            self.emit_text_inserted(offset, len);
+            self.obj().emit_by_name::<()>("changed", &[]);
+            InlinePos::new(offset + len)
+        }
+
+        /// Delete the text between `start` and `end`, along with anchors inside it
+        pub fn delete(&self, start: InlinePos, end: InlinePos) {
+            let (start, end) = (
+                start.offset().min(end.offset()),
+                start.offset().max(end.offset()),
+            );
+            {
+                let text = self.text.borrow();
+                if end > text.len() || !text.is_char_boundary(start) || !text.is_char_boundary(end)
+                {
+                    glib::g_warning!("potato-md", "Invalid delete range {start}..{end}");
+                    return;
+                }
+            }
+            if start == end {
+                return;
+            }
+
+            if self.is_recording() {
+                let attributes = self
+                    .attributes
+                    .borrow()
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, span)| span.start.offset() < end && span.end.offset() > start)
+                    .map(|(index, span)| (index, span.clone()))
+                    .collect();
+                let anchors = self
+                    .anchors
+                    .borrow()
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, anchor)| (start..end).contains(&anchor.pos.offset()))
+                    .map(|(index, anchor)| (index, anchor.clone()))
+                    .collect();
+                self.record(Edit::Delete {
+                    offset: start,
+                    text: self.text.borrow()[start..end].to_owned(),
+                    attributes,
+                    anchors,
+                });
+            }
+            self.delete_text(start, end);
+        }
+
+        /// Delete text without recording it, dropping anchors inside the range
+        fn delete_text(&self, start: usize, end: usize) {
+            let len = end - start;
+            let shift = |offset: usize| {
+                if offset >= end {
+                    offset - len
+                } else {
+                    offset.min(start)
+                }
+            };
+            self.text.borrow_mut().replace_range(start..end, "");
+            self.attributes.borrow_mut().retain_mut(|span| {
+                span.start = InlinePos::new(shift(span.start.offset()));
+                span.end = InlinePos::new(shift(span.end.offset()));
+                span.start < span.end
+            });
+            self.anchors.borrow_mut().retain_mut(|anchor| {
+                let offset = anchor.pos.offset();
+                anchor.pos = InlinePos::new(shift(offset));
+                !(start..end).contains(&offset)
+            });
+
+            self.bump_generation();
+            self.emit_text_deleted(start, end);
+            self.obj().emit_by_name::<()>("changed", &[]);
+        }
+
         /// Apply an attribute to a range of text
         pub fn apply_attribute(&self, start: InlinePos, end: InlinePos, attr: TextAttr) {
             let attr_type = attr.attr_type();
             let span = AttributeSpan { attr, start, end };
+            self.record(Edit::Attribute(span.clone()));
             self.attributes.borrow_mut().push(span);
 
             // Emit signals
+            self.bump_generation();
             self.obj()
                 .emit_by_name::<()>("attribute-added", &[&attr_type, &start, &end]);
+            self.obj()
+                .emit_by_name::<()>("attributes-changed", &[&start, &end]);
             self.obj().emit_by_name::<()>("changed", &[]);
         }
 
-        /// Create an anchor at a specific position
-        pub fn push_anchor(&self) -> InlineAnchor {
-            let mut anchors = self.anchors.borrow_mut();
+        /// Remove an attribute type from a range, splitting spans that extend past it
+        pub fn remove_attribute(&self, start: InlinePos, end: InlinePos, attr_type: TextAttrType) {
+            let before = self.attributes.borrow().clone();
+            let mut after = Vec::with_capacity(before.len());
+            for span in &before {
+                if span.attr.attr_type() != attr_type || span.end <= start || span.start >= end {
+                    after.push(span.clone());
+                    continue;
+                }
+                if span.start < start {
+                    after.push(AttributeSpan {
+                        end: start,
+                        ..span.clone()
+                    });
+                }
+                if span.end > end {
+                    after.push(AttributeSpan {
+                        start: end,
+                        ..span.clone()
+                    });
+                }
+            }
+            if after.len() == before.len()
+                && after
+                    .iter()
+                    .zip(&before)
+                    .all(|(a, b)| a.start == b.start && a.end == b.end)
+            {
+                return;
+            }
+
+            self.record(Edit::Attributes {
+                before,
+                after: after.clone(),
+            });
+            self.attributes.replace(after);
+            self.bump_generation();
+            self.obj()
+                .emit_by_name::<()>("attributes-changed", &[&start, &end]);
+            self.obj().emit_by_name::<()>("changed", &[]);
+        }
+
+        /// Whether every character between `start` and `end` has an attribute of the type
+        pub fn has_attribute(
+            &self,
+            start: InlinePos,
+            end: InlinePos,
+            attr_type: TextAttrType,
+        ) -> bool {
+            let mut ranges: Vec<_> = self
+                .attributes
+                .borrow()
+                .iter()
+                .filter(|span| span.attr.attr_type() == attr_type)
+                .map(|span| (span.start, span.end))
+                .collect();
+            ranges.sort();
+
+            let mut covered = start;
+            for (span_start, span_end) in ranges {
+                if span_start > covered {
+                    break;
+                }
+                covered = covered.max(span_end);
+            }
+            start < end && covered >= end
+        }
+
+        /// Get all attributes with their ranges, in the order they were applied
+        pub fn attributes(&self) -> Vec<(TextAttr, InlinePos, InlinePos)> {
+            self.attributes
+                .borrow()
+                .iter()
+                .map(|span| (span.attr.clone(), span.start, span.end))
+                .collect()
+        }
+
+        /// Get the source of the anchor at `pos`, if there is one with a source
+        pub fn anchor_source_at(&self, pos: InlinePos) -> Option<String> {
+            self.anchors
+                .borrow()
+                .iter()
+                .find(|a| a.pos == pos)
+                .and_then(|a| a.source.clone())
+        }
+
+        /// Create an anchor at the end of the buffer, standing for `source` when exported
+        pub fn push_anchor(&self, source: Option<&str>) -> InlineAnchor {
             let id = self.next_id();
-            let pos = self.push_str("\u{FFFC}"); // Object Replacement Character to represent the anchor in text
+            let pos = self.current_pos();
 
-            let anchor_data = AnchorData {
+            let data = AnchorData {
                 id,
                 pos,
                 paintable: None,
+                baseline: None,
+                source: source.map(str::to_owned),
             };
-            anchors.push(anchor_data);
+            self.record(Edit::Anchor {
+                index: self.anchors.borrow().len(),
+                anchor: data.clone(),
+            });
+
+            // Register the anchor before the text changes so views see it on rebuild
+            self.anchors.borrow_mut().push(data);
+            // The anchor edit above covers the placeholder, the Object Replacement
+            // Character that represents the anchor in the text
+            self.without_history(|| {
+                self.push_str(OBJECT_REPLACEMENT);
+            });
 
             let anchor = InlineAnchor::new(id);
 
@@ -274,19 +613,140 @@ mod imp {
         }
 
         /// Insert a paintable (e.g., image) at an anchor
-        pub fn insert_paintable_at_anchor(&self, anchor: InlineAnchor, paintable: &gdk::Paintable) {
-            let mut anchors = self.anchors.borrow_mut();
-            if let Some(anchor_data) = anchors.iter_mut().find(|a| a.id == anchor.id()) {
-                anchor_data.paintable = Some(paintable.clone());
-
-                // Emit signals
-                drop(anchors); // Release borrow before emit
+        pub fn insert_paintable_at_anchor(
+            &self,
+            anchor: InlineAnchor,
+            paintable: &gdk::Paintable,
+            baseline: Option<i32>,
+        ) {
+            let old = self
+                .anchors
+                .borrow()
+                .iter()
+                .find(|a| a.id == anchor.id())
+                .map(|a| a.paintable.clone().map(|p| (p, a.baseline)));
+            if let Some(old) = old {
+                let new = Some((paintable.clone(), baseline));
+                self.record(Edit::Paintable {
+                    id: anchor.id(),
+                    old,
+                    new: new.clone(),
+                });
+                self.set_anchor_paintable(anchor.id(), new);
                 self.obj()
                     .emit_by_name::<()>("paintable-inserted", &[&anchor]);
-                self.obj().emit_by_name::<()>("changed", &[]);
             }
         }
 
+        /// Replace the paintable of an anchor without recording it
+        fn set_anchor_paintable(&self, id: usize, paintable: Option<AnchoredPaintable>) {
+            let mut anchors = self.anchors.borrow_mut();
+            let Some(anchor_data) = anchors.iter_mut().find(|a| a.id == id) else {
+                return;
+            };
+            let (paintable, baseline) = paintable.unzip();
+            anchor_data.paintable = paintable;
+            anchor_data.baseline = baseline.flatten();
+            let start = anchor_data.pos;
+            let end = InlinePos::new(start.offset() + OBJECT_REPLACEMENT.len());
+
+            // Emit signals
+            drop(anchors); // Release borrow before emit
+            self.bump_generation();
+            self.obj()
+                .emit_by_name::<()>("attributes-changed", &[&start, &end]);
+            self.obj().emit_by_name::<()>("changed", &[]);
+        }
+
+        /// Start grouping edits so they are undone and redone as one step. Calls nest.
+        pub fn begin_user_action(&self) {
+            self.user_action_depth.set(self.user_action_depth.get() + 1);
+        }
+
+        /// Finish the group started by the matching `begin_user_action`
+        pub fn end_user_action(&self) {
+            let depth = self.user_action_depth.get();
+            if depth == 0 {
+                glib::g_warning!("potato-md", "end_user_action without begin_user_action");
+                return;
+            }
+            self.user_action_depth.set(depth - 1);
+            if depth == 1 {
+                let action = self.pending_action.take();
+                if !action.is_empty() {
+                    self.undo_stack.borrow_mut().push(action);
+                    self.update_history_state();
+                }
+            }
+        }
+
+        /// Start a group of edits that cannot be undone, such as loading content.
+        /// The history is cleared once the group ends.
+        pub fn begin_irreversible_action(&self) {
+            self.irreversible_depth
+                .set(self.irreversible_depth.get() + 1);
+        }
+
+        /// Finish the group started by `begin_irreversible_action`
+        pub fn end_irreversible_action(&self) {
+            let depth = self.irreversible_depth.get();
+            if depth == 0 {
+                glib::g_warning!(
+                    "potato-md",
+                    "end_irreversible_action without begin_irreversible_action"
+                );
+                return;
+            }
+            self.irreversible_depth.set(depth - 1);
+            if depth == 1 {
+                self.undo_stack.borrow_mut().clear();
+                self.redo_stack.borrow_mut().clear();
+                self.update_history_state();
+            }
+        }
+
+        /// Run `edit` without recording its changes, keeping the history as it is. For
+        /// changes that do not touch the text, such as an image that finished loading.
+        pub fn without_history(&self, edit: impl FnOnce()) {
+            self.unrecorded_depth.set(self.unrecorded_depth.get() + 1);
+            edit();
+            self.unrecorded_depth.set(self.unrecorded_depth.get() - 1);
+        }
+
+        /// Revert the most recent user action
+        pub fn undo(&self) {
+            if self.user_action_depth.get() > 0 {
+                return;
+            }
+            let Some(action) = self.undo_stack.borrow_mut().pop() else {
+                return;
+            };
+            self.begin_replay();
+            for edit in action.iter().rev() {
+                self.revert(edit);
+            }
+            self.end_replay();
+            self.redo_stack.borrow_mut().push(action);
+            self.update_history_state();
+        }
+
+        /// Reapply the most recently undone user action
+        pub fn redo(&self) {
+            if self.user_action_depth.get() > 0 {
+                return;
+            }
+            let Some(action) = self.redo_stack.borrow_mut().pop() else {
+                return;
+            };
+            self.begin_replay();
+            for edit in &action {
+                self.replay(edit);
+            }
+            self.end_replay();
+            self.undo_stack.borrow_mut().push(action);
+            self.update_history_state();
+        }
+
         /// Get the position of an anchor
         pub fn get_anchor_position(&self, anchor: InlineAnchor) -> Option<InlinePos> {
             self.anchors
@@ -308,9 +768,29 @@ mod imp {
 
         /// Clear all content, attributes, and anchors
         pub fn clear(&self) {
-            self.text.borrow_mut().clear();
-            self.attributes.borrow_mut().clear();
-            self.anchors.borrow_mut().clear();
+            self.set_text(String::new());
+        }
+
+        /// Get the positions of all anchors with their paintables and baselines, if inserted yet
+        pub fn anchors(&self) -> Vec<(InlinePos, Option<AnchoredPaintable>)> {
+            self.anchors
+                .borrow()
+                .iter()
+                .map(|a| (a.pos, a.paintable.clone().map(|p| (p, a.baseline))))
+                .collect()
+        }
+
+        /// Find the link covering `pos`, returning its URL and range
+        pub fn link_at(&self, pos: InlinePos) -> Option<(String, InlinePos, InlinePos)> {
+            self.attributes
+                .borrow()
+                .iter()
+                .find_map(|span| match &span.attr {
+                    TextAttr::Link(url) if span.start <= pos && pos < span.end => {
+                        Some((url.clone(), span.start, span.end))
+                    }
+                    _ => None,
+                })
         }
 
         /// Build a Pango attribute list from the stored attributes
@@ -330,6 +810,166 @@ mod imp {
         fn next_id(&self) -> usize {
             self.next_anchor_id.replace_with(|&mut id| id + 1)
         }
+
+        fn is_recording(&self) -> bool {
+            !self.replaying.get()
+                && self.irreversible_depth.get() == 0
+                && self.unrecorded_depth.get() == 0
+        }
+
+        fn begin_replay(&self) {
+            self.replaying.set(true);
+        }
+
+        fn end_replay(&self) {
+            self.replaying.set(false);
+        }
+
+        /// Add an edit to the history, as its own user action unless one is in progress
+        fn record(&self, edit: Edit) {
+            if !self.is_recording() {
+                return;
+            }
+            self.redo_stack.borrow_mut().clear();
+            if self.user_action_depth.get() > 0 {
+                self.pending_action.borrow_mut().push(edit);
+            } else {
+                self.undo_stack.borrow_mut().push(vec![edit]);
+            }
+            self.update_history_state();
+        }
+
+        /// Apply the inverse of an edit
+        fn revert(&self, edit: &Edit) {
+            match edit {
+                Edit::Insert { offset, text } => self.delete_text(*offset, offset + text.len()),
+                Edit::Delete {
+                    offset,
+                    text,
+                    attributes,
+                    anchors,
+                } => {
+                    self.insert_text(*offset, text);
+                    self.restore_deleted(*offset, offset + text.len(), attributes, anchors);
+                    self.emit_styling_changed();
+                }
+                Edit::Attribute(span) => {
+                    // Spans added without history may have followed it, so it is not
+                    // necessarily the last one
+                    let mut attributes = self.attributes.borrow_mut();
+                    if let Some(index) = attributes.iter().rposition(|s| s.same_as(span)) {
+                        attributes.remove(index);
+                    }
+                    drop(attributes);
+                    self.emit_styling_changed();
+                }
+                Edit::Attributes { before, .. } => {
+                    self.attributes.replace(before.clone());
+                    self.emit_styling_changed();
+                }
+                Edit::Anchor { anchor, .. } => {
+                    let offset = anchor.pos.offset();
+                    self.delete_text(offset, offset + OBJECT_REPLACEMENT.len());
+                }
+                Edit::Paintable { id, old, .. } => self.set_anchor_paintable(*id, old.clone()),
+            }
+        }
+
+        /// Put back the spans and anchors recorded for a deletion of `start..end` once its
+        /// text is inserted again. Spans that outlived the deletion clipped are replaced.
+        fn restore_deleted(
+            &self,
+            start: usize,
+            end: usize,
+            attributes: &[(usize, AttributeSpan)],
+            anchors: &[(usize, AnchorData)],
+        ) {
+            let mut current = self.attributes.take().into_iter();
+            let mut restored = Vec::with_capacity(current.len() + attributes.len());
+            let mut recorded = attributes.iter().peekable();
+            loop {
+                match recorded.next_if(|(index, _)| *index == restored.len()) {
+                    Some((_, span)) => {
+                        // The deletion only dropped spans that were entirely inside it
+                        if span.start.offset() < start || span.end.offset() > end {
+                            current.next();
+                        }
+                        restored.push(span.clone());
+                    }
+                    None => match current.next() {
+                        Some(span) => restored.push(span),
+                        None => break,
+                    },
+                }
+            }
+            self.attributes.replace(restored);
+
+            let mut current = self.anchors.borrow_mut();
+            for (index, anchor) in anchors {
+                current.insert(*index, anchor.clone());
+            }
+        }
+
+        /// Apply an edit again after it was reverted
+        fn replay(&self, edit: &Edit) {
+            match edit {
+                Edit::Insert { offset, text } => {
+                    self.insert_text(*offset, text);
+                }
+                Edit::Delete { offset, text, .. } => self.delete_text(*offset, offset + text.len()),
+                Edit::Attribute(span) => {
+                    self.attributes.borrow_mut().push(span.clone());
+                    self.emit_styling_changed();
+                }
+                Edit::Attributes { after, .. } => {
+                    self.attributes.replace(after.clone());
+                    self.emit_styling_changed();
+                }
+                Edit::Anchor { index, anchor } => {
+                    // Insert the placeholder first, it would shift the anchor otherwise
+                    self.insert_text(anchor.pos.offset(), OBJECT_REPLACEMENT);
+                    self.anchors.borrow_mut().insert(*index, anchor.clone());
+                    self.emit_styling_changed();
+                }
+                Edit::Paintable { id, new, .. } => self.set_anchor_paintable(*id, new.clone()),
+            }
+        }
+
+        fn update_history_state(&self) {
+            let can_undo = !self.undo_stack.borrow().is_empty();
+            let can_redo = !self.redo_stack.borrow().is_empty();
+            if self.can_undo.replace(can_undo) != can_undo {
+                self.obj().notify_can_undo();
+            }
+            if self.can_redo.replace(can_redo) != can_redo {
+                self.obj().notify_can_redo();
+            }
+        }
+
+        /// Signal a styling change over the whole buffer
+        fn emit_styling_changed(&self) {
+            let end = self.current_pos();
+            self.bump_generation();
+            self.obj()
+                .emit_by_name::<()>("attributes-changed", &[&self.start_pos(), &end]);
+            self.obj().emit_by_name::<()>("changed", &[]);
+        }
+
+        fn bump_generation(&self) {
+            self.generation.set(self.generation.get() + 1);
+        }
+
+        fn emit_text_inserted(&self, offset: usize, len: usize) {
+            self.obj()
+                .emit_by_name::<()>("text-inserted", &[&InlinePos::new(offset), &(len as u64)]);
+        }
+
+        fn emit_text_deleted(&self, start: usize, end: usize) {
+            self.obj().emit_by_name::<()>(
+                "text-deleted",
+                &[&InlinePos::new(start), &InlinePos::new(end)],
+            );
+        }
     }
 }
 
@@ -353,6 +993,16 @@ impl InlineBuffer {
         self.imp().push_str(text)
     }
 
+    /// Insert text at `pos` and return the position after it
+    pub fn insert(&self, pos: InlinePos, text: &str) -> InlinePos {
+        self.imp().insert(pos, text)
+    }
+
+    /// Delete the text between `start` and `end`
+    pub fn delete(&self, start: InlinePos, end: InlinePos) {
+        self.imp().delete(start, end)
+    }
+
     /// Apply an attribute to a range of text
     pub fn apply_attribute(&self, start: InlinePos, end: InlinePos, attr: TextAttr) {
         self.imp().apply_attribute(start, end, attr)
@@ -360,12 +1010,85 @@ impl InlineBuffer {
 
     /// Create an anchor at a specific position
     pub fn push_anchor(&self) -> InlineAnchor {
-        self.imp().push_anchor()
+        self.imp().push_anchor(None)
+    }
+
+    /// Create an anchor that stands for `source`, such as an image reference, when exported
+    pub fn push_anchor_with_source(&self, source: &str) -> InlineAnchor {
+        self.imp().push_anchor(Some(source))
+    }
+
+    /// Remove an attribute type from a range
+    pub fn remove_attribute(&self, start: InlinePos, end: InlinePos, attr_type: TextAttrType) {
+        self.imp().remove_attribute(start, end, attr_type)
+    }
+
+    /// Whether the whole range has an attribute of the type
+    pub fn has_attribute(&self, start: InlinePos, end: InlinePos, attr_type: TextAttrType) -> bool {
+        self.imp().has_attribute(start, end, attr_type)
+    }
+
+    /// Get all attributes with their ranges, in the order they were applied
+    pub fn attributes(&self) -> Vec<(TextAttr, InlinePos, InlinePos)> {
+        self.imp().attributes()
+    }
+
+    /// Get the source of the anchor at `pos`
+    pub fn anchor_source_at(&self, pos: InlinePos) -> Option<String> {
+        self.imp().anchor_source_at(pos)
+    }
+
+    /// Start grouping edits into one undo step, calls nest
+    pub fn begin_user_action(&self) {
+        self.imp().begin_user_action()
+    }
+
+    /// Finish the undo step started by `begin_user_action`
+    pub fn end_user_action(&self) {
+        self.imp().end_user_action()
+    }
+
+    /// Start edits that are not recorded for undo, the history is cleared when they end
+    pub fn begin_irreversible_action(&self) {
+        self.imp().begin_irreversible_action()
+    }
+
+    /// Finish the edits started by `begin_irreversible_action`
+    pub fn end_irreversible_action(&self) {
+        self.imp().end_irreversible_action()
+    }
+
+    /// Run `edit` without recording it for undo, keeping the history of earlier edits
+    pub fn without_history(&self, edit: impl FnOnce()) {
+        self.imp().without_history(edit)
+    }
+
+    /// Revert the most recent user action
+    pub fn undo(&self) {
+        self.imp().undo()
+    }
+
+    /// Reapply the most recently undone user action
+    pub fn redo(&self) {
+        self.imp().redo()
     }
 
     /// Insert a paintable (e.g., image) at an anchor
     pub fn insert_paintable_at_anchor(&self, anchor: InlineAnchor, paintable: &gdk::Paintable) {
-        self.imp().insert_paintable_at_anchor(anchor, paintable)
+        self.imp()
+            .insert_paintable_at_anchor(anchor, paintable, None)
+    }
+
+    /// Insert a paintable that is aligned to the text baseline at `baseline` pixels from its top,
+    /// so that content such as formulas can extend below the line
+    pub fn insert_paintable_at_anchor_with_baseline(
+        &self,
+        anchor: InlineAnchor,
+        paintable: &gdk::Paintable,
+        baseline: i32,
+    ) {
+        self.imp()
+            .insert_paintable_at_anchor(anchor, paintable, Some(baseline))
     }
 
     /// Get the position of an anchor
@@ -388,10 +1111,55 @@ impl InlineBuffer {
         self.imp().clear()
     }
 
+    /// Get the positions of all anchors with their paintables and baselines, if inserted yet
+    pub(crate) fn anchors(&self) -> Vec<(InlinePos, Option<AnchoredPaintable>)> {
+        self.imp().anchors()
+    }
+
+    /// Find the link covering `pos`, returning its URL and range
+    pub fn link_at(&self, pos: InlinePos) -> Option<(String, InlinePos, InlinePos)> {
+        self.imp().link_at(pos)
+    }
+
     /// Build a Pango attribute list from the stored attributes
     pub(crate) fn build_pango_attributes(&self) -> pango::AttrList {
         self.imp().build_pango_attributes()
     }
+
+    /// Inline markdown content for the text, nested by its attributes
+    pub fn to_inlines(&self) -> Vec<Inline> {
+        serialize::to_inlines(self)
+    }
+
+    /// Inline content for the text before and after `pos`, as if the text were split there
+    pub fn split_inlines(&self, pos: InlinePos) -> (Vec<Inline>, Vec<Inline>) {
+        let end = self.text().len();
+        let offset = pos.offset().min(end);
+        (
+            serialize::to_inlines_between(self, 0, offset),
+            serialize::to_inlines_between(self, offset, end),
+        )
+    }
+
+    /// Markdown source for the text, with anchors as the references they stand for
+    pub fn to_markdown(&self) -> String {
+        serialize::to_markdown(self)
+    }
+
+    /// Pango markup for the text, links written as `<a href="…">` like `gtk::Label` takes them
+    pub fn to_pango_markup(&self) -> String {
+        serialize::to_pango_markup(self)
+    }
+
+    /// HTML fragment for the text, with anchors as the images they stand for
+    pub fn to_html(&self) -> String {
+        serialize::to_html(self)
+    }
+
+    /// Create a buffer from Pango markup, as written by `to_pango_markup`
+    pub fn from_pango_markup(markup: &str) -> Result<Self, glib::Error> {
+        serialize::from_pango_markup(markup)
+    }
 }
 
 impl Default for InlineBuffer {
@@ -399,3 +1167,206 @@ impl Default for InlineBuffer {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Anchors = Vec<(usize, usize, Option<String>)>;
+
+    /// Text, spans in their order and anchors of `buffer`, to compare whole states
+    fn snapshot(buffer: &InlineBuffer) -> (String, Vec<String>, Anchors) {
+        let imp = buffer.imp();
+        let attributes = imp
+            .attributes
+            .borrow()
+            .iter()
+            .map(|span| {
+                format!(
+                    "{:?} {}..{}",
+                    span.attr,
+                    span.start.offset(),
+                    span.end.offset()
+                )
+            })
+            .collect();
+        let anchors = imp
+            .anchors
+            .borrow()
+            .iter()
+            .map(|anchor| (anchor.id, anchor.pos.offset(), anchor.source.clone()))
+            .collect();
+        (buffer.text(), attributes, anchors)
+    }
+
+    #[test]
+    fn text_edits_undo_and_redo() {
+        let buffer = InlineBuffer::new();
+        let empty = snapshot(&buffer);
+        buffer.push_str("Hello");
+        let pushed = snapshot(&buffer);
+        buffer.insert(InlinePos::new(5), " world");
+        let inserted = snapshot(&buffer);
+        buffer.delete(InlinePos::new(0), InlinePos::new(1));
+        let deleted = snapshot(&buffer);
+        assert_eq!(buffer.text(), "ello world");
+
+        buffer.undo();
+        assert_eq!(snapshot(&buffer), inserted);
+        buffer.undo();
+        assert_eq!(snapshot(&buffer), pushed);
+        buffer.undo();
+        assert_eq!(snapshot(&buffer), empty);
+        assert!(!buffer.can_undo());
+
+        buffer.redo();
+        buffer.redo();
+        buffer.redo();
+        assert_eq!(snapshot(&buffer), deleted);
+        assert!(!buffer.can_redo());
+    }
+
+    #[test]
+    fn attribute_edits_undo_and_redo() {
+        let buffer = InlineBuffer::new();
+        buffer.push_str("Hello world");
+        let plain = snapshot(&buffer);
+        buffer.apply_attribute(InlinePos::new(0), InlinePos::new(5), TextAttr::Bold);
+        let bold = snapshot(&buffer);
+        buffer.remove_attribute(InlinePos::new(2), InlinePos::new(3), TextAttrType::Bold);
+        let split = snapshot(&buffer);
+        assert_eq!(split.1, ["Bold 0..2", "Bold 3..5"]);
+
+        buffer.undo();
+        assert_eq!(snapshot(&buffer), bold);
+        buffer.undo();
+        assert_eq!(snapshot(&buffer), plain);
+        buffer.redo();
+        buffer.redo();
+        assert_eq!(snapshot(&buffer), split);
+    }
+
+    #[test]
+    fn attribute_undo_removes_its_own_span() {
+        let buffer = InlineBuffer::new();
+        buffer.push_str("Hello world");
+        buffer.apply_attribute(InlinePos::new(0), InlinePos::new(5), TextAttr::Bold);
+        buffer.apply_attribute(InlinePos::new(6), InlinePos::new(11), TextAttr::Italic);
+        buffer.undo();
+        buffer.redo();
+
+        // A span added without history follows the recorded ones
+        buffer.without_history(|| {
+            buffer.apply_attribute(InlinePos::new(2), InlinePos::new(8), TextAttr::Underline);
+        });
+        buffer.undo();
+        assert_eq!(snapshot(&buffer).1, ["Bold 0..5", "Underline 2..8"]);
+        buffer.undo();
+        assert_eq!(snapshot(&buffer).1, ["Underline 2..8"]);
+    }
+
+    #[test]
+    fn anchor_and_placeholder_undo_together() {
+        let buffer = InlineBuffer::new();
+        buffer.push_str("Photo: ");
+        buffer.push_anchor_with_source("![](photo.png)");
+        let after = snapshot(&buffer);
+
+        buffer.undo();
+        assert_eq!(buffer.text(), "Photo: ");
+        assert!(buffer.anchors().is_empty());
+        buffer.redo();
+        assert_eq!(snapshot(&buffer), after);
+    }
+
+    #[test]
+    fn user_action_is_undone_as_one_step() {
+        let buffer = InlineBuffer::new();
+        buffer.push_str("Hello");
+        let before = snapshot(&buffer);
+
+        buffer.begin_user_action();
+        buffer.insert(InlinePos::new(5), " world");
+        buffer.apply_attribute(InlinePos::new(6), InlinePos::new(11), TextAttr::Italic);
+        // Nested groups join the outer one
+        buffer.begin_user_action();
+        buffer.delete(InlinePos::new(0), InlinePos::new(1));
+        buffer.end_user_action();
+        // Undo waits for the group to end
+        buffer.undo();
+        buffer.end_user_action();
+        let after = snapshot(&buffer);
+        assert_eq!(after.0, "ello world");
+
+        buffer.undo();
+        assert_eq!(snapshot(&buffer), before);
+        buffer.redo();
+        assert_eq!(snapshot(&buffer), after);
+    }
+
+    #[test]
+    fn new_edit_clears_redo() {
+        let buffer = InlineBuffer::new();
+        buffer.push_str("Hello");
+        buffer.insert(InlinePos::new(5), "!");
+        buffer.undo();
+        assert!(buffer.can_redo());
+
+        buffer.insert(InlinePos::new(5), "?");
+        assert!(!buffer.can_redo());
+        buffer.redo();
+        assert_eq!(buffer.text(), "Hello?");
+    }
+
+    #[test]
+    fn without_history_keeps_undo_and_redo() {
+        let buffer = InlineBuffer::new();
+        buffer.push_str("Photo: ");
+        let anchor = buffer.push_anchor_with_source("![](photo.png)");
+        buffer.push_str(" taken today");
+        buffer.undo();
+        assert!(buffer.can_undo());
+        assert!(buffer.can_redo());
+
+        let paintable = gdk::Paintable::new_empty(1, 1);
+        buffer.without_history(|| buffer.insert_paintable_at_anchor(anchor, &paintable));
+        assert!(buffer.anchors()[0].1.is_some());
+        assert!(buffer.can_undo());
+        assert!(buffer.can_redo());
+
+        buffer.redo();
+        assert_eq!(buffer.text(), "Photo: \u{FFFC} taken today");
+        buffer.undo();
+        buffer.undo();
+        assert_eq!(buffer.text(), "Photo: ");
+        assert!(buffer.anchors().is_empty());
+    }
+
+    #[test]
+    fn deletion_across_anchor_and_span_is_restored() {
+        let buffer = InlineBuffer::new();
+        buffer.push_str("ab");
+        buffer.push_anchor_with_source("![x](x.png)");
+        buffer.push_str("cd efg");
+        // The anchor takes up 2..5
+        buffer.apply_attribute(InlinePos::new(0), InlinePos::new(7), TextAttr::Bold);
+        buffer.apply_attribute(InlinePos::new(2), InlinePos::new(5), TextAttr::Italic);
+        buffer.apply_attribute(InlinePos::new(8), InlinePos::new(10), TextAttr::Underline);
+        let before = snapshot(&buffer);
+
+        buffer.delete(InlinePos::new(1), InlinePos::new(6));
+        let after = snapshot(&buffer);
+        assert_eq!(after.0, "ad efg");
+        assert_eq!(after.1, ["Bold 0..2", "Underline 3..5"]);
+        assert!(after.2.is_empty());
+
+        buffer.undo();
+        assert_eq!(snapshot(&buffer), before);
+        assert_eq!(
+            buffer.anchor_source_at(InlinePos::new(2)).as_deref(),
+            Some("![x](x.png)")
+        );
+        buffer.redo();
+        assert_eq!(snapshot(&buffer), after);
+    }
+}
@@ -0,0 +1,176 @@
+//! Cursor movement over a laid out paragraph, used when the view is editable
+
+use adw::gtk::glib::translate::ToGlibPtr;
+use adw::gtk::pango;
+
+/// Byte offset `chars` characters after `index`
+fn advance(text: &str, index: usize, chars: usize) -> usize {
+    text[index..]
+        .char_indices()
+        .nth(chars)
+        .map_or(text.len(), |(offset, _)| index + offset)
+}
+
+/// Move one cursor position to the left (`direction` -1) or right (1) on screen,
+/// `None` at the edge of the layout
+pub(super) fn move_visually(layout: &pango::Layout, index: usize, direction: i32) -> Option<usize> {
+    let (new_index, trailing) = layout.move_cursor_visually(true, index as i32, 0, direction);
+    if new_index < 0 || new_index == i32::MAX {
+        return None;
+    }
+    Some(advance(
+        &layout.text(),
+        new_index as usize,
+        trailing as usize,
+    ))
+}
+
+/// Bit of `is_cursor_position` in a `PangoLogAttr`, which is a single `guint` of one-bit
+/// fields. Compilers fill bit-fields from the low end on little-endian targets and from the
+/// high end on big-endian ones.
+#[cfg(target_endian = "little")]
+const CURSOR_POSITION_BIT: u32 = 1 << 4;
+#[cfg(target_endian = "big")]
+const CURSOR_POSITION_BIT: u32 = 1 << 27;
+
+/// All offsets the cursor may rest at, which fall between grapheme clusters, in order.
+/// pango-rs does not bind the logical attributes of a layout, so they are read through FFI.
+fn cursor_positions(layout: &pango::Layout) -> Vec<usize> {
+    let text = layout.text();
+    let mut n_attrs = 0;
+    // SAFETY: the layout owns the array and keeps it unchanged while it is borrowed here
+    let attrs = unsafe {
+        let attrs =
+            pango::ffi::pango_layout_get_log_attrs_readonly(layout.to_glib_none().0, &mut n_attrs);
+        if attrs.is_null() {
+            return vec![0, text.len()];
+        }
+        std::slice::from_raw_parts(attrs.cast::<u32>(), n_attrs.max(0) as usize)
+    };
+    positions_from_attrs(&text, attrs)
+}
+
+/// Offsets in `text` whose logical attribute has `CURSOR_POSITION_BIT` set
+fn positions_from_attrs(text: &str, attrs: &[u32]) -> Vec<usize> {
+    // There is an attribute for every character and one for the end of the text
+    text.char_indices()
+        .map(|(offset, _)| offset)
+        .chain([text.len()])
+        .zip(attrs)
+        .filter(|(_, attr)| *attr & CURSOR_POSITION_BIT != 0)
+        .map(|(offset, _)| offset)
+        .collect()
+}
+
+/// Cursor position before `index`, a whole grapheme cluster back
+pub(super) fn previous_position(layout: &pango::Layout, index: usize) -> Option<usize> {
+    let positions = cursor_positions(layout);
+    let before = positions.partition_point(|&position| position < index);
+    before.checked_sub(1).map(|i| positions[i])
+}
+
+/// Cursor position after `index`, a whole grapheme cluster on
+pub(super) fn next_position(layout: &pango::Layout, index: usize) -> Option<usize> {
+    let positions = cursor_positions(layout);
+    let after = positions.partition_point(|&position| position <= index);
+    positions.get(after).copied()
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Start of the word before `index`, skipping any spaces and punctuation in between
+pub(super) fn previous_word_start(text: &str, index: usize) -> usize {
+    let mut chars = text[..index].char_indices().rev().peekable();
+    while chars.next_if(|(_, c)| !is_word_char(*c)).is_some() {}
+    let mut start = chars.peek().map_or(0, |(offset, c)| offset + c.len_utf8());
+    while let Some((offset, _)) = chars.next_if(|(_, c)| is_word_char(*c)) {
+        start = offset;
+    }
+    start
+}
+
+/// End of the word after `index`, skipping any spaces and punctuation in between
+pub(super) fn next_word_end(text: &str, index: usize) -> usize {
+    let mut chars = text[index..].char_indices().peekable();
+    while chars.next_if(|(_, c)| !is_word_char(*c)).is_some() {}
+    while chars.next_if(|(_, c)| is_word_char(*c)).is_some() {}
+    chars
+        .peek()
+        .map_or(text.len(), |(offset, _)| index + offset)
+}
+
+/// Start and end of the visual line containing `index`, the end before any line break
+pub(super) fn line_bounds(layout: &pango::Layout, index: usize) -> (usize, usize) {
+    let text = layout.text();
+    let (line, _) = layout.index_to_line_x(index as i32, false);
+    let Some(line) = layout.line_readonly(line) else {
+        return (0, text.len());
+    };
+
+    let start = line.start_index() as usize;
+    let end = start + line.length() as usize;
+    let end = end
+        - text[start..end]
+            .chars()
+            .rev()
+            .take_while(|c| *c == '\n' || *c == '\r')
+            .count();
+    (start, end)
+}
+
+/// Position `lines` lines above or below `index` at the same horizontal offset,
+/// `None` when there is no such line
+pub(super) fn move_vertically(layout: &pango::Layout, index: usize, lines: i32) -> Option<usize> {
+    let (line, x) = layout.index_to_line_x(index as i32, false);
+    let target = line + lines;
+    if target < 0 || target >= layout.line_count() {
+        return None;
+    }
+
+    let hit = layout.line_readonly(target)?.x_to_index(x);
+    let (start, end) = line_bounds(layout, hit.index() as usize);
+    let offset = advance(
+        &layout.text(),
+        hit.index() as usize,
+        hit.trailing() as usize,
+    );
+    Some(offset.clamp(start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use adw::gtk::glib::translate::mut_override;
+
+    use super::*;
+
+    /// Cursor positions in `text`, from logical attributes read the way `cursor_positions`
+    /// reads those of a layout
+    fn positions(text: &str) -> Vec<usize> {
+        let mut attrs = vec![0_u32; text.chars().count() + 1];
+        let language = pango::Language::from_string("en");
+        // SAFETY: there is room for an attribute per character and one for the end
+        unsafe {
+            pango::ffi::pango_get_log_attrs(
+                text.to_glib_none().0,
+                text.len() as i32,
+                -1,
+                mut_override(language.to_glib_none().0),
+                attrs.as_mut_ptr().cast(),
+                attrs.len() as i32,
+            );
+        }
+        positions_from_attrs(text, &attrs)
+    }
+
+    #[test]
+    fn cursor_positions_fall_between_grapheme_clusters() {
+        assert_eq!(positions("ab"), [0, 1, 2]);
+        // A combining accent stays with its letter
+        assert_eq!(positions("e\u{301}x"), [0, 3, 4]);
+        // An emoji ZWJ sequence is a single step
+        let emoji = "\u{1F469}\u{200D}\u{1F4BB}";
+        assert_eq!(positions(emoji), [0, emoji.len()]);
+    }
+}
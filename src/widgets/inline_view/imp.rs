@@ -1,12 +1,34 @@
-use std::cell::RefCell;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::HashMap;
+use std::time::Duration;
 
-use adw::gtk::glib;
+use adw::gdk;
 use adw::gtk::pango::{self, SCALE};
 use adw::gtk::prelude::*;
 use adw::gtk::subclass::prelude::*;
+use adw::gtk::{self, gio, glib};
 use glib_macros::Properties;
 
-use super::buffer::InlineBuffer;
+use super::buffer::{InlineBuffer, InlinePos, OBJECT_REPLACEMENT};
+use super::editing;
+use crate::markdown;
+
+/// Paintable placed at an anchor, with its byte index in the layout text
+struct AnchoredPaintable {
+    index: i32,
+    paintable: gdk::Paintable,
+    /// Distance from the top of the paintable to the text baseline
+    baseline: i32,
+    handlers: Vec<glib::SignalHandlerId>,
+}
+
+/// Text being composed by the input method, shown at the cursor until committed
+struct Preedit {
+    text: String,
+    attributes: pango::AttrList,
+    /// Cursor within the preedit text in bytes
+    cursor: usize,
+}
 
 #[derive(Default, Properties)]
 #[properties(wrapper_type = super::InlineView)]
@@ -14,24 +36,122 @@ pub struct InlineView {
     #[property(get, set = Self::set_buffer, nullable)]
     #[property(name = "text", get = Self::get_text, set = Self::set_text, type = String)]
     buffer: RefCell<Option<InlineBuffer>>,
-    buffer_signal_id: RefCell<Option<glib::SignalHandlerId>>,
+    buffer_signal_ids: RefCell<Vec<glib::SignalHandlerId>>,
+    /// The text changed, the layout has to be refilled
     needs_update: RefCell<bool>,
+    /// Only styling changed, the layout text can stay
+    needs_attributes: Cell<bool>,
     layout: RefCell<Option<pango::Layout>>,
+    /// Layout size in Pango units by buffer generation and width in pixels (-1 for unwrapped)
+    size_cache: RefCell<HashMap<(u64, i32), (i32, i32)>>,
+    /// Paintables placed at anchors
+    paintables: RefCell<Vec<AnchoredPaintable>>,
+    /// Link under the pointer with its range in the buffer
+    hovered_link: RefCell<Option<(String, InlinePos, InlinePos)>>,
+    /// Text whose occurrences are highlighted, used for search
+    #[property(get, set = Self::set_highlight, nullable)]
+    highlight: RefCell<Option<String>>,
+    /// Byte range of the search match marked as the current one
+    current_match: Cell<Option<(usize, usize)>>,
+    /// Accept keyboard input and show a caret
+    #[property(get, set = Self::set_editable)]
+    editable: Cell<bool>,
+    /// Line spacing as a multiple of the font's line height, 0 for the font's own spacing
+    #[property(get, set = Self::set_line_height, minimum = 0.0)]
+    line_height: Cell<f64>,
+    /// Factor all font sizes are multiplied by, including those of `TextAttr::FontSize`
+    /// spans, with paintables scaled to match. Used for zooming.
+    #[property(get, set = Self::set_scale, minimum = 0.1, default = 1.0)]
+    scale: Cell<f64>,
+    /// Font family that replaces `monospace` in the text attributes, such as for code
+    #[property(get, set = Self::set_monospace_family, nullable)]
+    monospace_family: RefCell<Option<String>>,
+    /// Serial of the Pango context the cached sizes were measured with, it changes
+    /// with the font from CSS
+    context_serial: Cell<u32>,
+    /// Caret position in the buffer
+    cursor: Cell<usize>,
+    /// Other end of the selection, equal to `cursor` when nothing is selected
+    selection_bound: Cell<usize>,
+    im_context: OnceCell<gtk::IMMulticontext>,
+    preedit: RefCell<Option<Preedit>>,
+    /// The caret is in the visible phase of its blink
+    cursor_visible: Cell<bool>,
+    blink_source: RefCell<Option<glib::SourceId>>,
 }
 
 impl InlineView {
     fn set_buffer(&self, buffer: Option<InlineBuffer>) {
-        // Disconnect previous signal if exists
-        if let Some(signal_id) = self.buffer_signal_id.borrow_mut().take() {
-            if let Some(old_buffer) = self.buffer.borrow().as_ref() {
+        // Disconnect from the previous buffer
+        if let Some(old_buffer) = self.buffer.borrow().as_ref() {
+            for signal_id in self.buffer_signal_ids.take() {
                 old_buffer.disconnect(signal_id);
             }
         }
 
-        // Connect to new buffer's changed signal
+        // Text edits refill the layout, styling changes only replace its attributes
         if let Some(ref buf) = buffer {
-            let signal_id = buf.connect_local(
-                "changed",
+            let mut signal_ids = Vec::new();
+            for signal in ["text-inserted", "text-deleted"] {
+                signal_ids.push(buf.connect_local(
+                    signal,
+                    false,
+                    glib_macros::clone!(
+                        #[weak(rename_to = view)]
+                        self,
+                        #[upgrade_or]
+                        None,
+                        move |_| {
+                            view.needs_update.replace(true);
+                            view.obj().queue_resize();
+                            None
+                        }
+                    ),
+                ));
+            }
+            // Keep the caret on the same text, after the layout is marked stale
+            signal_ids.push(buf.connect_local(
+                "text-inserted",
+                false,
+                glib_macros::clone!(
+                    #[weak(rename_to = view)]
+                    self,
+                    #[upgrade_or]
+                    None,
+                    move |args| {
+                        let pos = args[1].get::<InlinePos>().ok()?.offset();
+                        let len = args[2].get::<u64>().ok()? as usize;
+                        view.shift_cursor(
+                            |offset| if offset >= pos { offset + len } else { offset },
+                        );
+                        None
+                    }
+                ),
+            ));
+            signal_ids.push(buf.connect_local(
+                "text-deleted",
+                false,
+                glib_macros::clone!(
+                    #[weak(rename_to = view)]
+                    self,
+                    #[upgrade_or]
+                    None,
+                    move |args| {
+                        let start = args[1].get::<InlinePos>().ok()?.offset();
+                        let end = args[2].get::<InlinePos>().ok()?.offset();
+                        view.shift_cursor(|offset| {
+                            if offset >= end {
+                                offset - (end - start)
+                            } else {
+                                offset.min(start)
+                            }
+                        });
+                        None
+                    }
+                ),
+            ));
+            signal_ids.push(buf.connect_local(
+                "attributes-changed",
                 false,
                 glib_macros::clone!(
                     #[weak(rename_to = view)]
@@ -39,17 +159,501 @@ impl InlineView {
                     #[upgrade_or]
                     None,
                     move |_| {
-                        view.needs_update.replace(true);
+                        view.needs_attributes.set(true);
                         view.obj().queue_resize();
                         None
                     }
                 ),
-            );
-            self.buffer_signal_id.replace(Some(signal_id));
+            ));
+            for property in ["can-undo", "can-redo"] {
+                signal_ids.push(buf.connect_notify_local(
+                    Some(property),
+                    glib_macros::clone!(
+                        #[weak(rename_to = view)]
+                        self,
+                        move |buffer, _| view.update_history_actions(Some(buffer))
+                    ),
+                ));
+            }
+            self.buffer_signal_ids.replace(signal_ids);
         }
 
+        self.update_history_actions(buffer.as_ref());
+        let len = buffer.as_ref().map_or(0, |b| b.text().len());
         self.buffer.replace(buffer);
+        self.cursor.set(len);
+        self.selection_bound.set(len);
+        self.invalidate();
+    }
+
+    /// Enable the undo and redo actions to match the buffer history
+    fn update_history_actions(&self, buffer: Option<&InlineBuffer>) {
+        let obj = self.obj();
+        let editable = self.editable.get();
+        obj.action_set_enabled(
+            "text.undo",
+            editable && buffer.is_some_and(|b| b.can_undo()),
+        );
+        obj.action_set_enabled(
+            "text.redo",
+            editable && buffer.is_some_and(|b| b.can_redo()),
+        );
+    }
+
+    fn set_editable(&self, editable: bool) {
+        if self.editable.replace(editable) == editable {
+            return;
+        }
+
+        let obj = self.obj();
+        obj.set_focusable(editable);
+        obj.set_cursor_from_name(editable.then_some("text"));
+        if !editable {
+            self.im_context().reset();
+            self.preedit.take();
+            self.stop_blink();
+            self.selection_bound.set(self.cursor.get());
+        }
+        self.update_history_actions(self.buffer.borrow().as_ref());
+        self.invalidate();
+    }
+
+    fn im_context(&self) -> &gtk::IMMulticontext {
+        self.im_context.get_or_init(gtk::IMMulticontext::new)
+    }
+
+    /// Cursor and selection bound in ascending order
+    pub(super) fn selection_bounds(&self) -> (usize, usize) {
+        let (cursor, bound) = (self.cursor.get(), self.selection_bound.get());
+        (cursor.min(bound), cursor.max(bound))
+    }
+
+    /// Move the cursor, keeping the selection bound when `extend` is set
+    pub(super) fn place_cursor(&self, index: usize, extend: bool) {
+        let len = self.buffer.borrow().as_ref().map_or(0, |b| b.text().len());
+        let index = index.min(len);
+        let had_selection = self.cursor.get() != self.selection_bound.get();
+        self.cursor.set(index);
+        if !extend {
+            self.selection_bound.set(index);
+        }
+        if had_selection || extend {
+            // Selections are drawn as attributes
+            self.needs_attributes.set(true);
+        }
+        self.cursor_moved();
+    }
+
+    /// Map cursor and selection through a buffer edit
+    fn shift_cursor(&self, shift: impl Fn(usize) -> usize) {
+        self.cursor.set(shift(self.cursor.get()));
+        self.selection_bound.set(shift(self.selection_bound.get()));
+        if self.editable.get() {
+            self.cursor_moved();
+        }
+    }
+
+    fn cursor_moved(&self) {
+        self.reset_blink();
+        self.update_im_cursor_location();
+        self.obj().queue_draw();
+    }
+
+    /// Show the caret and restart its blinking while the view has focus
+    fn reset_blink(&self) {
+        self.stop_blink();
+        self.cursor_visible.set(true);
+
+        let obj = self.obj();
+        let settings = obj.settings();
+        if !self.editable.get() || !obj.has_focus() || !settings.is_gtk_cursor_blink() {
+            return;
+        }
+        let half_period =
+            Duration::from_millis(settings.gtk_cursor_blink_time().max(100) as u64 / 2);
+        let source = glib::timeout_add_local(
+            half_period,
+            glib_macros::clone!(
+                #[weak(rename_to = view)]
+                self,
+                #[upgrade_or]
+                glib::ControlFlow::Break,
+                move || {
+                    view.cursor_visible.set(!view.cursor_visible.get());
+                    view.obj().queue_draw();
+                    glib::ControlFlow::Continue
+                }
+            ),
+        );
+        self.blink_source.replace(Some(source));
+    }
+
+    fn stop_blink(&self) {
+        if let Some(source) = self.blink_source.take() {
+            source.remove();
+        }
+    }
+
+    /// Tell the input method where to show its candidate window
+    fn update_im_cursor_location(&self) {
+        if !self.editable.get() {
+            return;
+        }
+        self.ensure_layout();
+        let Some(layout) = self.layout.borrow().clone() else {
+            return;
+        };
+        let (strong, _) = layout.cursor_pos(self.layout_cursor() as i32);
+        self.im_context().set_cursor_location(&gdk::Rectangle::new(
+            strong.x() / SCALE,
+            strong.y() / SCALE,
+            1,
+            strong.height() / SCALE,
+        ));
+    }
+
+    /// Caret index in the layout text, which includes any preedit
+    fn layout_cursor(&self) -> usize {
+        let cursor = self.cursor.get();
+        match self.preedit.borrow().as_ref() {
+            Some(preedit) => cursor + preedit.cursor,
+            None => cursor,
+        }
+    }
+
+    fn on_preedit_changed(&self) {
+        let (text, attributes, cursor) = self.im_context().preedit_string();
+        let preedit = (!text.is_empty()).then(|| {
+            let cursor = text
+                .char_indices()
+                .nth(cursor.max(0) as usize)
+                .map_or(text.len(), |(offset, _)| offset);
+            Preedit {
+                text: text.to_string(),
+                attributes,
+                cursor,
+            }
+        });
+        self.preedit.replace(preedit);
+        self.invalidate();
+        self.cursor_moved();
+    }
+
+    /// Replace the selection with `text` as one undo step
+    fn insert_at_cursor(&self, text: &str) {
+        let Some(buffer) = self.buffer.borrow().clone() else {
+            return;
+        };
+        buffer.begin_user_action();
+        self.delete_selection(&buffer);
+        let pos = buffer.insert(InlinePos::new(self.cursor.get()), text);
+        buffer.end_user_action();
+        self.place_cursor(pos.offset(), false);
+    }
+
+    /// Delete the selected text, returning whether there was any
+    fn delete_selection(&self, buffer: &InlineBuffer) -> bool {
+        let (start, end) = self.selection_bounds();
+        if start == end {
+            return false;
+        }
+        buffer.delete(InlinePos::new(start), InlinePos::new(end));
+        self.place_cursor(start, false);
+        true
+    }
+
+    /// Delete the selection, or from the cursor to `target` when nothing is selected
+    fn delete_to(&self, target: Option<usize>) {
+        let Some(buffer) = self.buffer.borrow().clone() else {
+            return;
+        };
+        buffer.begin_user_action();
+        if !self.delete_selection(&buffer)
+            && let Some(target) = target
+        {
+            buffer.delete(InlinePos::new(self.cursor.get()), InlinePos::new(target));
+        }
+        buffer.end_user_action();
+    }
+
+    /// Handle navigation and deletion keys, text input goes through the input method
+    fn on_key_pressed(&self, key: gdk::Key, state: gdk::ModifierType) -> glib::Propagation {
+        if !self.editable.get() || self.preedit.borrow().is_some() {
+            return glib::Propagation::Proceed;
+        }
+        self.ensure_layout();
+        let (Some(buffer), Some(layout)) =
+            (self.buffer.borrow().clone(), self.layout.borrow().clone())
+        else {
+            return glib::Propagation::Proceed;
+        };
+
+        let text = buffer.text();
+        let cursor = self.cursor.get();
+        let (start, end) = self.selection_bounds();
+        let control = state.contains(gdk::ModifierType::CONTROL_MASK);
+        let extend = state.contains(gdk::ModifierType::SHIFT_MASK);
+
+        // Moving without extending collapses a selection towards the key's direction
+        let target = match key {
+            gdk::Key::Left | gdk::Key::KP_Left if !extend && start != end => Some(start),
+            gdk::Key::Right | gdk::Key::KP_Right if !extend && start != end => Some(end),
+            gdk::Key::Left | gdk::Key::KP_Left if control => {
+                Some(editing::previous_word_start(&text, cursor))
+            }
+            gdk::Key::Right | gdk::Key::KP_Right if control => {
+                Some(editing::next_word_end(&text, cursor))
+            }
+            gdk::Key::Left | gdk::Key::KP_Left => editing::move_visually(&layout, cursor, -1),
+            gdk::Key::Right | gdk::Key::KP_Right => editing::move_visually(&layout, cursor, 1),
+            gdk::Key::Up | gdk::Key::KP_Up => editing::move_vertically(&layout, cursor, -1),
+            gdk::Key::Down | gdk::Key::KP_Down => editing::move_vertically(&layout, cursor, 1),
+            gdk::Key::Home | gdk::Key::KP_Home if control => Some(0),
+            gdk::Key::End | gdk::Key::KP_End if control => Some(text.len()),
+            gdk::Key::Home | gdk::Key::KP_Home => Some(editing::line_bounds(&layout, cursor).0),
+            gdk::Key::End | gdk::Key::KP_End => Some(editing::line_bounds(&layout, cursor).1),
+            gdk::Key::BackSpace => {
+                let target = if control {
+                    Some(editing::previous_word_start(&text, cursor))
+                } else {
+                    editing::previous_position(&layout, cursor)
+                };
+                self.delete_to(target);
+                return glib::Propagation::Stop;
+            }
+            gdk::Key::Delete | gdk::Key::KP_Delete => {
+                let target = if control {
+                    Some(editing::next_word_end(&text, cursor))
+                } else {
+                    editing::next_position(&layout, cursor)
+                };
+                self.delete_to(target);
+                return glib::Propagation::Stop;
+            }
+            gdk::Key::Return | gdk::Key::KP_Enter | gdk::Key::ISO_Enter => {
+                self.on_enter(extend);
+                return glib::Propagation::Stop;
+            }
+            gdk::Key::a if control => {
+                self.selection_bound.set(0);
+                self.place_cursor(text.len(), true);
+                return glib::Propagation::Stop;
+            }
+            _ => return glib::Propagation::Proceed,
+        };
+
+        // Leave keys that run off the edge to the parent, for moving between fields
+        match target {
+            Some(target) => {
+                self.place_cursor(target, extend);
+                glib::Propagation::Stop
+            }
+            None => glib::Propagation::Proceed,
+        }
+    }
+
+    /// Replace the selection and let the `split` handler split the text at the caret,
+    /// or insert a line break when `line_break` is set or no handler splits it
+    fn on_enter(&self, line_break: bool) {
+        let Some(buffer) = self.buffer.borrow().clone() else {
+            return;
+        };
+        if !line_break {
+            buffer.begin_user_action();
+            self.delete_selection(&buffer);
+            buffer.end_user_action();
+            let offset = self.cursor.get() as u32;
+            if self.obj().emit_by_name::<bool>("split", &[&offset]) {
+                return;
+            }
+        }
+        self.insert_at_cursor("\n");
+    }
+
+    /// Buffer offset at widget coordinates, clamped to the nearest position
+    fn index_at(&self, x: f64, y: f64) -> Option<usize> {
+        self.ensure_layout();
+        let layout = self.layout.borrow().clone()?;
+        let (_, index, trailing) =
+            layout.xy_to_index((x * SCALE as f64) as i32, (y * SCALE as f64) as i32);
+        let text = layout.text();
+        let index = text[index as usize..]
+            .char_indices()
+            .nth(trailing as usize)
+            .map_or(text.len(), |(offset, _)| index as usize + offset);
+        Some(index)
+    }
+
+    fn setup_editing(&self) {
+        let obj = self.obj();
+        let im_context = self.im_context();
+        im_context.set_client_widget(Some(&*obj));
+        im_context.connect_commit(glib_macros::clone!(
+            #[weak(rename_to = view)]
+            self,
+            move |_, text| view.insert_at_cursor(text)
+        ));
+        im_context.connect_preedit_changed(glib_macros::clone!(
+            #[weak(rename_to = view)]
+            self,
+            move |_| view.on_preedit_changed()
+        ));
+        im_context.connect_retrieve_surrounding(glib_macros::clone!(
+            #[weak(rename_to = view)]
+            self,
+            #[upgrade_or]
+            false,
+            move |context| {
+                let text = view.get_text();
+                context.set_surrounding_with_selection(
+                    &text,
+                    view.cursor.get() as i32,
+                    view.selection_bound.get() as i32,
+                );
+                true
+            }
+        ));
+        im_context.connect_delete_surrounding(glib_macros::clone!(
+            #[weak(rename_to = view)]
+            self,
+            #[upgrade_or]
+            false,
+            move |_, offset, n_chars| {
+                // Both are counted in characters from the cursor
+                let text = view.get_text();
+                let cursor = view.cursor.get();
+                let char_offset = |chars: i32| {
+                    if chars < 0 {
+                        text[..cursor]
+                            .char_indices()
+                            .rev()
+                            .nth((-chars - 1) as usize)
+                            .map_or(0, |(offset, _)| offset)
+                    } else {
+                        text[cursor..]
+                            .char_indices()
+                            .nth(chars as usize)
+                            .map_or(text.len(), |(offset, _)| cursor + offset)
+                    }
+                };
+                let start = char_offset(offset);
+                let end = char_offset(offset + n_chars);
+                if let Some(buffer) = view.buffer.borrow().clone() {
+                    buffer.delete(InlinePos::new(start), InlinePos::new(end));
+                }
+                true
+            }
+        ));
+
+        let key = gtk::EventControllerKey::new();
+        key.set_im_context(Some(im_context));
+        key.connect_key_pressed(glib_macros::clone!(
+            #[weak(rename_to = view)]
+            self,
+            #[upgrade_or]
+            glib::Propagation::Proceed,
+            move |_, key, _, state| view.on_key_pressed(key, state)
+        ));
+        obj.add_controller(key);
+
+        let focus = gtk::EventControllerFocus::new();
+        focus.connect_enter(glib_macros::clone!(
+            #[weak(rename_to = view)]
+            self,
+            move |_| {
+                if view.editable.get() {
+                    view.im_context().focus_in();
+                    view.cursor_moved();
+                }
+            }
+        ));
+        focus.connect_leave(glib_macros::clone!(
+            #[weak(rename_to = view)]
+            self,
+            move |_| {
+                view.im_context().focus_out();
+                view.stop_blink();
+                view.obj().queue_draw();
+            }
+        ));
+        obj.add_controller(focus);
+
+        // Pressing places the caret, dragging selects
+        let drag = gtk::GestureDrag::new();
+        drag.connect_drag_begin(glib_macros::clone!(
+            #[weak(rename_to = view)]
+            self,
+            move |gesture, x, y| {
+                if !view.editable.get() {
+                    gesture.set_state(gtk::EventSequenceState::Denied);
+                    return;
+                }
+                view.obj().grab_focus();
+                view.im_context().reset();
+                let extend = gesture
+                    .current_event_state()
+                    .contains(gdk::ModifierType::SHIFT_MASK);
+                if let Some(index) = view.index_at(x, y) {
+                    view.place_cursor(index, extend);
+                }
+            }
+        ));
+        drag.connect_drag_update(glib_macros::clone!(
+            #[weak(rename_to = view)]
+            self,
+            move |gesture, dx, dy| {
+                if let Some((x, y)) = gesture.start_point()
+                    && let Some(index) = view.index_at(x + dx, y + dy)
+                {
+                    view.place_cursor(index, true);
+                }
+            }
+        ));
+        obj.add_controller(drag);
+    }
+
+    /// Rebuild the layout and drop cached sizes for changes that do not come from the buffer
+    fn invalidate(&self) {
         self.needs_update.replace(true);
+        self.size_cache.borrow_mut().clear();
+        self.obj().queue_resize();
+    }
+
+    fn set_highlight(&self, highlight: Option<String>) {
+        let highlight = highlight.filter(|h| !h.is_empty());
+        if *self.highlight.borrow() == highlight {
+            return;
+        }
+        self.highlight.replace(highlight);
+        self.invalidate();
+    }
+
+    pub(super) fn set_current_match(&self, range: Option<(usize, usize)>) {
+        if self.current_match.replace(range) != range {
+            self.needs_attributes.set(true);
+            self.obj().queue_draw();
+        }
+    }
+
+    fn set_line_height(&self, line_height: f64) {
+        if self.line_height.replace(line_height) != line_height {
+            self.invalidate();
+        }
+    }
+
+    fn set_scale(&self, scale: f64) {
+        if self.scale.replace(scale) != scale {
+            self.invalidate();
+        }
+    }
+
+    fn set_monospace_family(&self, family: Option<String>) {
+        let family = family.filter(|f| !f.is_empty());
+        if *self.monospace_family.borrow() == family {
+            return;
+        }
+        self.monospace_family.replace(family);
+        self.invalidate();
     }
 
     fn get_text(&self) -> String {
@@ -61,55 +665,352 @@ impl InlineView {
     }
 
     fn set_text(&self, text: &str) {
-        if let Some(buffer) = self.buffer.borrow().as_ref() {
-            buffer.set_text(text);
-            self.needs_update.replace(true);
-        } else {
-            let buffer = InlineBuffer::new();
-            buffer.set_text(text);
-            self.buffer.replace(Some(buffer));
-            self.needs_update.replace(true);
+        let buffer = self.buffer.borrow().clone();
+        match buffer {
+            Some(buffer) => buffer.set_text(text),
+            None => {
+                let buffer = InlineBuffer::new();
+                buffer.set_text(text);
+                self.set_buffer(Some(buffer));
+            }
         }
     }
 
-    fn rebuild_layout(&self) {
-        if let Some(buffer) = self.buffer.borrow().as_ref() {
-            let layout = self.obj().create_pango_layout(Some(&buffer.text()));
-            layout.set_wrap(pango::WrapMode::WordChar);
+    /// Find the link at widget coordinates
+    fn link_at(&self, x: f64, y: f64) -> Option<(String, InlinePos, InlinePos)> {
+        let layout = self.layout.borrow();
+        let (inside, index, _) = layout
+            .as_ref()?
+            .xy_to_index((x * SCALE as f64) as i32, (y * SCALE as f64) as i32);
+        if !inside {
+            return None;
+        }
 
-            // Apply attributes from buffer
-            let attr_list = buffer.build_pango_attributes();
-            layout.set_attributes(Some(&attr_list));
+        let index = self.buffer_index(index as usize)?;
+        self.buffer
+            .borrow()
+            .as_ref()?
+            .link_at(InlinePos::new(index))
+    }
+
+    /// Buffer offset of an index in the layout text, none inside the preedit
+    fn buffer_index(&self, index: usize) -> Option<usize> {
+        let cursor = self.cursor.get();
+        match self.preedit.borrow().as_ref() {
+            Some(preedit) if index >= cursor + preedit.text.len() => {
+                Some(index - preedit.text.len())
+            }
+            Some(_) if index >= cursor => None,
+            _ => Some(index),
+        }
+    }
+
+    /// Let handlers of `activate-link` claim the URL, otherwise open it externally
+    fn activate_link(&self, url: &str) {
+        let obj = self.obj();
+        if obj.emit_by_name::<bool>("activate-link", &[&url]) {
+            return;
+        }
+
+        let window = obj.root().and_downcast::<gtk::Window>();
+        gtk::UriLauncher::new(url).launch(window.as_ref(), gio::Cancellable::NONE, |result| {
+            if let Err(err) = result {
+                glib::g_warning!("potato-md", "Failed to open link: {err}");
+            }
+        });
+    }
+
+    fn update_hovered_link(&self, link: Option<(String, InlinePos, InlinePos)>) {
+        if *self.hovered_link.borrow() == link {
+            return;
+        }
+
+        let obj = self.obj();
+        if self.hovered_link.replace(link.clone()).is_some() {
+            obj.emit_by_name::<()>("link-leave", &[]);
+        }
+
+        if let Some((url, start, end)) = link {
+            obj.set_cursor_from_name(Some("pointer"));
+            let rect = self.range_rect(start, end);
+            obj.emit_by_name::<()>("link-enter", &[&url, &rect]);
+        } else {
+            obj.set_cursor_from_name(self.editable.get().then_some("text"));
+        }
+    }
 
-            self.layout.borrow_mut().replace(layout);
+    /// Area covered by a range on its first line, in widget coordinates
+    pub(super) fn range_rect(&self, start: InlinePos, end: InlinePos) -> gdk::Rectangle {
+        let Some(layout) = self.layout.borrow().clone() else {
+            return gdk::Rectangle::new(0, 0, 0, 0);
+        };
+
+        let first = layout.index_to_pos(start.offset() as i32);
+        let last = layout.index_to_pos(end.offset() as i32);
+        let width = if last.y() == first.y() {
+            last.x() - first.x()
         } else {
+            first.width()
+        };
+
+        gdk::Rectangle::new(
+            first.x() / SCALE,
+            first.y() / SCALE,
+            (width / SCALE).max(1),
+            first.height() / SCALE,
+        )
+    }
+
+    /// Refill the layout with the buffer text, reusing the layout object
+    fn rebuild_layout(&self) {
+        let Some(buffer) = self.buffer.borrow().clone() else {
+            self.disconnect_paintables();
             self.layout.borrow_mut().take();
+            return;
+        };
+
+        let text = buffer.text();
+        let mut layout_text = text.to_string();
+        if let Some(preedit) = self.preedit.borrow().as_ref() {
+            layout_text.insert_str(self.cursor.get(), &preedit.text);
         }
+        let layout = self.layout.borrow().clone();
+        match layout {
+            Some(layout) => layout.set_text(&layout_text),
+            None => {
+                let layout = self.obj().create_pango_layout(Some(&layout_text));
+                layout.set_wrap(pango::WrapMode::WordChar);
+                self.layout.replace(Some(layout));
+            }
+        }
+        if let Some(layout) = self.layout.borrow().as_ref() {
+            layout.set_line_spacing(self.line_height.get() as f32);
+        }
+        self.rebuild_attributes();
+
+        self.obj()
+            .update_property(&[gtk::accessible::Property::Label(&text)]);
     }
 
-    fn update_layout(&self, orientation: adw::gtk::Orientation, for_size: i32) -> (i32, i32) {
+    /// Apply attributes from the buffer, anchored paintables and search highlights
+    fn rebuild_attributes(&self) {
+        self.disconnect_paintables();
+
+        let (Some(buffer), Some(layout)) =
+            (self.buffer.borrow().clone(), self.layout.borrow().clone())
+        else {
+            return;
+        };
+
+        let attr_list = buffer.build_pango_attributes();
+        if let Some(family) = self.monospace_family.borrow().as_deref() {
+            replace_monospace(&attr_list, family);
+        }
+        if self.scale.get() != 1.0 {
+            attr_list.insert(pango::AttrFloat::new_scale(self.scale.get()));
+        }
+        self.reserve_paintable_space(&buffer, &attr_list);
+        if let Some(highlight) = self.highlight.borrow().as_deref() {
+            highlight_matches(&buffer.text(), highlight, &attr_list);
+        }
+        if let Some((start, end)) = self.current_match.get() {
+            highlight_current_match(start, end, &attr_list);
+        }
+        let (start, end) = self.selection_bounds();
+        if self.editable.get() && start != end {
+            highlight_selection(start, end, &attr_list);
+        }
+
+        // The preedit sits in the layout text at the cursor, shift everything after it
+        if let Some(preedit) = self.preedit.borrow().as_ref() {
+            let cursor = self.cursor.get();
+            let len = preedit.text.len();
+            attr_list.splice(&preedit.attributes, cursor as i32, len as i32);
+            for anchored in self.paintables.borrow_mut().iter_mut() {
+                if anchored.index as usize >= cursor {
+                    anchored.index += len as i32;
+                }
+            }
+        }
+        layout.set_attributes(Some(&attr_list));
+    }
+
+    /// Reserve room for anchors with shape attributes and track changes of their paintables
+    fn reserve_paintable_space(&self, buffer: &InlineBuffer, attr_list: &pango::AttrList) {
+        let mut paintables = self.paintables.borrow_mut();
+        let scale = |size: i32| (size as f64 * self.scale.get()).round() as i32;
+
+        for (pos, anchored) in buffer.anchors() {
+            let index = pos.offset() as i32;
+            let (width, height) = anchored
+                .as_ref()
+                .map(|(p, _)| (p.intrinsic_width().max(0), p.intrinsic_height().max(0)))
+                .unwrap_or_default();
+            let baseline = anchored
+                .as_ref()
+                .and_then(|(_, baseline)| *baseline)
+                .unwrap_or(height);
+            let (width, height, baseline) = (scale(width), scale(height), scale(baseline));
+
+            // Sit the paintable on the baseline, anchors without one collapse to nothing
+            let rect = pango::Rectangle::new(0, -baseline * SCALE, width * SCALE, height * SCALE);
+            let mut attr = pango::AttrShape::new(&rect, &rect);
+            attr.set_start_index(index as u32);
+            attr.set_end_index((index as usize + OBJECT_REPLACEMENT.len()) as u32);
+            attr_list.insert(attr);
+
+            let Some((paintable, _)) = anchored else {
+                continue;
+            };
+            let obj = self.obj();
+            let handlers = vec![
+                paintable.connect_invalidate_contents(glib_macros::clone!(
+                    #[weak]
+                    obj,
+                    move |_| obj.queue_draw()
+                )),
+                paintable.connect_invalidate_size(glib_macros::clone!(
+                    #[weak(rename_to = view)]
+                    self,
+                    move |_| view.invalidate()
+                )),
+            ];
+            paintables.push(AnchoredPaintable {
+                index,
+                paintable,
+                baseline,
+                handlers,
+            });
+        }
+    }
+
+    fn disconnect_paintables(&self) {
+        for anchored in self.paintables.take() {
+            for handler in anchored.handlers {
+                anchored.paintable.disconnect(handler);
+            }
+        }
+    }
+
+    /// Draw anchored paintables on top of the space reserved for them in the layout.
+    /// Symbolic paintables such as formulas are drawn in the text color.
+    fn snapshot_paintables(&self, snapshot: &adw::gtk::Snapshot, layout: &pango::Layout) {
+        let color = self.obj().color();
+
+        for anchored in self.paintables.borrow().iter() {
+            let paintable = &anchored.paintable;
+            let pos = layout.index_to_pos(anchored.index);
+            let (width, height) = (
+                paintable.intrinsic_width() as f64 * self.scale.get(),
+                paintable.intrinsic_height() as f64 * self.scale.get(),
+            );
+            let x = pos.x() as f32 / SCALE as f32;
+            let baseline = baseline_at(layout, anchored.index) as f32 / SCALE as f32;
+
+            snapshot.save();
+            snapshot.translate(&adw::gtk::graphene::Point::new(
+                x,
+                baseline - anchored.baseline as f32,
+            ));
+            if let Some(symbolic) = paintable.dynamic_cast_ref::<gtk::SymbolicPaintable>() {
+                symbolic.snapshot_symbolic(snapshot, width, height, &[color]);
+            } else {
+                paintable.snapshot(snapshot, width, height);
+            }
+            snapshot.restore();
+        }
+    }
+
+    /// Bring the layout up to date with the buffer
+    pub(super) fn ensure_layout(&self) {
         if self.needs_update.replace(false) {
+            self.needs_attributes.set(false);
             self.rebuild_layout();
+        } else if self.needs_attributes.replace(false) {
+            self.rebuild_attributes();
         }
+    }
 
-        if let Some(layout) = self.layout.borrow().as_ref() {
-            if orientation == adw::gtk::Orientation::Vertical
-                && for_size > 0
-                && layout.width() != for_size * SCALE
-            {
-                layout.set_width(for_size * SCALE);
-            }
+    /// Draw the caret while focused, in the visible phase of the blink and with nothing selected
+    fn snapshot_cursor(&self, snapshot: &adw::gtk::Snapshot, layout: &pango::Layout) {
+        let obj = self.obj();
+        let (start, end) = self.selection_bounds();
+        if !self.editable.get() || !obj.has_focus() || !self.cursor_visible.get() || start != end {
+            return;
         }
 
-        if let Some(layout) = self.layout.borrow().as_ref() {
-            let (width, height) = layout.size();
-            match orientation {
-                adw::gtk::Orientation::Horizontal => (0, width / SCALE),
-                adw::gtk::Orientation::Vertical => (height / SCALE, height / SCALE),
-                _ => (0, 0),
+        let (strong, _) = layout.cursor_pos(self.layout_cursor() as i32);
+        snapshot.append_color(
+            &obj.color(),
+            &adw::gtk::graphene::Rect::new(
+                strong.x() as f32 / SCALE as f32,
+                strong.y() as f32 / SCALE as f32,
+                1.0,
+                strong.height() as f32 / SCALE as f32,
+            ),
+        );
+    }
+
+    fn update_layout(&self, orientation: adw::gtk::Orientation, for_size: i32) -> (i32, i32) {
+        self.ensure_layout();
+
+        match orientation {
+            adw::gtk::Orientation::Horizontal => {
+                // Wrapping allows any width, the natural one is the unwrapped text
+                let (width, _) = self.layout_size(-1);
+                (0, width / SCALE)
             }
-        } else {
-            (0, 0)
+            adw::gtk::Orientation::Vertical => {
+                let (_, height) = self.layout_size(if for_size > 0 { for_size } else { -1 });
+                (height / SCALE, height / SCALE)
+            }
+            _ => (0, 0),
+        }
+    }
+
+    /// Size of the layout wrapped at `width` pixels, laid out only once per width and generation
+    fn layout_size(&self, width: i32) -> (i32, i32) {
+        let generation = self
+            .buffer
+            .borrow()
+            .as_ref()
+            .map(|buffer| buffer.generation())
+            .unwrap_or_default();
+        let key = (generation, width);
+        let serial = self.obj().pango_context().serial();
+        if self.context_serial.replace(serial) != serial {
+            self.size_cache.borrow_mut().clear();
+        }
+        if let Some(size) = self.size_cache.borrow().get(&key) {
+            return *size;
+        }
+        let Some(layout) = self.layout.borrow().clone() else {
+            return (0, 0);
+        };
+
+        let pango_width = if width < 0 { -1 } else { width * SCALE };
+        if layout.width() != pango_width {
+            layout.set_width(pango_width);
+        }
+        let size = layout.size();
+
+        // Sizes of older generations are stale, and widths come and go while
+        // a window is resized, so keep the cache small
+        let mut cache = self.size_cache.borrow_mut();
+        cache.retain(|(generation, _), _| *generation == key.0);
+        if cache.len() >= 16 {
+            cache.clear();
+        }
+        cache.insert(key, size);
+        size
+    }
+
+    /// Wrap the layout at `width` pixels unless it already is
+    pub(super) fn set_layout_width(&self, width: i32) {
+        if let Some(layout) = self.layout.borrow().as_ref()
+            && layout.width() != width * SCALE
+        {
+            layout.set_width(width * SCALE);
         }
     }
 }
@@ -119,14 +1020,128 @@ impl ObjectSubclass for InlineView {
     const NAME: &'static str = "InlineView";
     type Type = super::InlineView;
     type ParentType = adw::gtk::Widget;
+
+    fn class_init(klass: &mut Self::Class) {
+        klass.set_accessible_role(gtk::AccessibleRole::Label);
+
+        klass.install_action("text.undo", None, |view, _, _| {
+            if let Some(buffer) = view.buffer() {
+                buffer.undo();
+            }
+        });
+        klass.install_action("text.redo", None, |view, _, _| {
+            if let Some(buffer) = view.buffer() {
+                buffer.redo();
+            }
+        });
+        klass.add_binding_action(gdk::Key::z, gdk::ModifierType::CONTROL_MASK, "text.undo");
+        klass.add_binding_action(
+            gdk::Key::z,
+            gdk::ModifierType::CONTROL_MASK | gdk::ModifierType::SHIFT_MASK,
+            "text.redo",
+        );
+    }
 }
 
 #[glib::derived_properties]
 impl ObjectImpl for InlineView {
+    fn signals() -> &'static [glib::subclass::Signal] {
+        use std::sync::OnceLock;
+        static SIGNALS: OnceLock<Vec<glib::subclass::Signal>> = OnceLock::new();
+        SIGNALS.get_or_init(|| {
+            vec![
+                // Signal emitted when a link is clicked: (url: String) -> handled: bool
+                glib::subclass::Signal::builder("activate-link")
+                    .param_types([String::static_type()])
+                    .return_type::<bool>()
+                    .build(),
+                // Signal emitted when a link is clicked with the middle button: (url: String)
+                glib::subclass::Signal::builder("link-middle-clicked")
+                    .param_types([String::static_type()])
+                    .build(),
+                // Signal emitted when the pointer moves onto a link: (url: String, area: gdk::Rectangle)
+                glib::subclass::Signal::builder("link-enter")
+                    .param_types([String::static_type(), gdk::Rectangle::static_type()])
+                    .build(),
+                // Signal emitted when the pointer leaves the hovered link
+                glib::subclass::Signal::builder("link-leave").build(),
+                // Signal emitted when Enter is pressed in editable text, to split it at
+                // the caret: (offset: u32) -> handled: bool
+                glib::subclass::Signal::builder("split")
+                    .param_types([u32::static_type()])
+                    .return_type::<bool>()
+                    .build(),
+            ]
+        })
+    }
+
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let obj = self.obj();
+        // Properties passed to the builder are set after this, so this only sets the default
+        self.scale.set(1.0);
+        self.update_history_actions(self.buffer.borrow().as_ref());
+
+        let click = gtk::GestureClick::new();
+        click.set_button(0);
+        click.connect_released(glib_macros::clone!(
+            #[weak(rename_to = view)]
+            self,
+            move |gesture, n_press, x, y| {
+                // Editable text takes clicks for the caret, links open with Control held
+                let follow = !view.editable.get()
+                    || gesture
+                        .current_event_state()
+                        .contains(gdk::ModifierType::CONTROL_MASK);
+                if n_press != 1 {
+                    return;
+                }
+                let Some((url, _, _)) = view.link_at(x, y) else {
+                    return;
+                };
+                match gesture.current_button() {
+                    gdk::BUTTON_PRIMARY if follow => {
+                        gesture.set_state(gtk::EventSequenceState::Claimed);
+                        view.activate_link(&url);
+                    }
+                    gdk::BUTTON_MIDDLE => {
+                        gesture.set_state(gtk::EventSequenceState::Claimed);
+                        view.obj()
+                            .emit_by_name::<()>("link-middle-clicked", &[&url]);
+                    }
+                    _ => {}
+                }
+            }
+        ));
+        obj.add_controller(click);
+
+        let motion = gtk::EventControllerMotion::new();
+        motion.connect_motion(glib_macros::clone!(
+            #[weak(rename_to = view)]
+            self,
+            move |_, x, y| view.update_hovered_link(view.link_at(x, y))
+        ));
+        motion.connect_leave(glib_macros::clone!(
+            #[weak(rename_to = view)]
+            self,
+            move |_| view.update_hovered_link(None)
+        ));
+        obj.add_controller(motion);
+
+        self.setup_editing();
+    }
+
     fn dispose(&self) {
-        // Disconnect signal when widget is being destroyed
-        if let Some(signal_id) = self.buffer_signal_id.borrow_mut().take() {
-            if let Some(buffer) = self.buffer.borrow().as_ref() {
+        self.disconnect_paintables();
+        self.stop_blink();
+        if let Some(im_context) = self.im_context.get() {
+            im_context.set_client_widget(gtk::Widget::NONE);
+        }
+
+        // Disconnect signals when widget is being destroyed
+        if let Some(buffer) = self.buffer.borrow().as_ref() {
+            for signal_id in self.buffer_signal_ids.take() {
                 buffer.disconnect(signal_id);
             }
         }
@@ -143,16 +1158,24 @@ impl WidgetImpl for InlineView {
         (min, nat, -1, -1)
     }
 
+    fn size_allocate(&self, width: i32, height: i32, baseline: i32) {
+        self.parent_size_allocate(width, height, baseline);
+        self.set_layout_width(width);
+    }
+
     fn snapshot(&self, snapshot: &adw::gtk::Snapshot) {
+        // Selection changes only mark the attributes, and measuring at other widths
+        // may have rewrapped the layout since the allocation
+        self.ensure_layout();
+        self.set_layout_width(self.obj().width());
+
         let layout = self.layout.borrow();
-        let width = self.obj().width();
         if let Some(layout) = layout.as_ref() {
-            if layout.width() != width * SCALE {
-                layout.set_width(width * SCALE);
-            }
             snapshot.save();
             snapshot.translate(&adw::gtk::graphene::Point::new(0., 0.));
             snapshot.append_layout(layout, &self.obj().color());
+            self.snapshot_paintables(snapshot, layout);
+            self.snapshot_cursor(snapshot, layout);
             snapshot.restore();
         }
     }
@@ -167,3 +1190,75 @@ where
         Self::parent_class_init::<T>(class.upcast_ref_mut());
     }
 }
+
+/// Mark occurrences of `query` with a highlighter background
+fn highlight_matches(text: &str, query: &str, attr_list: &pango::AttrList) {
+    for range in markdown::find_matches(text, query) {
+        let mut color = pango::AttrColor::new_background(0xf6f6, 0xd3d3, 0x2d2d);
+        color.set_start_index(range.start as u32);
+        color.set_end_index(range.end as u32);
+        attr_list.insert(color);
+
+        let mut alpha = pango::AttrInt::new_background_alpha(0x9999);
+        alpha.set_start_index(range.start as u32);
+        alpha.set_end_index(range.end as u32);
+        attr_list.insert(alpha);
+    }
+}
+
+/// Mark the current search match with a stronger highlighter than the other matches
+fn highlight_current_match(start: usize, end: usize, attr_list: &pango::AttrList) {
+    let mut color = pango::AttrColor::new_background(0xf5f5, 0x7979, 0x0000);
+    color.set_start_index(start as u32);
+    color.set_end_index(end as u32);
+    attr_list.insert(color);
+
+    let mut alpha = pango::AttrInt::new_background_alpha(0xcccc);
+    alpha.set_start_index(start as u32);
+    alpha.set_end_index(end as u32);
+    attr_list.insert(alpha);
+}
+
+/// Replace the `monospace` font family in `attr_list` with `family`
+fn replace_monospace(attr_list: &pango::AttrList, family: &str) {
+    let Some(monospace) = attr_list.filter(|attr| {
+        attr.downcast_ref::<pango::AttrString>()
+            .is_some_and(|attr| {
+                attr.type_() == pango::AttrType::Family && attr.value() == "monospace"
+            })
+    }) else {
+        return;
+    };
+    for attr in monospace.attributes() {
+        let mut replacement = pango::AttrString::new_family(family);
+        replacement.set_start_index(attr.start_index());
+        replacement.set_end_index(attr.end_index());
+        attr_list.insert(replacement);
+    }
+}
+
+/// Mark the selected range with the selection color
+fn highlight_selection(start: usize, end: usize, attr_list: &pango::AttrList) {
+    let mut color = pango::AttrColor::new_background(0x3535, 0x8484, 0xe4e4);
+    color.set_start_index(start as u32);
+    color.set_end_index(end as u32);
+    attr_list.insert(color);
+
+    let mut alpha = pango::AttrInt::new_background_alpha(0x5555);
+    alpha.set_start_index(start as u32);
+    alpha.set_end_index(end as u32);
+    attr_list.insert(alpha);
+}
+
+/// Baseline of the line containing the byte `index`, in Pango units
+pub(super) fn baseline_at(layout: &pango::Layout, index: i32) -> i32 {
+    let mut iter = layout.iter();
+    let mut baseline = iter.baseline();
+    while iter.next_line() {
+        if iter.index() > index {
+            break;
+        }
+        baseline = iter.baseline();
+    }
+    baseline
+}
@@ -1,12 +1,20 @@
 mod buffer;
+mod editing;
 mod imp;
+mod print;
+mod serialize;
 
-use adw::gtk::glib;
-// use adw::gtk::subclass::prelude::*;
+use adw::gtk::prelude::*;
+use adw::gtk::subclass::prelude::*;
+use adw::gtk::{gdk, glib};
 
-pub use buffer::{InlineAnchor, InlineBuffer, InlinePos, TextAttr};
+pub use buffer::{InlineAnchor, InlineBuffer, InlinePos, TextAttr, TextAttrType};
+pub use print::PrintLayout;
 
 glib::wrapper! {
+    /// Text with inline formatting, links and paintables from an `InlineBuffer`.
+    /// The `buffer` and `text` properties give access to the content; setting `text`
+    /// replaces it with plain text, creating a buffer if the view has none.
     pub struct InlineView(ObjectSubclass<imp::InlineView>)
         @extends adw::gtk::Widget,
         @implements adw::gtk::Accessible, adw::gtk::Buildable, adw::gtk::ConstraintTarget;
@@ -18,32 +26,45 @@ impl InlineView {
         glib::Object::builder().build()
     }
 
-    // /// Create a new InlineView with a specific buffer
-    // pub fn with_buffer(buffer: &InlineBuffer) -> Self {
-    //     let view: Self = glib::Object::builder().build();
-    //     view.set_buffer(Some(buffer));
-    //     view
-    // }
-
-    // /// Get the buffer used by this view
-    // pub fn buffer(&self) -> Option<InlineBuffer> {
-    //     self.imp().buffer()
-    // }
-
-    // /// Set the buffer for this view
-    // pub fn set_buffer(&self, buffer: Option<&InlineBuffer>) {
-    //     self.imp().set_buffer(buffer.cloned());
-    // }
-
-    // /// Get the current text (convenience method)
-    // pub fn text(&self) -> String {
-    //     self.imp().text()
-    // }
-
-    // /// Set the text (convenience method - creates/updates buffer)
-    // pub fn set_text(&self, text: &str) {
-    //     self.imp().set_text(text);
-    // }
+    /// Start and end of the selection, equal when only the caret is placed
+    pub fn selection_bounds(&self) -> (InlinePos, InlinePos) {
+        let (start, end) = self.imp().selection_bounds();
+        (InlinePos::new(start), InlinePos::new(end))
+    }
+
+    /// Move the caret to `pos`, clearing the selection
+    pub fn place_cursor(&self, pos: InlinePos) {
+        self.imp().place_cursor(pos.offset(), false);
+    }
+
+    /// Select the text between `start` and `end`, with the caret at `end`
+    pub fn select_range(&self, start: InlinePos, end: InlinePos) {
+        self.imp().place_cursor(start.offset(), false);
+        self.imp().place_cursor(end.offset(), true);
+    }
+
+    /// Mark the text between the bounds as the current search match, drawn over the
+    /// `highlight` of the other matches, or remove the mark with `None`
+    pub fn set_current_match(&self, range: Option<(InlinePos, InlinePos)>) {
+        self.imp()
+            .set_current_match(range.map(|(start, end)| (start.offset(), end.offset())));
+    }
+
+    /// Area the text between `start` and `end` covers on the line of `start`, in widget
+    /// coordinates
+    pub fn range_area(&self, start: InlinePos, end: InlinePos) -> gdk::Rectangle {
+        let imp = self.imp();
+        imp.ensure_layout();
+        if self.width() > 0 {
+            imp.set_layout_width(self.width());
+        }
+        imp.range_rect(start, end)
+    }
+
+    /// Create a new InlineView showing `buffer`
+    pub fn with_buffer(buffer: &InlineBuffer) -> Self {
+        glib::Object::builder().property("buffer", buffer).build()
+    }
 }
 
 impl Default for InlineView {
@@ -0,0 +1,140 @@
+//! Laying out buffers for drawing with cairo, such as on printed pages
+
+use adw::gtk::prelude::*;
+use adw::gtk::{self, cairo, gdk, graphene, pango};
+use pango::SCALE;
+
+use super::buffer::{InlineBuffer, OBJECT_REPLACEMENT};
+use super::imp::baseline_at;
+
+/// Resolution that paintable sizes are given at, CSS pixels
+const PAINTABLE_DPI: f64 = 96.0;
+
+/// Paintable drawn at an anchor, with its size in device units
+struct Anchored {
+    index: i32,
+    paintable: gdk::Paintable,
+    width: f64,
+    /// Distance from the top to the baseline
+    baseline: f64,
+}
+
+/// Buffer content laid out on a Pango context for a device other than the screen.
+/// Anchored paintables are scaled from pixels to the resolution of the context.
+pub struct PrintLayout {
+    layout: pango::Layout,
+    anchored: Vec<Anchored>,
+}
+
+impl PrintLayout {
+    /// Lay out `buffer` in `font`, wrapped at `width` device units of a context
+    /// with `dpi` device units per inch
+    pub fn new(
+        buffer: &InlineBuffer,
+        context: &pango::Context,
+        font: &pango::FontDescription,
+        width: f64,
+        dpi: f64,
+    ) -> Self {
+        let layout = pango::Layout::new(context);
+        layout.set_font_description(Some(font));
+        layout.set_wrap(pango::WrapMode::WordChar);
+        layout.set_width((width * SCALE as f64) as i32);
+        layout.set_text(&buffer.text());
+
+        let scale = dpi / PAINTABLE_DPI;
+        let attr_list = buffer.build_pango_attributes();
+        let mut anchored = Vec::new();
+        for (pos, paintable) in buffer.anchors() {
+            let index = pos.offset() as i32;
+            let (width, height, baseline) = paintable
+                .as_ref()
+                .map(|(p, baseline)| {
+                    let height = p.intrinsic_height().max(0) as f64 * scale;
+                    let baseline = baseline.map_or(height, |baseline| baseline as f64 * scale);
+                    (p.intrinsic_width().max(0) as f64 * scale, height, baseline)
+                })
+                .unwrap_or_default();
+
+            // Sit the paintable on the baseline, like `InlineView` does
+            let units = |value: f64| (value * SCALE as f64) as i32;
+            let rect = pango::Rectangle::new(0, -units(baseline), units(width), units(height));
+            let mut attr = pango::AttrShape::new(&rect, &rect);
+            attr.set_start_index(index as u32);
+            attr.set_end_index((index as usize + OBJECT_REPLACEMENT.len()) as u32);
+            attr_list.insert(attr);
+
+            if let Some((paintable, _)) = paintable {
+                anchored.push(Anchored {
+                    index,
+                    paintable,
+                    width,
+                    baseline,
+                });
+            }
+        }
+        layout.set_attributes(Some(&attr_list));
+
+        Self { layout, anchored }
+    }
+
+    /// Height of the whole layout in device units
+    pub fn height(&self) -> f64 {
+        self.layout.size().1 as f64 / SCALE as f64
+    }
+
+    /// Top of every line followed by the bottom of the last, in device units
+    pub fn line_bounds(&self) -> Vec<f64> {
+        let mut bounds = Vec::new();
+        let mut iter = self.layout.iter();
+        loop {
+            let (_, logical) = iter.line_extents();
+            bounds.push(logical.y() as f64 / SCALE as f64);
+            if !iter.next_line() {
+                break;
+            }
+        }
+        bounds.push(self.height());
+        bounds
+    }
+
+    /// Draw the text and paintables with the top left corner at the origin
+    pub fn draw(&self, cr: &cairo::Context, color: &gdk::RGBA) {
+        let snapshot = gtk::Snapshot::new();
+        snapshot.append_layout(&self.layout, color);
+
+        for anchored in &self.anchored {
+            let pos = self.layout.index_to_pos(anchored.index);
+            let baseline = baseline_at(&self.layout, anchored.index) as f64 / SCALE as f64;
+            let (width, height) = (
+                anchored.paintable.intrinsic_width() as f64,
+                anchored.paintable.intrinsic_height() as f64,
+            );
+            let scale = if width > 0.0 {
+                (anchored.width / width) as f32
+            } else {
+                1.0
+            };
+
+            snapshot.save();
+            snapshot.translate(&graphene::Point::new(
+                (pos.x() as f64 / SCALE as f64) as f32,
+                (baseline - anchored.baseline) as f32,
+            ));
+            snapshot.scale(scale, scale);
+            if let Some(symbolic) = anchored
+                .paintable
+                .dynamic_cast_ref::<gtk::SymbolicPaintable>()
+            {
+                symbolic.snapshot_symbolic(&snapshot, width, height, &[*color]);
+            } else {
+                anchored.paintable.snapshot(&snapshot, width, height);
+            }
+            snapshot.restore();
+        }
+
+        if let Some(node) = snapshot.to_node() {
+            node.draw(cr);
+        }
+    }
+}
@@ -0,0 +1,779 @@
+//! Converting buffer content to and from markdown, Pango markup and HTML
+
+use std::collections::BTreeSet;
+
+use adw::gtk::{gdk, glib, pango};
+use glib::translate::IntoGlib;
+
+use super::buffer::{InlineBuffer, InlinePos, OBJECT_REPLACEMENT, TextAttr};
+use crate::markdown::{self, Block, Inline, Style};
+use crate::math;
+
+/// Inline markup that wraps other content, from the outermost
+#[derive(Debug, Clone, PartialEq)]
+enum Wrapper {
+    Link(String),
+    Strong,
+    Emphasis,
+    Strikethrough,
+}
+
+impl Wrapper {
+    fn wrap(self, content: Vec<Inline>) -> Inline {
+        match self {
+            Wrapper::Link(url) => match url.strip_prefix(markdown::WIKI_LINK_SCHEME) {
+                Some(target) => Inline::WikiLink {
+                    target: target.to_string(),
+                    content,
+                },
+                None => Inline::Link {
+                    url,
+                    title: String::new(),
+                    content,
+                },
+            },
+            Wrapper::Strong => Inline::Strong(content),
+            Wrapper::Emphasis => Inline::Emphasis(content),
+            Wrapper::Strikethrough => Inline::Strikethrough(content),
+        }
+    }
+}
+
+/// Attributes that apply to a run of text
+#[derive(Default)]
+struct Marks {
+    link: Option<String>,
+    strong: bool,
+    emphasis: bool,
+    strikethrough: bool,
+    code: bool,
+    superscript: bool,
+}
+
+impl Marks {
+    /// Marks of the attributes covering the byte range from `start` to `end`, with the
+    /// bounds of the link among them, or of the range itself without one
+    fn between(
+        attributes: &[(TextAttr, InlinePos, InlinePos)],
+        start: usize,
+        end: usize,
+    ) -> (Self, (usize, usize)) {
+        let mut marks = Marks::default();
+        let mut link_bounds = (start, end);
+        for (attr, attr_start, attr_end) in attributes {
+            if attr_start.offset() > start || attr_end.offset() < end {
+                continue;
+            }
+            match attr {
+                TextAttr::Bold => marks.strong = true,
+                TextAttr::Italic => marks.emphasis = true,
+                TextAttr::Strikethrough => marks.strikethrough = true,
+                TextAttr::FontFamily(family) if family == "monospace" => marks.code = true,
+                TextAttr::Superscript => marks.superscript = true,
+                TextAttr::Link(url) => {
+                    marks.link = Some(url.clone());
+                    link_bounds = (attr_start.offset(), attr_end.offset());
+                }
+                _ => {}
+            }
+        }
+        (marks, link_bounds)
+    }
+
+    fn wrappers(&self) -> Vec<Wrapper> {
+        let mut wrappers: Vec<Wrapper> = self.link.clone().map(Wrapper::Link).into_iter().collect();
+        if self.strong {
+            wrappers.push(Wrapper::Strong);
+        }
+        if self.emphasis {
+            wrappers.push(Wrapper::Emphasis);
+        }
+        if self.strikethrough {
+            wrappers.push(Wrapper::Strikethrough);
+        }
+        wrappers
+    }
+}
+
+/// Nests runs of inline content under their wrappers, sharing wrappers between neighbours
+#[derive(Default)]
+struct Nester {
+    root: Vec<Inline>,
+    open: Vec<(Wrapper, Vec<Inline>)>,
+}
+
+impl Nester {
+    fn push(&mut self, wrappers: Vec<Wrapper>, inline: Inline) {
+        let shared = self
+            .open
+            .iter()
+            .zip(&wrappers)
+            .take_while(|((open, _), wrapper)| open == *wrapper)
+            .count();
+        while self.open.len() > shared {
+            self.close();
+        }
+        for wrapper in wrappers.into_iter().skip(shared) {
+            self.open.push((wrapper, Vec::new()));
+        }
+
+        let content = match self.open.last_mut() {
+            Some((_, content)) => content,
+            None => &mut self.root,
+        };
+        match (content.last_mut(), inline) {
+            (Some(Inline::Text(last)), Inline::Text(text))
+            | (Some(Inline::Code(last)), Inline::Code(text)) => last.push_str(&text),
+            (_, inline) => content.push(inline),
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some((wrapper, content)) = self.open.pop() {
+            let inline = wrapper.wrap(content);
+            match self.open.last_mut() {
+                Some((_, parent)) => parent.push(inline),
+                None => self.root.push(inline),
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<Inline> {
+        while !self.open.is_empty() {
+            self.close();
+        }
+        self.root
+    }
+}
+
+/// Inline content for the text and attributes of a buffer, the inverse of rendering.
+/// Styling markdown has no syntax for, such as colours, is dropped.
+pub(super) fn to_inlines(buffer: &InlineBuffer) -> Vec<Inline> {
+    to_inlines_between(buffer, 0, buffer.text().len())
+}
+
+/// Inline content for the text between the byte offsets `from` and `to`. Footnote
+/// references are only kept when the range covers them whole, and get number 0 since
+/// the document they belong to numbers them.
+pub(super) fn to_inlines_between(buffer: &InlineBuffer, from: usize, to: usize) -> Vec<Inline> {
+    let text = buffer.text();
+    let attributes = buffer.attributes();
+
+    // Split the text wherever an attribute starts or ends, and around anchors and line breaks
+    let mut bounds = BTreeSet::from([from, to]);
+    for (_, start, end) in &attributes {
+        bounds.extend([start.offset(), end.offset()]);
+    }
+    for (offset, c) in text[from..to].char_indices() {
+        if OBJECT_REPLACEMENT.starts_with(c) || c == '\n' {
+            bounds.extend([from + offset, from + offset + c.len_utf8()]);
+        }
+    }
+    let bounds: Vec<usize> = bounds
+        .into_iter()
+        .filter(|&offset| (from..=to).contains(&offset) && text.is_char_boundary(offset))
+        .collect();
+
+    let mut nester = Nester::default();
+    for range in bounds.windows(2) {
+        let (start, end) = (range[0], range[1]);
+        let (mut marks, link_bounds) = Marks::between(&attributes, start, end);
+
+        let piece = &text[start..end];
+        if piece == OBJECT_REPLACEMENT {
+            let Some(source) = buffer.anchor_source_at(InlinePos::new(start)) else {
+                continue;
+            };
+            let wrappers = marks.wrappers();
+            for inline in source_inlines(&source) {
+                nester.push(wrappers.clone(), inline);
+            }
+            continue;
+        }
+
+        let footnote = marks
+            .link
+            .as_deref()
+            .filter(|_| marks.superscript)
+            .and_then(|url| url.strip_prefix("#fn-"))
+            .map(str::to_string);
+        if footnote.is_some() {
+            marks.link = None;
+        }
+        let inline = if let Some(label) = footnote {
+            // The digits of a reference are one piece, other attributes may split them
+            // and a reference cut by the range is left as plain text
+            let (link_start, link_end) = link_bounds;
+            if link_start < from || link_end > to {
+                Inline::Text(piece.to_string())
+            } else {
+                if start == link_start {
+                    let (mut marks, _) = Marks::between(&attributes, link_start, link_end);
+                    marks.link = None;
+                    nester.push(
+                        marks.wrappers(),
+                        Inline::FootnoteReference { label, number: 0 },
+                    );
+                }
+                continue;
+            }
+        } else if piece == "\n" {
+            Inline::HardBreak
+        } else if marks.code {
+            Inline::Code(piece.to_string())
+        } else {
+            Inline::Text(piece.to_string())
+        };
+        nester.push(marks.wrappers(), inline);
+    }
+    nester.finish()
+}
+
+/// Inline content for the markdown an anchor stands for
+fn source_inlines(source: &str) -> Vec<Inline> {
+    match markdown::parse(source).blocks.into_iter().next() {
+        Some(Block::Paragraph(content)) => content,
+        Some(Block::Image(image)) => vec![Inline::Image(image)],
+        Some(Block::Math(source)) => vec![Inline::Math {
+            source,
+            display: true,
+        }],
+        _ => Vec::new(),
+    }
+}
+
+pub(super) fn to_markdown(buffer: &InlineBuffer) -> String {
+    markdown::write_inlines(&to_inlines(buffer), &Style::default())
+}
+
+/// Write the buffer text with a start and end tag around every attribute span.
+/// Spans are opened outermost first and reopened where they overlap, so tags always nest.
+fn write_tagged(
+    buffer: &InlineBuffer,
+    tag: impl Fn(&TextAttr) -> (String, &'static str),
+    mut write_text: impl FnMut(&str, InlinePos, &mut String),
+) -> String {
+    let text = buffer.text();
+    let mut spans: Vec<_> = buffer
+        .attributes()
+        .into_iter()
+        .filter(|(_, start, end)| start < end)
+        .map(|(attr, start, end)| {
+            let (open, close) = tag(&attr);
+            (start.offset(), end.offset(), open, close)
+        })
+        .collect();
+    spans.sort_by_key(|&(start, end, ..)| (start, std::cmp::Reverse(end)));
+
+    let mut bounds = BTreeSet::from([0, text.len()]);
+    for &(start, end, ..) in &spans {
+        bounds.extend([start, end]);
+    }
+    let bounds: Vec<usize> = bounds
+        .into_iter()
+        .filter(|&offset| offset <= text.len() && text.is_char_boundary(offset))
+        .collect();
+
+    let mut out = String::new();
+    let mut open: Vec<usize> = Vec::new();
+    for range in bounds.windows(2) {
+        let (start, end) = (range[0], range[1]);
+        let active: Vec<usize> = (0..spans.len())
+            .filter(|&i| spans[i].0 <= start && spans[i].1 >= end)
+            .collect();
+
+        let shared = open
+            .iter()
+            .zip(&active)
+            .take_while(|(open, active)| open == active)
+            .count();
+        for &i in open[shared..].iter().rev() {
+            out.push_str(spans[i].3);
+        }
+        for &i in &active[shared..] {
+            out.push_str(&spans[i].2);
+        }
+        open = active;
+
+        write_text(&text[start..end], InlinePos::new(start), &mut out);
+    }
+    for &i in open.iter().rev() {
+        out.push_str(spans[i].3);
+    }
+    out
+}
+
+/// `#rrggbb` notation for a colour
+fn hex_color(color: &gdk::RGBA) -> String {
+    let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        channel(color.red()),
+        channel(color.green()),
+        channel(color.blue())
+    )
+}
+
+/// Markup that `pango::parse_markup` accepts, apart from links which are written as
+/// `<a href="…">` the way `gtk::Label` takes them. Anchors stay object replacement characters.
+pub(super) fn to_pango_markup(buffer: &InlineBuffer) -> String {
+    write_tagged(
+        buffer,
+        |attr| match attr {
+            TextAttr::Bold => ("<b>".into(), "</b>"),
+            TextAttr::Italic => ("<i>".into(), "</i>"),
+            TextAttr::Underline => ("<u>".into(), "</u>"),
+            TextAttr::Strikethrough => ("<s>".into(), "</s>"),
+            TextAttr::Color(color) => (
+                format!("<span foreground=\"{}\">", hex_color(color)),
+                "</span>",
+            ),
+            TextAttr::Link(url) => (
+                format!("<a href=\"{}\">", glib::markup_escape_text(url)),
+                "</a>",
+            ),
+            TextAttr::FontSize(size) => (
+                format!("<span size=\"{}\">", size * pango::SCALE),
+                "</span>",
+            ),
+            TextAttr::FontFamily(family) => (
+                format!(
+                    "<span font_family=\"{}\">",
+                    glib::markup_escape_text(family)
+                ),
+                "</span>",
+            ),
+            TextAttr::Superscript => ("<sup>".into(), "</sup>"),
+        },
+        |text, _, out| out.push_str(&glib::markup_escape_text(text)),
+    )
+}
+
+/// HTML fragment for the buffer, with line breaks as `<br>` and anchors as the images
+/// or MathML formulas they stand for
+pub(super) fn to_html(buffer: &InlineBuffer) -> String {
+    write_tagged(
+        buffer,
+        |attr| match attr {
+            TextAttr::Bold => ("<strong>".into(), "</strong>"),
+            TextAttr::Italic => ("<em>".into(), "</em>"),
+            TextAttr::Underline => ("<u>".into(), "</u>"),
+            TextAttr::Strikethrough => ("<s>".into(), "</s>"),
+            TextAttr::Color(color) => (
+                format!("<span style=\"color: {}\">", hex_color(color)),
+                "</span>",
+            ),
+            TextAttr::Link(url) => (
+                format!("<a href=\"{}\">", glib::markup_escape_text(url)),
+                "</a>",
+            ),
+            TextAttr::FontSize(size) => {
+                (format!("<span style=\"font-size: {size}pt\">"), "</span>")
+            }
+            TextAttr::FontFamily(family) if family == "monospace" => ("<code>".into(), "</code>"),
+            TextAttr::FontFamily(family) => (
+                format!(
+                    "<span style=\"font-family: {}\">",
+                    glib::markup_escape_text(family)
+                ),
+                "</span>",
+            ),
+            TextAttr::Superscript => ("<sup>".into(), "</sup>"),
+        },
+        |text, pos, out| {
+            for (offset, c) in text.char_indices() {
+                match c {
+                    '\n' => out.push_str("<br>\n"),
+                    c if OBJECT_REPLACEMENT.starts_with(c) => {
+                        let source = buffer.anchor_source_at(InlinePos::new(pos.offset() + offset));
+                        if let Some(source) = source {
+                            out.push_str(&anchor_html(&source));
+                        }
+                    }
+                    c => out.push_str(&glib::markup_escape_text(c.encode_utf8(&mut [0; 4]))),
+                }
+            }
+        },
+    )
+}
+
+/// HTML for the markdown an anchor stands for
+fn anchor_html(source: &str) -> String {
+    source_inlines(source)
+        .iter()
+        .map(|inline| match inline {
+            Inline::Image(image) => {
+                let mut html = format!(
+                    "<img src=\"{}\" alt=\"{}\"",
+                    glib::markup_escape_text(&image.url),
+                    glib::markup_escape_text(&image.alt)
+                );
+                if !image.title.is_empty() {
+                    html.push_str(&format!(
+                        " title=\"{}\"",
+                        glib::markup_escape_text(&image.title)
+                    ));
+                }
+                html.push('>');
+                html
+            }
+            Inline::Math { source, display } => match math::parse(source) {
+                Ok(formula) => math::to_mathml(&formula, *display),
+                Err(_) => format!(
+                    "<code class=\"math\">{}</code>",
+                    glib::markup_escape_text(source)
+                ),
+            },
+            inline => glib::markup_escape_text(&Inline::plain_text(std::slice::from_ref(inline)))
+                .to_string(),
+        })
+        .collect()
+}
+
+/// Prefix of the font features that stand in for links while Pango parses the markup
+const LINK_FEATURES: &str = "potato-md-link-";
+
+/// Replace `<a href="…">` elements with spans Pango accepts, returning the link URLs.
+/// Each span carries the index of its URL in its font features.
+fn extract_links(markup: &str) -> (String, Vec<String>) {
+    let mut out = String::with_capacity(markup.len());
+    let mut urls = Vec::new();
+    let mut rest = markup;
+
+    while let Some(start) = rest.find('<') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+        let tag = &rest[..end];
+
+        if tag == "</a>" {
+            out.push_str("</span>");
+        } else if tag.starts_with("<a ") || tag == "<a>" {
+            let url = attribute_value(tag, "href").unwrap_or_default();
+            out.push_str(&format!(
+                "<span font_features=\"{LINK_FEATURES}{}\">",
+                urls.len()
+            ));
+            urls.push(unescape(url));
+        } else {
+            out.push_str(tag);
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    (out, urls)
+}
+
+/// Value of an attribute in a start tag, still escaped
+fn attribute_value<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let (_, value) = tag.split_once(&format!("{name}="))?;
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let value = &value[1..];
+    value.find(quote).map(|end| &value[..end])
+}
+
+/// Resolve the entities `glib::markup_escape_text` produces
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Buffer holding the text and the styling Pango markup describes that `TextAttr` can express.
+/// Links may be given as `<a href="…">`, and object replacement characters become anchors.
+pub(super) fn from_pango_markup(markup: &str) -> Result<InlineBuffer, glib::Error> {
+    let (markup, urls) = extract_links(markup);
+    let (attr_list, text, _) = pango::parse_markup(&markup, '\0')?;
+
+    let buffer = InlineBuffer::new();
+    buffer.begin_irreversible_action();
+    for (i, piece) in text.split(OBJECT_REPLACEMENT).enumerate() {
+        if i > 0 {
+            buffer.push_anchor();
+        }
+        buffer.push_str(piece);
+    }
+
+    for attribute in attr_list.attributes() {
+        let int_value = || {
+            attribute
+                .downcast_ref::<pango::AttrInt>()
+                .map(pango::AttrInt::value)
+        };
+        let attr = match attribute.type_() {
+            pango::AttrType::Weight => int_value()
+                .filter(|weight| *weight >= pango::Weight::Bold.into_glib())
+                .map(|_| TextAttr::Bold),
+            pango::AttrType::Style => int_value()
+                .filter(|style| *style != pango::Style::Normal.into_glib())
+                .map(|_| TextAttr::Italic),
+            pango::AttrType::Underline => int_value()
+                .filter(|underline| *underline != pango::Underline::None.into_glib())
+                .map(|_| TextAttr::Underline),
+            pango::AttrType::Strikethrough => int_value()
+                .filter(|strikethrough| *strikethrough != 0)
+                .map(|_| TextAttr::Strikethrough),
+            pango::AttrType::BaselineShift => int_value()
+                .filter(|shift| *shift == pango::BaselineShift::Superscript.into_glib())
+                .map(|_| TextAttr::Superscript),
+            pango::AttrType::Foreground => {
+                attribute.downcast_ref::<pango::AttrColor>().map(|attr| {
+                    let color = attr.color();
+                    TextAttr::Color(gdk::RGBA::new(
+                        color.red() as f32 / 65535.0,
+                        color.green() as f32 / 65535.0,
+                        color.blue() as f32 / 65535.0,
+                        1.0,
+                    ))
+                })
+            }
+            pango::AttrType::Size => attribute
+                .downcast_ref::<pango::AttrSize>()
+                .map(|attr| TextAttr::FontSize(attr.size() / pango::SCALE)),
+            pango::AttrType::Family => attribute
+                .downcast_ref::<pango::AttrString>()
+                .map(|attr| TextAttr::FontFamily(attr.value().to_string())),
+            pango::AttrType::FontFeatures => attribute
+                .downcast_ref::<pango::AttrFontFeatures>()
+                .and_then(|attr| {
+                    attr.features()
+                        .strip_prefix(LINK_FEATURES)
+                        .and_then(|index| index.parse::<usize>().ok())
+                })
+                .and_then(|index| urls.get(index).cloned())
+                .map(TextAttr::Link),
+            _ => None,
+        };
+
+        if let Some(attr) = attr {
+            let end = (attribute.end_index() as usize).min(text.len());
+            buffer.apply_attribute(
+                InlinePos::new(attribute.start_index() as usize),
+                InlinePos::new(end),
+                attr,
+            );
+        }
+    }
+    buffer.end_irreversible_action();
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    /// Buffer with `text` and each attribute applied to its byte range
+    fn styled(text: &str, attributes: Vec<(TextAttr, usize, usize)>) -> InlineBuffer {
+        let buffer = InlineBuffer::new();
+        buffer.push_str(text);
+        for (attr, start, end) in attributes {
+            buffer.apply_attribute(InlinePos::new(start), InlinePos::new(end), attr);
+        }
+        buffer
+    }
+
+    /// Ranges covered by each attribute, merged where spans touch, since writing markup
+    /// may split a span where it overlaps others
+    fn coverage(buffer: &InlineBuffer) -> BTreeMap<String, Vec<(usize, usize)>> {
+        let mut coverage = BTreeMap::<_, Vec<(usize, usize)>>::new();
+        for (attr, start, end) in buffer.attributes() {
+            coverage
+                .entry(format!("{attr:?}"))
+                .or_default()
+                .push((start.offset(), end.offset()));
+        }
+        for ranges in coverage.values_mut() {
+            ranges.sort();
+            let mut merged: Vec<(usize, usize)> = Vec::new();
+            for &(start, end) in ranges.iter() {
+                match merged.last_mut() {
+                    Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                    _ => merged.push((start, end)),
+                }
+            }
+            *ranges = merged;
+        }
+        coverage
+    }
+
+    fn assert_markup_round_trip(buffer: &InlineBuffer) {
+        let markup = to_pango_markup(buffer);
+        let parsed = from_pango_markup(&markup).expect("Markup does not parse");
+        assert_eq!(parsed.text(), buffer.text(), "markup: {markup}");
+        assert_eq!(coverage(&parsed), coverage(buffer), "markup: {markup}");
+    }
+
+    #[test]
+    fn markup_round_trips_nested_attributes() {
+        let buffer = styled(
+            "bold italic struck code",
+            vec![
+                (TextAttr::Bold, 0, 11),
+                (TextAttr::Italic, 5, 18),
+                (TextAttr::Strikethrough, 12, 18),
+                (TextAttr::FontFamily("monospace".into()), 19, 23),
+                (TextAttr::Superscript, 19, 23),
+            ],
+        );
+        assert_markup_round_trip(&buffer);
+    }
+
+    #[test]
+    fn markup_round_trips_links() {
+        let buffer = styled(
+            "see the docs or the page",
+            vec![
+                (
+                    TextAttr::Link("https://example.com/?a=1&b=\"2\"".into()),
+                    8,
+                    12,
+                ),
+                (TextAttr::Bold, 4, 12),
+                (TextAttr::Link("wiki:Other Page".into()), 20, 24),
+            ],
+        );
+        assert_markup_round_trip(&buffer);
+    }
+
+    #[test]
+    fn markup_escapes_text() {
+        let buffer = styled("a < b && c > 'd'", vec![(TextAttr::Bold, 2, 3)]);
+        let markup = to_pango_markup(&buffer);
+        assert!(markup.contains("&lt;"), "{markup}");
+        assert!(markup.contains("&amp;&amp;"), "{markup}");
+        assert_markup_round_trip(&buffer);
+    }
+
+    #[test]
+    fn markup_keeps_anchors() {
+        let buffer = InlineBuffer::new();
+        buffer.push_str("before ");
+        buffer.push_anchor();
+        buffer.push_str(" after");
+        buffer.apply_attribute(buffer.start_pos(), buffer.current_pos(), TextAttr::Italic);
+
+        let parsed = from_pango_markup(&to_pango_markup(&buffer)).unwrap();
+        assert_eq!(parsed.text(), buffer.text());
+        let positions = |buffer: &InlineBuffer| {
+            buffer
+                .anchors()
+                .into_iter()
+                .map(|(pos, _)| pos)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(positions(&parsed), positions(&buffer));
+        assert_eq!(coverage(&parsed), coverage(&buffer));
+    }
+
+    #[test]
+    fn inlines_nest_under_shared_wrappers() {
+        let buffer = styled(
+            "link text after",
+            vec![
+                (TextAttr::Link("https://example.com".into()), 0, 9),
+                (TextAttr::Bold, 0, 4),
+            ],
+        );
+        assert_eq!(
+            to_inlines(&buffer),
+            [
+                Inline::Link {
+                    url: "https://example.com".into(),
+                    title: String::new(),
+                    content: vec![
+                        Inline::Strong(vec![Inline::Text("link".into())]),
+                        Inline::Text(" text".into()),
+                    ],
+                },
+                Inline::Text(" after".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn inlines_keep_anchor_sources() {
+        let buffer = InlineBuffer::new();
+        buffer.push_str("An image ");
+        buffer.push_anchor_with_source("![alt](image.png)");
+        assert_eq!(
+            to_inlines(&buffer),
+            [
+                Inline::Text("An image ".into()),
+                Inline::Image(markdown::Image {
+                    url: "image.png".into(),
+                    alt: "alt".into(),
+                    ..Default::default()
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn footnote_references_are_atomic() {
+        let buffer = styled(
+            "See 12 here",
+            vec![
+                (TextAttr::Superscript, 4, 6),
+                (TextAttr::Link("#fn-note".into()), 4, 6),
+                (TextAttr::Bold, 4, 5),
+            ],
+        );
+        let reference = Inline::FootnoteReference {
+            label: "note".into(),
+            number: 0,
+        };
+        assert_eq!(
+            to_inlines(&buffer),
+            [
+                Inline::Text("See ".into()),
+                reference.clone(),
+                Inline::Text(" here".into()),
+            ]
+        );
+        assert_eq!(
+            to_inlines_between(&buffer, 0, 5),
+            [
+                Inline::Text("See ".into()),
+                Inline::Strong(vec![Inline::Text("1".into())]),
+            ]
+        );
+        assert_eq!(
+            to_inlines_between(&buffer, 5, 11),
+            [Inline::Text("2 here".into())]
+        );
+        assert_eq!(to_inlines_between(&buffer, 4, 6), [reference]);
+    }
+
+    #[test]
+    fn markdown_escapes_delimiters_at_word_boundaries() {
+        let buffer = styled(
+            "2*3 is snake_case and *not emphasis*",
+            vec![(TextAttr::Italic, 13, 17)],
+        );
+        let markdown = to_markdown(&buffer);
+        assert_eq!(markdown, r"2\*3 is snake\_*case* and \*not emphasis\*");
+        match &markdown::parse(&markdown).blocks[..] {
+            [Block::Paragraph(content)] => assert_eq!(*content, to_inlines(&buffer)),
+            blocks => panic!("Unexpected blocks {blocks:?}"),
+        }
+    }
+
+    #[test]
+    fn html_escapes_text_and_writes_anchors() {
+        let buffer = styled(
+            "a < b & code",
+            vec![(TextAttr::FontFamily("monospace".into()), 8, 12)],
+        );
+        buffer.push_str(" ");
+        buffer.push_anchor_with_source("![a \"quote\"](x.png)");
+        assert_eq!(
+            to_html(&buffer),
+            "a &lt; b &amp; <code>code</code> <img src=\"x.png\" alt=\"a &quot;quote&quot;\">"
+        );
+    }
+}
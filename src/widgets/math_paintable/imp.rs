@@ -0,0 +1,91 @@
+use std::cell::OnceCell;
+
+use adw::gdk;
+use adw::gtk::prelude::*;
+use adw::gtk::subclass::prelude::*;
+use adw::gtk::{self, glib};
+
+use super::layout::MathBox;
+
+/// Room around the ink so antialiased edges are not clipped
+const PADDING: f32 = 1.0;
+
+#[derive(Default)]
+pub struct MathPaintable {
+    pub(super) math_box: OnceCell<MathBox>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for MathPaintable {
+    const NAME: &'static str = "MathPaintable";
+    type Type = super::MathPaintable;
+    type Interfaces = (gdk::Paintable, gtk::SymbolicPaintable);
+}
+
+impl ObjectImpl for MathPaintable {}
+
+impl PaintableImpl for MathPaintable {
+    fn intrinsic_width(&self) -> i32 {
+        self.math_box
+            .get()
+            .map(|b| (b.width + 2.0 * PADDING).ceil() as i32)
+            .unwrap_or(0)
+    }
+
+    fn intrinsic_height(&self) -> i32 {
+        self.math_box
+            .get()
+            .map(|b| (b.ascent.ceil() + b.descent.ceil() + 2.0 * PADDING) as i32)
+            .unwrap_or(0)
+    }
+
+    fn flags(&self) -> gdk::PaintableFlags {
+        gdk::PaintableFlags::SIZE | gdk::PaintableFlags::CONTENTS
+    }
+
+    fn snapshot(&self, snapshot: &gdk::Snapshot, width: f64, height: f64) {
+        self.snapshot_symbolic(snapshot, width, height, &[]);
+    }
+}
+
+impl SymbolicPaintableImpl for MathPaintable {
+    fn snapshot_symbolic(
+        &self,
+        snapshot: &gdk::Snapshot,
+        width: f64,
+        height: f64,
+        colors: &[gdk::RGBA],
+    ) {
+        let (Some(math_box), Some(snapshot)) = (
+            self.math_box.get(),
+            snapshot.downcast_ref::<gtk::Snapshot>(),
+        ) else {
+            return;
+        };
+        let color = colors.first().copied().unwrap_or(gdk::RGBA::BLACK);
+
+        let (intrinsic_width, intrinsic_height) = (
+            self.intrinsic_width() as f64,
+            self.intrinsic_height() as f64,
+        );
+        snapshot.save();
+        if intrinsic_width > 0.0 && intrinsic_height > 0.0 {
+            snapshot.scale(
+                (width / intrinsic_width) as f32,
+                (height / intrinsic_height) as f32,
+            );
+        }
+        snapshot.translate(&gtk::graphene::Point::new(PADDING, self.baseline() as f32));
+        math_box.snapshot(snapshot, &color);
+        snapshot.restore();
+    }
+}
+
+impl MathPaintable {
+    pub(super) fn baseline(&self) -> i32 {
+        self.math_box
+            .get()
+            .map(|b| (b.ascent.ceil() + PADDING) as i32)
+            .unwrap_or(0)
+    }
+}
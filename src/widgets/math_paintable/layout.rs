@@ -0,0 +1,744 @@
+use std::cell::Cell;
+
+use adw::gdk;
+use adw::gtk::pango::{self, SCALE};
+use adw::gtk::prelude::*;
+use adw::gtk::{graphene, gsk};
+
+use crate::math::{MathNode, SymbolClass};
+
+/// Math style, which decides the font size and how scripts and limits are placed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Style {
+    Display,
+    Text,
+    Script,
+    ScriptScript,
+}
+
+impl Style {
+    fn scale(self) -> f32 {
+        match self {
+            Style::Display | Style::Text => 1.0,
+            Style::Script => 0.7,
+            Style::ScriptScript => 0.5,
+        }
+    }
+
+    fn script(self) -> Style {
+        match self {
+            Style::Display | Style::Text => Style::Script,
+            Style::Script | Style::ScriptScript => Style::ScriptScript,
+        }
+    }
+
+    fn fraction(self) -> Style {
+        match self {
+            Style::Display => Style::Text,
+            style => style.script(),
+        }
+    }
+
+    fn is_script(self) -> bool {
+        matches!(self, Style::Script | Style::ScriptScript)
+    }
+}
+
+/// Something to draw, positioned relative to the baseline origin of its box
+enum Item {
+    /// Pango layout with its top-left corner at `(x, y)`, stretched vertically by `scale_y`
+    Text {
+        layout: pango::Layout,
+        x: f32,
+        y: f32,
+        scale_y: f32,
+    },
+    Rule {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    },
+    Polyline {
+        points: Vec<(f32, f32)>,
+        line_width: f32,
+    },
+}
+
+/// Laid out formula: extents around the baseline and the items to draw
+pub(super) struct MathBox {
+    pub(super) width: f32,
+    pub(super) ascent: f32,
+    pub(super) descent: f32,
+    items: Vec<Item>,
+}
+
+impl MathBox {
+    fn empty(width: f32) -> Self {
+        Self {
+            width,
+            ascent: 0.0,
+            descent: 0.0,
+            items: Vec::new(),
+        }
+    }
+
+    /// Move the contents of `other` into this box at `(dx, dy)` without changing the extents
+    fn append(&mut self, other: MathBox, dx: f32, dy: f32) {
+        for mut item in other.items {
+            match &mut item {
+                Item::Text { x, y, .. } | Item::Rule { x, y, .. } => {
+                    *x += dx;
+                    *y += dy;
+                }
+                Item::Polyline { points, .. } => {
+                    for (x, y) in points {
+                        *x += dx;
+                        *y += dy;
+                    }
+                }
+            }
+            self.items.push(item);
+        }
+    }
+
+    /// Shift the box up by `dy`
+    fn raise(mut self, dy: f32) -> Self {
+        let items = std::mem::take(&mut self.items);
+        self.append(
+            MathBox {
+                width: 0.0,
+                ascent: 0.0,
+                descent: 0.0,
+                items,
+            },
+            0.0,
+            -dy,
+        );
+        self.ascent += dy;
+        self.descent -= dy;
+        self
+    }
+
+    /// Centre the contents in a box of `width`
+    fn with_width(self, width: f32) -> Self {
+        let mut hbox = MathBox {
+            width,
+            ascent: self.ascent,
+            descent: self.descent,
+            items: Vec::new(),
+        };
+        let x = (width - self.width) / 2.0;
+        hbox.append(self, x, 0.0);
+        hbox
+    }
+
+    pub(super) fn snapshot(&self, snapshot: &adw::gtk::Snapshot, color: &gdk::RGBA) {
+        for item in &self.items {
+            match item {
+                Item::Text {
+                    layout,
+                    x,
+                    y,
+                    scale_y,
+                } => {
+                    snapshot.save();
+                    snapshot.translate(&graphene::Point::new(*x, *y));
+                    snapshot.scale(1.0, *scale_y);
+                    snapshot.append_layout(layout, color);
+                    snapshot.restore();
+                }
+                Item::Rule {
+                    x,
+                    y,
+                    width,
+                    height,
+                } => {
+                    snapshot.append_color(color, &graphene::Rect::new(*x, *y, *width, *height));
+                }
+                Item::Polyline { points, line_width } => {
+                    let builder = gsk::PathBuilder::new();
+                    for (i, (x, y)) in points.iter().enumerate() {
+                        if i == 0 {
+                            builder.move_to(*x, *y);
+                        } else {
+                            builder.line_to(*x, *y);
+                        }
+                    }
+                    let stroke = gsk::Stroke::new(*line_width);
+                    stroke.set_line_join(gsk::LineJoin::Round);
+                    snapshot.append_stroke(&builder.to_path(), &stroke, color);
+                }
+            }
+        }
+    }
+}
+
+/// Spacing class of a laid out atom
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    Ordinary,
+    Operator,
+    Binary,
+    Relation,
+    Open,
+    Close,
+    Punctuation,
+}
+
+impl From<SymbolClass> for Class {
+    fn from(class: SymbolClass) -> Self {
+        match class {
+            SymbolClass::Ordinary => Class::Ordinary,
+            SymbolClass::Binary => Class::Binary,
+            SymbolClass::Relation => Class::Relation,
+            SymbolClass::Punctuation => Class::Punctuation,
+            SymbolClass::Open => Class::Open,
+            SymbolClass::Close => Class::Close,
+        }
+    }
+}
+
+fn class_of(node: &MathNode) -> Option<Class> {
+    match node {
+        MathNode::Space(_) => None,
+        MathNode::Row(items) if items.is_empty() => None,
+        MathNode::Symbol(_, class) => Some((*class).into()),
+        MathNode::Operator { .. } => Some(Class::Operator),
+        MathNode::Scripts { base, .. } => class_of(base).or(Some(Class::Ordinary)),
+        _ => Some(Class::Ordinary),
+    }
+}
+
+/// Space between two adjacent atoms in em, after TeX's inter-atom spacing table
+fn spacing(left: Class, right: Class, style: Style) -> f32 {
+    const THIN: f32 = 3.0 / 18.0;
+    const MEDIUM: f32 = 4.0 / 18.0;
+    const THICK: f32 = 5.0 / 18.0;
+
+    let script = style.is_script();
+    match (left, right) {
+        (Class::Operator, Class::Ordinary | Class::Operator)
+        | (Class::Ordinary | Class::Close, Class::Operator) => THIN,
+        (Class::Binary, _) | (_, Class::Binary) if !script => MEDIUM,
+        (Class::Relation, Class::Relation)
+        | (Class::Open, Class::Relation)
+        | (Class::Relation, Class::Close) => 0.0,
+        (Class::Relation, _) | (_, Class::Relation) if !script => THICK,
+        (Class::Punctuation, _) if !script => THIN,
+        _ => 0.0,
+    }
+}
+
+/// Lays out a formula with Pango, using the metrics of the surrounding text font
+pub(super) struct Layouter {
+    context: pango::Context,
+    font: pango::FontDescription,
+    /// Font size in pixels
+    em: f32,
+    /// Height of the math axis above the baseline, fraction bars sit on it
+    axis: f32,
+    /// Thickness of fraction bars and radicals
+    rule: f32,
+    bold: Cell<bool>,
+}
+
+impl Layouter {
+    pub(super) fn new(context: &pango::Context) -> Self {
+        let mut font = context.font_description().unwrap_or_default();
+        font.set_family("serif");
+
+        let mut layouter = Self {
+            context: context.clone(),
+            font,
+            em: 16.0,
+            axis: 4.0,
+            rule: 1.0,
+            bold: Cell::new(false),
+        };
+
+        // An em space is exactly one em wide, the minus sign sits on the math axis
+        let (_, logical) = layouter.pango_layout("\u{2003}", 1.0, false).extents();
+        layouter.em = (logical.width() as f32 / SCALE as f32).max(1.0);
+        let minus = layouter.text("−", Style::Text, false);
+        layouter.axis = (minus.ascent - minus.descent) / 2.0;
+        layouter.rule = (minus.ascent + minus.descent).clamp(1.0, layouter.em / 8.0);
+        layouter
+    }
+
+    pub(super) fn layout(&self, node: &MathNode, display: bool) -> MathBox {
+        let style = if display { Style::Display } else { Style::Text };
+        self.node(node, style)
+    }
+
+    fn pango_layout(&self, text: &str, scale: f32, italic: bool) -> pango::Layout {
+        let layout = pango::Layout::new(&self.context);
+        let mut font = self.font.clone();
+        font.set_size((self.font.size() as f32 * scale) as i32);
+        if italic {
+            font.set_style(pango::Style::Italic);
+        }
+        if self.bold.get() {
+            font.set_weight(pango::Weight::Bold);
+        }
+        layout.set_font_description(Some(&font));
+        layout.set_text(text);
+        layout
+    }
+
+    fn text(&self, text: &str, style: Style, italic: bool) -> MathBox {
+        self.scaled_text(text, style.scale(), italic)
+    }
+
+    fn scaled_text(&self, text: &str, scale: f32, italic: bool) -> MathBox {
+        let layout = self.pango_layout(text, scale, italic);
+        let (ink, logical) = layout.extents();
+        let baseline = layout.baseline() as f32 / SCALE as f32;
+        let (ascent, descent) = if ink.height() > 0 {
+            (
+                baseline - ink.y() as f32 / SCALE as f32,
+                (ink.y() + ink.height()) as f32 / SCALE as f32 - baseline,
+            )
+        } else {
+            (0.0, 0.0)
+        };
+
+        MathBox {
+            width: logical.width() as f32 / SCALE as f32,
+            ascent,
+            descent,
+            items: vec![Item::Text {
+                layout,
+                x: 0.0,
+                y: -baseline,
+                scale_y: 1.0,
+            }],
+        }
+    }
+
+    fn node(&self, node: &MathNode, style: Style) -> MathBox {
+        let em = self.em * style.scale();
+
+        match node {
+            MathNode::Row(items) => self.row(items, style),
+            MathNode::Ident(text) => self.text(text, style, !self.bold.get()),
+            MathNode::Number(text) | MathNode::Text(text) => self.text(text, style, false),
+            MathNode::Symbol(text, _) => self.text(text, style, false),
+            MathNode::Operator { text, large, .. } => {
+                if *large {
+                    let scale = if style == Style::Display { 1.6 } else { 1.15 };
+                    let operator = self.scaled_text(text, style.scale() * scale, false);
+                    self.center_on_axis(operator, style)
+                } else {
+                    self.text(text, style, false)
+                }
+            }
+            MathNode::Space(width) => MathBox::empty(width * em),
+            MathNode::Frac {
+                numerator,
+                denominator,
+            } => self.fraction(numerator, denominator, style),
+            MathNode::Sqrt { index, body } => self.sqrt(index.as_deref(), body, style),
+            MathNode::Scripts { base, sub, sup } => {
+                self.scripts(base, sub.as_deref(), sup.as_deref(), style)
+            }
+            MathNode::Fenced { open, close, body } => {
+                let body = self.node(body, style);
+                self.fence(open, close, body, style)
+            }
+            MathNode::Matrix {
+                rows,
+                open,
+                close,
+                align_left,
+            } => {
+                let style = if style == Style::Display {
+                    Style::Text
+                } else {
+                    style
+                };
+                let matrix = self.matrix(rows, *align_left, style);
+                self.fence(open, close, matrix, style)
+            }
+            MathNode::Accent { accent, body } => self.accent(accent, body, style),
+            MathNode::Overline(body) => {
+                let body = self.node(body, style);
+                let rule = self.rule * style.scale().max(0.7);
+                let top = body.ascent + 2.0 * rule;
+                let mut hbox = MathBox {
+                    width: body.width,
+                    ascent: top + rule,
+                    descent: body.descent,
+                    items: vec![Item::Rule {
+                        x: 0.0,
+                        y: -top - rule,
+                        width: body.width,
+                        height: rule,
+                    }],
+                };
+                hbox.append(body, 0.0, 0.0);
+                hbox
+            }
+            MathNode::Bold(body) => {
+                let bold = self.bold.replace(true);
+                let hbox = self.node(body, style);
+                self.bold.set(bold);
+                hbox
+            }
+        }
+    }
+
+    fn row(&self, items: &[MathNode], style: Style) -> MathBox {
+        let em = self.em * style.scale();
+        let mut hbox = MathBox::empty(0.0);
+        let mut previous: Option<Class> = None;
+
+        for item in items {
+            let mut class = class_of(item);
+
+            // A binary operator without a left operand is a sign, as in `-x`
+            if class == Some(Class::Binary)
+                && matches!(
+                    previous,
+                    None | Some(
+                        Class::Binary
+                            | Class::Relation
+                            | Class::Open
+                            | Class::Punctuation
+                            | Class::Operator
+                    )
+                )
+            {
+                class = Some(Class::Ordinary);
+            }
+
+            if let (Some(left), Some(right)) = (previous, class) {
+                hbox.width += spacing(left, right, style) * em;
+            }
+
+            let child = self.node(item, style);
+            hbox.ascent = hbox.ascent.max(child.ascent);
+            hbox.descent = hbox.descent.max(child.descent);
+            let x = hbox.width;
+            hbox.width += child.width;
+            hbox.append(child, x, 0.0);
+
+            if class.is_some() {
+                previous = class;
+            }
+        }
+
+        hbox
+    }
+
+    fn center_on_axis(&self, hbox: MathBox, style: Style) -> MathBox {
+        let axis = self.axis * style.scale();
+        let center = (hbox.ascent - hbox.descent) / 2.0;
+        hbox.raise(axis - center)
+    }
+
+    fn fraction(&self, numerator: &MathNode, denominator: &MathNode, style: Style) -> MathBox {
+        let em = self.em * style.scale();
+        let axis = self.axis * style.scale();
+        let rule = self.rule * style.scale().max(0.7);
+        let gap = if style == Style::Display {
+            3.0 * rule
+        } else {
+            rule
+        } + 0.05 * em;
+
+        let numerator = self.node(numerator, style.fraction());
+        let denominator = self.node(denominator, style.fraction());
+        let padding = 0.12 * em;
+        let width = numerator.width.max(denominator.width) + 2.0 * padding;
+
+        let numerator_shift = axis + rule / 2.0 + gap + numerator.descent;
+        let denominator_shift = -axis + rule / 2.0 + gap + denominator.ascent;
+
+        let mut hbox = MathBox {
+            width,
+            ascent: numerator_shift + numerator.ascent,
+            descent: denominator_shift + denominator.descent,
+            items: vec![Item::Rule {
+                x: padding / 2.0,
+                y: -axis - rule / 2.0,
+                width: width - padding,
+                height: rule,
+            }],
+        };
+        let x = (width - numerator.width) / 2.0;
+        hbox.append(numerator, x, -numerator_shift);
+        let x = (width - denominator.width) / 2.0;
+        hbox.append(denominator, x, denominator_shift);
+        hbox
+    }
+
+    fn sqrt(&self, index: Option<&MathNode>, body: &MathNode, style: Style) -> MathBox {
+        let em = self.em * style.scale();
+        let rule = self.rule * style.scale().max(0.7);
+        let body = self.node(body, style);
+        let gap = rule + 0.1 * em;
+
+        let top = -(body.ascent.max(0.6 * em) + gap + rule / 2.0);
+        let bottom = body.descent.max(0.1 * em);
+        let hook = (0.5 * em).min(bottom - top);
+        let radical_width = 0.6 * em;
+
+        // Room for a root index over the short leg of the radical
+        let index = index.map(|index| self.node(index, Style::ScriptScript));
+        let (offset, index_shift) = match &index {
+            Some(index) => {
+                let shift = -top * 0.55 + index.descent;
+                ((index.width - radical_width * 0.45).max(0.0), shift)
+            }
+            None => (0.0, 0.0),
+        };
+
+        let body_x = offset + radical_width + 0.1 * em;
+        let width = body_x + body.width + 0.1 * em;
+        let mut hbox = MathBox {
+            width,
+            ascent: -top + rule,
+            descent: bottom + rule,
+            items: vec![Item::Polyline {
+                points: vec![
+                    (offset, bottom - hook * 0.45),
+                    (offset + radical_width * 0.2, bottom - hook * 0.55),
+                    (offset + radical_width * 0.5, bottom),
+                    (offset + radical_width, top),
+                    (width, top),
+                ],
+                line_width: rule,
+            }],
+        };
+        hbox.append(body, body_x, 0.0);
+
+        if let Some(index) = index {
+            hbox.ascent = hbox.ascent.max(index_shift + index.ascent);
+            let x = offset + radical_width * 0.45 - index.width;
+            hbox.append(index, x, -index_shift);
+        }
+        hbox
+    }
+
+    fn scripts(
+        &self,
+        base: &MathNode,
+        sub: Option<&MathNode>,
+        sup: Option<&MathNode>,
+        style: Style,
+    ) -> MathBox {
+        let em = self.em * style.scale();
+        let limits =
+            style == Style::Display && matches!(base, MathNode::Operator { limits: true, .. });
+
+        let base = self.node(base, style);
+        let sub = sub.map(|sub| self.node(sub, style.script()));
+        let sup = sup.map(|sup| self.node(sup, style.script()));
+
+        if limits {
+            return self.limits(base, sub, sup, style);
+        }
+
+        let rule = self.rule * style.scale();
+        let script_em = self.em * style.script().scale();
+        let mut sup_shift = 0.0;
+        let mut sub_shift = 0.0;
+        if let Some(sup) = &sup {
+            sup_shift = (base.ascent - 0.3 * script_em)
+                .max(0.36 * em)
+                .max(sup.descent + 0.2 * em);
+        }
+        if let Some(sub) = &sub {
+            sub_shift = (base.descent + 0.1 * script_em)
+                .max(0.15 * em)
+                .max(sub.ascent - 0.6 * em);
+        }
+        if let (Some(sup), Some(sub)) = (&sup, &sub) {
+            let clearance = (sup_shift - sup.descent) - (sub.ascent - sub_shift);
+            if clearance < 4.0 * rule {
+                sub_shift += 4.0 * rule - clearance;
+            }
+        }
+
+        let x = base.width;
+        let mut hbox = MathBox {
+            width: base.width,
+            ascent: base.ascent,
+            descent: base.descent,
+            items: Vec::new(),
+        };
+        hbox.append(base, 0.0, 0.0);
+
+        let mut scripts_width: f32 = 0.0;
+        if let Some(sup) = sup {
+            hbox.ascent = hbox.ascent.max(sup_shift + sup.ascent);
+            hbox.descent = hbox.descent.max(sup.descent - sup_shift);
+            scripts_width = scripts_width.max(sup.width);
+            hbox.append(sup, x, -sup_shift);
+        }
+        if let Some(sub) = sub {
+            hbox.ascent = hbox.ascent.max(sub.ascent - sub_shift);
+            hbox.descent = hbox.descent.max(sub_shift + sub.descent);
+            scripts_width = scripts_width.max(sub.width);
+            hbox.append(sub, x, sub_shift);
+        }
+        hbox.width += scripts_width + 0.05 * em;
+        hbox
+    }
+
+    /// Scripts stacked above and below a large operator in display style
+    fn limits(
+        &self,
+        base: MathBox,
+        sub: Option<MathBox>,
+        sup: Option<MathBox>,
+        style: Style,
+    ) -> MathBox {
+        let gap = 0.15 * self.em * style.scale();
+        let width = [Some(&base), sub.as_ref(), sup.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|b| b.width)
+            .fold(0.0, f32::max);
+
+        let mut hbox = MathBox {
+            width,
+            ascent: base.ascent,
+            descent: base.descent,
+            items: Vec::new(),
+        };
+        if let Some(sup) = sup {
+            let shift = base.ascent + gap + sup.descent;
+            hbox.ascent = shift + sup.ascent;
+            hbox.append(sup.with_width(width), 0.0, -shift);
+        }
+        if let Some(sub) = sub {
+            let shift = base.descent + gap + sub.ascent;
+            hbox.descent = shift + sub.descent;
+            hbox.append(sub.with_width(width), 0.0, shift);
+        }
+        hbox.append(base.with_width(width), 0.0, 0.0);
+        hbox
+    }
+
+    /// Wrap `body` in delimiters stretched to cover it symmetrically around the axis
+    fn fence(&self, open: &str, close: &str, body: MathBox, style: Style) -> MathBox {
+        let axis = self.axis * style.scale();
+        let half = (body.ascent - axis).max(body.descent + axis) + 0.05 * self.em;
+
+        let open = self.delimiter(open, 2.0 * half, style);
+        let close = self.delimiter(close, 2.0 * half, style);
+
+        let mut hbox = MathBox::empty(0.0);
+        for part in [open, body, close] {
+            hbox.ascent = hbox.ascent.max(part.ascent);
+            hbox.descent = hbox.descent.max(part.descent);
+            let x = hbox.width;
+            hbox.width += part.width;
+            hbox.append(part, x, 0.0);
+        }
+        hbox
+    }
+
+    /// Delimiter glyph scaled vertically to at least `height`, centred on the axis
+    fn delimiter(&self, text: &str, height: f32, style: Style) -> MathBox {
+        if text.is_empty() {
+            return MathBox::empty(0.0);
+        }
+
+        let mut glyph = self.text(text, style, false);
+        let natural = glyph.ascent + glyph.descent;
+        if natural > 0.0 && height > natural * 1.1 {
+            let scale_y = height / natural;
+            for item in &mut glyph.items {
+                if let Item::Text {
+                    y, scale_y: scale, ..
+                } = item
+                {
+                    *y *= scale_y;
+                    *scale = scale_y;
+                }
+            }
+            glyph.ascent *= scale_y;
+            glyph.descent *= scale_y;
+        }
+        self.center_on_axis(glyph, style)
+    }
+
+    fn matrix(&self, rows: &[Vec<MathNode>], align_left: bool, style: Style) -> MathBox {
+        let em = self.em * style.scale();
+        let column_gap = if align_left { 1.0 } else { 0.8 } * em;
+        let row_gap = 0.3 * em;
+
+        let cells: Vec<Vec<MathBox>> = rows
+            .iter()
+            .map(|row| row.iter().map(|cell| self.node(cell, style)).collect())
+            .collect();
+        let columns = cells.iter().map(Vec::len).max().unwrap_or(0);
+        let mut widths = vec![0.0_f32; columns];
+        for row in &cells {
+            for (i, cell) in row.iter().enumerate() {
+                widths[i] = widths[i].max(cell.width);
+            }
+        }
+
+        let padding = 0.15 * em;
+        let width = widths.iter().sum::<f32>()
+            + column_gap * columns.saturating_sub(1) as f32
+            + 2.0 * padding;
+        let mut hbox = MathBox::empty(width);
+        let mut y = 0.0;
+
+        for (i, row) in cells.into_iter().enumerate() {
+            let ascent = row.iter().map(|c| c.ascent).fold(0.6 * em, f32::max);
+            let descent = row.iter().map(|c| c.descent).fold(0.2 * em, f32::max);
+            if i > 0 {
+                y += row_gap;
+            }
+            y += ascent;
+
+            let mut x = padding;
+            for (cell, column_width) in row.into_iter().zip(&widths) {
+                let offset = if align_left {
+                    0.0
+                } else {
+                    (column_width - cell.width) / 2.0
+                };
+                hbox.append(cell, x + offset, y);
+                x += column_width + column_gap;
+            }
+            y += descent;
+        }
+
+        // Rows were placed downwards from the top, centre the grid on the axis
+        let axis = self.axis * style.scale();
+        hbox.ascent = 0.0;
+        hbox.descent = y;
+        hbox.raise(axis + y / 2.0)
+    }
+
+    fn accent(&self, accent: &str, body: &MathNode, style: Style) -> MathBox {
+        let em = self.em * style.scale();
+        let body = self.node(body, style);
+        let mark = if accent == "→" {
+            self.text(accent, style.script(), false)
+        } else {
+            self.text(accent, style, false)
+        };
+
+        let shift = body.ascent.max(0.45 * em) + 0.08 * em + mark.descent;
+        let width = body.width.max(mark.width);
+        let mut hbox = MathBox {
+            width,
+            ascent: shift + mark.ascent,
+            descent: body.descent,
+            items: Vec::new(),
+        };
+        let x = (width - mark.width) / 2.0;
+        hbox.append(mark, x, -shift);
+        let x = (width - body.width) / 2.0;
+        hbox.append(body, x, 0.0);
+        hbox
+    }
+}
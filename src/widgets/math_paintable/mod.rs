@@ -0,0 +1,30 @@
+mod imp;
+mod layout;
+
+use adw::gdk;
+use adw::gtk::subclass::prelude::*;
+use adw::gtk::{self, glib, pango};
+
+use crate::math::MathNode;
+
+glib::wrapper! {
+    /// Paintable that draws a TeX formula laid out with Pango.
+    /// Drawn as a symbolic paintable it takes the foreground color of the widget.
+    pub struct MathPaintable(ObjectSubclass<imp::MathPaintable>)
+        @implements gdk::Paintable, gtk::SymbolicPaintable;
+}
+
+impl MathPaintable {
+    /// Lay out `formula` with the font of `context`, in display style if `display` is set
+    pub fn new(formula: &MathNode, display: bool, context: &pango::Context) -> Self {
+        let paintable: Self = glib::Object::builder().build();
+        let math_box = layout::Layouter::new(context).layout(formula, display);
+        let _ = paintable.imp().math_box.set(math_box);
+        paintable
+    }
+
+    /// Distance from the top edge to the baseline of the formula, in pixels
+    pub fn baseline(&self) -> i32 {
+        self.imp().baseline()
+    }
+}
@@ -0,0 +1,167 @@
+//! Helpers for applying edits to the document model
+
+use crate::markdown::{Block, Footnote, Inline};
+
+/// Level and remaining content of a paragraph typed as `# Heading`
+pub(super) fn heading_prefix(content: &[Inline]) -> Option<(u8, Vec<Inline>)> {
+    let Some(Inline::Text(first)) = content.first() else {
+        return None;
+    };
+    let level = first.chars().take_while(|c| *c == '#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let rest = first[level..].strip_prefix(' ')?;
+
+    let mut content = content.to_vec();
+    if rest.is_empty() {
+        content.remove(0);
+    } else {
+        content[0] = Inline::Text(rest.to_string());
+    }
+    Some((level as u8, content))
+}
+
+/// Give the footnote references in `content` the numbers of their footnotes in the
+/// document, the content of an edited buffer only knows their labels
+pub(super) fn number_footnotes(content: &mut [Inline], footnotes: &[Footnote]) {
+    for inline in content {
+        match inline {
+            Inline::FootnoteReference { label, number } => {
+                if let Some(footnote) = footnotes.iter().find(|f| f.label == *label) {
+                    *number = footnote.number;
+                }
+            }
+            Inline::Emphasis(content)
+            | Inline::Strong(content)
+            | Inline::Strikethrough(content)
+            | Inline::Link { content, .. }
+            | Inline::WikiLink { content, .. } => number_footnotes(content, footnotes),
+            _ => {}
+        }
+    }
+}
+
+/// Block nested inside `block` at `path`: an index per quote, two per list item and block
+pub(super) fn block_at_mut<'a>(block: &'a mut Block, path: &[usize]) -> Option<&'a mut Block> {
+    match (block, path) {
+        (block, []) => Some(block),
+        (Block::Quote(blocks), [index, rest @ ..]) => block_at_mut(blocks.get_mut(*index)?, rest),
+        (Block::List { items, .. }, [item, index, rest @ ..]) => {
+            block_at_mut(items.get_mut(*item)?.get_mut(*index)?, rest)
+        }
+        _ => None,
+    }
+}
+
+/// Keep the `head` of a paragraph or heading in `block` and return a paragraph with the
+/// `tail`, for splitting it at the caret
+pub(super) fn split(block: &mut Block, head: Vec<Inline>, tail: Vec<Inline>) -> Option<Block> {
+    match block {
+        Block::Heading { content, .. } | Block::Paragraph(content) => {
+            *content = head;
+            Some(Block::Paragraph(tail))
+        }
+        _ => None,
+    }
+}
+
+/// Insert `new` after the block nested inside `block` at `path`, returning the path of
+/// `new`. A list item is split in two, `new` starting the second item with the blocks
+/// that followed it.
+pub(super) fn insert_after(block: &mut Block, path: &[usize], new: Block) -> Option<Vec<usize>> {
+    match (block, path) {
+        (Block::Quote(blocks), [index]) if *index < blocks.len() => {
+            blocks.insert(index + 1, new);
+            Some(vec![index + 1])
+        }
+        (Block::List { items, .. }, [item, index])
+            if items.get(*item).is_some_and(|blocks| *index < blocks.len()) =>
+        {
+            let rest = items[*item].split_off(index + 1);
+            items.insert(item + 1, std::iter::once(new).chain(rest).collect());
+            Some(vec![item + 1, 0])
+        }
+        (Block::Quote(blocks), [index, rest @ ..]) => {
+            let mut path = insert_after(blocks.get_mut(*index)?, rest, new)?;
+            path.insert(0, *index);
+            Some(path)
+        }
+        (Block::List { items, .. }, [item, index, rest @ ..]) => {
+            let mut path = insert_after(items.get_mut(*item)?.get_mut(*index)?, rest, new)?;
+            path.splice(0..0, [*item, *index]);
+            Some(path)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown::parse;
+
+    fn text(text: &str) -> Vec<Inline> {
+        vec![Inline::Text(text.to_string())]
+    }
+
+    #[test]
+    fn footnotes_are_numbered_by_label() {
+        let document = parse("One[^a] and *two[^b]*.\n\n[^a]: A\n\n[^b]: B");
+        let reference = |label: &str| Inline::FootnoteReference {
+            label: label.to_string(),
+            number: 0,
+        };
+        let mut content = vec![
+            Inline::Text("One".to_string()),
+            reference("a"),
+            Inline::Text(" and ".to_string()),
+            Inline::Emphasis(vec![Inline::Text("two".to_string()), reference("b")]),
+            Inline::Text(".".to_string()),
+        ];
+        number_footnotes(&mut content, &document.footnotes);
+        assert_eq!(document.blocks, [Block::Paragraph(content)]);
+    }
+
+    #[test]
+    fn heading_splits_into_a_paragraph() {
+        let mut block = Block::Heading {
+            level: 2,
+            content: text("Title and text"),
+        };
+        let new = split(&mut block, text("Title"), text("and text"));
+        assert_eq!(
+            block,
+            Block::Heading {
+                level: 2,
+                content: text("Title")
+            }
+        );
+        assert_eq!(new, Some(Block::Paragraph(text("and text"))));
+        assert_eq!(split(&mut Block::Rule, Vec::new(), Vec::new()), None);
+    }
+
+    #[test]
+    fn split_in_a_quote_adds_a_block() {
+        let mut quote = parse("> one\n>\n> two").blocks.remove(0);
+        let path = insert_after(&mut quote, &[0], Block::Paragraph(text("new")));
+        assert_eq!(path, Some(vec![1]));
+        assert_eq!(quote, parse("> one\n>\n> new\n>\n> two").blocks[0]);
+    }
+
+    #[test]
+    fn split_in_a_list_item_adds_an_item() {
+        let mut list = parse("- one\n\n  more\n- two").blocks.remove(0);
+        let path = insert_after(&mut list, &[0, 0], Block::Paragraph(text("new")));
+        assert_eq!(path, Some(vec![1, 0]));
+        assert_eq!(list, parse("- one\n- new\n\n  more\n- two").blocks[0]);
+    }
+
+    #[test]
+    fn split_in_a_nested_block_keeps_the_outer_path() {
+        let mut quote = parse("> - one\n> - two").blocks.remove(0);
+        let path = insert_after(&mut quote, &[0, 1, 0], Block::Paragraph(text("new")));
+        assert_eq!(path, Some(vec![0, 2, 0]));
+        assert_eq!(insert_after(&mut quote, &[0, 5, 0], Block::Rule), None);
+    }
+}
@@ -0,0 +1,38 @@
+use std::path::{Path, PathBuf};
+
+use adw::gdk;
+use adw::gtk::gdk_pixbuf::{self, prelude::*};
+use adw::gtk::{gio, glib};
+
+use crate::widgets::AnimatedPaintable;
+
+/// Load an image without blocking the main loop.
+/// Animated GIFs become an `AnimatedPaintable`, everything else a `gdk::Texture`
+/// decoded on a worker thread (SVG goes through the gdk-pixbuf loader).
+pub(super) async fn load_paintable(path: PathBuf) -> Result<gdk::Paintable, glib::Error> {
+    let file = gio::File::for_path(&path);
+    let (bytes, _) = file.load_bytes_future().await?;
+
+    if bytes.starts_with(b"GIF8") {
+        let stream = gio::MemoryInputStream::from_bytes(&bytes);
+        let animation = gdk_pixbuf::PixbufAnimation::from_stream_future(&stream).await?;
+        if !animation.is_static_image() {
+            return Ok(AnimatedPaintable::new(&animation).upcast());
+        }
+    }
+
+    gio::spawn_blocking(move || gdk::Texture::from_bytes(&bytes))
+        .await
+        .unwrap_or_else(|_| {
+            Err(glib::Error::new(
+                gio::IOErrorEnum::Failed,
+                "Image decoder panicked",
+            ))
+        })
+        .map(|texture| texture.upcast())
+}
+
+/// Load an image as a single texture, blocking until it is decoded
+pub(super) fn load_texture(path: &Path) -> Result<gdk::Texture, glib::Error> {
+    gdk::Texture::from_filename(path)
+}
@@ -1,9 +1,109 @@
-use adw::gtk::glib;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::rc::Rc;
+
 use adw::gtk::prelude::*;
 use adw::gtk::subclass::prelude::*;
+use adw::gtk::{self, gdk, gio, glib, pango};
+
+use super::Heading;
+use super::edit;
+use super::print::Pages;
+use super::render::{Renderer, Row};
+use crate::i18n::{self, gettext, ngettext};
+use crate::markdown::{self, Block, Document, Footnote, FrontMatter, Inline, Style};
+use crate::widgets::{
+    InlineBuffer, InlinePos, InlineView, ScaledPaintable, TextAttr, TextAttrType,
+};
+
+/// Documents with more top-level blocks than this are shown in a `gtk::ListView`
+/// that only creates widgets for the rows near the viewport
+const VIRTUALIZE_THRESHOLD: usize = 1000;
 
-#[derive(Default)]
-pub struct MdViewer {}
+/// Frames `reveal_match` waits at most for the row of the match to be laid out
+const REVEAL_FRAMES: u32 = 30;
+
+/// Zoom levels stepped through with Ctrl+plus and Ctrl+minus
+const ZOOM_LEVELS: [f64; 12] = [
+    0.5, 0.67, 0.8, 0.9, 1.0, 1.1, 1.25, 1.5, 1.75, 2.0, 2.5, 3.0,
+];
+
+#[derive(Default, glib::Properties)]
+#[properties(wrapper_type = super::MdViewer)]
+pub struct MdViewer {
+    /// Document title from the front matter
+    #[property(get, nullable)]
+    title: RefCell<Option<String>>,
+    /// Whether paragraphs and headings can be edited in place
+    #[property(get, set = Self::set_editable)]
+    pub(super) editable: Cell<bool>,
+    /// Whether the document has been edited since it was loaded or saved
+    #[property(get, set)]
+    modified: Cell<bool>,
+    /// Line spacing of the text as a multiple of the font's line height, 0 for the font's
+    /// own spacing
+    #[property(get, set = Self::set_line_height, minimum = 0.0)]
+    line_height: Cell<f64>,
+    /// Font family of inline code and code blocks, the monospace font when unset
+    #[property(get, set = Self::set_code_font, nullable)]
+    code_font: RefCell<Option<String>>,
+    /// Widest the text gets in pixels at the normal zoom, it is centred in wider viewers.
+    /// 0 for no limit.
+    #[property(get, set = Self::set_max_content_width, minimum = 0)]
+    max_content_width: Cell<i32>,
+    /// Factor text, images and code are scaled by, 1 for their normal size
+    #[property(get, set = Self::set_zoom, minimum = 0.1, default = 1.0)]
+    zoom: Cell<f64>,
+    /// Whether the viewer grows to the height of the whole document instead of scrolling,
+    /// with images loaded before the content is shown
+    pub(super) fit_content: Cell<bool>,
+    /// Markdown the document was parsed from
+    source: RefCell<String>,
+    /// Style of the source, followed when writing edited blocks
+    style: RefCell<Style>,
+    /// Source range of every row that comes from a top-level block
+    row_ranges: RefCell<Vec<Option<Range<usize>>>>,
+    /// Rows edited since the source was set, written back by `to_markdown`
+    dirty_rows: RefCell<BTreeSet<usize>>,
+    /// Block whose view takes the keyboard focus the next time its row is rendered
+    focus_path: RefCell<Option<Vec<usize>>>,
+    pub(super) front_matter: RefCell<Option<FrontMatter>>,
+    /// Directory that relative image paths are resolved against
+    pub(super) base_dir: RefCell<Option<PathBuf>>,
+    scrolled: gtk::ScrolledWindow,
+    /// Width limits of the content box and of the list view
+    clamp: adw::Clamp,
+    clamp_scrollable: adw::ClampScrollable,
+    search_bar: gtk::SearchBar,
+    search_entry: gtk::SearchEntry,
+    search_status: gtk::Label,
+    /// Top-level rows of the document: front matter header, blocks and footnotes
+    rows: RefCell<Vec<Row>>,
+    /// Content box holding a widget per row, unless the document is virtualised
+    content: RefCell<Option<gtk::Box>>,
+    list_view: RefCell<Option<gtk::ListView>>,
+    /// Widgets of the rows the list view has created, by row
+    bound_rows: RefCell<HashMap<usize, gtk::Widget>>,
+    /// Widgets that internal `#name` links scroll to
+    anchors: RefCell<HashMap<String, gtk::Widget>>,
+    /// Row of every anchor, used when the anchor widget is not realised
+    row_anchors: RefCell<HashMap<String, usize>>,
+    pub(super) headings: RefCell<Vec<Heading>>,
+    footnotes: RefCell<Vec<Footnote>>,
+    preview: RefCell<Option<gtk::Popover>>,
+    search_query: RefCell<String>,
+    /// Rows containing the search text and the one currently scrolled to
+    search_rows: RefCell<Vec<usize>>,
+    search_index: Cell<usize>,
+    /// Match shown by `reveal_match`, as its row and its number among the matches of the row
+    current_match: Cell<Option<(usize, usize)>>,
+    /// Row to scroll to once the content has been laid out
+    pending_scroll: Cell<Option<usize>>,
+    /// Offset to scroll to once the content has been laid out
+    pending_offset: Cell<Option<f64>>,
+}
 
 #[glib::object_subclass]
 impl ObjectSubclass for MdViewer {
@@ -12,15 +112,1314 @@ impl ObjectSubclass for MdViewer {
     type ParentType = adw::gtk::Box;
 }
 
+#[glib::derived_properties]
 impl ObjectImpl for MdViewer {
+    fn signals() -> &'static [glib::subclass::Signal] {
+        use std::sync::OnceLock;
+        static SIGNALS: OnceLock<Vec<glib::subclass::Signal>> = OnceLock::new();
+        SIGNALS.get_or_init(|| {
+            vec![
+                // Signal emitted when the headings of the document change
+                glib::subclass::Signal::builder("headings-changed").build(),
+                // Signal emitted when a link out of the document is followed, in a new tab
+                // when middle-clicked: (url: String, new_tab: bool) -> handled: bool
+                glib::subclass::Signal::builder("open-link")
+                    .param_types([String::static_type(), bool::static_type()])
+                    .return_type::<bool>()
+                    .build(),
+                // Signal emitted for every wiki-link when the document is rendered, links
+                // a handler cannot resolve are shown as broken: (target: String) -> found: bool
+                glib::subclass::Signal::builder("resolve-wiki-link")
+                    .param_types([String::static_type()])
+                    .return_type::<bool>()
+                    .run_first()
+                    .class_handler(|_| Some(true.to_value()))
+                    .build(),
+            ]
+        })
+    }
+
     fn constructed(&self) {
         self.parent_constructed();
 
         let obj = self.obj();
         obj.set_orientation(adw::gtk::Orientation::Vertical);
-        obj.set_spacing(0);
+        obj.add_css_class("md-viewer");
+        self.zoom.set(1.0);
+
+        self.setup_search();
+        self.setup_zoom();
+        self.setup_editing();
+        obj.append(&self.search_bar);
+
+        self.scrolled.set_hscrollbar_policy(gtk::PolicyType::Never);
+        self.scrolled.set_vexpand(true);
+        self.update_clamp();
+        self.scrolled
+            .vadjustment()
+            .connect_changed(glib_macros::clone!(
+                #[weak(rename_to = viewer)]
+                self,
+                move |adjustment| {
+                    if let Some(row) = viewer.pending_scroll.take() {
+                        viewer.scroll_to_row(row);
+                    }
+                    if let Some(offset) = viewer.pending_offset.take() {
+                        adjustment.set_value(offset);
+                    }
+                }
+            ));
+        obj.append(&self.scrolled);
+    }
+
+    fn dispose(&self) {
+        self.hide_link_preview();
     }
 }
 
 impl WidgetImpl for MdViewer {}
 impl BoxImpl for MdViewer {}
+
+impl MdViewer {
+    fn setup_search(&self) {
+        let entry_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+        entry_box.append(&self.search_entry);
+        self.search_status.add_css_class("dim-label");
+        entry_box.append(&self.search_status);
+        self.search_bar.set_child(Some(&entry_box));
+        self.search_bar.connect_entry(&self.search_entry);
+        self.search_bar.set_key_capture_widget(Some(&*self.obj()));
+
+        self.search_entry
+            .connect_search_changed(glib_macros::clone!(
+                #[weak(rename_to = viewer)]
+                self,
+                move |entry| {
+                    // `reveal_match` searches before the entry catches up
+                    if entry.text() != *viewer.search_query.borrow() {
+                        viewer.search(&entry.text());
+                    }
+                }
+            ));
+        self.search_entry.connect_activate(glib_macros::clone!(
+            #[weak(rename_to = viewer)]
+            self,
+            move |_| viewer.search_step(true)
+        ));
+        self.search_entry.connect_next_match(glib_macros::clone!(
+            #[weak(rename_to = viewer)]
+            self,
+            move |_| viewer.search_step(true)
+        ));
+        self.search_entry
+            .connect_previous_match(glib_macros::clone!(
+                #[weak(rename_to = viewer)]
+                self,
+                move |_| viewer.search_step(false)
+            ));
+        self.search_bar
+            .connect_search_mode_enabled_notify(glib_macros::clone!(
+                #[weak(rename_to = viewer)]
+                self,
+                move |bar| {
+                    if !bar.is_search_mode() {
+                        viewer.search("");
+                    }
+                }
+            ));
+
+        let controller = gtk::ShortcutController::new();
+        controller.set_scope(gtk::ShortcutScope::Managed);
+        controller.add_shortcut(gtk::Shortcut::new(
+            gtk::ShortcutTrigger::parse_string("<Control>f"),
+            Some(gtk::CallbackAction::new(glib_macros::clone!(
+                #[weak(rename_to = viewer)]
+                self,
+                #[upgrade_or]
+                glib::Propagation::Proceed,
+                move |_, _| {
+                    viewer.search_bar.set_search_mode(true);
+                    viewer.search_entry.grab_focus();
+                    glib::Propagation::Stop
+                }
+            ))),
+        ));
+        self.obj().add_controller(controller);
+    }
+
+    fn setup_zoom(&self) {
+        let controller = gtk::ShortcutController::new();
+        controller.set_scope(gtk::ShortcutScope::Managed);
+        type Zoom = fn(&MdViewer);
+        let shortcuts: [(&str, Zoom); 3] = [
+            (
+                "<Control>plus|<Control>equal|<Control>KP_Add",
+                Self::zoom_in,
+            ),
+            ("<Control>minus|<Control>KP_Subtract", Self::zoom_out),
+            ("<Control>0|<Control>KP_0", |viewer| viewer.set_zoom(1.0)),
+        ];
+        for (trigger, zoom) in shortcuts {
+            controller.add_shortcut(gtk::Shortcut::new(
+                gtk::ShortcutTrigger::parse_string(trigger),
+                Some(gtk::CallbackAction::new(glib_macros::clone!(
+                    #[weak(rename_to = viewer)]
+                    self,
+                    #[upgrade_or]
+                    glib::Propagation::Proceed,
+                    move |_, _| {
+                        zoom(&viewer);
+                        glib::Propagation::Stop
+                    }
+                ))),
+            ));
+        }
+        self.obj().add_controller(controller);
+    }
+
+    /// Step to the next larger zoom level
+    pub(super) fn zoom_in(&self) {
+        let zoom = self.zoom.get();
+        if let Some(&level) = ZOOM_LEVELS.iter().find(|&&level| level > zoom + 0.01) {
+            self.set_zoom(level);
+        }
+    }
+
+    /// Step to the next smaller zoom level
+    pub(super) fn zoom_out(&self) {
+        let zoom = self.zoom.get();
+        if let Some(&level) = ZOOM_LEVELS.iter().rev().find(|&&level| level < zoom - 0.01) {
+            self.set_zoom(level);
+        }
+    }
+
+    /// Parse and show markdown source, forgetting any edits
+    pub(super) fn set_source(&self, source: &str) {
+        self.source.replace(source.to_string());
+        self.set_document(&markdown::parse(source));
+    }
+
+    /// Replace the current content with widgets for `document`
+    pub(super) fn set_document(&self, document: &Document) {
+        self.footnotes.replace(document.footnotes.clone());
+        self.front_matter.replace(document.front_matter.clone());
+        self.style.replace(document.style.clone());
+        self.rows.replace(Row::from_document(document));
+
+        let mut row_ranges: Vec<_> = self.rows.borrow().iter().map(|_| None).collect();
+        let first_block = usize::from(document.front_matter.is_some());
+        for (range, block_range) in row_ranges[first_block..]
+            .iter_mut()
+            .zip(&document.block_ranges)
+        {
+            *range = Some(block_range.clone());
+        }
+        self.row_ranges.replace(row_ranges);
+        self.dirty_rows.borrow_mut().clear();
+        self.obj().set_modified(false);
+
+        self.index_rows();
+        self.render();
+
+        let obj = self.obj();
+        let title = document.front_matter.as_ref().and_then(FrontMatter::title);
+        if *self.title.borrow() != title {
+            self.title.replace(title);
+            obj.notify_title();
+        }
+    }
+
+    /// Create the widgets for the rows, keeping the search highlight
+    fn render(&self) {
+        self.hide_link_preview();
+        self.anchors.borrow_mut().clear();
+        self.search_rows.borrow_mut().clear();
+
+        if !self.fit_content.get() && self.rows.borrow().len() > VIRTUALIZE_THRESHOLD {
+            self.show_list_view();
+        } else {
+            self.show_content_box();
+        }
+
+        let query = self.search_query.borrow().clone();
+        if !query.is_empty() {
+            self.search(&query);
+        }
+    }
+
+    pub(super) fn update_wiki_links(&self) {
+        if self.editable.get() {
+            return;
+        }
+        let offset = self.scroll_offset();
+        self.render();
+        self.set_scroll_offset(offset);
+    }
+
+    pub(super) fn set_fit_content(&self, fit: bool) {
+        if self.fit_content.replace(fit) != fit {
+            self.scrolled.set_vscrollbar_policy(if fit {
+                gtk::PolicyType::Never
+            } else {
+                gtk::PolicyType::Automatic
+            });
+            self.render();
+        }
+    }
+
+    fn set_line_height(&self, line_height: f64) {
+        if self.line_height.replace(line_height) != line_height {
+            self.update_text_style();
+        }
+    }
+
+    fn set_code_font(&self, code_font: Option<String>) {
+        let code_font = code_font.filter(|font| !font.is_empty());
+        if *self.code_font.borrow() != code_font {
+            self.code_font.replace(code_font);
+            self.update_text_style();
+        }
+    }
+
+    fn set_zoom(&self, zoom: f64) {
+        if self.zoom.replace(zoom) != zoom {
+            self.update_text_style();
+            self.update_clamp();
+        }
+    }
+
+    fn set_max_content_width(&self, width: i32) {
+        if self.max_content_width.replace(width) != width {
+            self.update_clamp();
+        }
+    }
+
+    /// Apply the zoom, line height and code font to the rendered rows
+    fn update_text_style(&self) {
+        if let Some(child) = self.scrolled.child() {
+            self.set_text_style(&child);
+        }
+    }
+
+    /// Apply the zoom, line height and code font to the views below `widget`
+    fn set_text_style(&self, widget: &gtk::Widget) {
+        set_text_style(
+            widget,
+            &TextStyle {
+                zoom: self.zoom.get(),
+                line_height: self.line_height.get(),
+                code_font: self.code_font.borrow().as_deref(),
+            },
+        );
+    }
+
+    /// Limit the width of the content to `max-content-width`, wider when zoomed in so
+    /// lines keep their length
+    fn update_clamp(&self) {
+        // Without a tightening range the content grows to the limit like the viewer
+        let width = match self.max_content_width.get() {
+            0 => i32::MAX,
+            width => (width as f64 * self.zoom.get()).round() as i32,
+        };
+        self.clamp.set_maximum_size(width);
+        self.clamp.set_tightening_threshold(width);
+        self.clamp_scrollable.set_maximum_size(width);
+        self.clamp_scrollable.set_tightening_threshold(width);
+    }
+
+    fn set_editable(&self, editable: bool) {
+        if self.editable.replace(editable) != editable {
+            self.render();
+        }
+    }
+
+    /// Collect headings and the rows anchors are found in, without creating widgets
+    fn index_rows(&self) {
+        let mut headings = Vec::new();
+        let mut row_anchors = HashMap::new();
+
+        for (index, row) in self.rows.borrow().iter().enumerate() {
+            for name in row.anchors() {
+                row_anchors.entry(name).or_insert(index);
+            }
+            if let Row::Block(markdown::Block::Heading { level, content }) = row {
+                let title = markdown::Inline::plain_text(content);
+                headings.push(Heading {
+                    level: *level,
+                    anchor: markdown::slug(&title),
+                    title,
+                });
+            }
+        }
+
+        self.row_anchors.replace(row_anchors);
+        if *self.headings.borrow() != headings {
+            self.headings.replace(headings);
+            self.obj().emit_by_name::<()>("headings-changed", &[]);
+        }
+    }
+
+    /// Create a widget for every row, fine for documents of moderate size
+    fn show_content_box(&self) {
+        self.list_view.take();
+        self.bound_rows.borrow_mut().clear();
+        self.clamp_scrollable.set_child(gtk::Widget::NONE);
+
+        let content = gtk::Box::new(gtk::Orientation::Vertical, 12);
+        content.add_css_class("document");
+        let renderer = Renderer::new(&self.obj());
+        for (index, row) in self.rows.borrow().iter().enumerate() {
+            content.append(&self.render_row(&renderer, index, row));
+        }
+
+        self.clamp.set_child(Some(&content));
+        self.scrolled.set_child(Some(&self.clamp));
+        self.content.replace(Some(content));
+    }
+
+    /// Show rows in a list view; GTK estimates the height of rows that are not realised
+    fn show_list_view(&self) {
+        self.content.take();
+        self.bound_rows.borrow_mut().clear();
+        self.clamp.set_child(gtk::Widget::NONE);
+
+        let model = gio::ListStore::new::<glib::BoxedAnyObject>();
+        let items: Vec<glib::BoxedAnyObject> = (0..self.rows.borrow().len())
+            .map(glib::BoxedAnyObject::new)
+            .collect();
+        model.extend_from_slice(&items);
+
+        let factory = gtk::SignalListItemFactory::new();
+        factory.connect_setup(|_, item| {
+            if let Some(item) = item.downcast_ref::<gtk::ListItem>() {
+                item.set_activatable(false);
+                item.set_selectable(false);
+                item.set_focusable(false);
+            }
+        });
+        factory.connect_bind(glib_macros::clone!(
+            #[weak(rename_to = viewer)]
+            self,
+            move |_, item| {
+                let Some(item) = item.downcast_ref::<gtk::ListItem>() else {
+                    return;
+                };
+                let Some(index) = item
+                    .item()
+                    .and_downcast::<glib::BoxedAnyObject>()
+                    .map(|object| *object.borrow::<usize>())
+                else {
+                    return;
+                };
+                let rows = viewer.rows.borrow();
+                let Some(row) = rows.get(index) else {
+                    return;
+                };
+
+                let widget = viewer.render_row(&Renderer::new(&viewer.obj()), index, row);
+                set_highlight(&widget, viewer.highlight().as_deref());
+                item.set_child(Some(&widget));
+                viewer.bound_rows.borrow_mut().insert(index, widget);
+                if viewer
+                    .current_match
+                    .get()
+                    .is_some_and(|(row, _)| row == index)
+                {
+                    viewer.mark_current_match();
+                }
+            }
+        ));
+        factory.connect_unbind(glib_macros::clone!(
+            #[weak(rename_to = viewer)]
+            self,
+            move |_, item| {
+                let Some(item) = item.downcast_ref::<gtk::ListItem>() else {
+                    return;
+                };
+                // A row created again may already be bound to another item
+                if let Some(index) = item
+                    .item()
+                    .and_downcast::<glib::BoxedAnyObject>()
+                    .map(|object| *object.borrow::<usize>())
+                {
+                    let mut bound_rows = viewer.bound_rows.borrow_mut();
+                    if bound_rows.get(&index) == item.child().as_ref() {
+                        bound_rows.remove(&index);
+                    }
+                }
+                item.set_child(gtk::Widget::NONE);
+            }
+        ));
+
+        let list_view = gtk::ListView::new(Some(gtk::NoSelection::new(Some(model))), Some(factory));
+        list_view.add_css_class("document");
+        self.clamp_scrollable.set_child(Some(&list_view));
+        self.scrolled.set_child(Some(&self.clamp_scrollable));
+        self.list_view.replace(Some(list_view));
+    }
+
+    /// Widget for a row, focusing the view of the block `focus_path` points into
+    fn render_row(&self, renderer: &Renderer, index: usize, row: &Row) -> gtk::Widget {
+        let focus_path = self
+            .focus_path
+            .borrow()
+            .clone()
+            .filter(|path| path.first() == Some(&index));
+        if let Some(path) = &focus_path {
+            renderer.set_focus_path(path);
+        }
+
+        let widget = renderer.row(index, row);
+        self.set_text_style(&widget);
+
+        if focus_path.is_some() {
+            self.focus_path.take();
+            if let Some(view) = renderer.focus_view() {
+                // The widget is not in the tree yet, so it cannot take the focus now
+                glib::idle_add_local_once(move || {
+                    view.grab_focus();
+                    if let Some(buffer) = view.buffer() {
+                        view.place_cursor(buffer.start_pos());
+                    }
+                });
+            }
+        }
+        widget
+    }
+
+    /// Create the widget for a row again after its block has changed
+    fn rerender_row(&self, index: usize) {
+        if let Some(list_view) = self.list_view.borrow().as_ref() {
+            let Some(model) = list_view
+                .model()
+                .and_then(|model| model.downcast::<gtk::NoSelection>().ok())
+                .and_then(|selection| selection.model())
+                .and_downcast::<gio::ListStore>()
+            else {
+                return;
+            };
+            model.splice(index as u32, 1, &[glib::BoxedAnyObject::new(index)]);
+            return;
+        }
+
+        let Some(content) = self.content.borrow().clone() else {
+            return;
+        };
+        let mut old = content.first_child();
+        for _ in 0..index {
+            old = old.and_then(|child| child.next_sibling());
+        }
+        let Some(old) = old else {
+            return;
+        };
+
+        self.anchors
+            .borrow_mut()
+            .retain(|_, widget| widget != &old && !widget.is_ancestor(&old));
+        let widget = {
+            let rows = self.rows.borrow();
+            let Some(row) = rows.get(index) else {
+                return;
+            };
+            self.render_row(&Renderer::new(&self.obj()), index, row)
+        };
+        set_highlight(&widget, self.highlight().as_deref());
+        content.insert_child_after(&widget, old.prev_sibling().as_ref());
+        content.remove(&old);
+    }
+
+    /// Bring the document in line with the content of an edited paragraph or heading.
+    /// A paragraph typed to start with `#` followed by a space becomes a heading.
+    pub(super) fn block_edited(&self, path: &[usize], buffer: &InlineBuffer) {
+        let Some((&index, nested)) = path.split_first() else {
+            return;
+        };
+        let mut content = buffer.to_inlines();
+        edit::number_footnotes(&mut content, &self.footnotes.borrow());
+
+        let mut is_heading = false;
+        let mut new_heading = None;
+        {
+            let mut rows = self.rows.borrow_mut();
+            let Some(Row::Block(block)) = rows.get_mut(index) else {
+                return;
+            };
+            let Some(block) = edit::block_at_mut(block, nested) else {
+                return;
+            };
+            match block {
+                Block::Heading { content: old, .. } if *old != content => {
+                    *old = content;
+                    is_heading = true;
+                }
+                Block::Paragraph(old) if *old != content => {
+                    if edit::heading_prefix(old).is_none() {
+                        new_heading = edit::heading_prefix(&content);
+                    }
+                    *old = content;
+                }
+                _ => return,
+            }
+        }
+
+        self.dirty_rows.borrow_mut().insert(index);
+        self.obj().set_modified(true);
+        if is_heading {
+            self.index_rows();
+        }
+
+        if let Some((level, content)) = new_heading {
+            // The buffer is still emitting its change, so replace its view afterwards
+            let path = path.to_vec();
+            glib::idle_add_local_once(glib_macros::clone!(
+                #[weak(rename_to = viewer)]
+                self,
+                move || viewer.make_heading(&path, level, content)
+            ));
+        }
+    }
+
+    /// Turn the paragraph at `path` into a heading and move the caret into it
+    fn make_heading(&self, path: &[usize], level: u8, content: Vec<Inline>) {
+        let Some((&index, nested)) = path.split_first() else {
+            return;
+        };
+        {
+            let mut rows = self.rows.borrow_mut();
+            let Some(Row::Block(block)) = rows.get_mut(index) else {
+                return;
+            };
+            let Some(block @ Block::Paragraph(_)) = edit::block_at_mut(block, nested) else {
+                return;
+            };
+            *block = Block::Heading { level, content };
+        }
+
+        self.index_rows();
+        self.focus_path.replace(Some(path.to_vec()));
+        self.rerender_row(index);
+    }
+
+    /// Split the paragraph or heading at `path` at `offset` of its buffer, the text after
+    /// it going into a new paragraph that takes the caret. Like structural edits in
+    /// general, the split is not in the undo history of the buffers.
+    pub(super) fn split_block(&self, path: &[usize], buffer: &InlineBuffer, offset: usize) {
+        let Some((&index, nested)) = path.split_first() else {
+            return;
+        };
+        let (mut head, mut tail) = buffer.split_inlines(InlinePos::new(offset));
+        edit::number_footnotes(&mut head, &self.footnotes.borrow());
+        edit::number_footnotes(&mut tail, &self.footnotes.borrow());
+
+        let new_path = {
+            let mut rows = self.rows.borrow_mut();
+            let Some(Row::Block(block)) = rows.get_mut(index) else {
+                return;
+            };
+            if nested.is_empty() {
+                let Some(new) = edit::split(block, head, tail) else {
+                    return;
+                };
+                rows.insert(index + 1, Row::Block(new));
+                vec![index + 1]
+            } else {
+                let Some(new) =
+                    edit::block_at_mut(block, nested).and_then(|old| edit::split(old, head, tail))
+                else {
+                    return;
+                };
+                let Some(new_nested) = edit::insert_after(block, nested, new) else {
+                    return;
+                };
+                std::iter::once(index).chain(new_nested).collect()
+            }
+        };
+
+        if nested.is_empty() {
+            // The new row has no source yet, `to_markdown` writes it after the block's source
+            let mut row_ranges = self.row_ranges.borrow_mut();
+            let end = row_ranges
+                .get(index)
+                .cloned()
+                .flatten()
+                .map(|range| range.end);
+            row_ranges.insert(index + 1, end.map(|end| end..end));
+
+            let mut dirty_rows = self.dirty_rows.borrow_mut();
+            *dirty_rows = dirty_rows
+                .iter()
+                .map(|&row| if row > index { row + 1 } else { row })
+                .collect();
+            dirty_rows.insert(index + 1);
+        }
+        self.dirty_rows.borrow_mut().insert(index);
+        self.obj().set_modified(true);
+        self.index_rows();
+        self.focus_path.replace(Some(new_path));
+
+        if nested.is_empty() {
+            // Rows after the new one have moved, so their widgets edit the wrong blocks
+            let offset = self.scroll_offset();
+            self.render();
+            self.set_scroll_offset(offset);
+        } else {
+            self.rerender_row(index);
+        }
+    }
+
+    /// Markdown for the document, splicing edited blocks into the original source
+    pub(super) fn to_markdown(&self) -> String {
+        let mut source = self.source.borrow().clone();
+        let rows = self.rows.borrow();
+        let row_ranges = self.row_ranges.borrow();
+        let style = self.style.borrow();
+
+        for &index in self.dirty_rows.borrow().iter().rev() {
+            let (Some(Row::Block(block)), Some(Some(range))) =
+                (rows.get(index), row_ranges.get(index))
+            else {
+                continue;
+            };
+            let written = markdown::write_block(block, &style);
+            if !range.is_empty() {
+                source.replace_range(range.clone(), &written);
+            } else if !written.is_empty() {
+                // A block split off the one before, in the empty range at the end of its source
+                source.insert_str(range.start, &format!("\n\n{written}"));
+            }
+        }
+        source
+    }
+
+    /// Print operation that lays out the document when printing begins, so pages follow
+    /// the paper size chosen in the dialog
+    pub(super) fn print_operation(&self) -> gtk::PrintOperation {
+        let operation = gtk::PrintOperation::new();
+        let job_name = self
+            .title
+            .borrow()
+            .clone()
+            .unwrap_or_else(|| gettext("Document"));
+        operation.set_job_name(&job_name);
+        operation.set_embed_page_setup(true);
+
+        let pages = Rc::new(RefCell::new(None::<Pages>));
+        operation.connect_begin_print(glib_macros::clone!(
+            #[weak(rename_to = imp)]
+            self,
+            #[strong]
+            pages,
+            move |operation, context| {
+                let laid_out = Pages::new(&imp.obj(), &imp.rows.borrow(), context);
+                operation.set_n_pages(laid_out.len() as i32);
+                pages.replace(Some(laid_out));
+            }
+        ));
+        operation.connect_draw_page(move |_, context, page| {
+            if let Some(pages) = pages.borrow().as_ref() {
+                pages.draw_page(context, page as usize);
+            }
+        });
+        operation
+    }
+
+    /// Shortcuts to format the selection of the focused view: Ctrl+B, Ctrl+I and Ctrl+K for links
+    fn setup_editing(&self) {
+        let controller = gtk::ShortcutController::new();
+        controller.set_scope(gtk::ShortcutScope::Managed);
+
+        type Handler = fn(&MdViewer, &InlineView);
+        let shortcuts: [(&str, Handler); 3] = [
+            ("<Control>b", |viewer, view| {
+                viewer.toggle_attribute(view, TextAttr::Bold)
+            }),
+            ("<Control>i", |viewer, view| {
+                viewer.toggle_attribute(view, TextAttr::Italic)
+            }),
+            ("<Control>k", Self::edit_link),
+        ];
+        for (trigger, handler) in shortcuts {
+            controller.add_shortcut(gtk::Shortcut::new(
+                gtk::ShortcutTrigger::parse_string(trigger),
+                Some(gtk::CallbackAction::new(glib_macros::clone!(
+                    #[weak(rename_to = viewer)]
+                    self,
+                    #[upgrade_or]
+                    glib::Propagation::Proceed,
+                    move |_, _| match viewer.focused_view() {
+                        Some(view) => {
+                            handler(&viewer, &view);
+                            glib::Propagation::Stop
+                        }
+                        None => glib::Propagation::Proceed,
+                    }
+                ))),
+            ));
+        }
+        self.obj().add_controller(controller);
+    }
+
+    /// Editable view of the document that has the keyboard focus
+    fn focused_view(&self) -> Option<InlineView> {
+        let obj = self.obj();
+        obj.root()?
+            .focus()
+            .and_downcast::<InlineView>()
+            .filter(|view| view.editable() && view.is_ancestor(&*obj))
+    }
+
+    /// Remove `attr` from the selection if all of it has the attribute, apply it otherwise
+    fn toggle_attribute(&self, view: &InlineView, attr: TextAttr) {
+        let Some(buffer) = view.buffer() else {
+            return;
+        };
+        let (start, end) = view.selection_bounds();
+        if start == end {
+            return;
+        }
+
+        buffer.begin_user_action();
+        if buffer.has_attribute(start, end, attr.attr_type()) {
+            buffer.remove_attribute(start, end, attr.attr_type());
+        } else {
+            buffer.apply_attribute(start, end, attr);
+        }
+        buffer.end_user_action();
+    }
+
+    /// Remove the link from the selection, or ask for a URL to link it to.
+    /// Without a selection the URL is inserted as its own link text.
+    fn edit_link(&self, view: &InlineView) {
+        let Some(buffer) = view.buffer() else {
+            return;
+        };
+        let (start, end) = view.selection_bounds();
+        if start != end && buffer.has_attribute(start, end, TextAttrType::Link) {
+            buffer.begin_user_action();
+            buffer.remove_attribute(start, end, TextAttrType::Link);
+            buffer.end_user_action();
+            return;
+        }
+
+        let entry = gtk::Entry::builder()
+            .placeholder_text("https://")
+            .input_purpose(gtk::InputPurpose::Url)
+            .width_chars(32)
+            .build();
+        let popover = gtk::Popover::builder().child(&entry).build();
+        if let Some(rect) = view
+            .compute_bounds(view)
+            .map(|bounds| gdk::Rectangle::new(0, 0, bounds.width() as i32, bounds.height() as i32))
+        {
+            popover.set_pointing_to(Some(&rect));
+        }
+        popover.set_parent(view);
+        popover.connect_closed(|popover| popover.unparent());
+
+        entry.connect_activate(glib_macros::clone!(
+            #[weak]
+            view,
+            #[weak]
+            buffer,
+            #[weak]
+            popover,
+            move |entry| {
+                let url = entry.text().trim().to_string();
+                popover.popdown();
+                if url.is_empty() {
+                    return;
+                }
+
+                buffer.begin_user_action();
+                let end = if start == end {
+                    buffer.insert(start, &url)
+                } else {
+                    end
+                };
+                buffer.apply_attribute(start, end, TextAttr::Link(url));
+                buffer.end_user_action();
+                view.grab_focus();
+                view.place_cursor(end);
+            }
+        ));
+        popover.popup();
+        entry.grab_focus();
+    }
+
+    /// Register a scroll target, the first widget registered under a name wins.
+    /// Virtualised rows come and go, so only row anchors are used for them.
+    pub(super) fn add_anchor(&self, name: &str, widget: &gtk::Widget) {
+        if self.list_view.borrow().is_some() {
+            return;
+        }
+        self.anchors
+            .borrow_mut()
+            .entry(name.to_string())
+            .or_insert_with(|| widget.clone());
+    }
+
+    /// Scroll so the anchor is at the top
+    pub(super) fn scroll_to_anchor(&self, name: &str) -> bool {
+        let target = self.anchors.borrow().get(name).cloned();
+        if let Some(target) = target
+            && self.is_laid_out()
+        {
+            return self.scroll_to_widget(&target);
+        }
+
+        let row = self.row_anchors.borrow().get(name).copied();
+        match row {
+            Some(row) => self.scroll_to_row(row),
+            None => false,
+        }
+    }
+
+    fn scroll_to_widget(&self, target: &gtk::Widget) -> bool {
+        let Some(content) = self.content.borrow().clone() else {
+            return false;
+        };
+        let Some(point) = target.compute_point(&content, &gtk::graphene::Point::zero()) else {
+            return false;
+        };
+
+        self.scrolled.vadjustment().set_value(point.y() as f64);
+        true
+    }
+
+    fn scroll_to_row(&self, row: usize) -> bool {
+        // A document that was just loaded has no positions to scroll to yet
+        if !self.is_laid_out() {
+            self.pending_scroll.set(Some(row));
+            return row < self.rows.borrow().len();
+        }
+
+        if let Some(list_view) = self.list_view.borrow().as_ref() {
+            list_view.scroll_to(row as u32, gtk::ListScrollFlags::NONE, None);
+            return true;
+        }
+
+        self.row_widget(row)
+            .is_some_and(|widget| self.scroll_to_widget(&widget))
+    }
+
+    /// Widget of a row, if it has one; virtualised rows only have one near the viewport
+    fn row_widget(&self, row: usize) -> Option<gtk::Widget> {
+        if self.list_view.borrow().is_some() {
+            return self.bound_rows.borrow().get(&row).cloned();
+        }
+        let mut child = self.content.borrow().as_ref()?.first_child();
+        for _ in 0..row {
+            child = child?.next_sibling();
+        }
+        child
+    }
+
+    pub(super) fn scroll_offset(&self) -> f64 {
+        match self.pending_offset.get() {
+            Some(offset) => offset,
+            None => self.scrolled.vadjustment().value(),
+        }
+    }
+
+    pub(super) fn set_scroll_offset(&self, offset: f64) {
+        if self.is_laid_out() {
+            self.scrolled.vadjustment().set_value(offset);
+        } else {
+            self.pending_offset.set(Some(offset));
+        }
+    }
+
+    /// Last heading at or above the top of the viewport
+    pub(super) fn visible_heading(&self) -> Option<Heading> {
+        // Rows have no positions before the viewer is first shown
+        if !self.is_laid_out() {
+            return None;
+        }
+        let top_row = self.top_row();
+        let row_anchors = self.row_anchors.borrow();
+        self.headings
+            .borrow()
+            .iter()
+            .take_while(|heading| {
+                row_anchors
+                    .get(&heading.anchor)
+                    .is_some_and(|&row| row <= top_row)
+            })
+            .last()
+            .cloned()
+    }
+
+    /// Row at the top of the viewport
+    fn top_row(&self) -> usize {
+        let offset = self.scroll_offset();
+        let Some(content) = self.content.borrow().clone() else {
+            // The first of the rows the list view has created that reaches into the viewport
+            return self
+                .bound_rows
+                .borrow()
+                .iter()
+                .filter(|(_, widget)| {
+                    widget.is_mapped()
+                        && widget
+                            .compute_point(
+                                &self.scrolled,
+                                &gtk::graphene::Point::new(0.0, widget.height() as f32),
+                            )
+                            .is_some_and(|bottom| bottom.y() > 0.0)
+                })
+                .map(|(&row, _)| row)
+                .min()
+                .unwrap_or(0);
+        };
+
+        let mut row = 0;
+        let mut child = content.first_child();
+        while let Some(widget) = child {
+            let bottom = widget
+                .compute_point(
+                    &content,
+                    &gtk::graphene::Point::new(0.0, widget.height() as f32),
+                )
+                .map(|point| point.y() as f64);
+            if bottom.is_none_or(|bottom| bottom > offset) {
+                break;
+            }
+            row += 1;
+            child = widget.next_sibling();
+        }
+        row
+    }
+
+    fn is_laid_out(&self) -> bool {
+        self.scrolled
+            .child()
+            .is_some_and(|child| child.height() > 0)
+    }
+
+    /// Text being searched for, if any
+    fn highlight(&self) -> Option<String> {
+        let query = self.search_query.borrow();
+        (!query.is_empty()).then(|| query.clone())
+    }
+
+    /// Highlight `query` everywhere and scroll to the first row that contains it
+    pub(super) fn search(&self, query: &str) -> usize {
+        let mut count = 0;
+        let mut search_rows = Vec::new();
+        for (index, row) in self.rows.borrow().iter().enumerate() {
+            let matches = markdown::find_matches(&row.searchable_text(), query).len();
+            if matches > 0 {
+                count += matches;
+                search_rows.push(index);
+            }
+        }
+
+        self.search_query.replace(query.to_string());
+        self.current_match.set(None);
+        if let Some(child) = self.scrolled.child() {
+            set_highlight(&child, self.highlight().as_deref());
+        }
+
+        self.search_status
+            .set_label(&match (query.is_empty(), count) {
+                (true, _) => String::new(),
+                (false, 0) => gettext("No matches"),
+                // Translators: {count} is the number of matches in the document
+                (false, count) => ngettext("{count} match", "{count} matches", count as u32)
+                    .replace("{count}", &i18n::number(&count.to_string())),
+            });
+        self.search_entry.remove_css_class("error");
+        if !query.is_empty() && count == 0 {
+            self.search_entry.add_css_class("error");
+        }
+
+        if let Some(&first) = search_rows.first() {
+            self.scroll_to_row(first);
+        }
+        self.search_rows.replace(search_rows);
+        self.search_index.set(0);
+        count
+    }
+
+    /// Scroll to the next or previous row with a search match, wrapping around
+    pub(super) fn search_step(&self, forward: bool) {
+        let rows = self.search_rows.borrow();
+        if rows.is_empty() {
+            return;
+        }
+
+        let index = self.search_index.get();
+        let index = if forward {
+            (index + 1) % rows.len()
+        } else {
+            (index + rows.len() - 1) % rows.len()
+        };
+        self.search_index.set(index);
+        self.scroll_to_row(rows[index]);
+
+        // The match shown by `reveal_match` is left behind
+        if self.current_match.take().is_some()
+            && let Some(child) = self.scrolled.child()
+        {
+            set_highlight(&child, self.highlight().as_deref());
+        }
+    }
+
+    /// Search for `query`, mark its match number `occurrence`, counted from the start of
+    /// the document, as the current one and scroll to it
+    pub(super) fn reveal_match(&self, query: &str, occurrence: usize) {
+        self.search(query);
+        self.search_bar.set_search_mode(true);
+        self.search_entry.set_text(query);
+
+        let mut before = 0;
+        let mut found = None;
+        for (index, row) in self.rows.borrow().iter().enumerate() {
+            let matches = markdown::find_matches(&row.searchable_text(), query).len();
+            if occurrence < before + matches {
+                found = Some((index, occurrence - before));
+                break;
+            }
+            before += matches;
+        }
+        let Some((row, number)) = found else {
+            return;
+        };
+        if let Some(index) = self.search_rows.borrow().iter().position(|&r| r == row) {
+            self.search_index.set(index);
+        }
+        self.current_match.set(Some((row, number)));
+        self.scroll_to_row(row);
+
+        // The row may only get a widget, or its position, in one of the next frames
+        let frames = Cell::new(0);
+        self.obj().add_tick_callback(move |viewer, _| {
+            frames.set(frames.get() + 1);
+            if viewer.imp().scroll_to_current_match() || frames.get() >= REVEAL_FRAMES {
+                glib::ControlFlow::Break
+            } else {
+                glib::ControlFlow::Continue
+            }
+        });
+    }
+
+    /// Mark the match `current_match` points at in the view of its row that contains it.
+    /// Returns the view and the byte range of the match in its text, unless the row has
+    /// no widget.
+    fn mark_current_match(&self) -> Option<(InlineView, Range<usize>)> {
+        let (row, mut number) = self.current_match.get()?;
+        let query = self.highlight()?;
+        let widget = self.row_widget(row)?;
+
+        let mut views = Vec::new();
+        collect_views(&widget, &mut views);
+        for view in views {
+            let matches = markdown::find_matches(&view.text(), &query);
+            let Some(range) = matches.get(number) else {
+                number -= matches.len();
+                continue;
+            };
+            view.set_current_match(Some((
+                InlinePos::new(range.start),
+                InlinePos::new(range.end),
+            )));
+            return Some((view, range.clone()));
+        }
+        None
+    }
+
+    /// Scroll the line of the current match to the middle of the viewport.
+    /// Returns false while its row has not been laid out.
+    fn scroll_to_current_match(&self) -> bool {
+        if self.current_match.get().is_none() {
+            return true;
+        }
+        if !self.is_laid_out() || self.pending_scroll.get().is_some() {
+            return false;
+        }
+        let Some((view, range)) = self.mark_current_match() else {
+            return false;
+        };
+        if view.height() == 0 {
+            return false;
+        }
+
+        let area = view.range_area(InlinePos::new(range.start), InlinePos::new(range.end));
+        let Some(point) = view.compute_point(
+            &self.scrolled,
+            &gtk::graphene::Point::new(area.x() as f32, area.y() as f32),
+        ) else {
+            return false;
+        };
+        let adjustment = self.scrolled.vadjustment();
+        adjustment.set_value(
+            adjustment.value() + point.y() as f64
+                - (adjustment.page_size() - area.height() as f64) / 2.0,
+        );
+        true
+    }
+
+    /// Handle links inside the document and let `open-link` handlers claim the others,
+    /// unclaimed ones are left to the view
+    pub(super) fn activate_link(&self, url: &str) -> bool {
+        match url.strip_prefix('#') {
+            Some(name) => {
+                self.hide_link_preview();
+                self.scroll_to_anchor(name);
+                true
+            }
+            // Wiki-links mean nothing outside the application
+            None => {
+                self.obj()
+                    .emit_by_name::<bool>("open-link", &[&url, &false])
+                    || url.starts_with(markdown::WIKI_LINK_SCHEME)
+            }
+        }
+    }
+
+    /// Offer a middle-clicked link to `open-link` handlers, links inside the document are
+    /// ignored
+    pub(super) fn open_link_in_new_tab(&self, url: &str) {
+        if !url.starts_with('#') {
+            self.hide_link_preview();
+            self.obj().emit_by_name::<bool>("open-link", &[&url, &true]);
+        }
+    }
+
+    /// Pop up the footnote text next to a hovered footnote reference
+    pub(super) fn show_link_preview(&self, view: &InlineView, url: &str, area: &gdk::Rectangle) {
+        self.hide_link_preview();
+
+        let Some(label) = url.strip_prefix("#fn-") else {
+            return;
+        };
+        let footnotes = self.footnotes.borrow();
+        let Some(footnote) = footnotes.iter().find(|f| f.label == label) else {
+            return;
+        };
+
+        let preview = Renderer::new(&self.obj()).footnote_preview(footnote);
+        self.set_text_style(&preview);
+        let popover = gtk::Popover::builder()
+            .autohide(false)
+            .child(&preview)
+            .pointing_to(area)
+            .build();
+        popover.set_parent(view);
+        popover.connect_closed(|popover| popover.unparent());
+        popover.popup();
+
+        self.preview.replace(Some(popover));
+    }
+
+    pub(super) fn hide_link_preview(&self) {
+        if let Some(popover) = self.preview.take() {
+            popover.popdown();
+        }
+    }
+}
+
+/// Set the search highlight on every searched `InlineView` below `widget`, without a
+/// current match
+fn set_highlight(widget: &gtk::Widget, highlight: Option<&str>) {
+    if let Some(view) = widget.downcast_ref::<InlineView>() {
+        if is_searched(view) {
+            view.set_highlight(highlight);
+            view.set_current_match(None);
+        }
+        return;
+    }
+
+    let mut child = widget.first_child();
+    while let Some(widget) = child {
+        set_highlight(&widget, highlight);
+        child = widget.next_sibling();
+    }
+}
+
+/// Every searched `InlineView` below `widget`, in the order they are shown
+fn collect_views(widget: &gtk::Widget, views: &mut Vec<InlineView>) {
+    if let Some(view) = widget.downcast_ref::<InlineView>() {
+        if is_searched(view) {
+            views.push(view.clone());
+        }
+        return;
+    }
+
+    let mut child = widget.first_child();
+    while let Some(widget) = child {
+        collect_views(&widget, views);
+        child = widget.next_sibling();
+    }
+}
+
+/// Whether the text of `view` is part of `Row::searchable_text`, which leaves out the alt
+/// text of block images and the links back from footnotes
+fn is_searched(view: &InlineView) -> bool {
+    !view.has_css_class("image-alt") && !view.has_css_class("footnote-back")
+}
+
+/// Settings of the viewer that apply to the text of every row
+struct TextStyle<'a> {
+    zoom: f64,
+    line_height: f64,
+    code_font: Option<&'a str>,
+}
+
+/// Apply `style` to every `InlineView`, label, picture and code block below `widget`
+fn set_text_style(widget: &gtk::Widget, style: &TextStyle) {
+    if let Some(view) = widget.downcast_ref::<InlineView>() {
+        view.set_scale(style.zoom);
+        view.set_line_height(style.line_height);
+        view.set_monospace_family(style.code_font);
+        return;
+    }
+    if let Some(view) = widget.downcast_ref::<sourceview5::View>() {
+        set_code_style(&view.buffer(), style);
+        return;
+    }
+    if let Some(label) = widget.downcast_ref::<gtk::Label>() {
+        // Keep the label's own attributes, only its scale follows the zoom
+        let attributes = label
+            .attributes()
+            .and_then(|attributes| attributes.copy())
+            .unwrap_or_default();
+        attributes.filter(|attribute| attribute.type_() == pango::AttrType::Scale);
+        if style.zoom != 1.0 {
+            attributes.insert(pango::AttrFloat::new_scale(style.zoom));
+        }
+        label.set_attributes(Some(&attributes));
+        return;
+    }
+    if let Some(paintable) = widget
+        .downcast_ref::<gtk::Picture>()
+        .and_then(|picture| picture.paintable())
+        .and_downcast::<ScaledPaintable>()
+    {
+        paintable.set_scale(style.zoom);
+        return;
+    }
+
+    let mut child = widget.first_child();
+    while let Some(widget) = child {
+        set_text_style(&widget, style);
+        child = widget.next_sibling();
+    }
+}
+
+/// Show the whole text of a code block in the code font and at the zoom of `style`
+fn set_code_style(buffer: &gtk::TextBuffer, style: &TextStyle) {
+    let tag_table = buffer.tag_table();
+    let tag = match tag_table.lookup("code-style") {
+        Some(tag) => tag,
+        None if style.code_font.is_none() && style.zoom == 1.0 => return,
+        None => {
+            let tag = gtk::TextTag::new(Some("code-style"));
+            tag_table.add(&tag);
+            buffer.apply_tag(&tag, &buffer.start_iter(), &buffer.end_iter());
+            tag
+        }
+    };
+    tag.set_family(style.code_font);
+    tag.set_scale(style.zoom);
+}
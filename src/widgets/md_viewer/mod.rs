@@ -1,8 +1,31 @@
+mod edit;
+mod image;
 mod imp;
+mod print;
+mod render;
 
-use adw::gtk::glib;
+use std::path::Path;
+
+use adw::gtk::prelude::*;
+use adw::gtk::subclass::prelude::*;
+use adw::gtk::{self, gio, glib};
+
+use crate::markdown::FrontMatter;
+
+/// Entry of the document outline
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heading {
+    pub level: u8,
+    pub title: String,
+    /// Anchor name to pass to `MdViewer::scroll_to_anchor`
+    pub anchor: String,
+}
 
 glib::wrapper! {
+    /// Markdown document view with its own scrolling and a search bar (Ctrl+F).
+    /// Very large documents are virtualised so only rows near the viewport have widgets.
+    /// When editable, paragraphs and headings can be edited in place and written back with
+    /// `to_markdown`.
     pub struct MdViewer(ObjectSubclass<imp::MdViewer>)
         @extends adw::gtk::Box, adw::gtk::Widget,
         @implements adw::gtk::Accessible, adw::gtk::Buildable, adw::gtk::ConstraintTarget, adw::gtk::Orientable;
@@ -12,6 +35,156 @@ impl MdViewer {
     pub fn new() -> Self {
         glib::Object::builder().build()
     }
+
+    /// Render markdown source, resolving relative image paths against `base_dir`
+    pub fn set_markdown(&self, source: &str, base_dir: Option<&Path>) {
+        self.imp().base_dir.replace(base_dir.map(Path::to_path_buf));
+        self.imp().set_source(source);
+    }
+
+    /// Grow to the height of the whole document instead of scrolling and load images
+    /// before showing them, so the document can be rendered to an image in one frame
+    pub fn set_fit_content(&self, fit: bool) {
+        self.imp().set_fit_content(fit);
+    }
+
+    /// Markdown source of the document with the edits made so far. Blocks that were not
+    /// edited keep their original source, edited ones follow the style of the document.
+    pub fn to_markdown(&self) -> String {
+        self.imp().to_markdown()
+    }
+
+    /// Ask `resolve-wiki-link` about every wiki-link again by rendering the document
+    /// anew, keeping the scroll position. While the document is edited this is left to
+    /// the end of editing, which renders it anyway, so the caret is not lost.
+    pub fn update_wiki_links(&self) {
+        self.imp().update_wiki_links();
+    }
+
+    /// Connect to the signal emitted when the headings of the document change
+    pub fn connect_headings_changed<F: Fn(&Self) + 'static>(&self, f: F) -> glib::SignalHandlerId {
+        self.connect_local("headings-changed", false, move |args| {
+            let viewer = args[0].get::<Self>().ok()?;
+            f(&viewer);
+            None
+        })
+    }
+
+    /// Connect to the signal emitted when a link out of the document is followed.
+    /// `new_tab` is set when it was middle-clicked; return `true` to stop the link from
+    /// being opened externally.
+    pub fn connect_open_link<F: Fn(&Self, &str, bool) -> bool + 'static>(
+        &self,
+        f: F,
+    ) -> glib::SignalHandlerId {
+        self.connect_local("open-link", false, move |args| {
+            let viewer = args[0].get::<Self>().ok()?;
+            let url = args[1].get::<String>().ok()?;
+            let new_tab = args[2].get::<bool>().ok()?;
+            Some(f(&viewer, &url, new_tab).to_value())
+        })
+    }
+
+    /// Connect to the signal emitted for every wiki-link as the document is rendered,
+    /// with the `[[target]]` of the link. Return `false` to show it as broken.
+    pub fn connect_resolve_wiki_link<F: Fn(&Self, &str) -> bool + 'static>(
+        &self,
+        f: F,
+    ) -> glib::SignalHandlerId {
+        self.connect_local("resolve-wiki-link", false, move |args| {
+            let viewer = args[0].get::<Self>().ok()?;
+            let target = args[1].get::<String>().ok()?;
+            Some(f(&viewer, &target).to_value())
+        })
+    }
+
+    /// Front matter of the current document, if it has any
+    pub fn front_matter(&self) -> Option<FrontMatter> {
+        self.imp().front_matter.borrow().clone()
+    }
+
+    /// Top-level headings of the current document, in order
+    pub fn headings(&self) -> Vec<Heading> {
+        self.imp().headings.borrow().clone()
+    }
+
+    /// Scroll to a heading slug or another `#name` link target
+    pub fn scroll_to_anchor(&self, name: &str) -> bool {
+        self.imp().scroll_to_anchor(name)
+    }
+
+    /// Highlight `query` and scroll to its first occurrence, returning the number of matches
+    pub fn search(&self, query: &str) -> usize {
+        self.imp().search(query)
+    }
+
+    /// Scroll to the next block with a search match
+    pub fn search_next(&self) {
+        self.imp().search_step(true)
+    }
+
+    /// Scroll to the previous block with a search match
+    pub fn search_previous(&self) {
+        self.imp().search_step(false)
+    }
+
+    /// Distance in pixels the document is scrolled down by
+    pub fn scroll_offset(&self) -> f64 {
+        self.imp().scroll_offset()
+    }
+
+    /// Scroll the document down by `offset` pixels, once it has been laid out
+    pub fn set_scroll_offset(&self, offset: f64) {
+        self.imp().set_scroll_offset(offset)
+    }
+
+    /// Heading of the section shown at the top of the viewer, if it is below one and the
+    /// viewer has been shown
+    pub fn visible_heading(&self) -> Option<Heading> {
+        self.imp().visible_heading()
+    }
+
+    /// Show the search bar with `query` and scroll to its match number `occurrence`,
+    /// counting the matches of every block from the start of the document. The match is
+    /// highlighted as the current one until the search changes.
+    pub fn reveal_match(&self, query: &str, occurrence: usize) {
+        self.imp().reveal_match(query, occurrence)
+    }
+
+    /// Scale the text, images and code up a step, like Ctrl+plus
+    pub fn zoom_in(&self) {
+        self.imp().zoom_in()
+    }
+
+    /// Scale the text, images and code down a step, like Ctrl+minus
+    pub fn zoom_out(&self) {
+        self.imp().zoom_out()
+    }
+
+    /// Show the print dialog and print the document, paginated for the chosen paper
+    pub fn print(&self, parent: Option<&impl IsA<gtk::Window>>) {
+        let operation = self.imp().print_operation();
+        operation.set_allow_async(true);
+        if let Err(err) = operation.run(gtk::PrintOperationAction::PrintDialog, parent) {
+            glib::g_warning!("potato-md", "Failed to print: {err}");
+        }
+    }
+
+    /// Write the document to a PDF file without showing a dialog
+    pub fn export_pdf(&self, path: &Path) -> Result<(), glib::Error> {
+        let operation = self.imp().print_operation();
+        operation.set_export_filename(path);
+        operation.run(gtk::PrintOperationAction::Export, None::<&gtk::Window>)?;
+        Ok(())
+    }
+
+    /// Read and render a markdown file
+    pub fn load_file(&self, file: &gio::File) -> Result<(), glib::Error> {
+        let (contents, _) = file.load_contents(gio::Cancellable::NONE)?;
+        let base_dir = file.parent().and_then(|parent| parent.path());
+        self.set_markdown(&String::from_utf8_lossy(&contents), base_dir.as_deref());
+        Ok(())
+    }
 }
 
 impl Default for MdViewer {
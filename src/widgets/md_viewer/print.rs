@@ -0,0 +1,437 @@
+//! Paginating the document onto printed pages
+
+use std::ops::Range;
+use std::path::PathBuf;
+
+use adw::gtk::prelude::*;
+use adw::gtk::subclass::prelude::*;
+use adw::gtk::{self, gdk, graphene, pango};
+
+use super::MdViewer;
+use super::image;
+use super::render::{self, Renderer, Row};
+use crate::i18n;
+use crate::markdown::{Block, Footnote, FrontMatter, Inline};
+use crate::widgets::{InlineBuffer, PrintLayout, TextAttr};
+
+/// Gap between blocks, in points
+const SPACING: f64 = 9.0;
+/// Indentation of list items and quotes, in points
+const INDENT: f64 = 18.0;
+/// Body font size, in points
+const FONT_SIZE: f64 = 11.0;
+
+/// Font size relative to the body and weight of each heading level, as in the stylesheet
+const HEADINGS: [(f64, pango::Weight); 6] = [
+    (2.0, pango::Weight::Ultrabold),
+    (1.6, pango::Weight::Ultrabold),
+    (1.35, pango::Weight::Bold),
+    (1.15, pango::Weight::Bold),
+    (1.0, pango::Weight::Bold),
+    (1.0, pango::Weight::Bold),
+];
+
+enum Content {
+    Text(PrintLayout),
+    Image {
+        texture: gdk::Texture,
+        width: f64,
+        height: f64,
+    },
+    Rule,
+}
+
+/// Block of the document as it is placed on the pages
+struct Item {
+    /// Left edge, in device units
+    x: f64,
+    width: f64,
+    content: Content,
+    /// List marker drawn before the first line
+    marker: Option<PrintLayout>,
+    /// Left edges of the quotes the item is in, a bar is drawn there
+    bars: Vec<f64>,
+    /// Drawn on a grey background, for code
+    shaded: bool,
+    space_before: f64,
+    /// Headings are not left at the bottom of a page without the text that follows them
+    keep_with_next: bool,
+}
+
+impl Item {
+    /// Top of every line followed by the bottom of the last; items only break between lines
+    fn line_bounds(&self, points: f64) -> Vec<f64> {
+        match &self.content {
+            Content::Text(layout) => layout.line_bounds(),
+            Content::Image { height, .. } => vec![0.0, *height],
+            Content::Rule => vec![0.0, points],
+        }
+    }
+}
+
+/// Part of an item on a page
+struct Slice {
+    item: usize,
+    lines: Range<usize>,
+    /// Top of the slice on the page
+    y: f64,
+}
+
+/// Document laid out for a print context, split into pages
+pub(super) struct Pages {
+    items: Vec<Item>,
+    pages: Vec<Vec<Slice>>,
+    /// Device units per point
+    points: f64,
+}
+
+impl Pages {
+    /// Lay out the rows of `viewer` for the paper and resolution of `context`
+    pub(super) fn new(viewer: &MdViewer, rows: &[Row], context: &gtk::PrintContext) -> Self {
+        let points = context.dpi_y() / 72.0;
+        let mut builder = Builder {
+            renderer: Renderer::for_print(viewer),
+            base_dir: viewer.imp().base_dir.borrow().clone(),
+            pango_context: context.create_pango_context(),
+            dpi: context.dpi_y(),
+            points,
+            width: context.width(),
+            items: Vec::new(),
+        };
+
+        for row in rows {
+            match row {
+                Row::Header(front_matter) => builder.metadata_header(front_matter),
+                Row::Block(block) => builder.block(block, 0.0, &[]),
+                Row::Footnotes(footnotes) => builder.footnotes(footnotes),
+            }
+        }
+
+        let mut pages = Self {
+            items: builder.items,
+            pages: Vec::new(),
+            points,
+        };
+        pages.paginate(context.height());
+        pages
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Fill pages of `page_height` device units, breaking items between lines
+    fn paginate(&mut self, page_height: f64) {
+        let mut pages = vec![Vec::new()];
+        let mut y = 0.0;
+
+        for (index, item) in self.items.iter().enumerate() {
+            let bounds = item.line_bounds(self.points);
+            let lines = bounds.len() - 1;
+            let mut space = if y > 0.0 { item.space_before } else { 0.0 };
+
+            // The first line has to fit, together with the first line of the next item
+            // when they are kept together
+            let mut needed = space + bounds[1] - bounds[0];
+            if item.keep_with_next
+                && let Some(next) = self.items.get(index + 1)
+            {
+                let next_bounds = next.line_bounds(self.points);
+                needed += next.space_before + next_bounds[1] - next_bounds[0];
+            }
+            if y > 0.0 && y + needed > page_height {
+                pages.push(Vec::new());
+                y = 0.0;
+                space = 0.0;
+            }
+            y += space;
+
+            let mut first = 0;
+            loop {
+                let top = bounds[first];
+                let mut last = first + 1;
+                while last < lines && bounds[last + 1] - top <= page_height - y {
+                    last += 1;
+                }
+                if let Some(page) = pages.last_mut() {
+                    page.push(Slice {
+                        item: index,
+                        lines: first..last,
+                        y,
+                    });
+                }
+                y += bounds[last] - top;
+
+                if last == lines {
+                    break;
+                }
+                pages.push(Vec::new());
+                y = 0.0;
+                first = last;
+            }
+        }
+
+        self.pages = pages;
+    }
+
+    /// Draw page `page` on the cairo context of `context`
+    pub(super) fn draw_page(&self, context: &gtk::PrintContext, page: usize) {
+        let cr = context.cairo_context();
+        let Some(slices) = self.pages.get(page) else {
+            return;
+        };
+
+        for slice in slices {
+            let item = &self.items[slice.item];
+            let bounds = item.line_bounds(self.points);
+            let top = bounds[slice.lines.start];
+            let height = bounds[slice.lines.end] - top;
+
+            for &bar in &item.bars {
+                cr.set_source_rgba(0.0, 0.0, 0.0, 0.25);
+                cr.rectangle(bar, slice.y, 2.0 * self.points, height);
+                let _ = cr.fill();
+            }
+            if item.shaded {
+                cr.set_source_rgba(0.0, 0.0, 0.0, 0.06);
+                cr.rectangle(item.x, slice.y, item.width, height);
+                let _ = cr.fill();
+            }
+
+            let _ = cr.save();
+            cr.rectangle(
+                item.x - INDENT * self.points,
+                slice.y,
+                item.width + INDENT * self.points,
+                height,
+            );
+            cr.clip();
+            cr.translate(item.x, slice.y - top);
+            if let (Some(marker), 0) = (&item.marker, slice.lines.start) {
+                let _ = cr.save();
+                cr.translate(-INDENT * self.points, 0.0);
+                marker.draw(&cr, &gdk::RGBA::BLACK);
+                let _ = cr.restore();
+            }
+            match &item.content {
+                Content::Text(layout) => layout.draw(&cr, &gdk::RGBA::BLACK),
+                Content::Image {
+                    texture,
+                    width,
+                    height,
+                } => {
+                    let snapshot = gtk::Snapshot::new();
+                    snapshot.append_texture(
+                        texture,
+                        &graphene::Rect::new(0.0, 0.0, *width as f32, *height as f32),
+                    );
+                    if let Some(node) = snapshot.to_node() {
+                        node.draw(&cr);
+                    }
+                }
+                Content::Rule => {
+                    cr.set_source_rgba(0.0, 0.0, 0.0, 0.25);
+                    cr.rectangle(0.0, 0.0, item.width, self.points);
+                    let _ = cr.fill();
+                }
+            }
+            let _ = cr.restore();
+        }
+    }
+}
+
+/// Turns document blocks into items
+struct Builder {
+    renderer: Renderer,
+    /// Directory that relative image paths are resolved against
+    base_dir: Option<PathBuf>,
+    pango_context: pango::Context,
+    dpi: f64,
+    /// Device units per point
+    points: f64,
+    /// Width of the printable area
+    width: f64,
+    items: Vec<Item>,
+}
+
+impl Builder {
+    fn font(&self, scale: f64, weight: pango::Weight) -> pango::FontDescription {
+        let mut font = gtk::Settings::default()
+            .and_then(|settings| settings.gtk_font_name())
+            .map(|name| pango::FontDescription::from_string(&name))
+            .unwrap_or_else(|| pango::FontDescription::from_string("Sans"));
+        font.set_size((FONT_SIZE * scale * pango::SCALE as f64) as i32);
+        font.set_weight(weight);
+        font
+    }
+
+    fn text(&self, buffer: &InlineBuffer, font: &pango::FontDescription, x: f64) -> Content {
+        Content::Text(PrintLayout::new(
+            buffer,
+            &self.pango_context,
+            font,
+            self.width - x,
+            self.dpi,
+        ))
+    }
+
+    fn push(&mut self, x: f64, bars: &[f64], content: Content) -> &mut Item {
+        self.items.push(Item {
+            x,
+            width: self.width - x,
+            content,
+            marker: None,
+            bars: bars.to_vec(),
+            shaded: false,
+            space_before: SPACING * self.points,
+            keep_with_next: false,
+        });
+        let last = self.items.len() - 1;
+        &mut self.items[last]
+    }
+
+    fn metadata_header(&mut self, front_matter: &FrontMatter) {
+        if let Some(title) = front_matter.title() {
+            let buffer = InlineBuffer::new();
+            buffer.push_str(&title);
+            let (scale, weight) = HEADINGS[0];
+            let content = self.text(&buffer, &self.font(scale, weight), 0.0);
+            self.push(0.0, &[], content);
+        }
+
+        let tags = front_matter.tag_values();
+        let byline = [
+            front_matter.author(),
+            front_matter.date().map(|date| i18n::date(&date)),
+        ]
+        .into_iter()
+        .flatten()
+        .chain((!tags.is_empty()).then(|| {
+            let tags = tags.iter().map(render::tag_label).collect::<Vec<_>>();
+            tags.join(", ")
+        }))
+        .collect::<Vec<_>>()
+        .join(" · ");
+        if !byline.is_empty() {
+            let buffer = InlineBuffer::new();
+            let end = buffer.push_str(&byline);
+            buffer.apply_attribute(buffer.start_pos(), end, TextAttr::Italic);
+            let content = self.text(&buffer, &self.font(1.0, pango::Weight::Normal), 0.0);
+            self.push(0.0, &[], content);
+        }
+    }
+
+    fn block(&mut self, block: &Block, x: f64, bars: &[f64]) {
+        let body = self.font(1.0, pango::Weight::Normal);
+        match block {
+            Block::Heading { level, content } => {
+                let (scale, weight) = HEADINGS[(*level as usize).clamp(1, 6) - 1];
+                let buffer = self.renderer.inline_buffer(content);
+                let content = self.text(&buffer, &self.font(scale, weight), x);
+                let points = self.points;
+                let item = self.push(x, bars, content);
+                item.space_before = SPACING * 1.5 * points;
+                item.keep_with_next = true;
+            }
+            Block::Paragraph(content) => {
+                let buffer = self.renderer.inline_buffer(content);
+                let content = self.text(&buffer, &body, x);
+                self.push(x, bars, content);
+            }
+            Block::Image(image) => {
+                let texture = image
+                    .local_path(self.base_dir.as_deref())
+                    .and_then(|path| image::load_texture(&path).ok());
+                match texture {
+                    Some(texture) => {
+                        // Images are sized in pixels, at most as wide as the page
+                        let scale = self.dpi / 96.0;
+                        let width = texture.width() as f64 * scale;
+                        let fit = ((self.width - x) / width).min(1.0);
+                        let content = Content::Image {
+                            width: width * fit,
+                            height: texture.height() as f64 * scale * fit,
+                            texture,
+                        };
+                        self.push(x, bars, content);
+                    }
+                    None => {
+                        let buffer = InlineBuffer::new();
+                        let end = buffer.push_str(&image.alt);
+                        buffer.apply_attribute(buffer.start_pos(), end, TextAttr::Italic);
+                        let content = self.text(&buffer, &body, x);
+                        self.push(x, bars, content);
+                    }
+                }
+            }
+            Block::Code { code, .. } => {
+                let buffer = InlineBuffer::new();
+                let end = buffer.push_str(code.strip_suffix('\n').unwrap_or(code));
+                buffer.apply_attribute(
+                    buffer.start_pos(),
+                    end,
+                    TextAttr::FontFamily("monospace".into()),
+                );
+                let content = self.text(&buffer, &self.font(0.9, pango::Weight::Normal), x);
+                self.push(x, bars, content).shaded = true;
+            }
+            Block::Quote(blocks) => {
+                let mut bars = bars.to_vec();
+                bars.push(x);
+                for block in blocks {
+                    self.block(block, x + INDENT * self.points, &bars);
+                }
+            }
+            Block::List { start, items } => {
+                for (i, item) in items.iter().enumerate() {
+                    let marker = match start {
+                        Some(start) => format!("{}.", start + i as u64),
+                        None => "•".to_string(),
+                    };
+                    self.list_item(&marker, item, x, bars);
+                }
+            }
+            Block::Math(source) => {
+                let buffer = self.renderer.inline_buffer(&[Inline::Math {
+                    source: source.clone(),
+                    display: true,
+                }]);
+                let content = self.text(&buffer, &body, x);
+                self.push(x, bars, content);
+            }
+            Block::Rule => {
+                self.push(x, bars, Content::Rule);
+            }
+        }
+    }
+
+    /// Blocks of a list item indented after a marker
+    fn list_item(&mut self, marker: &str, blocks: &[Block], x: f64, bars: &[f64]) {
+        let first = self.items.len();
+        let indent = x + INDENT * self.points;
+        for block in blocks {
+            self.block(block, indent, bars);
+        }
+
+        let buffer = InlineBuffer::new();
+        buffer.push_str(marker);
+        let marker = PrintLayout::new(
+            &buffer,
+            &self.pango_context,
+            &self.font(1.0, pango::Weight::Normal),
+            INDENT * self.points,
+            self.dpi,
+        );
+        if let Some(item) = self.items.get_mut(first) {
+            item.marker = Some(marker);
+            item.space_before = SPACING / 2.0 * self.points;
+        }
+    }
+
+    fn footnotes(&mut self, footnotes: &[Footnote]) {
+        self.push(0.0, &[], Content::Rule).space_before = SPACING * 2.0 * self.points;
+        for footnote in footnotes {
+            self.list_item(&format!("{}.", footnote.number), &footnote.blocks, 0.0, &[]);
+        }
+    }
+}
@@ -0,0 +1,761 @@
+use std::cell::{Cell, RefCell};
+use std::path::PathBuf;
+
+use adw::gtk::{self, gdk, glib};
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+use sourceview5::prelude::*;
+
+use super::{MdViewer, image};
+use crate::i18n::{self, gettext};
+use crate::markdown::{
+    self, Block, Document, Footnote, FrontMatter, Image, Inline, MetaValue, Style,
+};
+use crate::math::{self, MathNode};
+use crate::widgets::{
+    HeadingView, InlineBuffer, InlineView, MathPaintable, ScaledPaintable, TextAttr,
+};
+
+/// Text color of wiki-links to pages that do not exist
+const BROKEN_LINK_COLOR: gdk::RGBA = gdk::RGBA::new(0.75, 0.11, 0.16, 1.0);
+
+/// Top-level entry of a rendered document
+pub(super) enum Row {
+    Header(FrontMatter),
+    Block(Block),
+    Footnotes(Vec<Footnote>),
+}
+
+impl Row {
+    pub(super) fn from_document(document: &Document) -> Vec<Row> {
+        let mut rows = Vec::with_capacity(document.blocks.len() + 2);
+        if let Some(front_matter) = &document.front_matter {
+            rows.push(Row::Header(front_matter.clone()));
+        }
+        rows.extend(document.blocks.iter().cloned().map(Row::Block));
+        if !document.footnotes.is_empty() {
+            rows.push(Row::Footnotes(document.footnotes.clone()));
+        }
+        rows
+    }
+
+    /// Names of the anchors the rendered row registers
+    pub(super) fn anchors(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        match self {
+            Row::Header(_) => {}
+            Row::Block(block) => block_anchors(block, &mut names),
+            Row::Footnotes(footnotes) => {
+                for footnote in footnotes {
+                    names.push(format!("fn-{}", footnote.label));
+                    for block in &footnote.blocks {
+                        block_anchors(block, &mut names);
+                    }
+                }
+            }
+        }
+        names
+    }
+
+    /// Text of the views of the row, which search matches are counted in
+    pub(super) fn searchable_text(&self) -> String {
+        match self {
+            // The header is made of labels, which are not searched
+            Row::Header(_) => String::new(),
+            Row::Block(block) => block.searchable_text(),
+            Row::Footnotes(footnotes) => Footnote::searchable_text(footnotes),
+        }
+    }
+}
+
+/// Builds the widget tree for document blocks
+pub(super) struct Renderer {
+    viewer: MdViewer,
+    base_dir: Option<PathBuf>,
+    /// Anchor names found in the inline content that has not got a view yet
+    pending_anchors: RefCell<Vec<String>>,
+    /// Paragraphs and headings accept editing
+    editable: bool,
+    /// Position of the block being rendered in the document, see `edit::block_at_mut`.
+    /// Unset outside of the document rows, such as in footnote previews.
+    path: RefCell<Option<Vec<usize>>>,
+    /// Path of the view to return from `focus_view`
+    focus_path: RefCell<Option<Vec<usize>>>,
+    focus_view: RefCell<Option<InlineView>>,
+    /// Images are loaded before `inline_buffer` returns, for printing
+    load_sync: bool,
+}
+
+impl Renderer {
+    pub(super) fn new(viewer: &MdViewer) -> Self {
+        Self {
+            viewer: viewer.clone(),
+            base_dir: viewer.imp().base_dir.borrow().clone(),
+            pending_anchors: RefCell::default(),
+            editable: viewer.imp().editable.get(),
+            path: RefCell::default(),
+            focus_path: RefCell::default(),
+            focus_view: RefCell::default(),
+            load_sync: viewer.imp().fit_content.get(),
+        }
+    }
+
+    /// Renderer for content drawn once, such as on printed pages: nothing is editable
+    /// and images are loaded right away
+    pub(super) fn for_print(viewer: &MdViewer) -> Self {
+        Self {
+            editable: false,
+            load_sync: true,
+            ..Self::new(viewer)
+        }
+    }
+
+    /// Remember the editable view rendered for the block at `path`
+    pub(super) fn set_focus_path(&self, path: &[usize]) {
+        self.focus_path.replace(Some(path.to_vec()));
+    }
+
+    /// The view rendered for the path given to `set_focus_path`
+    pub(super) fn focus_view(&self) -> Option<InlineView> {
+        self.focus_view.borrow().clone()
+    }
+
+    pub(super) fn row(&self, index: usize, row: &Row) -> gtk::Widget {
+        match row {
+            Row::Header(front_matter) => self
+                .metadata_header(front_matter)
+                .unwrap_or_else(|| gtk::Box::new(gtk::Orientation::Vertical, 0).upcast()),
+            Row::Block(block) => {
+                self.path.replace(Some(vec![index]));
+                let widget = self.block(block);
+                self.path.replace(None);
+                widget
+            }
+            Row::Footnotes(footnotes) => self.footnotes(footnotes),
+        }
+    }
+
+    pub(super) fn block(&self, block: &Block) -> gtk::Widget {
+        match block {
+            Block::Heading { level, content } => {
+                let buffer = self.inline_buffer(content);
+                let view = HeadingView::new(*level);
+                self.pending_anchors
+                    .borrow_mut()
+                    .push(markdown::slug(&Inline::plain_text(content)));
+                self.inline_view(view.upcast_ref(), &buffer);
+                self.edit_view(view.upcast_ref(), &buffer);
+                view.upcast()
+            }
+            Block::Paragraph(content) => {
+                let buffer = self.inline_buffer(content);
+                let view = InlineView::new();
+                self.inline_view(&view, &buffer);
+                self.edit_view(&view, &buffer);
+                view.upcast()
+            }
+            Block::Image(image) => self.block_image(image),
+            Block::Code { language, code } => code_block(language.as_deref(), code),
+            Block::Quote(blocks) => {
+                let container = self.container(blocks);
+                container.add_css_class("blockquote");
+                container.upcast()
+            }
+            Block::List { start, items } => self.list(*start, items),
+            Block::Math(source) => {
+                let buffer = InlineBuffer::new();
+                self.push_math(&buffer, source, true);
+                let view = InlineView::new();
+                view.set_halign(gtk::Align::Center);
+                view.add_css_class("math-display");
+                self.inline_view(&view, &buffer);
+                view.upcast()
+            }
+            Block::Rule => gtk::Separator::new(gtk::Orientation::Horizontal).upcast(),
+        }
+    }
+
+    /// Title, author, date and tags from the front matter, shown above the document
+    pub(super) fn metadata_header(&self, front_matter: &FrontMatter) -> Option<gtk::Widget> {
+        let header = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        header.add_css_class("metadata");
+
+        if let Some(title) = front_matter.title() {
+            let title = gtk::Label::builder()
+                .label(title)
+                .wrap(true)
+                .xalign(0.0)
+                .css_classes(["title-1"])
+                .build();
+            header.append(&title);
+        }
+
+        let byline = [
+            front_matter.author(),
+            front_matter.date().map(|date| i18n::date(&date)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" · ");
+        if !byline.is_empty() {
+            let byline = gtk::Label::builder()
+                .label(byline)
+                .wrap(true)
+                .xalign(0.0)
+                .css_classes(["dim-label"])
+                .build();
+            header.append(&byline);
+        }
+
+        let tags = front_matter.tag_values();
+        if !tags.is_empty() {
+            let flow = gtk::FlowBox::builder()
+                .selection_mode(gtk::SelectionMode::None)
+                .max_children_per_line(32)
+                .row_spacing(6)
+                .column_spacing(6)
+                .build();
+            for tag in tags {
+                let tag = gtk::Label::builder()
+                    .label(tag_label(&tag))
+                    .css_classes(["tag", "caption"])
+                    .build();
+                flow.append(&tag);
+            }
+            header.append(&flow);
+        }
+
+        header.first_child().map(|_| header.upcast())
+    }
+
+    /// Section at the end of the document listing footnotes with links back to their references
+    pub(super) fn footnotes(&self, footnotes: &[Footnote]) -> gtk::Widget {
+        let section = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        section.add_css_class("footnotes");
+        section.append(&gtk::Separator::new(gtk::Orientation::Horizontal));
+
+        for footnote in footnotes {
+            let marker = gtk::Label::builder()
+                .label(format!("{}.", footnote.number))
+                .valign(gtk::Align::Start)
+                .css_classes(["list-marker"])
+                .build();
+
+            let content = self.container(&footnote.blocks);
+            content.set_hexpand(true);
+
+            let back_buffer = InlineBuffer::new();
+            let end = back_buffer.push_str("↩");
+            back_buffer.apply_attribute(
+                back_buffer.start_pos(),
+                end,
+                TextAttr::Link(format!("#fnref-{}", footnote.label)),
+            );
+            let back_link = InlineView::new();
+            back_link.set_valign(gtk::Align::Start);
+            back_link.add_css_class("footnote-back");
+            back_link.set_tooltip_text(Some(&gettext("Back to reference")));
+            self.inline_view(&back_link, &back_buffer);
+
+            let row = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+            row.append(&marker);
+            row.append(&content);
+            row.append(&back_link);
+            section.append(&row);
+
+            self.viewer
+                .imp()
+                .add_anchor(&format!("fn-{}", footnote.label), row.upcast_ref());
+        }
+
+        section.upcast()
+    }
+
+    /// Contents of the popover shown when hovering a footnote reference
+    pub(super) fn footnote_preview(&self, footnote: &Footnote) -> gtk::Widget {
+        let content = self.container(&footnote.blocks);
+        content.add_css_class("footnote-preview");
+
+        adw::Clamp::builder()
+            .maximum_size(400)
+            .child(&content)
+            .build()
+            .upcast()
+    }
+
+    /// Show `buffer` in `view`, hooking up links and anchors found while filling it
+    fn inline_view(&self, view: &InlineView, buffer: &InlineBuffer) {
+        view.set_buffer(Some(buffer));
+
+        for name in self.pending_anchors.take() {
+            self.viewer.imp().add_anchor(&name, view.upcast_ref());
+        }
+
+        view.connect_local(
+            "activate-link",
+            false,
+            glib_macros::clone!(
+                #[weak(rename_to = viewer)]
+                self.viewer,
+                #[upgrade_or]
+                None,
+                move |args| {
+                    let url = args[1].get::<String>().ok()?;
+                    Some(viewer.imp().activate_link(&url).to_value())
+                }
+            ),
+        );
+        view.connect_local(
+            "link-middle-clicked",
+            false,
+            glib_macros::clone!(
+                #[weak(rename_to = viewer)]
+                self.viewer,
+                #[upgrade_or]
+                None,
+                move |args| {
+                    let url = args[1].get::<String>().ok()?;
+                    viewer.imp().open_link_in_new_tab(&url);
+                    None
+                }
+            ),
+        );
+        view.connect_local(
+            "link-enter",
+            false,
+            glib_macros::clone!(
+                #[weak(rename_to = viewer)]
+                self.viewer,
+                #[upgrade_or]
+                None,
+                move |args| {
+                    let view = args[0].get::<InlineView>().ok()?;
+                    let url = args[1].get::<String>().ok()?;
+                    let area = args[2].get::<gdk::Rectangle>().ok()?;
+                    viewer.imp().show_link_preview(&view, &url, &area);
+                    None
+                }
+            ),
+        );
+        view.connect_local(
+            "link-leave",
+            false,
+            glib_macros::clone!(
+                #[weak(rename_to = viewer)]
+                self.viewer,
+                #[upgrade_or]
+                None,
+                move |_| {
+                    viewer.imp().hide_link_preview();
+                    None
+                }
+            ),
+        );
+    }
+
+    /// Let the user edit the block at the current path, keeping the document in sync
+    fn edit_view(&self, view: &InlineView, buffer: &InlineBuffer) {
+        let Some(path) = self.path.borrow().clone().filter(|_| self.editable) else {
+            return;
+        };
+        view.set_editable(true);
+        if self.focus_path.borrow().as_ref() == Some(&path) {
+            self.focus_view.replace(Some(view.clone()));
+        }
+
+        view.connect_local(
+            "split",
+            false,
+            glib_macros::clone!(
+                #[weak(rename_to = viewer)]
+                self.viewer,
+                #[weak]
+                buffer,
+                #[strong]
+                path,
+                #[upgrade_or]
+                None,
+                move |args| {
+                    let offset = args[1].get::<u32>().ok()?;
+                    // Splitting replaces the view, so wait until its key handler has returned
+                    glib::idle_add_local_once(glib_macros::clone!(
+                        #[weak]
+                        viewer,
+                        #[strong]
+                        path,
+                        move || viewer.imp().split_block(&path, &buffer, offset as usize)
+                    ));
+                    Some(true.to_value())
+                }
+            ),
+        );
+        buffer.connect_local(
+            "changed",
+            false,
+            glib_macros::clone!(
+                #[weak(rename_to = viewer)]
+                self.viewer,
+                #[upgrade_or]
+                None,
+                move |args| {
+                    let buffer = args[0].get::<InlineBuffer>().ok()?;
+                    viewer.imp().block_edited(&path, &buffer);
+                    None
+                }
+            ),
+        );
+    }
+
+    /// Run `render` for a child at `index` of the block at the current path
+    fn nested<T>(&self, index: usize, render: impl FnOnce() -> T) -> T {
+        if let Some(path) = self.path.borrow_mut().as_mut() {
+            path.push(index);
+        }
+        let result = render();
+        if let Some(path) = self.path.borrow_mut().as_mut() {
+            path.pop();
+        }
+        result
+    }
+
+    fn container(&self, blocks: &[Block]) -> gtk::Box {
+        let container = gtk::Box::new(gtk::Orientation::Vertical, 12);
+        for (index, block) in blocks.iter().enumerate() {
+            container.append(&self.nested(index, || self.block(block)));
+        }
+        container
+    }
+
+    fn list(&self, start: Option<u64>, items: &[Vec<Block>]) -> gtk::Widget {
+        let list = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        list.add_css_class("list");
+
+        for (i, item) in items.iter().enumerate() {
+            let marker = match start {
+                Some(start) => format!("{}.", start + i as u64),
+                None => "•".to_string(),
+            };
+            let marker = gtk::Label::builder()
+                .label(marker)
+                .valign(gtk::Align::Start)
+                .css_classes(["list-marker"])
+                .build();
+
+            let content = self.nested(i, || self.container(item));
+            content.set_hexpand(true);
+
+            let row = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+            row.append(&marker);
+            row.append(&content);
+            list.append(&row);
+        }
+
+        list.upcast()
+    }
+
+    pub(super) fn inline_buffer(&self, content: &[Inline]) -> InlineBuffer {
+        let buffer = InlineBuffer::new();
+        // Filling the buffer is not something to undo
+        buffer.begin_irreversible_action();
+        for inline in content {
+            self.push_inline(&buffer, inline);
+        }
+        buffer.end_irreversible_action();
+        buffer
+    }
+
+    fn push_inline(&self, buffer: &InlineBuffer, inline: &Inline) {
+        match inline {
+            Inline::Text(text) => {
+                buffer.push_str(text);
+            }
+            Inline::Code(code) => {
+                let start = buffer.current_pos();
+                let end = buffer.push_str(code);
+                buffer.apply_attribute(start, end, TextAttr::FontFamily("monospace".into()));
+            }
+            Inline::Emphasis(content) => self.push_styled(buffer, content, TextAttr::Italic),
+            Inline::Strong(content) => self.push_styled(buffer, content, TextAttr::Bold),
+            Inline::Strikethrough(content) => {
+                self.push_styled(buffer, content, TextAttr::Strikethrough)
+            }
+            Inline::Link { url, content, .. } => {
+                self.push_styled(buffer, content, TextAttr::Link(url.clone()))
+            }
+            Inline::WikiLink { target, content } => {
+                let start = buffer.current_pos();
+                self.push_styled(
+                    buffer,
+                    content,
+                    TextAttr::Link(format!("{}{target}", markdown::WIKI_LINK_SCHEME)),
+                );
+                let resolved = self
+                    .viewer
+                    .emit_by_name::<bool>("resolve-wiki-link", &[target]);
+                if !resolved {
+                    buffer.apply_attribute(
+                        start,
+                        buffer.current_pos(),
+                        TextAttr::Color(BROKEN_LINK_COLOR),
+                    );
+                }
+            }
+            Inline::Image(image) => self.inline_image(buffer, image),
+            Inline::FootnoteReference { label, number } => {
+                let start = buffer.current_pos();
+                let end = buffer.push_str(&number.to_string());
+                buffer.apply_attribute(start, end, TextAttr::Superscript);
+                buffer.apply_attribute(start, end, TextAttr::Link(format!("#fn-{label}")));
+                self.pending_anchors
+                    .borrow_mut()
+                    .push(format!("fnref-{label}"));
+            }
+            Inline::Math { source, display } => self.push_math(buffer, source, *display),
+            Inline::SoftBreak => {
+                buffer.push_str(" ");
+            }
+            Inline::HardBreak => {
+                buffer.push_str("\n");
+            }
+        }
+    }
+
+    fn push_styled(&self, buffer: &InlineBuffer, content: &[Inline], attr: TextAttr) {
+        let start = buffer.current_pos();
+        for inline in content {
+            self.push_inline(buffer, inline);
+        }
+        buffer.apply_attribute(start, buffer.current_pos(), attr);
+    }
+
+    /// Place a typeset formula at an anchor, showing the TeX source if it does not parse.
+    /// Editable documents keep the source in the anchor so it is written back unchanged.
+    fn push_math(&self, buffer: &InlineBuffer, source: &str, display: bool) {
+        let formula = match math::parse(source) {
+            Ok(formula) => formula,
+            Err(err) if self.editable => {
+                glib::g_warning!("potato-md", "Failed to parse formula: {err}");
+                MathNode::Text(source.to_string())
+            }
+            Err(err) => {
+                glib::g_warning!("potato-md", "Failed to parse formula: {err}");
+                let start = buffer.current_pos();
+                let end = buffer.push_str(source);
+                buffer.apply_attribute(start, end, TextAttr::FontFamily("monospace".into()));
+                return;
+            }
+        };
+
+        let paintable = MathPaintable::new(&formula, display, &self.viewer.pango_context());
+        let markup = markdown::write_inlines(
+            &[Inline::Math {
+                source: source.to_string(),
+                display,
+            }],
+            &Style::default(),
+        );
+        let anchor = buffer.push_anchor_with_source(&markup);
+        buffer.insert_paintable_at_anchor_with_baseline(
+            anchor,
+            paintable.upcast_ref(),
+            paintable.baseline(),
+        );
+    }
+
+    /// Reserve an anchor for the image and fill it once it has loaded.
+    /// Editable documents show a placeholder icon for images that cannot be loaded,
+    /// so the reference is kept.
+    fn inline_image(&self, buffer: &InlineBuffer, image: &Image) {
+        let markup = markdown::write_inlines(&[Inline::Image(image.clone())], &Style::default());
+        let Some(path) = image.local_path(self.base_dir.as_deref()) else {
+            if self.editable {
+                let anchor = buffer.push_anchor_with_source(&markup);
+                buffer.insert_paintable_at_anchor(anchor, self.missing_image().upcast_ref());
+            } else {
+                buffer.push_str(&image.alt);
+            }
+            return;
+        };
+
+        let anchor = buffer.push_anchor_with_source(&markup);
+        if self.load_sync {
+            match image::load_texture(&path) {
+                Ok(texture) => buffer.insert_paintable_at_anchor(anchor, texture.upcast_ref()),
+                Err(err) => glib::g_warning!("potato-md", "Failed to load image: {err}"),
+            }
+            return;
+        }
+        glib::spawn_future_local(glib_macros::clone!(
+            #[weak]
+            buffer,
+            async move {
+                match image::load_paintable(path).await {
+                    // The image may arrive while the document is being edited
+                    Ok(paintable) => buffer
+                        .without_history(|| buffer.insert_paintable_at_anchor(anchor, &paintable)),
+                    Err(err) => glib::g_warning!("potato-md", "Failed to load image: {err}"),
+                }
+            }
+        ));
+    }
+
+    fn missing_image(&self) -> gtk::IconPaintable {
+        gtk::IconTheme::for_display(&self.viewer.display()).lookup_icon(
+            "image-missing-symbolic",
+            &[],
+            16,
+            self.viewer.scale_factor(),
+            gtk::TextDirection::None,
+            gtk::IconLookupFlags::empty(),
+        )
+    }
+
+    /// Image on its own line, scaled down to the viewer width.
+    /// Shows the alt text instead when the image cannot be loaded.
+    fn block_image(&self, image: &Image) -> gtk::Widget {
+        let container = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        container.add_css_class("image");
+
+        let alt = image.alt.clone();
+        let zoom = self.viewer.zoom();
+        let Some(path) = image.local_path(self.base_dir.as_deref()) else {
+            container.append(&alt_text(&alt));
+            return container.upcast();
+        };
+
+        if self.load_sync {
+            match image::load_texture(&path) {
+                Ok(texture) => container.append(&picture(texture.upcast_ref(), &alt, zoom)),
+                Err(err) => {
+                    glib::g_warning!("potato-md", "Failed to load image: {err}");
+                    container.append(&alt_text(&alt));
+                }
+            }
+            return container.upcast();
+        }
+        glib::spawn_future_local(glib_macros::clone!(
+            #[weak]
+            container,
+            async move {
+                match image::load_paintable(path).await {
+                    Ok(paintable) => container.append(&picture(&paintable, &alt, zoom)),
+                    Err(err) => {
+                        glib::g_warning!("potato-md", "Failed to load image: {err}");
+                        container.append(&alt_text(&alt));
+                    }
+                }
+            }
+        ));
+
+        container.upcast()
+    }
+}
+
+/// Text of a front matter tag, numbers written with the separators of the user's locale
+pub(super) fn tag_label(tag: &MetaValue) -> String {
+    match tag {
+        MetaValue::Integer(_) | MetaValue::Float(_) => i18n::number(&tag.to_string()),
+        tag => tag.to_string(),
+    }
+}
+
+/// Anchors registered while rendering `block`: heading slugs and footnote references
+fn block_anchors(block: &Block, names: &mut Vec<String>) {
+    match block {
+        Block::Heading { content, .. } => {
+            names.push(markdown::slug(&Inline::plain_text(content)));
+            inline_anchors(content, names);
+        }
+        Block::Paragraph(content) => inline_anchors(content, names),
+        Block::Quote(blocks) => blocks.iter().for_each(|b| block_anchors(b, names)),
+        Block::List { items, .. } => items.iter().flatten().for_each(|b| block_anchors(b, names)),
+        Block::Image(_) | Block::Code { .. } | Block::Math(_) | Block::Rule => {}
+    }
+}
+
+fn inline_anchors(content: &[Inline], names: &mut Vec<String>) {
+    for inline in content {
+        match inline {
+            Inline::FootnoteReference { label, .. } => names.push(format!("fnref-{label}")),
+            Inline::Emphasis(content)
+            | Inline::Strong(content)
+            | Inline::Strikethrough(content)
+            | Inline::Link { content, .. }
+            | Inline::WikiLink { content, .. } => inline_anchors(content, names),
+            _ => {}
+        }
+    }
+}
+
+/// Picture of an image at its own size times `zoom`, scaled down to fit
+fn picture(paintable: &gdk::Paintable, alt: &str, zoom: f64) -> gtk::Picture {
+    let paintable = ScaledPaintable::new(paintable);
+    paintable.set_scale(zoom);
+    let picture = gtk::Picture::for_paintable(&paintable);
+    picture.set_content_fit(gtk::ContentFit::ScaleDown);
+    picture.set_can_shrink(true);
+    picture.set_halign(gtk::Align::Start);
+    picture.set_alternative_text(Some(alt));
+    picture
+}
+
+fn alt_text(alt: &str) -> InlineView {
+    let buffer = InlineBuffer::new();
+    let end = buffer.push_str(alt);
+    buffer.apply_attribute(buffer.start_pos(), end, TextAttr::Italic);
+
+    let view = InlineView::new();
+    view.set_buffer(Some(&buffer));
+    view.add_css_class("image-alt");
+    view
+}
+
+/// Read-only, syntax highlighted source view that follows the dark style
+fn code_block(language: Option<&str>, code: &str) -> gtk::Widget {
+    let buffer = sourceview5::Buffer::new(None);
+    buffer.set_text(code.strip_suffix('\n').unwrap_or(code));
+    buffer.set_highlight_syntax(true);
+    if let Some(language) =
+        language.and_then(|l| sourceview5::LanguageManager::default().language(&l.to_lowercase()))
+    {
+        buffer.set_language(Some(&language));
+    }
+
+    let style_manager = adw::StyleManager::default();
+    set_style_scheme(&buffer, style_manager.is_dark());
+    let handler = style_manager.connect_dark_notify(glib_macros::clone!(
+        #[weak]
+        buffer,
+        move |style_manager| set_style_scheme(&buffer, style_manager.is_dark())
+    ));
+
+    let view = sourceview5::View::with_buffer(&buffer);
+    view.set_editable(false);
+    view.set_cursor_visible(false);
+    view.set_monospace(true);
+    view.add_css_class("code-block");
+
+    let handler = Cell::new(Some(handler));
+    view.connect_destroy(move |_| {
+        if let Some(handler) = handler.take() {
+            adw::StyleManager::default().disconnect(handler);
+        }
+    });
+
+    gtk::ScrolledWindow::builder()
+        .vscrollbar_policy(gtk::PolicyType::Never)
+        .propagate_natural_height(true)
+        .child(&view)
+        .build()
+        .upcast()
+}
+
+fn set_style_scheme(buffer: &sourceview5::Buffer, dark: bool) {
+    let scheme = if dark { "Adwaita-dark" } else { "Adwaita" };
+    buffer.set_style_scheme(
+        sourceview5::StyleSchemeManager::default()
+            .scheme(scheme)
+            .as_ref(),
+    );
+}
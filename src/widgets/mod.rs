@@ -1,11 +1,15 @@
+mod animated_paintable;
 mod heading_view;
 mod inline_view;
-
+mod math_paintable;
 mod md_viewer;
+mod scaled_paintable;
 
-#[allow(unused_imports)]
+pub use animated_paintable::AnimatedPaintable;
 pub use heading_view::HeadingView;
-#[allow(unused_imports)]
-pub use inline_view::{InlineAnchor, InlineBuffer, InlinePos, InlineView, TextAttr};
-#[allow(unused_imports)]
-pub use md_viewer::MdViewer;
+pub use inline_view::{
+    InlineAnchor, InlineBuffer, InlinePos, InlineView, PrintLayout, TextAttr, TextAttrType,
+};
+pub use math_paintable::MathPaintable;
+pub use md_viewer::{Heading, MdViewer};
+pub use scaled_paintable::ScaledPaintable;
@@ -0,0 +1,63 @@
+use std::cell::{Cell, OnceCell};
+
+use adw::gdk;
+use adw::gtk::glib;
+use adw::gtk::prelude::*;
+use adw::gtk::subclass::prelude::*;
+
+#[derive(Default)]
+pub struct ScaledPaintable {
+    pub(super) paintable: OnceCell<gdk::Paintable>,
+    pub(super) scale: Cell<f64>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for ScaledPaintable {
+    const NAME: &'static str = "ScaledPaintable";
+    type Type = super::ScaledPaintable;
+    type Interfaces = (gdk::Paintable,);
+}
+
+impl ObjectImpl for ScaledPaintable {}
+
+impl PaintableImpl for ScaledPaintable {
+    fn intrinsic_width(&self) -> i32 {
+        self.paintable
+            .get()
+            .map(|p| self.scaled(p.intrinsic_width()))
+            .unwrap_or(0)
+    }
+
+    fn intrinsic_height(&self) -> i32 {
+        self.paintable
+            .get()
+            .map(|p| self.scaled(p.intrinsic_height()))
+            .unwrap_or(0)
+    }
+
+    fn intrinsic_aspect_ratio(&self) -> f64 {
+        self.paintable
+            .get()
+            .map(|p| p.intrinsic_aspect_ratio())
+            .unwrap_or(0.0)
+    }
+
+    fn flags(&self) -> gdk::PaintableFlags {
+        self.paintable
+            .get()
+            .map(|p| p.flags())
+            .unwrap_or(gdk::PaintableFlags::empty())
+    }
+
+    fn snapshot(&self, snapshot: &gdk::Snapshot, width: f64, height: f64) {
+        if let Some(paintable) = self.paintable.get() {
+            paintable.snapshot(snapshot, width, height);
+        }
+    }
+}
+
+impl ScaledPaintable {
+    fn scaled(&self, size: i32) -> i32 {
+        (size as f64 * self.scale.get()).round() as i32
+    }
+}
@@ -0,0 +1,45 @@
+mod imp;
+
+use adw::gdk;
+use adw::gtk::glib;
+use adw::gtk::prelude::*;
+use adw::gtk::subclass::prelude::*;
+
+glib::wrapper! {
+    /// Paintable that draws another one with its intrinsic size multiplied by a factor,
+    /// so pictures follow the zoom of the text
+    pub struct ScaledPaintable(ObjectSubclass<imp::ScaledPaintable>)
+        @implements gdk::Paintable;
+}
+
+impl ScaledPaintable {
+    pub fn new(paintable: &gdk::Paintable) -> Self {
+        let scaled: Self = glib::Object::builder().build();
+        let imp = scaled.imp();
+        imp.scale.set(1.0);
+        let _ = imp.paintable.set(paintable.clone());
+
+        paintable.connect_invalidate_contents(glib_macros::clone!(
+            #[weak]
+            scaled,
+            move |_| scaled.invalidate_contents()
+        ));
+        paintable.connect_invalidate_size(glib_macros::clone!(
+            #[weak]
+            scaled,
+            move |_| scaled.invalidate_size()
+        ));
+        scaled
+    }
+
+    /// Factor the intrinsic size is multiplied by
+    pub fn scale(&self) -> f64 {
+        self.imp().scale.get()
+    }
+
+    pub fn set_scale(&self, scale: f64) {
+        if self.imp().scale.replace(scale) != scale {
+            self.invalidate_size();
+        }
+    }
+}
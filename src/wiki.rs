@@ -0,0 +1,150 @@
+//! Resolving `[[Page Name]]` wiki-links to the markdown files they name
+
+use std::path::{Path, PathBuf};
+
+use crate::sidebar;
+
+/// Split a wiki-link target into the page name and the heading after `#`, if any
+pub fn split_target(target: &str) -> (&str, Option<&str>) {
+    match target.split_once('#') {
+        Some((page, heading)) => (page.trim(), Some(heading.trim())),
+        None => (target.trim(), None),
+    }
+}
+
+/// Markdown file a wiki-link names. The page name is matched against the end of the
+/// paths of `files` without their extension, ignoring case, so `[[Notes]]` finds
+/// `notes.md` in any folder and `[[work/Notes]]` only the one under `work`. When
+/// several match the shortest path wins. Without `files`, such as when no folder is
+/// open, the page is looked for next to the document in `dir`.
+pub fn resolve(target: &str, files: &[PathBuf], dir: Option<&Path>) -> Option<PathBuf> {
+    let (page, _) = split_target(target);
+    let page = page.strip_suffix(".md").unwrap_or(page);
+    if page.is_empty() {
+        return None;
+    }
+    let name: Vec<String> = page.split('/').map(str::to_lowercase).collect();
+
+    let found = files
+        .iter()
+        .filter(|path| sidebar::is_markdown(path) && names_page(path, &name))
+        .min_by_key(|path| path.components().count());
+    if found.is_some() || !files.is_empty() {
+        return found.cloned();
+    }
+
+    let path = dir?.join(format!("{page}.md"));
+    path.is_file().then_some(path)
+}
+
+/// Whether the last components of `path`, without the extension, are `name`
+fn names_page(path: &Path, name: &[String]) -> bool {
+    let stem = path.with_extension("");
+    let components: Vec<String> = stem
+        .components()
+        .rev()
+        .take(name.len())
+        .map(|component| component.as_os_str().to_string_lossy().to_lowercase())
+        .collect();
+    components.len() == name.len() && components.iter().rev().eq(name.iter())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    /// Directory under the system temporary directory, removed again when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "potato-md-{name}-{}-{}",
+                std::process::id(),
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos(),
+            ));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn split_target_takes_the_heading() {
+        assert_eq!(split_target(" Notes # Plans "), ("Notes", Some("Plans")));
+        assert_eq!(split_target("Notes"), ("Notes", None));
+    }
+
+    #[test]
+    fn names_are_matched_ignoring_case() {
+        let files = files(&["/notes/Meeting Notes.md", "/notes/todo.txt"]);
+        assert_eq!(
+            resolve("meeting NOTES", &files, None),
+            Some(PathBuf::from("/notes/Meeting Notes.md"))
+        );
+        assert_eq!(resolve("todo", &files, None), None);
+    }
+
+    #[test]
+    fn folders_in_the_target_narrow_the_match() {
+        let files = files(&["/notes/home/Notes.md", "/notes/work/Notes.md"]);
+        assert_eq!(
+            resolve("work/Notes", &files, None),
+            Some(PathBuf::from("/notes/work/Notes.md"))
+        );
+        assert_eq!(resolve("play/Notes", &files, None), None);
+        // Components are matched whole
+        assert_eq!(resolve("ork/Notes", &files, None), None);
+    }
+
+    #[test]
+    fn shortest_path_wins() {
+        let files = files(&[
+            "/notes/a/b/Ideas.md",
+            "/notes/Ideas.md",
+            "/notes/a/Ideas.md",
+        ]);
+        assert_eq!(
+            resolve("Ideas", &files, None),
+            Some(PathBuf::from("/notes/Ideas.md"))
+        );
+    }
+
+    #[test]
+    fn md_suffix_and_heading_are_ignored() {
+        let files = files(&["/notes/Ideas.md"]);
+        assert_eq!(
+            resolve("Ideas.md#Later", &files, None),
+            Some(PathBuf::from("/notes/Ideas.md"))
+        );
+        assert_eq!(resolve("#Later", &files, None), None);
+    }
+
+    #[test]
+    fn without_files_the_page_is_looked_for_in_dir() {
+        let temp_dir = TempDir::new("wiki");
+        let dir = temp_dir.0.as_path();
+        std::fs::write(dir.join("Ideas.md"), "# Ideas\n").unwrap();
+
+        assert_eq!(resolve("Ideas", &[], Some(dir)), Some(dir.join("Ideas.md")));
+        assert_eq!(resolve("Plans", &[], Some(dir)), None);
+        // A folder is open, so the file next to the document is not looked at
+        assert_eq!(
+            resolve("Ideas", &files(&["/notes/Other.md"]), Some(dir)),
+            None
+        );
+        assert_eq!(resolve("Ideas", &[], None), None);
+    }
+}
@@ -1,13 +1,58 @@
-use crate::widgets::{InlineBuffer, InlineView, TextAttr};
-use adw::{glib, glib::subclass::InitializingObject, gtk, subclass::prelude::*};
+use std::cell::{Cell, RefCell};
+
+use adw::prelude::*;
+use adw::{gio, glib, glib::subclass::InitializingObject, gtk, subclass::prelude::*};
+use potato_md::i18n::gettext;
+
+use crate::page::DocumentPage;
+use crate::preferences::PreferencesDialog;
+use crate::search::SearchPanel;
+use crate::session;
+use crate::sidebar::FileSidebar;
 
 #[derive(Default, gtk4_macros::CompositeTemplate)]
 #[template(resource = "/com/example/potato-md/ui/window.ui")]
 pub struct PotatoWindow {
     #[template_child]
-    title_inline_view: TemplateChild<InlineView>,
+    pub(super) stack: TemplateChild<gtk::Stack>,
+    #[template_child]
+    pub(super) sidebar_button: TemplateChild<gtk::ToggleButton>,
+    #[template_child]
+    pub(super) sidebar_stack: TemplateChild<adw::ViewStack>,
+    #[template_child]
+    pub(super) file_sidebar: TemplateChild<FileSidebar>,
+    #[template_child]
+    pub(super) search_panel: TemplateChild<SearchPanel>,
+    #[template_child]
+    pub(super) backlinks_list: TemplateChild<gtk::ListBox>,
+    #[template_child]
+    pub(super) back_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub(super) forward_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub(super) outline_button: TemplateChild<gtk::MenuButton>,
+    #[template_child]
+    pub(super) outline_list: TemplateChild<gtk::ListBox>,
     #[template_child]
-    content_inline_view: TemplateChild<InlineView>,
+    pub(super) recent_box: TemplateChild<gtk::Box>,
+    #[template_child]
+    pub(super) recent_list: TemplateChild<gtk::ListBox>,
+    #[template_child]
+    pub(super) print_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub(super) edit_button: TemplateChild<gtk::ToggleButton>,
+    #[template_child]
+    pub(super) save_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub(super) tab_view: TemplateChild<adw::TabView>,
+    /// Bindings of the header bar to the selected page, replaced when it changes
+    pub(super) page_bindings: RefCell<Vec<glib::Binding>>,
+    /// Files listed as linking to the selected page, in the order of their rows
+    pub(super) backlinks: RefCell<Vec<gio::File>>,
+    /// Recently opened files listed on the start page, in the order of their rows
+    pub(super) recent: RefCell<Vec<gio::File>>,
+    /// Whether the user chose what to do with the unsaved edits of the closing window
+    pub(super) edits_confirmed: Cell<bool>,
 }
 
 #[glib::object_subclass]
@@ -17,7 +62,95 @@ impl ObjectSubclass for PotatoWindow {
     type ParentType = adw::ApplicationWindow;
 
     fn class_init(klass: &mut Self::Class) {
+        FileSidebar::ensure_type();
+        SearchPanel::ensure_type();
         klass.bind_template();
+
+        type Activate = fn(&DocumentPage, &super::PotatoWindow);
+        let page_actions: [(&str, Activate); 4] = [
+            ("win.save", |page, _| page.save()),
+            ("win.print", |page, window| {
+                page.md_viewer().print(Some(window))
+            }),
+            ("win.go-back", |page, _| page.go_back()),
+            ("win.go-forward", |page, _| page.go_forward()),
+        ];
+        for (name, activate) in page_actions {
+            klass.install_action(name, None, move |window, _, _| {
+                if let Some(page) = window.selected_page() {
+                    activate(&page, window);
+                }
+            });
+        }
+        klass.install_action("win.open-file", None, |window, _, _| window.choose_files());
+        klass.install_action("win.open-folder", None, |window, _, _| {
+            window.choose_folder()
+        });
+        klass.install_action("win.export-html", None, |window, _, _| window.export_html());
+        klass.install_action("win.export-pdf", None, |window, _, _| window.export_pdf());
+        klass.install_action("win.search-folder", None, |window, _, _| {
+            window.search_folder()
+        });
+        klass.install_action("win.preferences", None, |window, _, _| {
+            PreferencesDialog::new().present(Some(window));
+        });
+        klass.install_action("win.close-tab", None, |window, _, _| {
+            let tab_view = &window.imp().tab_view;
+            if let Some(tab_page) = tab_view.selected_page() {
+                tab_view.close_page(&tab_page);
+            }
+        });
+
+        let bindings = [
+            (
+                gtk::gdk::Key::o,
+                gtk::gdk::ModifierType::CONTROL_MASK,
+                "win.open-file",
+            ),
+            (
+                gtk::gdk::Key::o,
+                gtk::gdk::ModifierType::CONTROL_MASK | gtk::gdk::ModifierType::SHIFT_MASK,
+                "win.open-folder",
+            ),
+            (
+                gtk::gdk::Key::s,
+                gtk::gdk::ModifierType::CONTROL_MASK,
+                "win.save",
+            ),
+            (
+                gtk::gdk::Key::p,
+                gtk::gdk::ModifierType::CONTROL_MASK,
+                "win.print",
+            ),
+            (
+                gtk::gdk::Key::f,
+                gtk::gdk::ModifierType::CONTROL_MASK | gtk::gdk::ModifierType::SHIFT_MASK,
+                "win.search-folder",
+            ),
+            (
+                gtk::gdk::Key::comma,
+                gtk::gdk::ModifierType::CONTROL_MASK,
+                "win.preferences",
+            ),
+            (
+                gtk::gdk::Key::w,
+                gtk::gdk::ModifierType::CONTROL_MASK,
+                "win.close-tab",
+            ),
+            (
+                gtk::gdk::Key::Left,
+                gtk::gdk::ModifierType::ALT_MASK,
+                "win.go-back",
+            ),
+            (
+                gtk::gdk::Key::Right,
+                gtk::gdk::ModifierType::ALT_MASK,
+                "win.go-forward",
+            ),
+        ];
+        for (key, modifiers, action) in bindings {
+            klass.add_binding_action(key, modifiers, action);
+        }
     }
 
     fn instance_init(obj: &InitializingObject<Self>) {
@@ -29,30 +162,175 @@ impl ObjectImpl for PotatoWindow {
     fn constructed(&self) {
         self.parent_constructed();
 
-        let title_buffer = InlineBuffer::new();
-        let content_buffer = InlineBuffer::new();
-
-        self.title_inline_view.set_buffer(Some(&title_buffer));
-        self.content_inline_view.set_buffer(Some(&content_buffer));
-
-        glib::MainContext::default().spawn_local(glib_macros::clone!(
-            #[weak]
-            title_buffer,
-            #[weak]
-            content_buffer,
-            async move {
-                glib::timeout_future_seconds(1).await;
-                title_buffer.set_text("Welcome to Potato MD!");
-                glib::timeout_future_seconds(1).await;
-                content_buffer.set_text("This is the content area.");
+        // Document actions stay disabled until a tab is open
+        self.obj().page_changed();
+        // Searching needs a folder
+        self.obj().action_set_enabled("win.search-folder", false);
+
+        self.tab_view
+            .connect_selected_page_notify(glib_macros::clone!(
+                #[weak(rename_to = window)]
+                self,
+                move |_| window.obj().page_changed()
+            ));
+        self.tab_view.connect_n_pages_notify(glib_macros::clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |tab_view| {
+                let child = if tab_view.n_pages() > 0 {
+                    "document"
+                } else {
+                    "start"
+                };
+                window.stack.set_visible_child_name(child);
             }
         ));
+        self.tab_view.connect_close_page(glib_macros::clone!(
+            #[weak(rename_to = window)]
+            self,
+            #[upgrade_or]
+            glib::Propagation::Proceed,
+            move |_, tab_page| window.obj().close_page(tab_page)
+        ));
+        // Tabs dragged out of the window get a new one
+        self.tab_view.connect_create_window(glib_macros::clone!(
+            #[weak(rename_to = window)]
+            self,
+            #[upgrade_or]
+            None,
+            move |_| {
+                let app = window
+                    .obj()
+                    .application()
+                    .and_downcast::<adw::Application>()?;
+                let new_window = super::PotatoWindow::new(&app);
+                new_window.present();
+                Some(new_window.imp().tab_view.get())
+            }
+        ));
+
+        self.file_sidebar
+            .connect_file_activated(glib_macros::clone!(
+                #[weak(rename_to = window)]
+                self,
+                move |_, file| {
+                    window.obj().open_file(file);
+                }
+            ));
+        self.file_sidebar.connect_files_changed(glib_macros::clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |sidebar| {
+                let Some(folder) = sidebar.folder() else {
+                    return;
+                };
+                let files = sidebar.files();
+                for page in window.obj().pages() {
+                    page.set_folder_files(files.clone());
+                }
+                window.search_panel.set_files(&folder, files);
+            }
+        ));
+        self.search_panel
+            .connect_match_activated(glib_macros::clone!(
+                #[weak(rename_to = window)]
+                self,
+                move |_, file, query, occurrence| {
+                    if let Some(page) = window.obj().open_file(file) {
+                        page.md_viewer().reveal_match(query, occurrence);
+                    }
+                }
+            ));
+        self.search_panel.connect_index_updated(glib_macros::clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |_| window.obj().update_backlinks()
+        ));
+
+        self.backlinks_list.set_placeholder(Some(
+            &gtk::Label::builder()
+                .label(gettext("No Backlinks"))
+                .css_classes(["dim-label"])
+                .margin_top(12)
+                .build(),
+        ));
+        self.backlinks_list
+            .connect_row_activated(glib_macros::clone!(
+                #[weak(rename_to = window)]
+                self,
+                move |_, row| {
+                    let file = window.backlinks.borrow().get(row.index() as usize).cloned();
+                    if let Some(file) = file {
+                        window.obj().open_file(&file);
+                    }
+                }
+            ));
+
+        self.outline_list.connect_row_activated(glib_macros::clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |_, row| {
+                let Some(viewer) = window.obj().selected_page().map(|page| page.md_viewer()) else {
+                    return;
+                };
+                if let Some(heading) = viewer.headings().get(row.index() as usize) {
+                    window.outline_button.popdown();
+                    viewer.scroll_to_anchor(&heading.anchor);
+                }
+            }
+        ));
+
+        self.recent_list.connect_row_activated(glib_macros::clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |_, row| {
+                let file = window.recent.borrow().get(row.index() as usize).cloned();
+                if let Some(file) = file {
+                    window.obj().open_file(&file);
+                }
+            }
+        ));
+        gtk::RecentManager::default().connect_changed(glib_macros::clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |_| window.obj().update_recent()
+        ));
+        self.obj().update_recent();
+
+        // Markdown files and folders dropped anywhere on the window are opened
+        let drop_target = gtk::DropTarget::new(
+            gtk::gdk::FileList::static_type(),
+            gtk::gdk::DragAction::COPY,
+        );
+        drop_target.connect_drop(glib_macros::clone!(
+            #[weak(rename_to = window)]
+            self,
+            #[upgrade_or]
+            false,
+            move |_, value, _, _| {
+                value
+                    .get::<gtk::gdk::FileList>()
+                    .is_ok_and(|files| window.obj().open_dropped(&files.files()))
+            }
+        ));
+        self.obj().add_controller(drop_target);
     }
 }
 
 impl WidgetImpl for PotatoWindow {}
 
-impl WindowImpl for PotatoWindow {}
+impl WindowImpl for PotatoWindow {
+    fn close_request(&self) -> glib::Propagation {
+        // Tabs are destroyed with the window without asking to close them
+        if !self.edits_confirmed.get() && self.obj().confirm_close().is_stop() {
+            return glib::Propagation::Stop;
+        }
+        if let Some(app) = self.obj().application().and_downcast::<adw::Application>() {
+            session::window_closed(&app, &self.obj());
+        }
+        self.parent_close_request()
+    }
+}
 
 impl ApplicationWindowImpl for PotatoWindow {}
 
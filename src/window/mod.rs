@@ -1,7 +1,22 @@
 mod imp;
 
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use adw::prelude::*;
+use adw::subclass::prelude::*;
 use adw::{gio, glib, gtk};
 
+use potato_md::i18n::gettext;
+use potato_md::{export, markdown};
+
+use crate::page::DocumentPage;
+use crate::session::{DocumentState, WindowState};
+use crate::{settings, sidebar};
+
+/// Most recently opened files listed on the start page
+const MAX_RECENT: usize = 8;
+
 glib::wrapper! {
     pub struct PotatoWindow(ObjectSubclass<imp::PotatoWindow>)
         @extends adw::ApplicationWindow, gtk::ApplicationWindow, gtk::Window, gtk::Widget,
@@ -13,4 +28,539 @@ impl PotatoWindow {
     pub fn new(app: &adw::Application) -> Self {
         glib::Object::builder().property("application", app).build()
     }
+
+    /// Open a markdown file in a new tab and switch to it, or switch to the tab it is
+    /// already open in. Returns the page of the file, unless it could not be read.
+    pub fn open_file(&self, file: &gio::File) -> Option<DocumentPage> {
+        let tab_view = &self.imp().tab_view;
+        let open = (0..tab_view.n_pages())
+            .map(|i| tab_view.nth_page(i))
+            .find(|tab_page| {
+                tab_page
+                    .child()
+                    .downcast::<DocumentPage>()
+                    .ok()
+                    .and_then(|page| page.file())
+                    .is_some_and(|open| open.equal(file))
+            });
+        match open {
+            Some(tab_page) => {
+                tab_view.set_selected_page(&tab_page);
+                tab_page.child().downcast().ok()
+            }
+            None => self.open_in_tab(file, true),
+        }
+    }
+
+    /// List the markdown files of `folder` in the sidebar
+    pub fn open_folder(&self, folder: &gio::File) {
+        let imp = self.imp();
+        imp.file_sidebar.set_folder(folder);
+        imp.sidebar_button.set_visible(true);
+        imp.sidebar_button.set_active(true);
+        self.action_set_enabled("win.search-folder", true);
+    }
+
+    /// Size, folder and open documents of the window, to restore on the next launch
+    pub fn session_state(&self) -> WindowState {
+        let (width, height) = self.default_size();
+        let documents = self
+            .pages()
+            .iter()
+            .filter_map(|page| Some(DocumentState::new(page.file()?, &page.md_viewer())))
+            .collect();
+        let tab_view = &self.imp().tab_view;
+        let selected = tab_view
+            .selected_page()
+            .map(|tab_page| tab_view.page_position(&tab_page) as usize)
+            .unwrap_or_default();
+
+        WindowState {
+            width,
+            height,
+            maximized: self.is_maximized(),
+            folder: self.imp().file_sidebar.folder(),
+            documents,
+            selected,
+        }
+    }
+
+    /// Reopen the folder and documents of a window of an earlier session. Documents
+    /// that cannot be read any more are left out.
+    pub fn restore_session_state(&self, state: &WindowState, saved: SystemTime) {
+        self.set_default_size(state.width, state.height);
+        self.set_maximized(state.maximized);
+        if let Some(folder) = &state.folder {
+            self.open_folder(folder);
+        }
+        for (index, document) in state.documents.iter().enumerate() {
+            if let Some(page) = self.open_in_tab(&document.file, index == state.selected) {
+                document.restore_position(&page.md_viewer(), saved);
+            }
+        }
+    }
+
+    /// Ask for markdown files and open them in tabs
+    fn choose_files(&self) {
+        let filter = gtk::FileFilter::new();
+        filter.set_name(Some(&gettext("Markdown")));
+        filter.add_mime_type("text/markdown");
+        for suffix in ["md", "markdown"] {
+            filter.add_suffix(suffix);
+        }
+        let filters = gio::ListStore::new::<gtk::FileFilter>();
+        filters.append(&filter);
+        let dialog = gtk::FileDialog::builder()
+            .title(gettext("Open File"))
+            .filters(&filters)
+            .build();
+
+        glib::spawn_future_local(glib_macros::clone!(
+            #[weak(rename_to = window)]
+            self,
+            async move {
+                // An error here means the dialog was dismissed
+                let Ok(files) = dialog.open_multiple_future(Some(&window)).await else {
+                    return;
+                };
+                for file in files.iter::<gio::File>().flatten() {
+                    window.open_file(&file);
+                }
+            }
+        ));
+    }
+
+    /// Ask for a folder and list its markdown files in the sidebar
+    fn choose_folder(&self) {
+        let dialog = gtk::FileDialog::builder()
+            .title(gettext("Open Folder"))
+            .build();
+
+        glib::spawn_future_local(glib_macros::clone!(
+            #[weak(rename_to = window)]
+            self,
+            async move {
+                // An error here means the dialog was dismissed
+                if let Ok(folder) = dialog.select_folder_future(Some(&window)).await {
+                    window.open_folder(&folder);
+                }
+            }
+        ));
+    }
+
+    /// Open dropped markdown files in tabs and a dropped folder in the sidebar, returning
+    /// whether any of them could be opened
+    fn open_dropped(&self, files: &[gio::File]) -> bool {
+        let mut opened = false;
+        for file in files {
+            match file.query_file_type(gio::FileQueryInfoFlags::NONE, gio::Cancellable::NONE) {
+                gio::FileType::Directory => self.open_folder(file),
+                _ if file.path().is_some_and(|path| sidebar::is_markdown(&path)) => {
+                    if self.open_file(file).is_none() {
+                        continue;
+                    }
+                }
+                _ => continue,
+            }
+            opened = true;
+        }
+        opened
+    }
+
+    /// List the markdown files opened most recently on the start page
+    fn update_recent(&self) {
+        let imp = self.imp();
+        imp.recent_list.remove_all();
+
+        let app_name = glib::application_name();
+        let mut items: Vec<gtk::RecentInfo> = gtk::RecentManager::default()
+            .items()
+            .into_iter()
+            .filter(|item| {
+                app_name
+                    .as_deref()
+                    .is_some_and(|name| item.has_application(name))
+                    && item.exists()
+                    && sidebar::is_markdown(Path::new(item.uri().as_str()))
+            })
+            .collect();
+        items.sort_by_key(|item| std::cmp::Reverse(item.modified().to_unix()));
+        items.truncate(MAX_RECENT);
+
+        let mut files = Vec::new();
+        for item in items {
+            let file = gio::File::for_uri(&item.uri());
+            let row = adw::ActionRow::builder()
+                .use_markup(false)
+                .title(item.display_name())
+                .subtitle(
+                    file.parent()
+                        .and_then(|parent| parent.path())
+                        .map(|path| path.display().to_string())
+                        .unwrap_or_default(),
+                )
+                .activatable(true)
+                .build();
+            row.add_suffix(&gtk::Image::from_icon_name("go-next-symbolic"));
+            imp.recent_list.append(&row);
+            files.push(file);
+        }
+        imp.recent_box.set_visible(!files.is_empty());
+        imp.recent.replace(files);
+    }
+
+    /// Show the search of the open folder in the sidebar
+    fn search_folder(&self) {
+        let imp = self.imp();
+        imp.sidebar_button.set_active(true);
+        imp.sidebar_stack.set_visible_child_name("search");
+        imp.search_panel.focus_entry();
+    }
+
+    /// Open a markdown file in a new tab, in the background unless `select` is set
+    fn open_in_tab(&self, file: &gio::File, select: bool) -> Option<DocumentPage> {
+        let page = DocumentPage::new();
+        page.set_folder_files(self.imp().file_sidebar.files());
+        page.md_viewer()
+            .set_editable(settings::settings().string("open-mode") == "edit");
+        if let Err(err) = page.open_file(file) {
+            glib::g_warning!("potato-md", "Failed to open {}: {err}", file.uri());
+            return None;
+        }
+        gtk::RecentManager::default().add_item(&file.uri());
+
+        let tab_view = &self.imp().tab_view;
+        let tab_page = tab_view.append(&page);
+        page.bind_property("title", &tab_page, "title")
+            .sync_create()
+            .build();
+        if select {
+            tab_view.set_selected_page(&tab_page);
+        }
+
+        // Pages can be dragged to other windows, so handlers look up the current one
+        page.connect_open_in_new_tab(|page, file| {
+            if let Some(window) = page.root().and_downcast::<Self>() {
+                window.open_in_tab(file, false);
+            }
+        });
+        page.md_viewer()
+            .connect_headings_changed(glib_macros::clone!(
+                #[weak]
+                page,
+                move |_| {
+                    if let Some(window) = page.root().and_downcast::<Self>()
+                        && window.selected_page().as_ref() == Some(&page)
+                    {
+                        window.update_outline();
+                    }
+                }
+            ));
+        for property in ["can-go-back", "can-go-forward"] {
+            page.connect_notify_local(Some(property), |page, _| {
+                if let Some(window) = page.root().and_downcast::<Self>()
+                    && window.selected_page().as_ref() == Some(page)
+                {
+                    window.update_actions();
+                }
+            });
+        }
+        Some(page)
+    }
+
+    /// Pages of all tabs
+    fn pages(&self) -> Vec<DocumentPage> {
+        let tab_view = &self.imp().tab_view;
+        (0..tab_view.n_pages())
+            .filter_map(|i| tab_view.nth_page(i).child().downcast().ok())
+            .collect()
+    }
+
+    /// Page of the selected tab
+    fn selected_page(&self) -> Option<DocumentPage> {
+        self.imp()
+            .tab_view
+            .selected_page()
+            .and_then(|tab_page| tab_page.child().downcast().ok())
+    }
+
+    /// Point the header bar, actions and outline at the selected page
+    fn page_changed(&self) {
+        let imp = self.imp();
+        for binding in imp.page_bindings.take() {
+            binding.unbind();
+        }
+
+        let page = self.selected_page();
+        match &page {
+            Some(page) => {
+                let viewer = page.md_viewer();
+                imp.page_bindings.replace(vec![
+                    page.bind_property("title", self, "title")
+                        .sync_create()
+                        .build(),
+                    viewer
+                        .bind_property("editable", &*imp.edit_button, "active")
+                        .sync_create()
+                        .bidirectional()
+                        .build(),
+                    viewer
+                        .bind_property("modified", &*imp.save_button, "visible")
+                        .sync_create()
+                        .build(),
+                ]);
+            }
+            None => {
+                self.set_title(Some(&gettext("Potato MD")));
+                imp.save_button.set_visible(false);
+            }
+        }
+        for button in [
+            imp.back_button.upcast_ref::<gtk::Widget>(),
+            imp.forward_button.upcast_ref(),
+            imp.edit_button.upcast_ref(),
+            imp.print_button.upcast_ref(),
+        ] {
+            button.set_visible(page.is_some());
+        }
+
+        self.update_actions();
+        self.update_outline();
+        self.update_backlinks();
+    }
+
+    /// Enable the actions that apply to the selected page
+    fn update_actions(&self) {
+        let page = self.selected_page();
+        for action in [
+            "win.save",
+            "win.export-html",
+            "win.export-pdf",
+            "win.print",
+            "win.close-tab",
+        ] {
+            self.action_set_enabled(action, page.is_some());
+        }
+        self.action_set_enabled(
+            "win.go-back",
+            page.as_ref().is_some_and(DocumentPage::can_go_back),
+        );
+        self.action_set_enabled(
+            "win.go-forward",
+            page.as_ref().is_some_and(DocumentPage::can_go_forward),
+        );
+    }
+
+    /// Ask before closing a tab with unsaved edits
+    fn close_page(&self, tab_page: &adw::TabPage) -> glib::Propagation {
+        let Ok(page) = tab_page.child().downcast::<DocumentPage>() else {
+            return glib::Propagation::Proceed;
+        };
+        if !page.md_viewer().modified() {
+            return glib::Propagation::Proceed;
+        }
+
+        glib::spawn_future_local(glib_macros::clone!(
+            #[weak(rename_to = window)]
+            self,
+            #[strong]
+            tab_page,
+            async move {
+                let detail = gettext("Unsaved edits are lost when the tab is closed.");
+                let close = page.confirm_discard(&detail).await;
+                window.imp().tab_view.close_page_finish(&tab_page, close);
+            }
+        ));
+        glib::Propagation::Stop
+    }
+
+    /// Ask about the unsaved edits of every tab before the window closes, and close it
+    /// again once they are saved or discarded. Cancelling any of them keeps it open.
+    fn confirm_close(&self) -> glib::Propagation {
+        let modified: Vec<DocumentPage> = self
+            .pages()
+            .into_iter()
+            .filter(|page| page.md_viewer().modified())
+            .collect();
+        if modified.is_empty() {
+            return glib::Propagation::Proceed;
+        }
+
+        glib::spawn_future_local(glib_macros::clone!(
+            #[weak(rename_to = window)]
+            self,
+            async move {
+                let tab_view = &window.imp().tab_view;
+                let detail = gettext("Unsaved edits are lost when the window is closed.");
+                for page in modified {
+                    // Tabs may have been dragged to another window in the meantime
+                    if page.root().and_downcast::<Self>().as_ref() != Some(&window) {
+                        continue;
+                    }
+                    // Show the document the question is about
+                    tab_view.set_selected_page(&tab_view.page(&page));
+                    if !page.confirm_discard(&detail).await {
+                        return;
+                    }
+                }
+                window.imp().edits_confirmed.set(true);
+                window.close();
+            }
+        ));
+        glib::Propagation::Stop
+    }
+
+    /// Name of the open file with its extension replaced, to suggest for exports
+    fn export_name(&self, extension: &str) -> String {
+        self.selected_page()
+            .and_then(|page| page.file())
+            .and_then(|file| file.path())
+            .map(|path| path.with_extension(extension))
+            .and_then(|path| {
+                path.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+            .unwrap_or_else(|| format!("document.{extension}"))
+    }
+
+    /// Ask for a file and write the document there as standalone HTML
+    fn export_html(&self) {
+        let Some(page) = self.selected_page() else {
+            return;
+        };
+        let dialog = gtk::FileDialog::builder()
+            .title(gettext("Export as HTML"))
+            .initial_name(self.export_name("html"))
+            .build();
+
+        glib::spawn_future_local(glib_macros::clone!(
+            #[weak(rename_to = window)]
+            self,
+            async move {
+                // An error here means the dialog was dismissed
+                let Ok(target) = dialog.save_future(Some(&window)).await else {
+                    return;
+                };
+
+                let base_dir = page
+                    .file()
+                    .and_then(|file| file.parent())
+                    .and_then(|parent| parent.path());
+                let document = markdown::parse(&page.md_viewer().to_markdown());
+                let resolve_wiki_link = |target: &str| page.wiki_link_path(target);
+                let html = export::document_html(
+                    &document,
+                    base_dir.as_deref(),
+                    adw::StyleManager::default().is_dark(),
+                    Some(&resolve_wiki_link),
+                );
+                if let Err(err) = target.replace_contents(
+                    html.as_bytes(),
+                    None,
+                    false,
+                    gio::FileCreateFlags::NONE,
+                    gio::Cancellable::NONE,
+                ) {
+                    glib::g_warning!("potato-md", "Failed to export {}: {err}", target.uri());
+                }
+            }
+        ));
+    }
+
+    /// Ask for a file and write the document there as PDF, paginated like when printing
+    fn export_pdf(&self) {
+        let Some(page) = self.selected_page() else {
+            return;
+        };
+        let dialog = gtk::FileDialog::builder()
+            .title(gettext("Export as PDF"))
+            .initial_name(self.export_name("pdf"))
+            .build();
+
+        glib::spawn_future_local(glib_macros::clone!(
+            #[weak(rename_to = window)]
+            self,
+            async move {
+                // An error here means the dialog was dismissed
+                let Ok(target) = dialog.save_future(Some(&window)).await else {
+                    return;
+                };
+                let Some(path) = target.path() else {
+                    glib::g_warning!("potato-md", "Cannot export to {}", target.uri());
+                    return;
+                };
+
+                if let Err(err) = page.md_viewer().export_pdf(&path) {
+                    glib::g_warning!("potato-md", "Failed to export {}: {err}", target.uri());
+                }
+            }
+        ));
+    }
+
+    /// Fill the outline popover with the headings of the open document
+    fn update_outline(&self) {
+        let imp = self.imp();
+        imp.outline_list.remove_all();
+
+        let headings = self
+            .selected_page()
+            .map(|page| page.md_viewer().headings())
+            .unwrap_or_default();
+        imp.outline_button.set_visible(!headings.is_empty());
+
+        for heading in headings {
+            let label = gtk::Label::builder()
+                .label(&heading.title)
+                .xalign(0.0)
+                .ellipsize(gtk::pango::EllipsizeMode::End)
+                .max_width_chars(40)
+                .margin_start(12 * (heading.level as i32 - 1))
+                .build();
+            let row = gtk::ListBoxRow::builder().child(&label).build();
+            imp.outline_list.append(&row);
+        }
+    }
+
+    /// List the files of the open folder that link to the selected page
+    fn update_backlinks(&self) {
+        let Some(file) = self.selected_page().and_then(|page| page.file()) else {
+            self.show_backlinks(Vec::new());
+            return;
+        };
+
+        glib::spawn_future_local(glib_macros::clone!(
+            #[weak(rename_to = window)]
+            self,
+            async move {
+                let backlinks = window.imp().search_panel.backlinks(&file).await;
+                // Another page may have been selected meanwhile
+                let current = window.selected_page().and_then(|page| page.file());
+                if current.is_some_and(|current| current.equal(&file)) {
+                    window.show_backlinks(backlinks);
+                }
+            }
+        ));
+    }
+
+    fn show_backlinks(&self, paths: Vec<PathBuf>) {
+        let imp = self.imp();
+        imp.backlinks_list.remove_all();
+
+        let folder = imp.file_sidebar.folder().and_then(|folder| folder.path());
+        for path in &paths {
+            let name = folder
+                .as_deref()
+                .and_then(|folder| path.strip_prefix(folder).ok())
+                .unwrap_or(path);
+            let label = gtk::Label::builder()
+                .label(name.display().to_string())
+                .xalign(0.0)
+                .ellipsize(gtk::pango::EllipsizeMode::Middle)
+                .build();
+            let row = gtk::ListBoxRow::builder().child(&label).build();
+            imp.backlinks_list.append(&row);
+        }
+        imp.backlinks
+            .replace(paths.iter().map(gio::File::for_path).collect());
+    }
 }
@@ -0,0 +1,37 @@
+//! Export of documents to PDF the way `potato-md --export-pdf` does it.
+//!
+//! Pages are laid out with the widgets, so the test needs a display and is ignored by
+//! default. Without one it runs against the Broadway backend, see the README.
+
+use std::path::Path;
+
+use potato_md::MdViewer;
+
+#[test]
+#[ignore = "needs a display"]
+fn sample_exports_to_pdf() {
+    adw::init().expect("Exporting needs a display");
+    potato_md::init();
+
+    let input = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join("sample.md");
+    let source = std::fs::read_to_string(&input).expect("Failed to read the fixture");
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join("sample.pdf");
+
+    let viewer = MdViewer::new();
+    viewer.set_markdown(&source, input.parent());
+    viewer
+        .export_pdf(&output)
+        .expect("Failed to export the fixture");
+
+    let pdf = std::fs::read(&output).expect("Failed to read the exported file");
+    assert!(pdf.starts_with(b"%PDF-"), "The export is not a PDF file");
+    // Page objects, not the `/Type /Pages` tree holding them
+    let pages = pdf
+        .windows(12)
+        .filter(|w| w.starts_with(b"/Type /Page") && w[11] != b's')
+        .count();
+    assert!(pages > 0, "The PDF has no pages");
+}